@@ -0,0 +1,42 @@
+//! Injection runs on every outbound request: these measure writing the
+//! headers of each style, which should stay well under a microsecond.
+//! Run with `cargo bench --bench inject`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use dd_opentracing_rs::{
+    propagation::{inject, PropagatedContext, STYLES},
+    PropagationStyle, SamplingPriority,
+};
+
+fn bench_inject(c: &mut Criterion) {
+    let context = PropagatedContext {
+        trace_id: 0x1234_5678_9abc_def0,
+        trace_id_high: 0x640c_fd8d_0000_0000,
+        parent_id: 0x0fed_cba9_8765_4321,
+        sampling_priority: Some(SamplingPriority::UserKeep),
+        origin: String::from("synthetics"),
+        ..Default::default()
+    };
+    // Headers are only measured, not stored, to leave the carrier out.
+    let mut set = |key: &str, value: &str| -> Result<(), ()> {
+        black_box((key, value));
+        Ok(())
+    };
+
+    for style in STYLES.iter() {
+        c.bench_function(&format!("inject {}", style.name()), |b| {
+            b.iter(|| inject(style, black_box(&context), &mut set))
+        });
+    }
+
+    let mut context = context;
+    context
+        .baggage
+        .insert(String::from("user"), String::from("42"));
+    c.bench_function("inject datadog with baggage", |b| {
+        b.iter(|| inject(&PropagationStyle::Datadog, black_box(&context), &mut set))
+    });
+}
+
+criterion_group!(benches, bench_inject);
+criterion_main!(benches);
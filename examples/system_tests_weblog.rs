@@ -0,0 +1,313 @@
+//! Test app of Datadog's cross-tracer system-tests, which drive each tracer
+//! through the same HTTP interface (the "parametric" scenarios): spans are
+//! started, tagged, injected and finished on request, then the traces
+//! received by the test agent are compared across tracers.
+//!
+//! The tracer is configured from the usual `DD_` variables and listens on
+//! `APM_TEST_CLIENT_SERVER_PORT` (8080 by default). Every request is a POST
+//! of a JSON object answered with a JSON object:
+//!
+//! - `/trace/span/start` `{name, service?, resource?, type?, parent_id?,
+//!   span_tags?: [[key, value]]}` returns `{span_id, trace_id}`. The parent
+//!   is a started span or a context returned by `extract_headers`.
+//! - `/trace/span/finish` `{span_id}`
+//! - `/trace/span/set_meta` `{span_id, key, value}`
+//! - `/trace/span/set_metric` `{span_id, key, value}`
+//! - `/trace/span/error` `{span_id, type?, message?, stack?}`
+//! - `/trace/span/inject_headers` `{span_id}` returns `{http_headers: [[key,
+//!   value]]}`
+//! - `/trace/span/extract_headers` `{http_headers: [[key, value]]}` returns
+//!   `{span_id}`, the id to start children of the extracted context with.
+//! - `/trace/span/flush` and `/trace/stats/flush` send the finished traces.
+//!
+//! Run it with `cargo run --example system_tests_weblog --features ffi`.
+
+use dd_opentracing_rs::ffi::{
+    dd_span_finish, dd_span_id, dd_span_inject, dd_span_set_metric, dd_span_set_tag, dd_span_start,
+    dd_span_start_extracted, dd_span_trace_id, dd_tracer_flush, dd_tracer_new, DdSpan,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    env,
+    ffi::{CStr, CString},
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    os::raw::{c_char, c_int, c_void},
+    ptr,
+};
+
+/// Headers of a request, read and written by the tracer through the C
+/// callbacks.
+#[derive(Default)]
+struct Carrier {
+    headers: Vec<(String, String)>,
+    /// Keeps the last value looked up alive until the next lookup.
+    last_value: Option<CString>,
+}
+
+extern "C" fn lookup(carrier: *mut c_void, key: *const c_char) -> *const c_char {
+    let carrier = unsafe { &mut *(carrier as *mut Carrier) };
+    let key = unsafe { CStr::from_ptr(key) }.to_string_lossy();
+    let value = carrier
+        .headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(&key))
+        .and_then(|(_, value)| CString::new(value.as_str()).ok());
+    carrier.last_value = value;
+    carrier
+        .last_value
+        .as_ref()
+        .map_or(ptr::null(), |value| value.as_ptr())
+}
+
+extern "C" fn set(carrier: *mut c_void, key: *const c_char, value: *const c_char) -> c_int {
+    let carrier = unsafe { &mut *(carrier as *mut Carrier) };
+    let (key, value) = unsafe { (CStr::from_ptr(key), CStr::from_ptr(value)) };
+    carrier.headers.push((
+        key.to_string_lossy().into_owned(),
+        value.to_string_lossy().into_owned(),
+    ));
+    0
+}
+
+fn c_string(value: &str) -> CString {
+    CString::new(value.replace('\0', "")).unwrap_or_default()
+}
+
+fn tag_value(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// The spans and extracted contexts of the test, by id.
+struct Weblog {
+    /// The tracer created by `dd_tracer_new`, whose type isn't public.
+    tracer: *mut c_void,
+    spans: HashMap<u64, *mut DdSpan>,
+    extracted: HashMap<u64, Carrier>,
+    next_extracted_id: u64,
+}
+
+impl Weblog {
+    fn span(&self, request: &Value) -> Result<*mut DdSpan, String> {
+        let span_id = request["span_id"].as_u64().unwrap_or_default();
+        self.spans
+            .get(&span_id)
+            .copied()
+            .ok_or_else(|| format!("Unknown span {}", span_id))
+    }
+
+    fn set_tag(&self, request: &Value, key: &str, value: &str) -> Result<Value, String> {
+        let span = self.span(request)?;
+        let (key, value) = (c_string(key), c_string(value));
+        unsafe { dd_span_set_tag(span, key.as_ptr(), value.as_ptr()) };
+        Ok(json!({}))
+    }
+
+    fn start(&mut self, request: &Value) -> Result<Value, String> {
+        let name = c_string(request["name"].as_str().unwrap_or("span"));
+        let parent_id = request["parent_id"].as_u64().unwrap_or_default();
+        let span = unsafe {
+            match self.extracted.get_mut(&parent_id) {
+                Some(carrier) => dd_span_start_extracted(
+                    self.tracer.cast(),
+                    name.as_ptr(),
+                    lookup,
+                    carrier as *mut Carrier as *mut c_void,
+                ),
+                None => dd_span_start(
+                    self.tracer.cast(),
+                    name.as_ptr(),
+                    self.spans
+                        .get(&parent_id)
+                        .copied()
+                        .unwrap_or(ptr::null_mut()),
+                ),
+            }
+        };
+        if span.is_null() {
+            return Err(String::from("The span couldn't be started"));
+        }
+
+        let span_id = unsafe { dd_span_id(span) };
+        self.spans.insert(span_id, span);
+        let request_tags = [
+            ("service", "service.name"),
+            ("resource", "resource.name"),
+            ("type", "span.type"),
+        ];
+        for (field, tag) in request_tags.iter() {
+            if let Some(value) = request[*field].as_str() {
+                self.set_tag(&json!({ "span_id": span_id }), tag, value)?;
+            }
+        }
+        for tag in request["span_tags"].as_array().into_iter().flatten() {
+            if let (Some(key), value) = (tag[0].as_str(), &tag[1]) {
+                self.set_tag(&json!({ "span_id": span_id }), key, &tag_value(value))?;
+            }
+        }
+
+        Ok(json!({
+            "span_id": span_id,
+            "trace_id": unsafe { dd_span_trace_id(span) },
+        }))
+    }
+
+    fn handle(&mut self, path: &str, request: &Value) -> Result<Value, String> {
+        match path {
+            "/trace/span/start" => self.start(request),
+            "/trace/span/finish" => {
+                let span = self.span(request)?;
+                self.spans.retain(|_, other| *other != span);
+                unsafe { dd_span_finish(span) };
+                Ok(json!({}))
+            }
+            "/trace/span/set_meta" => {
+                let key = request["key"].as_str().unwrap_or_default();
+                self.set_tag(request, key, &tag_value(&request["value"]))
+            }
+            "/trace/span/set_metric" => {
+                let span = self.span(request)?;
+                let key = c_string(request["key"].as_str().unwrap_or_default());
+                let value = request["value"].as_f64().unwrap_or_default();
+                unsafe { dd_span_set_metric(span, key.as_ptr(), value) };
+                Ok(json!({}))
+            }
+            "/trace/span/error" => {
+                self.set_tag(request, "error", "true")?;
+                let fields = [
+                    ("type", "error.type"),
+                    ("message", "error.msg"),
+                    ("stack", "error.stack"),
+                ];
+                for (field, tag) in fields.iter() {
+                    if let Some(value) = request[*field].as_str() {
+                        self.set_tag(request, tag, value)?;
+                    }
+                }
+                Ok(json!({}))
+            }
+            "/trace/span/inject_headers" => {
+                let span = self.span(request)?;
+                let mut carrier = Carrier::default();
+                let carrier_ptr = &mut carrier as *mut Carrier as *mut c_void;
+                if unsafe { dd_span_inject(span, set, carrier_ptr) } != 0 {
+                    return Err(String::from("The span context couldn't be injected"));
+                }
+                Ok(json!({ "http_headers": carrier.headers }))
+            }
+            "/trace/span/extract_headers" => {
+                let headers = request["http_headers"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|header| {
+                        Some((
+                            String::from(header[0].as_str()?),
+                            String::from(header[1].as_str()?),
+                        ))
+                    })
+                    .collect();
+                // Ids above 2^63 can't be span ids of this tracer.
+                self.next_extracted_id += 1;
+                let id = (1 << 63) + self.next_extracted_id;
+                self.extracted.insert(
+                    id,
+                    Carrier {
+                        headers,
+                        last_value: None,
+                    },
+                );
+                Ok(json!({ "span_id": id }))
+            }
+            "/trace/span/flush" | "/trace/stats/flush" => {
+                match unsafe { dd_tracer_flush(self.tracer.cast(), 5000) } {
+                    -1 => Err(String::from("The traces couldn't be sent")),
+                    _ => Ok(json!({})),
+                }
+            }
+            _ => Err(format!("Unknown endpoint {}", path)),
+        }
+    }
+}
+
+/// Reads a request, returning its path and JSON body.
+fn read_request(stream: &TcpStream) -> std::io::Result<(String, Value)> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let path = line.split_whitespace().nth(1).unwrap_or("/").to_string();
+
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or_default();
+            }
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok((path, serde_json::from_slice(&body).unwrap_or(Value::Null)))
+}
+
+fn respond(mut stream: &TcpStream, status: &str, body: &Value) -> std::io::Result<()> {
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+fn main() {
+    let var = |name: &str| env::var(name).ok().filter(|value| !value.is_empty());
+    let mut config =
+        json!({ "service": var("DD_SERVICE").unwrap_or_else(|| String::from("weblog")) });
+    if let Some(host) = var("DD_AGENT_HOST") {
+        config["agent_host"] = json!(host);
+    }
+    if let Some(port) = var("DD_TRACE_AGENT_PORT").and_then(|port| port.parse::<u16>().ok()) {
+        config["agent_port"] = json!(port);
+    }
+    if let Some(env) = var("DD_ENV") {
+        config["environment"] = json!(env);
+    }
+    if let Some(version) = var("DD_VERSION") {
+        config["version"] = json!(version);
+    }
+
+    let config = c_string(&config.to_string());
+    let tracer = unsafe { dd_tracer_new(config.as_ptr()) };
+    assert!(!tracer.is_null(), "Invalid tracer configuration");
+    let mut weblog = Weblog {
+        tracer: tracer.cast(),
+        spans: HashMap::new(),
+        extracted: HashMap::new(),
+        next_extracted_id: 0,
+    };
+
+    let port = var("APM_TEST_CLIENT_SERVER_PORT").unwrap_or_else(|| String::from("8080"));
+    let listener = TcpListener::bind(("0.0.0.0", port.parse().unwrap_or(8080)))
+        .expect("The port is already used");
+    for stream in listener.incoming().flatten() {
+        let response = read_request(&stream).map(|(path, request)| weblog.handle(&path, &request));
+        let _ = match response {
+            Ok(Ok(body)) => respond(&stream, "200 OK", &body),
+            Ok(Err(error)) => respond(&stream, "400 Bad Request", &json!({ "error": error })),
+            Err(_) => continue,
+        };
+    }
+}
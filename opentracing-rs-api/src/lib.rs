@@ -0,0 +1,13 @@
+//! The OpenTracing API, as in opentracing-cpp: the traits a tracer
+//! implements and instrumentations call, with no tracer behind them.
+//! dd-opentracing-rs implements them for Datadog.
+
+mod propagation;
+mod span;
+mod tracer;
+mod tracer_factory;
+
+pub use propagation::*;
+pub use span::*;
+pub use tracer::*;
+pub use tracer_factory::*;
@@ -1,17 +1,20 @@
 use eyre::Result;
+use std::{collections::HashMap, fmt};
 
 use super::{SpanContext, Tracer};
 
 #[derive(Clone)]
-pub(crate) enum SpanReferenceType {
+pub enum SpanReferenceType {
     /// ChildOfRef refers to a parent Span that caused *and* somehow depends
     /// upon the new child Span. Often (but not always), the parent Span cannot
     /// finish until the child Span does.
     ///
     /// An timing diagram for a ChildOfRef that's blocked on the new Span:
     ///
+    /// ```text
     ///     [-Parent Span---------]
     ///          [-Child Span----]
+    /// ```
     ///
     /// See http://opentracing.io/spec/
     ///
@@ -28,6 +31,7 @@ pub(crate) enum SpanReferenceType {
     /// All of the following could be valid timing diagrams for children that
     /// "FollowFrom" a parent.
     ///
+    /// ```text
     ///     [-Parent Span-]  [-Child Span-]
     ///
     ///
@@ -37,6 +41,7 @@ pub(crate) enum SpanReferenceType {
     ///
     ///     [-Parent Span-]
     ///                 [-Child Span-]
+    /// ```
     ///
     /// See http://opentracing.io/spec/
     ///
@@ -44,7 +49,8 @@ pub(crate) enum SpanReferenceType {
     FollowsFromRef,
 }
 
-pub(crate) enum PropagationError {
+#[derive(Debug)]
+pub enum PropagationError {
     /// `InvalidSpanContext` occurs when Tracer::Inject() is asked to operate
     /// on a SpanContext which it is not prepared to handle (for example, since it
     /// was created by a different tracer implementation).
@@ -63,12 +69,41 @@ pub(crate) enum PropagationError {
     LookupKeyNotSupported,
 }
 
+/// ExtractionError is why Tracer::extract() couldn't read the span context
+/// of a carrier. A carrier without span context isn't an error: extract()
+/// returns `None` and a new trace should be started.
+#[derive(Debug)]
+pub enum ExtractionError {
+    /// The carrier failed to look up `key`, e.g. with
+    /// `PropagationError::InvalidCarrier`.
+    Carrier {
+        key: String,
+        error: PropagationError,
+    },
+    /// The carrier holds a span context, but it's corrupted
+    /// (`PropagationError::SpanContextCorrupted`).
+    Corrupted(String),
+}
+
+impl fmt::Display for ExtractionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExtractionError::Carrier { key, error } => {
+                write!(f, "Failed to look up {}: {:?}", key, error)
+            }
+            ExtractionError::Corrupted(reason) => write!(f, "Corrupted span context: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ExtractionError {}
+
 /// TextMapReader is the Extract() carrier for the TextMap builtin format. With
 /// it, the caller can decode a SpanContext from entries in a propagated map of
 /// Unicode strings.
 ///
 /// See the HTTPHeaders examples.
-pub(crate) trait TextMapReader {
+pub trait TextMapReader {
     /// LookupKey returns the value for the specified `key` if available. If no
     /// such key is present, it returns `PropagationError::KeyNotFound`.
     ///
@@ -90,10 +125,7 @@ pub(crate) trait TextMapReader {
     ///
     /// The "foreach" callback pattern reduces unnecessary copying in some cases
     /// and also allows implementations to hold locks while the map is read.
-    fn foreach_key<F>(&self, f: F) -> Result<()>
-    where
-        F: Fn(&str, &str) -> Result<()>,
-        Self: Sized;
+    fn foreach_key(&self, f: &mut dyn FnMut(&str, &str) -> Result<()>) -> Result<()>;
 }
 
 /// TextMapWriter is the Inject() carrier for the TextMap builtin format. With
@@ -101,7 +133,7 @@ pub(crate) trait TextMapReader {
 /// of unicode strings.
 ///
 /// See the HTTPHeaders examples.
-pub(crate) trait TextMapWriter {
+pub trait TextMapWriter {
     /// Set a key:value pair to the carrier. Multiple calls to Set() for the
     /// same key leads to undefined behavior.
     ///
@@ -112,28 +144,51 @@ pub(crate) trait TextMapWriter {
     fn set(&mut self, key: &str, value: &str) -> Result<()>;
 }
 
+/// Maps are text map carriers, as in the opentracing and rustracing crates,
+/// so that their instrumentations propagate contexts unchanged.
+impl TextMapReader for HashMap<String, String> {
+    fn lookup_key(&self, key: &str) -> Result<String, PropagationError> {
+        self.get(key).cloned().ok_or(PropagationError::KeyNotFound)
+    }
+
+    fn foreach_key(&self, f: &mut dyn FnMut(&str, &str) -> Result<()>) -> Result<()> {
+        for (key, value) in self {
+            f(key, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl TextMapWriter for HashMap<String, String> {
+    fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        self.insert(String::from(key), String::from(value));
+        Ok(())
+    }
+}
+
 /// HTTPHeadersReader is the Extract() carrier for the HttpHeaders builtin
 /// format. With it, the caller can decode a SpanContext from entries in HTTP
 /// request headers.
-pub(crate) trait HTTPHeadersReader: TextMapReader {}
+pub trait HTTPHeadersReader: TextMapReader {}
 
 /// HTTPHeadersWriter is the Inject() carrier for the TextMap builtin format.
 /// With it, the caller can encode a SpanContext for propagation as entries in
 /// http request headers
-pub(crate) trait HTTPHeadersWriter: TextMapWriter {}
+pub trait HTTPHeadersWriter: TextMapWriter {}
 
 /// CustomCarrierReader is the Extract() carrier for a custom format. With it,
 /// the caller can decode a SpanContext from entries in a custom protocol.
-pub(crate) trait CustomCarrierReader {
+pub trait CustomCarrierReader {
     /// Extract is expected to specialize on the tracer implementation so as to
     /// most efficiently decode its context.
-    fn extract(&self, tracer: &dyn Tracer) -> Result<Box<dyn SpanContext>>;
+    fn extract(&self, tracer: &dyn Tracer)
+        -> Result<Option<Box<dyn SpanContext>>, ExtractionError>;
 }
 
 /// CustomCarrierWriter is the Inject() carrier for a custom format.  With it,
 /// the caller can encode a SpanContext for propagation as entries in a custom
 /// protocol.
-pub(crate) trait CustomCarrierWriter {
+pub trait CustomCarrierWriter {
     /// Inject is expected to specialize on the tracer implementation so as to most
     /// efficiently encode its context.
     fn inject(tracer: &dyn Tracer, sc: &dyn SpanContext) -> Result<()>;
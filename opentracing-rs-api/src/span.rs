@@ -1,4 +1,7 @@
-use std::time::{Instant, SystemTime};
+use std::{
+    any::Any,
+    time::{Instant, SystemTime},
+};
 
 use eyre::Result;
 use serde_json::Value;
@@ -7,24 +10,32 @@ use super::Tracer;
 
 /// SpanContext represents Span state that must propagate to descendant Spans and
 /// across process boundaries (e.g., a <trace_id, span_id, sampled> tuple).
-pub(crate) trait SpanContext {
+pub trait SpanContext {
     /// ForeachBaggageItem calls a function for each baggage item in the
     /// context.  If the function returns false, it will not be called
     /// again and ForeachBaggageItem will return.
-    fn foreach_baggage_item<F>(&self, f: F) -> Result<()>
-    where
-        F: Fn(&str, &str) -> bool,
-        Self: Sized;
+    fn foreach_baggage_item(&self, f: &mut dyn FnMut(&str, &str) -> bool) -> Result<()>;
+
+    /// Returns the number of baggage items in the context.
+    fn baggage_len(&self) -> usize;
+
+    /// Returns a copy of the baggage items of the context, in no particular
+    /// order.
+    fn iter(&self) -> std::vec::IntoIter<(String, String)>;
+
+    /// Allows tracer implementations to recover their own SpanContext type
+    /// from a trait object.
+    fn as_any(&self) -> &dyn Any;
 }
 
-pub(crate) struct LogRecord {
+pub struct LogRecord {
     pub timestamp: SystemTime,
     pub fields: Vec<(String, Value)>,
 }
 
 /// FinishOptions allows Span.Finish callers to override the finish
 /// timestamp.
-pub(crate) struct FinishSpanOptions {
+pub struct FinishSpanOptions {
     pub finish_steady_timestamp: Instant,
 
     /// log_records allows the caller to specify the contents of many Log() calls
@@ -39,14 +50,14 @@ pub(crate) struct FinishSpanOptions {
 }
 
 /// FinishSpanOption instances (zero or more) may be passed to Span.Finish.
-pub(crate) trait FinishSpanOption {
+pub trait FinishSpanOption {
     fn apply(&mut self, options: &mut FinishSpanOptions);
 }
 
 /// Span represents an active, un-finished span in the OpenTracing system.
 ///
 /// Spans are created by the Tracer interface.
-pub(crate) trait Span {
+pub trait Span {
     /// Sets the end timestamp and finalizes Span state.
     ///
     /// If Finish is called a second time, it is guaranteed to do nothing.
@@ -107,6 +118,10 @@ pub(crate) trait Span {
     /// if the value isn't found in this Span.
     fn baggage_item(&self, restricted_key: &str) -> String;
 
+    /// Removes a baggage item from this Span and its SpanContext, so that it
+    /// isn't propagated to future descendants. Returns its value, if any.
+    fn remove_baggage_item(&mut self, restricted_key: &str) -> Option<String>;
+
     fn log(&mut self, fields: &[(String, Value)]);
 
     /// context() yields the SpanContext for this Span. Note that the return
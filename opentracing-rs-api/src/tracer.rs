@@ -1,8 +1,9 @@
-use super::{Span, SpanContext, SpanReferenceType, TextMapReader, TextMapWriter};
+use super::{ExtractionError, Span, SpanContext, SpanReferenceType, TextMapReader, TextMapWriter};
 use eyre::Result;
 use serde_json::Value;
 use std::{
     rc::Rc,
+    sync::{Arc, RwLock},
     time::{Instant, SystemTime},
 };
 
@@ -12,7 +13,7 @@ use std::{
 ///
 /// StartSpan() callers should look at the StartSpanOption interface and
 /// implementations available in this library.
-pub(crate) struct StartSpanOptions {
+pub struct StartSpanOptions {
     /// start_system_timestamp and start_steady_timestamp override the Span's start
     /// time, or implicitly become std::chrono::system_clock::now() and
     /// std::chrono::steady_clock::now() if both are equal to the epoch (default
@@ -27,6 +28,10 @@ pub(crate) struct StartSpanOptions {
     ///
     /// Any nullptrs provided will be ignored.
     pub references: Vec<(SpanReferenceType, Rc<dyn SpanContext>)>,
+    /// Parent of the Span, e.g. an extracted context, taking precedence over
+    /// `references`. The same context can be the parent of any number of
+    /// sibling Spans.
+    pub parent_context: Option<Rc<dyn SpanContext>>,
     /// Zero or more tags to apply to the newly created span.
     pub tags: Vec<(String, Value)>,
 }
@@ -37,19 +42,20 @@ impl Default for StartSpanOptions {
             start_system_time: SystemTime::now(),
             start_steady_time: Instant::now(),
             references: Vec::new(),
+            parent_context: None,
             tags: Vec::new(),
         }
     }
 }
 
 /// StartSpanOption instances (zero or more) may be passed to Tracer.StartSpan.
-pub(crate) trait StartSpanOption {
+pub trait StartSpanOption {
     fn apply(&mut self, options: &mut StartSpanOptions);
 }
 
 /// Tracer is a simple, thin interface for Span creation and SpanContext
 /// propagation.
-pub(crate) trait Tracer {
+pub trait Tracer {
     /// Create, start, and return a new Span with the given `operationName` and
     /// incorporate the given StartSpanOption `option_list`.
     ///
@@ -74,18 +80,38 @@ pub(crate) trait Tracer {
         options: &StartSpanOptions,
     ) -> Box<dyn Span + '_>;
 
-    fn inject(&mut self, sc: &dyn SpanContext, writer: &dyn TextMapWriter) -> Result<()>;
-    fn extract(&self, reader: &dyn TextMapReader) -> Result<Box<dyn SpanContext>>;
+    fn inject(&self, sc: &dyn SpanContext, writer: &mut dyn TextMapWriter) -> Result<()>;
+    /// Reads the span context propagated in `reader`. Returns `None` if
+    /// there's none, in which case a new trace should be started, and an
+    /// error if it can't be read.
+    fn extract(
+        &self,
+        reader: &dyn TextMapReader,
+    ) -> Result<Option<Box<dyn SpanContext>>, ExtractionError>;
 
     fn close(&mut self);
 }
 
-// static mut GLOBAL_TRACER: Rc<dyn Tracer> = Rc::new();
+static GLOBAL_TRACER: RwLock<Option<Arc<dyn Tracer + Send + Sync>>> = RwLock::new(None);
+
+/// Sets the tracer returned by `global`, as `Tracer::InitGlobal` in
+/// opentracing-cpp, so that instrumentations can trace without being handed
+/// a tracer. Returns the previous global tracer.
+pub fn init_global(tracer: Arc<dyn Tracer + Send + Sync>) -> Option<Arc<dyn Tracer + Send + Sync>> {
+    let mut global = GLOBAL_TRACER
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    global.replace(tracer)
+}
 
-// pub(crate) fn init_global(tracer: Rc<dyn Tracer>) {
-//     static
-// }
-pub(crate) struct StartTimestamp {
+/// Returns the tracer set by `init_global`, if any.
+pub fn global() -> Option<Arc<dyn Tracer + Send + Sync>> {
+    GLOBAL_TRACER
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+}
+pub struct StartTimestamp {
     system_when: SystemTime,
     steady_when: Instant,
 }
@@ -106,7 +132,7 @@ impl StartSpanOption for StartTimestamp {
     }
 }
 
-pub(crate) struct SpanReference {
+pub struct SpanReference {
     span_ref_type: SpanReferenceType,
     referenced: Rc<dyn SpanContext>,
 }
@@ -128,15 +154,15 @@ impl StartSpanOption for SpanReference {
     }
 }
 
-pub(crate) fn child_of(sc: Rc<dyn SpanContext>) -> SpanReference {
+pub fn child_of(sc: Rc<dyn SpanContext>) -> SpanReference {
     SpanReference::new(SpanReferenceType::ChildOfRef, sc)
 }
 
-pub(crate) fn follows_from(sc: Rc<dyn SpanContext>) -> SpanReference {
+pub fn follows_from(sc: Rc<dyn SpanContext>) -> SpanReference {
     SpanReference::new(SpanReferenceType::FollowsFromRef, sc)
 }
 
-pub(crate) struct SetTag {
+pub struct SetTag {
     key: String,
     value: Value,
 }
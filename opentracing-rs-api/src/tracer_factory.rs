@@ -4,7 +4,8 @@ use eyre::Result;
 
 use super::Tracer;
 
-pub(crate) enum TracerFactoryError {
+#[derive(Debug)]
+pub enum TracerFactoryError {
     /// `configuration_parse_error` occurs when the configuration string used to
     /// construct a tracer does not adhere to the expected format.
     ConfigurationError,
@@ -14,7 +15,7 @@ pub(crate) enum TracerFactoryError {
 }
 
 /// TracerFactory constructs tracers from configuration strings.
-pub(crate) trait TracerFactory {
+pub trait TracerFactory {
     /// Creates a tracer with the requested `configuration`.
     fn make_tracer(&self, configuration: &str) -> Result<Rc<dyn Tracer>>;
 }
@@ -0,0 +1,127 @@
+use eyre::{eyre, Result};
+use serde_json::Value;
+
+pub(crate) const INFO_ENDPOINT: &str = "/info";
+pub(crate) const TRACES_V03_ENDPOINT: &str = "/v0.3/traces";
+pub(crate) const TRACES_V04_ENDPOINT: &str = "/v0.4/traces";
+pub(crate) const TRACES_V05_ENDPOINT: &str = "/v0.5/traces";
+pub(crate) const STATS_ENDPOINT: &str = "/v0.6/stats";
+pub(crate) const FLARE_ENDPOINT: &str = "/tracer_flare/v1";
+
+/// Capabilities advertised by the trace agent through its `/info` endpoint.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AgentInfo {
+    pub version: String,
+    pub endpoints: Vec<String>,
+    pub client_drop_p0s: bool,
+}
+
+impl AgentInfo {
+    pub fn from_json(info: &Value) -> Result<AgentInfo> {
+        let object = if let Value::Object(object) = info {
+            object
+        } else {
+            return Err(eyre!("Invalid json for agent info. Expected Object."));
+        };
+
+        let endpoints = match object.get("endpoints") {
+            Some(Value::Array(endpoints)) => endpoints
+                .iter()
+                .filter_map(|endpoint| endpoint.as_str().map(String::from))
+                .collect(),
+            Some(_) => return Err(eyre!("Invalid json for agent info endpoints.")),
+            None => Vec::new(),
+        };
+
+        Ok(AgentInfo {
+            version: String::from(
+                object
+                    .get("version")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default(),
+            ),
+            endpoints,
+            client_drop_p0s: object
+                .get("client_drop_p0s")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+        })
+    }
+
+    pub fn supports_endpoint(&self, endpoint: &str) -> bool {
+        self.endpoints.iter().any(|e| e == endpoint)
+    }
+
+    pub fn supports_stats(&self) -> bool {
+        self.supports_endpoint(STATS_ENDPOINT)
+    }
+
+    /// Picks the newest traces endpoint this writer can encode for. Agents
+    /// predating `/info` are assumed to accept v0.4.
+    pub fn traces_endpoint(info: Option<&AgentInfo>) -> &'static str {
+        match info {
+            Some(info) if info.supports_endpoint(TRACES_V05_ENDPOINT) => TRACES_V05_ENDPOINT,
+            Some(info) if info.supports_endpoint(TRACES_V04_ENDPOINT) => TRACES_V04_ENDPOINT,
+            Some(info) if info.supports_endpoint(TRACES_V03_ENDPOINT) => TRACES_V03_ENDPOINT,
+            _ => TRACES_V04_ENDPOINT,
+        }
+    }
+
+    /// Returns the traces endpoint to try when the agent doesn't have
+    /// `endpoint`, None for the oldest.
+    pub fn older_traces_endpoint(endpoint: &str) -> Option<&'static str> {
+        match endpoint {
+            TRACES_V05_ENDPOINT => Some(TRACES_V04_ENDPOINT),
+            TRACES_V04_ENDPOINT => Some(TRACES_V03_ENDPOINT),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INFO_JSON: &str = r#"
+    {
+        "version": "7.45.0",
+        "endpoints": ["/v0.3/traces", "/v0.4/traces", "/v0.5/traces", "/v0.6/stats"],
+        "client_drop_p0s": true,
+        "obfuscation_version": 1,
+        "config": {
+            "default_env": "prod"
+        }
+    }"#;
+
+    #[test]
+    fn parses_info() {
+        let info = AgentInfo::from_json(&serde_json::from_str(INFO_JSON).unwrap()).unwrap();
+        assert_eq!(info.version, "7.45.0");
+        assert!(info.supports_endpoint(TRACES_V05_ENDPOINT));
+        assert!(info.supports_stats());
+        assert!(info.client_drop_p0s);
+        assert_eq!(AgentInfo::traces_endpoint(Some(&info)), TRACES_V05_ENDPOINT);
+    }
+
+    #[test]
+    fn handles_old_agents() {
+        let info = AgentInfo::from_json(&serde_json::from_str("{}").unwrap()).unwrap();
+        assert_eq!(info, AgentInfo::default());
+        assert!(!info.supports_stats());
+        assert_eq!(AgentInfo::traces_endpoint(None), TRACES_V04_ENDPOINT);
+
+        let info = AgentInfo {
+            endpoints: vec![String::from(TRACES_V04_ENDPOINT)],
+            ..Default::default()
+        };
+        assert_eq!(AgentInfo::traces_endpoint(Some(&info)), TRACES_V04_ENDPOINT);
+
+        let info = AgentInfo {
+            endpoints: vec![String::from(TRACES_V03_ENDPOINT)],
+            ..Default::default()
+        };
+        assert_eq!(AgentInfo::traces_endpoint(Some(&info)), TRACES_V03_ENDPOINT);
+
+        assert!(AgentInfo::from_json(&Value::Null).is_err());
+    }
+}
@@ -0,0 +1,251 @@
+use super::{HttpResponse, Proxy, Transport};
+use eyre::{eyre, Result};
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+/// Minimal blocking HTTP/1.1 client used to talk to the trace agent. Every
+/// request opens a new connection which is closed by the server once the
+/// response is sent.
+#[derive(Clone, Debug)]
+pub(crate) struct HttpClient {
+    host: String,
+    port: u16,
+    timeout: Duration,
+    proxy: Option<Proxy>,
+}
+
+impl HttpClient {
+    pub fn new(host: &str, port: u16, timeout: Duration) -> Self {
+        Self {
+            host: String::from(host),
+            port,
+            timeout,
+            proxy: None,
+        }
+    }
+
+    /// Creates a client from an `http://host:port` url. Any path component of
+    /// the url is ignored.
+    pub fn from_url(url: &str, timeout: Duration) -> Result<Self> {
+        let (host, port) = parse_url(url, "http://", 80)?;
+        Ok(Self::new(&host, port, timeout))
+    }
+
+    /// Sends the requests through the proxy resolved by `Proxy::resolve`.
+    pub fn with_proxy(mut self, proxy_url: &str) -> Result<Self> {
+        self.proxy = Proxy::resolve(proxy_url, &self.host, false)?;
+        Ok(self)
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, String)],
+        body: &[u8],
+    ) -> Result<HttpResponse> {
+        if let Some(proxy) = &self.proxy {
+            // Forwarded requests carry the absolute url.
+            let url = format!("http://{}:{}{}", self.host, self.port, path);
+            let mut headers = headers.to_vec();
+            headers.extend(proxy.headers());
+            let mut stream = proxy.connect(self.timeout)?;
+            return exchange(
+                &mut stream,
+                &self.host,
+                self.port,
+                method,
+                &url,
+                &headers,
+                body,
+            );
+        }
+
+        let mut stream = connect(&self.host, self.port, self.timeout)?;
+        exchange(
+            &mut stream,
+            &self.host,
+            self.port,
+            method,
+            path,
+            headers,
+            body,
+        )
+    }
+}
+
+/// Returns the host and port of an agent url with the given scheme.
+pub(crate) fn parse_url(url: &str, scheme: &str, default_port: u16) -> Result<(String, u16)> {
+    let authority = url
+        .strip_prefix(scheme)
+        .ok_or_else(|| eyre!("Unsupported agent url scheme: {}", url))?;
+    let authority = authority.split('/').next().unwrap_or_default();
+    parse_authority(authority, default_port).ok_or_else(|| eyre!("Invalid agent url: {}", url))
+}
+
+/// Splits a `host[:port]` authority, returning `None` if the host is empty
+/// or the port invalid.
+pub(crate) fn parse_authority(authority: &str, default_port: u16) -> Option<(String, u16)> {
+    let (host, port) = match authority.rfind(':') {
+        // The colons of bracketed IPv6 addresses don't start a port.
+        Some(pos) if !authority[pos..].contains(']') => {
+            (&authority[..pos], authority[pos + 1..].parse::<u16>().ok()?)
+        }
+        _ => (authority, default_port),
+    };
+    if host.is_empty() {
+        return None;
+    }
+
+    Some((String::from(host), port))
+}
+
+pub(crate) fn connect(host: &str, port: u16, timeout: Duration) -> Result<TcpStream> {
+    let address = (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| eyre!("Unable to resolve {}:{}", host, port))?;
+    let stream = TcpStream::connect_timeout(&address, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    Ok(stream)
+}
+
+/// Sends a request over `stream` and reads the response until the server
+/// closes the connection.
+pub(crate) fn exchange<S: Read + Write>(
+    stream: &mut S,
+    host: &str,
+    port: u16,
+    method: &str,
+    path: &str,
+    headers: &[(&str, String)],
+    body: &[u8],
+) -> Result<HttpResponse> {
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}:{}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        method,
+        path,
+        host,
+        port,
+        body.len()
+    );
+    for (key, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", key, value));
+    }
+    request.push_str("\r\n");
+
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    let mut raw = Vec::new();
+    match stream.read_to_end(&mut raw) {
+        Ok(_) => {}
+        // Some TLS servers close the connection without close_notify.
+        Err(error) if error.kind() == ErrorKind::UnexpectedEof && !raw.is_empty() => {}
+        Err(error) => return Err(error.into()),
+    }
+    parse_response(&raw)
+}
+
+impl Transport for HttpClient {
+    fn get(&self, path: &str) -> Result<HttpResponse> {
+        self.request("GET", path, &[], &[])
+    }
+
+    fn post(&self, path: &str, headers: &[(&str, String)], body: &[u8]) -> Result<HttpResponse> {
+        self.request("POST", path, headers, body)
+    }
+}
+
+fn parse_response(raw: &[u8]) -> Result<HttpResponse> {
+    let header_end = raw
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| eyre!("Malformed HTTP response"))?;
+    let head = String::from_utf8_lossy(&raw[..header_end]);
+    let mut lines = head.split("\r\n");
+
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| eyre!("Malformed HTTP status line"))?;
+
+    let chunked = lines.any(|line| {
+        let mut parts = line.splitn(2, ':');
+        let key = parts.next().unwrap_or_default().trim();
+        let value = parts.next().unwrap_or_default().trim();
+        key.eq_ignore_ascii_case("transfer-encoding") && value.eq_ignore_ascii_case("chunked")
+    });
+
+    let body = &raw[header_end + 4..];
+    let body = if chunked {
+        decode_chunked(body)?
+    } else {
+        body.to_vec()
+    };
+
+    Ok(HttpResponse { status, body })
+}
+
+fn decode_chunked(mut data: &[u8]) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = data
+            .windows(2)
+            .position(|window| window == b"\r\n")
+            .ok_or_else(|| eyre!("Malformed chunked body"))?;
+        let size_line = String::from_utf8_lossy(&data[..line_end]);
+        let size_str = size_line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size_str, 16)
+            .map_err(|_| eyre!("Malformed chunk size: {}", size_str))?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Ok(body);
+        }
+        if data.len() < size {
+            return Err(eyre!("Truncated chunked body"));
+        }
+        body.extend_from_slice(&data[..size]);
+        data = &data[(size + 2).min(data.len())..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_agent_urls() {
+        let client = HttpClient::from_url("http://agent:8126/", Duration::from_secs(1)).unwrap();
+        assert_eq!(client.host, "agent");
+        assert_eq!(client.port, 8126);
+
+        let client = HttpClient::from_url("http://agent", Duration::from_secs(1)).unwrap();
+        assert_eq!(client.port, 80);
+
+        assert!(HttpClient::from_url("unix:///var/run/apm.sock", Duration::from_secs(1)).is_err());
+        assert!(HttpClient::from_url("http://:8126", Duration::from_secs(1)).is_err());
+    }
+
+    #[test]
+    fn parses_responses() {
+        let response = parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}").unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"{}");
+
+        let response = parse_response(
+            b"HTTP/1.1 404 Not Found\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(response.status, 404);
+        assert!(!response.is_success());
+        assert_eq!(response.body, b"abcde");
+    }
+}
@@ -0,0 +1,21 @@
+mod agent_info;
+#[cfg(feature = "http-client")]
+mod http_client;
+#[cfg(feature = "tls")]
+mod https_client;
+#[cfg(all(windows, feature = "windows-pipes"))]
+mod pipe_client;
+#[cfg(feature = "http-client")]
+mod proxy;
+mod transport;
+
+pub use agent_info::*;
+#[cfg(feature = "http-client")]
+pub(crate) use http_client::*;
+#[cfg(feature = "tls")]
+pub(crate) use https_client::*;
+#[cfg(all(windows, feature = "windows-pipes"))]
+pub use pipe_client::*;
+#[cfg(feature = "http-client")]
+pub(crate) use proxy::*;
+pub use transport::*;
@@ -0,0 +1,90 @@
+use super::{exchange, HttpResponse, Transport};
+use eyre::{eyre, Result};
+use std::{
+    fs::{File, OpenOptions},
+    thread,
+    time::{Duration, Instant},
+};
+
+const PIPE_PREFIX: &str = r"\\.\pipe\";
+// Returned while every instance of the pipe is serving another client.
+const ERROR_PIPE_BUSY: i32 = 231;
+
+/// HTTP client talking to the Windows agent over its named pipe, by default
+/// `\\.\pipe\datadog-apm`. Every request opens a new pipe instance.
+#[derive(Clone, Debug)]
+pub(crate) struct PipeClient {
+    path: String,
+    timeout: Duration,
+}
+
+impl PipeClient {
+    /// Creates a client for a pipe name, either bare (`datadog-apm`) or a
+    /// full `\\.\pipe\` path as in `DD_TRACE_PIPE_NAME`.
+    pub fn new(name: &str, timeout: Duration) -> Self {
+        let path = if name.starts_with(r"\\") {
+            String::from(name)
+        } else {
+            format!("{}{}", PIPE_PREFIX, name)
+        };
+
+        Self { path, timeout }
+    }
+
+    /// Opens the pipe, waiting up to the timeout for a free instance. Pipes
+    /// have no read or write timeouts.
+    fn open(&self) -> Result<File> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match OpenOptions::new().read(true).write(true).open(&self.path) {
+                Ok(pipe) => return Ok(pipe),
+                Err(error)
+                    if error.raw_os_error() == Some(ERROR_PIPE_BUSY)
+                        && Instant::now() < deadline =>
+                {
+                    thread::sleep(Duration::from_millis(10))
+                }
+                Err(error) => return Err(eyre!("Unable to open {}: {}", self.path, error)),
+            }
+        }
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, String)],
+        body: &[u8],
+    ) -> Result<HttpResponse> {
+        let mut pipe = self.open()?;
+        exchange(&mut pipe, "localhost", 8126, method, path, headers, body)
+    }
+}
+
+impl Transport for PipeClient {
+    fn get(&self, path: &str) -> Result<HttpResponse> {
+        self.request("GET", path, &[], &[])
+    }
+
+    fn post(&self, path: &str, headers: &[(&str, String)], body: &[u8]) -> Result<HttpResponse> {
+        self.request("POST", path, headers, body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_pipe_names() {
+        let timeout = Duration::from_secs(1);
+        assert_eq!(
+            PipeClient::new("datadog-apm", timeout).path,
+            r"\\.\pipe\datadog-apm"
+        );
+        assert_eq!(
+            PipeClient::new(r"\\.\pipe\custom", timeout).path,
+            r"\\.\pipe\custom"
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use eyre::Result;
+#[cfg(test)]
+use std::{collections::VecDeque, sync::Mutex};
+
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// Transport sends requests to the trace agent. The built-in HttpClient needs
+/// TCP sockets; hosts without them (e.g. proxy-wasm filters) implement this
+/// trait on top of their own HTTP facilities.
+pub trait Transport: Send + Sync {
+    fn get(&self, path: &str) -> Result<HttpResponse>;
+
+    fn post(&self, path: &str, headers: &[(&str, String)], body: &[u8]) -> Result<HttpResponse>;
+}
+
+/// NullTransport sends nothing: posts are answered with 200 and `/info`
+/// with 404, as by agents predating it. Used when traces are only
+/// propagated.
+pub(crate) struct NullTransport;
+
+impl Transport for NullTransport {
+    fn get(&self, _path: &str) -> Result<HttpResponse> {
+        Ok(HttpResponse {
+            status: 404,
+            body: Vec::new(),
+        })
+    }
+
+    fn post(&self, _path: &str, _headers: &[(&str, String)], _body: &[u8]) -> Result<HttpResponse> {
+        Ok(HttpResponse {
+            status: 200,
+            body: Vec::new(),
+        })
+    }
+}
+
+/// MockTransport records the requests posted to it and answers them with 200
+/// and `response_body`, or 415 for compressed ones if `reject_compressed` is
+/// set. The first posts are answered with the `statuses` set, if any.
+/// `/info` is answered with `info_body`, or 404 without it as by agents
+/// predating it.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct MockTransport {
+    pub posts: Mutex<Vec<MockRequest>>,
+    /// Paths of the requests posted so far.
+    pub paths: Mutex<Vec<String>>,
+    pub statuses: Mutex<VecDeque<u16>>,
+    pub reject_compressed: bool,
+    pub response_body: Vec<u8>,
+    pub info_body: Option<Vec<u8>>,
+}
+
+/// Headers and body of a request posted to MockTransport.
+#[cfg(test)]
+pub(crate) type MockRequest = (Vec<(String, String)>, Vec<u8>);
+
+#[cfg(test)]
+impl MockTransport {
+    /// Values of `header` in the requests posted so far.
+    pub fn header_values(&self, header: &str) -> Vec<String> {
+        self.posts
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(headers, _)| headers.iter().find(|(key, _)| key == header))
+            .map(|(_, value)| value.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+impl Transport for MockTransport {
+    fn get(&self, _path: &str) -> Result<HttpResponse> {
+        Ok(match &self.info_body {
+            Some(body) => HttpResponse {
+                status: 200,
+                body: body.clone(),
+            },
+            None => HttpResponse {
+                status: 404,
+                body: Vec::new(),
+            },
+        })
+    }
+
+    fn post(&self, path: &str, headers: &[(&str, String)], body: &[u8]) -> Result<HttpResponse> {
+        let compressed = headers.iter().any(|(key, _)| *key == "Content-Encoding");
+        let headers = headers
+            .iter()
+            .map(|(key, value)| (String::from(*key), value.clone()))
+            .collect();
+        self.posts.lock().unwrap().push((headers, body.to_vec()));
+        self.paths.lock().unwrap().push(String::from(path));
+        let status = self.statuses.lock().unwrap().pop_front();
+        Ok(HttpResponse {
+            status: match status {
+                Some(status) => status,
+                None if compressed && self.reject_compressed => 415,
+                None => 200,
+            },
+            body: self.response_body.clone(),
+        })
+    }
+}
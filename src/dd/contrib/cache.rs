@@ -0,0 +1,138 @@
+use super::{tag_client, tag_result};
+use crate::{
+    dd::{
+        tags::{RESOURCE_NAME, SPAN_TYPE},
+        OwnedSpan, Tracer,
+    },
+    opentracing::StartSpanOptions,
+};
+use serde_json::Value;
+use std::fmt::Display;
+
+/// CacheCommand describes a command of a cache client such as redis-rs or
+/// a memcached client.
+#[derive(Debug, Default, Clone)]
+pub struct CacheCommand<'a> {
+    /// The cache system, e.g. `redis` or `memcached`.
+    pub system: &'a str,
+    /// The command, with or without its arguments, which aren't sent.
+    pub command: &'a str,
+    pub host: &'a str,
+    pub port: u16,
+}
+
+impl<'a> CacheCommand<'a> {
+    pub fn redis(command: &'a str, host: &'a str, port: u16) -> Self {
+        Self {
+            system: "redis",
+            command,
+            host,
+            port,
+        }
+    }
+
+    pub fn memcached(command: &'a str, host: &'a str, port: u16) -> Self {
+        Self {
+            system: "memcached",
+            command,
+            host,
+            port,
+        }
+    }
+
+    /// The name of the command, upper case and without its arguments, which
+    /// may hold keys or values.
+    fn name(&self) -> String {
+        self.command
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_uppercase()
+    }
+}
+
+/// Starts the `{system}.command` span of `command`, e.g. `redis.command`,
+/// whose resource is the name of the command. None if the integration of
+/// its system is disabled.
+pub fn start_cache_span(
+    tracer: &Tracer,
+    command: &CacheCommand,
+    options: &StartSpanOptions,
+) -> Option<OwnedSpan> {
+    if !tracer.integrations().enabled(command.system) {
+        return None;
+    }
+    let operation_name = format!("{}.command", command.system);
+    let mut span = tracer.start_owned_span(&operation_name, options);
+    tag_client(
+        &mut span,
+        tracer,
+        command.system,
+        command.host,
+        command.port,
+    );
+    span.set_tag(RESOURCE_NAME, &Value::from(command.name()));
+    span.set_tag(SPAN_TYPE, &Value::from(command.system));
+
+    Some(span)
+}
+
+/// Runs `execute` in the span of `command`, which is marked as an error if
+/// it fails.
+pub fn trace_cache_command<T, E: Display>(
+    tracer: &Tracer,
+    command: &CacheCommand,
+    options: &StartSpanOptions,
+    execute: impl FnOnce() -> Result<T, E>,
+) -> Result<T, E> {
+    let mut span = match start_cache_span(tracer, command, options) {
+        Some(span) => span,
+        None => return execute(),
+    };
+    let result = execute();
+    tag_result(&mut span, &result);
+    span.finish();
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dd::{
+        agent::MockTransport,
+        tags::{PEER_HOSTNAME, PEER_PORT, SPAN_KIND, SPAN_KIND_CLIENT},
+        TracerOptions,
+    };
+    use std::{sync::Arc, time::Duration};
+
+    #[test]
+    fn traces_commands_without_their_arguments() {
+        let transport = Arc::new(MockTransport::default());
+        let options = TracerOptions {
+            service: String::from("web"),
+            ..Default::default()
+        };
+        let tracer = Tracer::with_transport(options, transport.clone()).unwrap();
+        let command = CacheCommand::redis("set session:42 secret EX 60", "cache.local", 6379);
+        let stored: Result<(), String> =
+            trace_cache_command(&tracer, &command, &StartSpanOptions::default(), || Ok(()));
+        assert!(stored.is_ok());
+
+        assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 1);
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+        let span = &traces[0][0];
+        assert_eq!(span["name"], "redis.command");
+        assert_eq!(span["service"], "web-redis");
+        assert_eq!(span["resource"], "SET");
+        assert_eq!(span["type"], "redis");
+        assert_eq!(span["meta"][PEER_HOSTNAME], "cache.local");
+        assert_eq!(span["meta"][SPAN_KIND], SPAN_KIND_CLIENT);
+        assert_eq!(span["metrics"][PEER_PORT], 6379.0);
+        assert!(!posts[0].1.windows(6).any(|window| window == b"secret"));
+
+        assert_eq!(CacheCommand::memcached("get", "", 0).name(), "GET");
+        assert_eq!(CacheCommand::redis("", "", 0).name(), "");
+    }
+}
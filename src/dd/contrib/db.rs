@@ -0,0 +1,186 @@
+use super::{tag_client, tag_result};
+use crate::{
+    dd::{
+        tags::{RESOURCE_NAME, SPAN_TYPE},
+        OwnedSpan, Tracer,
+    },
+    opentracing::StartSpanOptions,
+};
+use serde_json::Value;
+use std::fmt::Display;
+
+const QUERY_OPERATION: &str = "db.query";
+const DB_SYSTEM: &str = "db.system";
+const DB_INSTANCE: &str = "db.instance";
+
+/// DbQuery describes a query of a database client such as sqlx or diesel.
+#[derive(Debug, Default, Clone)]
+pub struct DbQuery<'a> {
+    /// The database system, e.g. `postgresql` or `mysql`.
+    pub system: &'a str,
+    /// The name of the database.
+    pub instance: &'a str,
+    pub host: &'a str,
+    pub port: u16,
+    /// The query, obfuscated to be the resource of the span.
+    pub query: &'a str,
+}
+
+/// Starts the `db.query` span of `query`, whose resource is the obfuscated
+/// query. None if the integration of its system is disabled.
+pub fn start_query_span(
+    tracer: &Tracer,
+    query: &DbQuery,
+    options: &StartSpanOptions,
+) -> Option<OwnedSpan> {
+    if !tracer.integrations().enabled(query.system) {
+        return None;
+    }
+    let mut span = tracer.start_owned_span(QUERY_OPERATION, options);
+    tag_client(&mut span, tracer, query.system, query.host, query.port);
+    span.set_tag(RESOURCE_NAME, &Value::from(obfuscate_sql(query.query)));
+    span.set_tag(SPAN_TYPE, &Value::from("sql"));
+    span.set_tag(DB_SYSTEM, &Value::from(query.system));
+    if !query.instance.is_empty() {
+        span.set_tag(DB_INSTANCE, &Value::from(query.instance));
+    }
+
+    Some(span)
+}
+
+/// Runs `execute` in the `db.query` span of `query`, which is marked as an
+/// error if it fails.
+pub fn trace_query<T, E: Display>(
+    tracer: &Tracer,
+    query: &DbQuery,
+    options: &StartSpanOptions,
+    execute: impl FnOnce() -> Result<T, E>,
+) -> Result<T, E> {
+    let mut span = match start_query_span(tracer, query, options) {
+        Some(span) => span,
+        None => return execute(),
+    };
+    let result = execute();
+    tag_result(&mut span, &result);
+    span.finish();
+
+    result
+}
+
+/// Replaces the literals of `query` with `?` and removes its comments, so
+/// that the queries differing only by their parameters have the same
+/// resource and no values are sent. Whitespace is collapsed.
+pub fn obfuscate_sql(query: &str) -> String {
+    let mut obfuscated = String::with_capacity(query.len());
+    let mut chars = query.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                // Quotes are escaped by doubling them.
+                while let Some(c) = chars.next() {
+                    if c == '\'' && chars.next_if_eq(&'\'').is_none() {
+                        break;
+                    }
+                }
+                obfuscated.push('?');
+            }
+            '-' if chars.next_if_eq(&'-').is_some() => {
+                while chars.next_if(|c| *c != '\n').is_some() {}
+                obfuscated.push(' ');
+            }
+            '/' if chars.next_if_eq(&'*').is_some() => {
+                let mut previous = ' ';
+                for c in chars.by_ref() {
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+                obfuscated.push(' ');
+            }
+            c if c.is_ascii_digit()
+                && !obfuscated
+                    .chars()
+                    .next_back()
+                    .is_some_and(|c| c.is_alphanumeric() || "_$:.".contains(c)) =>
+            {
+                while chars
+                    .next_if(|c| c.is_ascii_alphanumeric() || *c == '.')
+                    .is_some()
+                {}
+                obfuscated.push('?');
+            }
+            c if c.is_whitespace() => obfuscated.push(' '),
+            c => obfuscated.push(c),
+        }
+        if obfuscated.ends_with("  ") {
+            obfuscated.pop();
+        }
+    }
+
+    obfuscated.trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dd::{
+        agent::MockTransport,
+        tags::{PEER_HOSTNAME, PEER_PORT, SPAN_KIND, SPAN_KIND_CLIENT},
+        TracerOptions,
+    };
+    use std::{sync::Arc, time::Duration};
+
+    #[test]
+    fn obfuscates_literals_and_comments() {
+        assert_eq!(
+            obfuscate_sql("SELECT * FROM users2 WHERE id = 42 AND name = 'O''Brien' -- admin"),
+            "SELECT * FROM users2 WHERE id = ? AND name = ?"
+        );
+        assert_eq!(
+            obfuscate_sql("UPDATE t /* batch 7 */ SET\n  x = 1.5e3\n WHERE id = $1"),
+            "UPDATE t SET x = ? WHERE id = $1"
+        );
+        assert_eq!(obfuscate_sql("SELECT 0x1F, -1"), "SELECT ?, -?");
+    }
+
+    #[test]
+    fn traces_queries() {
+        let transport = Arc::new(MockTransport::default());
+        let options = TracerOptions {
+            service: String::from("billing"),
+            ..Default::default()
+        };
+        let tracer = Tracer::with_transport(options, transport.clone()).unwrap();
+        let query = DbQuery {
+            system: "postgresql",
+            instance: "invoices",
+            host: "db.local",
+            port: 5432,
+            query: "SELECT total FROM invoices WHERE id = 7",
+        };
+        let options = StartSpanOptions::default();
+        let rows: Result<u32, String> = trace_query(&tracer, &query, &options, || Ok(1));
+        assert_eq!(rows, Ok(1));
+        let failed: Result<u32, String> =
+            trace_query(&tracer, &query, &options, || Err(String::from("timeout")));
+        assert!(failed.is_err());
+
+        assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 2);
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+        let span = &traces[0][0];
+        assert_eq!(span["name"], QUERY_OPERATION);
+        assert_eq!(span["service"], "billing-postgresql");
+        assert_eq!(span["resource"], "SELECT total FROM invoices WHERE id = ?");
+        assert_eq!(span["type"], "sql");
+        assert_eq!(span["meta"][DB_SYSTEM], "postgresql");
+        assert_eq!(span["meta"][DB_INSTANCE], "invoices");
+        assert_eq!(span["meta"][PEER_HOSTNAME], "db.local");
+        assert_eq!(span["meta"][SPAN_KIND], SPAN_KIND_CLIENT);
+        assert_eq!(span["metrics"][PEER_PORT], 5432.0);
+        assert_eq!(span["error"], 0);
+        assert_eq!(traces[1][0]["error"], 1);
+        assert_eq!(traces[1][0]["meta"]["error.msg"], "timeout");
+    }
+}
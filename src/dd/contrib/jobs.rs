@@ -0,0 +1,232 @@
+use crate::{
+    dd::{
+        tags::{RESOURCE_NAME, SPAN_KIND, SPAN_TYPE},
+        utils::{id_to_hex, trace_id_to_hex},
+        OwnedSpan, SpanContext, Tracer,
+    },
+    opentracing::{SpanReferenceType, StartSpanOptions, TextMapReader, TextMapWriter, Tracer as _},
+};
+use eyre::Result;
+use serde_json::{json, Value};
+use std::rc::Rc;
+
+const ENQUEUE_OPERATION: &str = "job.enqueue";
+const EXECUTE_OPERATION: &str = "job.execute";
+const MESSAGING_OPERATION: &str = "messaging.operation";
+const MESSAGING_SYSTEM: &str = "messaging.system";
+const MESSAGING_DESTINATION: &str = "messaging.destination.name";
+
+/// Job describes a background job or cron task.
+#[derive(Debug, Default, Clone)]
+pub struct Job<'a> {
+    /// The name of the job, the resource of its spans.
+    pub name: &'a str,
+    /// The job system, e.g. `sidekiq` or `cron`.
+    pub system: &'a str,
+    /// The queue the job went through, if any.
+    pub queue: &'a str,
+}
+
+impl Job<'_> {
+    /// The integration toggling the spans of the job: its system, or
+    /// `jobs` if it's unknown.
+    fn integration(&self) -> &str {
+        match self.system {
+            "" => "jobs",
+            system => system,
+        }
+    }
+}
+
+/// JobLink is how the execution of a job is tied to the trace which
+/// enqueued it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobLink {
+    /// The execution continues the trace of the enqueueing span, as a span
+    /// following from it.
+    FollowsFrom,
+    /// The execution starts a trace of its own with a span link to the
+    /// enqueueing span, for jobs enqueued long before they run or in
+    /// batches, which would make traces span hours.
+    SpanLink,
+}
+
+fn start_job_span(
+    tracer: &Tracer,
+    operation_name: &str,
+    job: &Job,
+    operation: &str,
+    kind: &str,
+    options: &StartSpanOptions,
+) -> OwnedSpan {
+    let mut span = tracer.start_owned_span(operation_name, options);
+    span.set_tag(RESOURCE_NAME, &Value::from(job.name));
+    span.set_tag(SPAN_TYPE, &Value::from("worker"));
+    span.set_tag(SPAN_KIND, &Value::from(kind));
+    span.set_tag(MESSAGING_OPERATION, &Value::from(operation));
+    if !job.system.is_empty() {
+        span.set_tag(MESSAGING_SYSTEM, &Value::from(job.system));
+    }
+    if !job.queue.is_empty() {
+        span.set_tag(MESSAGING_DESTINATION, &Value::from(job.queue));
+    }
+
+    span
+}
+
+/// Starts the `job.enqueue` span of enqueueing `job`, and writes its
+/// context to the payload of the job through `payload`. None if the
+/// integration of the job is disabled, when nothing is written.
+pub fn start_enqueue_span(
+    tracer: &Tracer,
+    job: &Job,
+    options: &StartSpanOptions,
+    payload: &mut dyn TextMapWriter,
+) -> Result<Option<OwnedSpan>> {
+    if !tracer.integrations().enabled(job.integration()) {
+        return Ok(None);
+    }
+    let span = start_job_span(
+        tracer,
+        ENQUEUE_OPERATION,
+        job,
+        "publish",
+        "producer",
+        options,
+    );
+    tracer.inject(span.context(), payload)?;
+    Ok(Some(span))
+}
+
+/// Starts the `job.execute` span of an execution of `job`, tied as set by
+/// `link` to the span which enqueued it if its context is in the payload
+/// read by `payload`. Otherwise, e.g. for cron tasks, it starts a new
+/// trace. None if the integration of the job is disabled.
+pub fn start_execute_span(
+    tracer: &Tracer,
+    job: &Job,
+    payload: &dyn TextMapReader,
+    link: JobLink,
+) -> Option<OwnedSpan> {
+    if !tracer.integrations().enabled(job.integration()) {
+        return None;
+    }
+    let enqueued = tracer.extract(payload).ok().flatten();
+    let mut options = StartSpanOptions::default();
+    let mut enqueue_link = None;
+    match (enqueued, link) {
+        (Some(enqueued), JobLink::FollowsFrom) => options
+            .references
+            .push((SpanReferenceType::FollowsFromRef, Rc::from(enqueued))),
+        (Some(enqueued), JobLink::SpanLink) => {
+            enqueue_link = enqueued
+                .as_any()
+                .downcast_ref::<SpanContext>()
+                .map(span_link);
+        }
+        (None, _) => {}
+    }
+
+    let mut span = start_job_span(
+        tracer,
+        EXECUTE_OPERATION,
+        job,
+        "process",
+        "consumer",
+        &options,
+    );
+    if let Some(link) = enqueue_link {
+        span.add_span_link(link);
+    }
+    Some(span)
+}
+
+/// The span link to `context`.
+fn span_link(context: &SpanContext) -> Value {
+    json!({
+        "trace_id": trace_id_to_hex(context.trace_id_high(), context.trace_id()),
+        "span_id": id_to_hex(context.id()),
+        "attributes": {"reason": "job enqueued"},
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dd::{agent::MockTransport, tags::SPAN_LINKS, MessageCarrier, TracerOptions};
+    use std::{sync::Arc, time::Duration};
+
+    #[test]
+    fn ties_executions_to_their_enqueueing_trace() {
+        let transport = Arc::new(MockTransport::default());
+        let tracer = Tracer::with_transport(TracerOptions::default(), transport.clone()).unwrap();
+        let job = Job {
+            name: "SendInvoice",
+            system: "sidekiq",
+            queue: "mailers",
+        };
+        let mut payload = MessageCarrier::new();
+        let mut enqueue =
+            start_enqueue_span(&tracer, &job, &StartSpanOptions::default(), &mut payload)
+                .unwrap()
+                .unwrap();
+        enqueue.finish();
+
+        let mut follows =
+            start_execute_span(&tracer, &job, &payload, JobLink::FollowsFrom).unwrap();
+        assert_eq!(follows.context().trace_id(), enqueue.context().trace_id());
+        follows.finish();
+        let mut linked = start_execute_span(&tracer, &job, &payload, JobLink::SpanLink).unwrap();
+        assert_ne!(linked.context().trace_id(), enqueue.context().trace_id());
+        linked.finish();
+        let mut cron =
+            start_execute_span(&tracer, &job, &MessageCarrier::new(), JobLink::FollowsFrom)
+                .unwrap();
+        cron.finish();
+
+        assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 4);
+        let posts = transport.posts.lock().unwrap();
+        let spans: Vec<Value> = posts
+            .iter()
+            .flat_map(|(_, body)| serde_json::from_slice::<Vec<Vec<Value>>>(body).unwrap())
+            .flatten()
+            .collect();
+        let span_with_id = |context: &SpanContext| {
+            spans
+                .iter()
+                .find(|span| span["span_id"] == context.id())
+                .unwrap()
+        };
+        let enqueue = span_with_id(enqueue.context());
+        assert_eq!(enqueue["name"], ENQUEUE_OPERATION);
+        assert_eq!(enqueue["resource"], "SendInvoice");
+        assert_eq!(enqueue["meta"][MESSAGING_OPERATION], "publish");
+        assert_eq!(enqueue["meta"][MESSAGING_DESTINATION], "mailers");
+
+        let follows = span_with_id(follows.context());
+        assert_eq!(follows["parent_id"], enqueue["span_id"]);
+        assert_eq!(follows["meta"][MESSAGING_OPERATION], "process");
+        assert_eq!(follows["meta"][SPAN_KIND], "consumer");
+
+        let linked = span_with_id(linked.context());
+        assert_eq!(linked["parent_id"], 0);
+        let links: Value =
+            serde_json::from_str(linked["meta"][SPAN_LINKS].as_str().unwrap()).unwrap();
+        assert_eq!(
+            links[0]["span_id"],
+            format!("{:016x}", enqueue["span_id"].as_u64().unwrap())
+        );
+
+        let cron = span_with_id(cron.context());
+        assert_eq!(cron["parent_id"], 0);
+        assert!(cron["meta"].get(SPAN_LINKS).is_none());
+
+        // Nothing is traced nor propagated for disabled integrations.
+        tracer.integrations().set_enabled("sidekiq", false).unwrap();
+        let mut disabled = MessageCarrier::new();
+        let enqueue =
+            start_enqueue_span(&tracer, &job, &StartSpanOptions::default(), &mut disabled).unwrap();
+        assert!(enqueue.is_none());
+        assert!(start_execute_span(&tracer, &job, &disabled, JobLink::FollowsFrom).is_none());
+    }
+}
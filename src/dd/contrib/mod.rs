@@ -0,0 +1,57 @@
+//! Helpers creating the spans of common clients, named and tagged as the
+//! integrations of the other Datadog tracers do, so that they're shown the
+//! same way. They don't depend on the client libraries: wrappers call them
+//! around each operation.
+
+mod cache;
+mod db;
+mod jobs;
+mod registry;
+mod span_pointers;
+#[cfg(feature = "tower")]
+mod tower;
+
+#[cfg(feature = "tower")]
+pub use self::tower::*;
+pub use cache::*;
+pub use db::*;
+pub use jobs::*;
+pub use registry::*;
+pub use span_pointers::*;
+
+use crate::dd::{
+    tags::{PEER_HOSTNAME, PEER_PORT, SERVICE_NAME, SPAN_KIND, SPAN_KIND_CLIENT},
+    OwnedSpan, Tracer,
+};
+use serde_json::Value;
+
+/// Tags the span of a client of `system` at `host` and `port`, either of
+/// which may be unknown (empty or 0). Its service is `{service}-{system}`
+/// as in the other tracers, so that servers are shown as services of their
+/// own.
+fn tag_client(span: &mut OwnedSpan, tracer: &Tracer, system: &str, host: &str, port: u16) {
+    let service = format!("{}-{}", tracer.options().service, system);
+    span.set_tag(SERVICE_NAME, &Value::from(service));
+    span.set_tag(SPAN_KIND, &Value::from(SPAN_KIND_CLIENT));
+    if !host.is_empty() {
+        span.set_tag(PEER_HOSTNAME, &Value::from(host));
+    }
+    if port != 0 {
+        span.set_metric(PEER_PORT, port as f64);
+    }
+}
+
+/// Marks `span` as failed with `message`.
+fn tag_error(span: &mut OwnedSpan, message: &str) {
+    span.log(&[
+        (String::from("event"), Value::from("error")),
+        (String::from("message"), Value::from(message)),
+    ]);
+}
+
+/// Marks `span` as failed with `error` if `result` is an error.
+fn tag_result<T, E: std::fmt::Display>(span: &mut OwnedSpan, result: &Result<T, E>) {
+    if let Err(error) = result {
+        tag_error(span, &error.to_string());
+    }
+}
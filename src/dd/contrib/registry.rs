@@ -0,0 +1,126 @@
+use crate::dd::{
+    tracer::env_bool,
+    utils::{LogLevel, RateLimitedLogger},
+};
+use eyre::{eyre, Result};
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+/// Returns the environment variable toggling `integration`, e.g.
+/// `DD_TRACE_REDIS_ENABLED` for `redis`.
+pub fn integration_env(integration: &str) -> String {
+    let name: String = integration
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect();
+    format!("DD_TRACE_{}_ENABLED", name)
+}
+
+/// Registry records the integrations of a tracer: the contrib helpers, by
+/// the system they trace, e.g. `redis` or `postgresql`, and the tower
+/// services by the integration their layer is named after, e.g. `axum`.
+/// Each integration is enabled unless its `DD_TRACE_<INTEGRATION>_ENABLED`
+/// variable is false or it's disabled in code; disabled ones make no spans.
+pub struct Registry {
+    integrations: Mutex<BTreeMap<String, bool>>,
+    logger: Arc<RateLimitedLogger>,
+}
+
+impl Registry {
+    pub(crate) fn new(logger: Arc<RateLimitedLogger>) -> Self {
+        Self {
+            integrations: Mutex::new(BTreeMap::new()),
+            logger,
+        }
+    }
+
+    /// Whether `integration` makes spans. Its variable is read the first
+    /// time, when the integration is logged as active.
+    pub fn enabled(&self, integration: &str) -> bool {
+        let mut integrations = match self.integrations.lock() {
+            Ok(integrations) => integrations,
+            Err(_) => return true,
+        };
+        if let Some(enabled) = integrations.get(integration) {
+            return *enabled;
+        }
+
+        let env = integration_env(integration);
+        let enabled = env_bool(&env).unwrap_or(true);
+        integrations.insert(String::from(integration), enabled);
+        drop(integrations);
+        let message = if enabled {
+            format!("Integration {} enabled", integration)
+        } else {
+            format!("Integration {} disabled by {}", integration, env)
+        };
+        self.logger.log(LogLevel::Debug, "integrations", &message);
+
+        enabled
+    }
+
+    /// Enables or disables `integration`, whatever its variable.
+    pub fn set_enabled(&self, integration: &str, enabled: bool) -> Result<()> {
+        self.integrations
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .insert(String::from(integration), enabled);
+
+        Ok(())
+    }
+
+    /// Returns the integrations used so far, e.g. for flares:
+    /// `[{"name": "redis", "enabled": true}, ...]`, sorted by name.
+    pub fn to_json(&self) -> Result<Value> {
+        let integrations = self
+            .integrations
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?;
+
+        Ok(integrations
+            .iter()
+            .map(|(name, enabled)| json!({"name": name, "enabled": enabled}))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_integrations_and_their_toggles() {
+        assert_eq!(integration_env("redis"), "DD_TRACE_REDIS_ENABLED");
+        assert_eq!(
+            integration_env("grpc-client"),
+            "DD_TRACE_GRPC_CLIENT_ENABLED"
+        );
+
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let captured = messages.clone();
+        let logger = RateLimitedLogger::new(Arc::new(move |_, message: &str| {
+            captured.lock().unwrap().push(String::from(message))
+        }));
+        logger.set_debug(true);
+        let registry = Registry::new(Arc::new(logger));
+        registry.set_enabled("sqlx", false).unwrap();
+        assert!(!registry.enabled("sqlx"));
+        assert!(registry.enabled("axum"));
+        assert!(registry.enabled("axum"));
+
+        assert_eq!(
+            registry.to_json().unwrap(),
+            json!([
+                {"name": "axum", "enabled": true},
+                {"name": "sqlx", "enabled": false},
+            ])
+        );
+        assert_eq!(*messages.lock().unwrap(), vec!["Integration axum enabled"]);
+    }
+}
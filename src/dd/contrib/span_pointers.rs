@@ -0,0 +1,206 @@
+use crate::dd::{utils::sha256, OwnedSpan};
+use eyre::{eyre, Result};
+use serde_json::json;
+
+const S3_OBJECT_KIND: &str = "aws.s3.object";
+const DYNAMODB_ITEM_KIND: &str = "aws.dynamodb.item";
+
+/// Which way a span pointer points: to the span which wrote the data, or
+/// to the spans which will read it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerDirection {
+    Upstream,
+    Downstream,
+}
+
+impl PointerDirection {
+    fn name(&self) -> &'static str {
+        match self {
+            PointerDirection::Upstream => "u",
+            PointerDirection::Downstream => "d",
+        }
+    }
+}
+
+/// DynamoValue is the value of a key attribute of a DynamoDB item, as in
+/// the `S`, `N` and `B` fields of the API.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DynamoValue<'a> {
+    String(&'a str),
+    /// A number, as written in the request.
+    Number(&'a str),
+    Binary(&'a [u8]),
+}
+
+impl DynamoValue<'_> {
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            DynamoValue::String(value) | DynamoValue::Number(value) => value.as_bytes(),
+            DynamoValue::Binary(value) => value,
+        }
+    }
+}
+
+/// The hash of a span pointer: the first 128 bits of the SHA-256 of its
+/// components separated by `|`, in hexadecimal.
+fn pointer_hash(components: &[&[u8]]) -> String {
+    let digest = sha256(&components.join(&b'|'));
+    digest[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Returns the hash of the span pointer to the S3 object `key` of `bucket`,
+/// in the version of `etag`, with or without its quotes.
+pub fn s3_object_hash(bucket: &str, key: &str, etag: &str) -> String {
+    let etag = etag.trim_matches('"');
+    pointer_hash(&[bucket.as_bytes(), key.as_bytes(), etag.as_bytes()])
+}
+
+/// Returns the hash of the span pointer to the item of `table` with the
+/// primary `key`: its partition key, and its sort key if it has one, by
+/// attribute name.
+pub fn dynamodb_item_hash(table: &str, key: &[(&str, DynamoValue)]) -> Result<String> {
+    let mut key = key.to_vec();
+    key.sort_by_key(|(name, _)| *name);
+    let (first, second) = match key.as_slice() {
+        [(name, value)] => ((*name, *value), ("", DynamoValue::String(""))),
+        [first, second] => (*first, *second),
+        _ => {
+            return Err(eyre!(
+                "DynamoDB primary keys have 1 or 2 attributes, not {}",
+                key.len()
+            ))
+        }
+    };
+
+    Ok(pointer_hash(&[
+        table.as_bytes(),
+        first.0.as_bytes(),
+        first.1.as_bytes(),
+        second.0.as_bytes(),
+        second.1.as_bytes(),
+    ]))
+}
+
+/// Adds the span pointer of `kind` with `hash` to `span`: a span link
+/// without trace, which Datadog joins to the spans with the same pointer in
+/// other traces, e.g. the span writing an object to those of the service
+/// notified of it.
+pub fn add_span_pointer(span: &mut OwnedSpan, kind: &str, direction: PointerDirection, hash: &str) {
+    span.add_span_link(json!({
+        "trace_id": "00000000000000000000000000000000",
+        "span_id": "0000000000000000",
+        "attributes": {
+            "ptr.kind": kind,
+            "ptr.dir": direction.name(),
+            "ptr.hash": hash,
+            "link.kind": "span-pointer",
+        },
+    }));
+}
+
+/// Points `span`, which wrote the S3 object `key` of `bucket`, to the
+/// spans reading the version of `etag`.
+pub fn add_s3_object_pointer(span: &mut OwnedSpan, bucket: &str, key: &str, etag: &str) {
+    let hash = s3_object_hash(bucket, key, etag);
+    add_span_pointer(span, S3_OBJECT_KIND, PointerDirection::Downstream, &hash);
+}
+
+/// Points `span`, which wrote the item of `table` with the primary `key`,
+/// to the spans reading it.
+pub fn add_dynamodb_item_pointer(
+    span: &mut OwnedSpan,
+    table: &str,
+    key: &[(&str, DynamoValue)],
+) -> Result<()> {
+    let hash = dynamodb_item_hash(table, key)?;
+    add_span_pointer(
+        span,
+        DYNAMODB_ITEM_KIND,
+        PointerDirection::Downstream,
+        &hash,
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dd::{agent::MockTransport, tags::SPAN_LINKS, Tracer, TracerOptions},
+        opentracing::StartSpanOptions,
+    };
+    use serde_json::Value;
+    use std::{sync::Arc, time::Duration};
+
+    #[test]
+    fn hashes_pointers_as_documented() {
+        assert_eq!(
+            s3_object_hash("some-bucket", "some-key.data", "ab12ef34"),
+            "e721375466d4116ab551213fdea08413"
+        );
+        assert_eq!(
+            s3_object_hash("some-bucket", "some-key.data", "\"ab12ef34\""),
+            "e721375466d4116ab551213fdea08413"
+        );
+
+        let string = [("some-key", DynamoValue::String("some-value"))];
+        assert_eq!(
+            dynamodb_item_hash("some-table", &string).unwrap(),
+            "7f1aee721472bcb48701d45c7c7f7821"
+        );
+        let binary = [("some-key", DynamoValue::Binary(b"some-value"))];
+        assert_eq!(
+            dynamodb_item_hash("some-table", &binary).unwrap(),
+            "7f1aee721472bcb48701d45c7c7f7821"
+        );
+        let number = [("some-key", DynamoValue::Number("123.456"))];
+        assert_eq!(
+            dynamodb_item_hash("some-table", &number).unwrap(),
+            "434a6dba3997ce4dbbadc98d87a0cc24"
+        );
+        let both = [
+            ("some-key", DynamoValue::String("some-value")),
+            ("other-key", DynamoValue::Number("123")),
+        ];
+        assert_eq!(
+            dynamodb_item_hash("some-table", &both).unwrap(),
+            "7aa1b80b0e49bd2078a5453399f4dd67"
+        );
+        assert!(dynamodb_item_hash("some-table", &[]).is_err());
+    }
+
+    #[test]
+    fn links_spans_to_pointers() {
+        let transport = Arc::new(MockTransport::default());
+        let tracer = Tracer::with_transport(TracerOptions::default(), transport.clone()).unwrap();
+        let mut span = tracer.start_owned_span("s3.request", &StartSpanOptions::default());
+        add_s3_object_pointer(&mut span, "some-bucket", "some-key.data", "ab12ef34");
+        let key = [("id", DynamoValue::String("42"))];
+        add_dynamodb_item_pointer(&mut span, "orders", &key).unwrap();
+        span.finish();
+
+        assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 1);
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+        let links: Value =
+            serde_json::from_str(traces[0][0]["meta"][SPAN_LINKS].as_str().unwrap()).unwrap();
+        assert_eq!(
+            links[0],
+            json!({
+                "trace_id": "00000000000000000000000000000000",
+                "span_id": "0000000000000000",
+                "attributes": {
+                    "ptr.kind": "aws.s3.object",
+                    "ptr.dir": "d",
+                    "ptr.hash": "e721375466d4116ab551213fdea08413",
+                    "link.kind": "span-pointer",
+                },
+            })
+        );
+        assert_eq!(links[1]["attributes"]["ptr.kind"], "aws.dynamodb.item");
+    }
+}
@@ -0,0 +1,586 @@
+use super::tag_error;
+use crate::{
+    dd::{tracer::StatusCodes, OwnedSpan, SpanContext, Tracer},
+    opentracing::{self, StartSpanOptions},
+};
+use std::{
+    fmt::Display,
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+type ParentFn<Req> = dyn Fn(&Tracer, &Req) -> Option<SpanContext> + Send + Sync;
+type RequestFn<Req> = dyn Fn(&Req, &mut OwnedSpan) + Send + Sync;
+type ResultFn<Res, E> = dyn Fn(&Result<Res, E>, &mut OwnedSpan) + Send + Sync;
+type ClassifyFn<Res, E> = dyn Fn(&Result<Res, E>) -> Option<String> + Send + Sync;
+type BodyFn<Res> = dyn Fn(Res, BodySpan) -> Res + Send + Sync;
+
+/// The metric of the time taken to stream the body of a response once its
+/// headers were produced, in milliseconds.
+pub const BODY_DURATION: &str = "http.response.body.duration";
+
+/// How a TraceLayer names and tags the spans of requests.
+struct Hooks<Req, Res, E> {
+    operation_name: String,
+    integration: String,
+    parent: Box<ParentFn<Req>>,
+    on_request: Box<RequestFn<Req>>,
+    on_result: Box<ResultFn<Res, E>>,
+    classify: Box<ClassifyFn<Res, E>>,
+    error_on_cancel: bool,
+    /// None unless the spans last until the body of responses is streamed.
+    stream_body: Option<Box<BodyFn<Res>>>,
+}
+
+/// TraceLayer traces the requests of a tower service, whatever the protocol:
+/// each call is a span which lasts until its response future completes. The
+/// closures set with its builder methods tag spans from requests and
+/// responses, and tell which responses are errors, e.g. HTTP 5xx responses
+/// or gRPC status codes.
+pub struct TraceLayer<Req, Res, E> {
+    tracer: Arc<Tracer>,
+    hooks: Arc<Hooks<Req, Res, E>>,
+}
+
+impl<Req, Res, E: Display> TraceLayer<Req, Res, E> {
+    /// Creates a layer whose spans are named `operation_name`, toggled as
+    /// the `tower` integration. Without parent they're roots, and failed
+    /// calls are errors.
+    pub fn new(tracer: Arc<Tracer>, operation_name: &str) -> Self {
+        Self {
+            tracer,
+            hooks: Arc::new(Hooks {
+                operation_name: String::from(operation_name),
+                integration: String::from("tower"),
+                parent: Box::new(|_, _| None),
+                on_request: Box::new(|_, _| {}),
+                on_result: Box::new(|_, _| {}),
+                classify: Box::new(|result| result.as_ref().err().map(ToString::to_string)),
+                error_on_cancel: true,
+                stream_body: None,
+            }),
+        }
+    }
+
+    fn hooks_mut(&mut self) -> &mut Hooks<Req, Res, E> {
+        Arc::get_mut(&mut self.hooks).expect("TraceLayer configured after being cloned")
+    }
+
+    /// Names the integration toggling the spans of the layer, e.g. `axum`
+    /// or `grpc` for the layers of these frameworks, see `Registry`.
+    pub fn with_integration(mut self, integration: &str) -> Self {
+        self.hooks_mut().integration = String::from(integration);
+        self
+    }
+
+    /// Reads the parent of the span of a request, e.g. the context
+    /// propagated in its headers with `Tracer::extract_or_new`.
+    pub fn with_parent(
+        mut self,
+        parent: impl Fn(&Tracer, &Req) -> Option<SpanContext> + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks_mut().parent = Box::new(parent);
+        self
+    }
+
+    /// Tags the span of a request, e.g. with its method or route as
+    /// resource, when the span starts.
+    pub fn on_request(
+        mut self,
+        on_request: impl Fn(&Req, &mut OwnedSpan) + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks_mut().on_request = Box::new(on_request);
+        self
+    }
+
+    /// Tags the span of a request with its result, before it finishes.
+    pub fn on_result(
+        mut self,
+        on_result: impl Fn(&Result<Res, E>, &mut OwnedSpan) + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks_mut().on_result = Box::new(on_result);
+        self
+    }
+
+    /// Tells whether a result is an error, returning its message.
+    pub fn classify_error(
+        mut self,
+        classify: impl Fn(&Result<Res, E>) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks_mut().classify = Box::new(classify);
+        self
+    }
+
+    /// Marks the calls of a server as errors when they fail, or when the
+    /// status of their response, read by `status`, is one of the
+    /// `http_server_error_statuses` of the tracer.
+    pub fn classify_server_status(
+        self,
+        status: impl Fn(&Res) -> Option<u16> + Send + Sync + 'static,
+    ) -> Self {
+        let statuses = self.tracer.options().http_server_error_statuses.clone();
+        self.classify_status(statuses, status)
+    }
+
+    /// Marks the calls of a client as errors as `classify_server_status`
+    /// does, with the `http_client_error_statuses` of the tracer.
+    pub fn classify_client_status(
+        self,
+        status: impl Fn(&Res) -> Option<u16> + Send + Sync + 'static,
+    ) -> Self {
+        let statuses = self.tracer.options().http_client_error_statuses.clone();
+        self.classify_status(statuses, status)
+    }
+
+    fn classify_status(
+        self,
+        statuses: StatusCodes,
+        status: impl Fn(&Res) -> Option<u16> + Send + Sync + 'static,
+    ) -> Self {
+        self.classify_error(move |result| match result {
+            Ok(response) => status(response)
+                .filter(|status| statuses.contains(*status))
+                .map(|status| format!("HTTP {}", status)),
+            Err(error) => Some(error.to_string()),
+        })
+    }
+
+    /// Whether calls whose response future is dropped before it completes,
+    /// e.g. when the client disconnects or a timeout elapses, are errors of
+    /// type `cancellation`, as they are by default. Otherwise their span
+    /// just finishes then.
+    pub fn error_on_cancel(mut self, error_on_cancel: bool) -> Self {
+        self.hooks_mut().error_on_cancel = error_on_cancel;
+        self
+    }
+
+    /// Keeps the spans of successful calls running until the body of their
+    /// response is streamed rather than until its headers are produced, e.g.
+    /// for server-sent events or downloads. `attach` moves the BodySpan into
+    /// the body of the response, which finishes it once its last chunk is
+    /// sent, or drops it if the client disconnects before.
+    pub fn stream_body(
+        mut self,
+        attach: impl Fn(Res, BodySpan) -> Res + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks_mut().stream_body = Some(Box::new(attach));
+        self
+    }
+}
+
+impl<Req, Res, E> Clone for TraceLayer<Req, Res, E> {
+    fn clone(&self) -> Self {
+        Self {
+            tracer: self.tracer.clone(),
+            hooks: self.hooks.clone(),
+        }
+    }
+}
+
+impl<S, Req, Res, E> Layer<S> for TraceLayer<Req, Res, E> {
+    type Service = TraceService<S, Req, Res, E>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TraceService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// The service of a TraceLayer, tracing the calls of `inner`.
+pub struct TraceService<S, Req, Res, E> {
+    inner: S,
+    layer: TraceLayer<Req, Res, E>,
+}
+
+impl<S: Clone, Req, Res, E> Clone for TraceService<S, Req, Res, E> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+impl<S, Req, E> Service<Req> for TraceService<S, Req, S::Response, E>
+where
+    S: Service<Req, Error = E>,
+{
+    type Response = S::Response;
+    type Error = E;
+    type Future = TraceFuture<S::Future, Req, S::Response, E>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), E>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Req) -> Self::Future {
+        let TraceLayer { tracer, hooks } = &self.layer;
+        if !tracer.integrations().enabled(&hooks.integration) {
+            return TraceFuture {
+                future: Box::pin(self.inner.call(request)),
+                span: None,
+                hooks: hooks.clone(),
+            };
+        }
+        let options = StartSpanOptions {
+            parent_context: (hooks.parent)(tracer, &request)
+                .map(|parent| Rc::new(parent) as Rc<dyn opentracing::SpanContext>),
+            ..Default::default()
+        };
+        let mut span = tracer.start_owned_span(&hooks.operation_name, &options);
+        (hooks.on_request)(&request, &mut span);
+
+        TraceFuture {
+            future: Box::pin(self.inner.call(request)),
+            span: Some(span),
+            hooks: hooks.clone(),
+        }
+    }
+}
+
+/// The response future of a TraceService, which finishes the span of the
+/// call when it completes. The span also finishes if it's dropped, e.g.
+/// when the call times out, as set by `TraceLayer::error_on_cancel`.
+pub struct TraceFuture<F, Req, Res, E> {
+    future: Pin<Box<F>>,
+    span: Option<OwnedSpan>,
+    hooks: Arc<Hooks<Req, Res, E>>,
+}
+
+impl<F, Req, Res, E> Future for TraceFuture<F, Req, Res, E>
+where
+    F: Future<Output = Result<Res, E>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let result = match this.future.as_mut().poll(cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => return Poll::Pending,
+        };
+
+        let mut span = match this.span.take() {
+            Some(span) => span,
+            None => return Poll::Ready(result),
+        };
+        (this.hooks.on_result)(&result, &mut span);
+        if let Some(message) = (this.hooks.classify)(&result) {
+            tag_error(&mut span, &message);
+        }
+        let (attach, response) = match (&this.hooks.stream_body, result) {
+            (Some(attach), Ok(response)) => (attach, response),
+            (_, result) => {
+                span.finish();
+                return Poll::Ready(result);
+            }
+        };
+
+        let body = BodySpan {
+            span: Some(span),
+            headers_sent: Instant::now(),
+            error_on_cancel: this.hooks.error_on_cancel,
+        };
+        Poll::Ready(Ok(attach(response, body)))
+    }
+}
+
+impl<F, Req, Res, E> Drop for TraceFuture<F, Req, Res, E> {
+    fn drop(&mut self) {
+        match self.span.take() {
+            Some(mut span) if self.hooks.error_on_cancel => span.finish_cancelled(),
+            Some(mut span) => span.finish(),
+            None => {}
+        }
+    }
+}
+
+/// BodySpan is the span of a call whose response body is still streamed,
+/// see `TraceLayer::stream_body`. It's finished by `finish` once the body
+/// is complete, with the time it took as `http.response.body.duration`, or
+/// when it's dropped before, as set by `TraceLayer::error_on_cancel`.
+pub struct BodySpan {
+    /// None once finished.
+    span: Option<OwnedSpan>,
+    headers_sent: Instant,
+    error_on_cancel: bool,
+}
+
+impl BodySpan {
+    /// The span, e.g. to tag it with the size of the body.
+    pub fn span_mut(&mut self) -> Option<&mut OwnedSpan> {
+        self.span.as_mut()
+    }
+
+    /// Finishes the span once the last chunk of the body is sent.
+    pub fn finish(&mut self) {
+        if let Some(mut span) = self.span.take() {
+            let elapsed = self.headers_sent.elapsed().as_secs_f64() * 1000.0;
+            span.set_metric(BODY_DURATION, elapsed);
+            span.finish();
+        }
+    }
+
+    /// Finishes the span of a body whose stream failed with `message`.
+    pub fn fail(&mut self, message: &str) {
+        if let Some(span) = self.span.as_mut() {
+            tag_error(span, message);
+        }
+        self.finish();
+    }
+}
+
+impl Drop for BodySpan {
+    fn drop(&mut self) {
+        match self.span.take() {
+            Some(mut span) if self.error_on_cancel => span.finish_cancelled(),
+            Some(mut span) => span.finish(),
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dd::{agent::MockTransport, tags::RESOURCE_NAME, TracerOptions};
+    use serde_json::Value;
+    use std::{
+        future::{pending, ready, Pending, Ready},
+        task::Waker,
+        time::Duration,
+    };
+
+    /// Doubles even numbers, and fails on odd ones.
+    struct Doubler;
+
+    impl Service<u32> for Doubler {
+        type Response = u32;
+        type Error = String;
+        type Future = Ready<Result<u32, String>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), String>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: u32) -> Self::Future {
+            ready(match request % 2 {
+                0 => Ok(request * 2),
+                _ => Err(format!("{} is odd", request)),
+            })
+        }
+    }
+
+    #[test]
+    fn traces_the_calls_of_services() {
+        let transport = Arc::new(MockTransport::default());
+        let tracer =
+            Arc::new(Tracer::with_transport(TracerOptions::default(), transport.clone()).unwrap());
+        let parent = tracer.start_owned_span("batch", &StartSpanOptions::default());
+        let parent_context = parent.context().clone();
+        let layer = TraceLayer::new(tracer.clone(), "double")
+            .with_parent(move |_, _| parent_context.with_id(parent_context.id()).ok())
+            .on_request(|request: &u32, span| {
+                span.set_tag(RESOURCE_NAME, &Value::from(format!("double {}", request)))
+            })
+            .on_result(|result: &Result<u32, String>, span| {
+                if let Ok(response) = result {
+                    span.set_metric("response", *response as f64);
+                }
+            })
+            .classify_error(|result| match result {
+                Ok(response) if *response > 10 => Some(String::from("too large")),
+                Ok(_) => None,
+                Err(error) => Some(error.clone()),
+            });
+        let mut service = layer.layer(Doubler);
+
+        let mut cx = Context::from_waker(Waker::noop());
+        for request in [2, 3, 8] {
+            assert!(service.poll_ready(&mut cx).is_ready());
+            let mut future = service.call(request);
+            assert!(Pin::new(&mut future).poll(&mut cx).is_ready());
+        }
+        drop(parent);
+
+        assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 1);
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+        let span = |resource: &str| {
+            traces[0]
+                .iter()
+                .find(|span| span["resource"] == resource)
+                .unwrap()
+                .clone()
+        };
+        let batch = span("batch");
+        let even = span("double 2");
+        assert_eq!(even["name"], "double");
+        assert_eq!(even["parent_id"], batch["span_id"]);
+        assert_eq!(even["metrics"]["response"], 4.0);
+        assert_eq!(even["error"], 0);
+        assert_eq!(span("double 3")["meta"]["error.msg"], "3 is odd");
+        assert_eq!(span("double 8")["meta"]["error.msg"], "too large");
+    }
+
+    #[test]
+    fn marks_error_statuses_as_errors() {
+        let transport = Arc::new(MockTransport::default());
+        let options = TracerOptions {
+            http_server_error_statuses: StatusCodes::parse("500-599,428").unwrap(),
+            ..Default::default()
+        };
+        let tracer = Arc::new(Tracer::with_transport(options, transport.clone()).unwrap());
+        // Doubler "responds" with twice the request as status.
+        let status = |response: &u32| Some(*response as u16);
+        let server = TraceLayer::new(tracer.clone(), "server").classify_server_status(status);
+        let client = TraceLayer::new(tracer.clone(), "client").classify_client_status(status);
+        let mut services = [server.layer(Doubler), client.layer(Doubler)];
+
+        let mut cx = Context::from_waker(Waker::noop());
+        for service in services.iter_mut() {
+            for request in [100, 202, 214, 250] {
+                let mut future = service.call(request);
+                assert!(Pin::new(&mut future).poll(&mut cx).is_ready());
+            }
+        }
+
+        assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 8);
+        let posts = transport.posts.lock().unwrap();
+        let errors: Vec<(String, String)> = posts
+            .iter()
+            .flat_map(|(_, body)| serde_json::from_slice::<Vec<Vec<Value>>>(body).unwrap())
+            .flatten()
+            .filter(|span| span["error"] == 1)
+            .map(|span| {
+                let name = span["name"].as_str().unwrap();
+                let message = span["meta"]["error.msg"].as_str().unwrap();
+                (String::from(name), String::from(message))
+            })
+            .collect();
+        let error = |name: &str, message: &str| (String::from(name), String::from(message));
+        assert_eq!(
+            errors,
+            vec![
+                error("server", "HTTP 428"),
+                error("server", "HTTP 500"),
+                error("client", "HTTP 404"),
+                error("client", "HTTP 428"),
+                error("client", "HTTP 500"),
+            ]
+        );
+    }
+
+    /// Never responds, as a service whose client gave up waiting.
+    struct Stalled;
+
+    impl Service<u32> for Stalled {
+        type Response = u32;
+        type Error = String;
+        type Future = Pending<Result<u32, String>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), String>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _request: u32) -> Self::Future {
+            pending()
+        }
+    }
+
+    #[test]
+    fn fails_the_spans_of_cancelled_calls() {
+        let transport = Arc::new(MockTransport::default());
+        let tracer =
+            Arc::new(Tracer::with_transport(TracerOptions::default(), transport.clone()).unwrap());
+        let mut cx = Context::from_waker(Waker::noop());
+        for (name, error_on_cancel) in [("cancelled", true), ("abandoned", false)] {
+            let layer = TraceLayer::new(tracer.clone(), name).error_on_cancel(error_on_cancel);
+            let mut service = layer.layer(Stalled);
+            let mut future = service.call(1);
+            assert!(Pin::new(&mut future).poll(&mut cx).is_pending());
+        }
+
+        assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 2);
+        let posts = transport.posts.lock().unwrap();
+        let spans: Vec<Value> = posts
+            .iter()
+            .flat_map(|(_, body)| serde_json::from_slice::<Vec<Vec<Value>>>(body).unwrap())
+            .flatten()
+            .collect();
+        let span = |name: &str| spans.iter().find(|span| span["name"] == name).unwrap();
+        assert_eq!(span("cancelled")["error"], 1);
+        assert_eq!(span("cancelled")["meta"]["error.type"], "cancellation");
+        assert_eq!(span("abandoned")["error"], 0);
+    }
+
+    /// Streams the events of a request, as server-sent events would be.
+    #[derive(Default)]
+    struct Events {
+        body: Option<BodySpan>,
+    }
+
+    struct Streamer;
+
+    impl Service<u32> for Streamer {
+        type Response = Events;
+        type Error = String;
+        type Future = Ready<Result<Events, String>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), String>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _request: u32) -> Self::Future {
+            ready(Ok(Events::default()))
+        }
+    }
+
+    #[test]
+    fn finishes_spans_once_bodies_are_streamed() {
+        let transport = Arc::new(MockTransport::default());
+        let tracer =
+            Arc::new(Tracer::with_transport(TracerOptions::default(), transport.clone()).unwrap());
+        let layer =
+            TraceLayer::new(tracer.clone(), "events").stream_body(|mut events: Events, body| {
+                events.body = Some(body);
+                events
+            });
+        let mut service = layer.layer(Streamer);
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut call = |service: &mut TraceService<Streamer, u32, Events, String>| {
+            let mut future = service.call(1);
+            match Pin::new(&mut future).poll(&mut cx) {
+                Poll::Ready(Ok(events)) => events,
+                _ => panic!("the call should be done"),
+            }
+        };
+
+        let mut streamed = call(&mut service);
+        let disconnected = call(&mut service);
+        assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 0);
+        std::thread::sleep(Duration::from_millis(5));
+        streamed.body.as_mut().unwrap().finish();
+        drop(disconnected);
+
+        assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 2);
+        let posts = transport.posts.lock().unwrap();
+        let spans: Vec<Value> = posts
+            .iter()
+            .flat_map(|(_, body)| serde_json::from_slice::<Vec<Vec<Value>>>(body).unwrap())
+            .flatten()
+            .collect();
+        assert_eq!(spans[0]["error"], 0);
+        assert!(spans[0]["metrics"][BODY_DURATION].as_f64().unwrap() >= 5.0);
+        assert!(spans[0]["duration"].as_u64().unwrap() >= 5_000_000);
+        assert_eq!(spans[1]["meta"]["error.type"], "cancellation");
+    }
+}
@@ -1,5 +1,22 @@
+mod agent;
+#[cfg(feature = "contrib")]
+pub mod contrib;
 mod sample;
 mod span;
 mod tags;
 mod tracer;
-mod utils;
+pub(crate) mod utils;
+mod writer;
+
+pub use agent::{AgentInfo, HttpResponse, Transport};
+pub use sample::{RateUpdateStats, SampleResult, SamplerOverride, SamplingDecision};
+pub use span::{
+    activate, active_context, spawn, BaggageLimits, OwnedSpan, SavedSpan, SavedTrace, Scope,
+    ScopedContext, Span, SpanContext, SpanData, SpanExt, SpanFinishHook, Timer, TraceFilter,
+    TraceProcessor, UrlFilter, UserInfo, WithContext, WithSpan,
+};
+pub use tracer::*;
+pub use utils::{
+    hex_to_id, id_to_hex, trace_id_to_hex, Clock, LogFunc, LogLevel, SystemClock, TimePoint,
+};
+pub use writer::{CircuitOpenPolicy, Compression, QueueFullPolicy};
@@ -0,0 +1,95 @@
+//! Golden vectors of the decisions of the other Datadog tracers, computed
+//! with the Knuth sampling of dd-trace-go (`sampledByRate`). Trace ids must
+//! be kept or dropped the same way at a given rate whichever sampler
+//! decides, or traces crossing services end up incomplete.
+
+use super::{PrioritySampler, RulesSampler, SamplingPriority};
+use crate::{
+    dd::span::SpanData,
+    sampling::{knuth_hash, sampled_by_rate},
+};
+use serde_json::json;
+
+/// Trace id, then whether it's kept at 0.1, 0.5 and 0.9.
+const VECTORS: [(u64, [bool; 3]); 16] = [
+    (1, [true, true, true]),
+    (2, [false, true, true]),
+    (3, [false, true, true]),
+    (1000, [false, true, true]),
+    (1882305164521835798, [false, true, true]),
+    (4439448776366754703, [true, true, true]),
+    (5198373796167680436, [false, false, true]),
+    (6272545487220484606, [false, true, true]),
+    (7283207964119141687, [true, true, true]),
+    (8696342848850656916, [false, true, true]),
+    (9808507260218814804, [true, true, true]),
+    (10430779633273967791, [false, false, false]),
+    (12078589664685934330, [false, false, true]),
+    (13794769880582338323, [false, true, true]),
+    (18444899399302180860, [false, false, true]),
+    (u64::MAX, [false, false, false]),
+];
+
+const RATES: [f64; 3] = [0.1, 0.5, 0.9];
+
+fn kept(priority: &Option<SamplingPriority>) -> bool {
+    *priority == Some(SamplingPriority::SamplerKeep)
+}
+
+#[test]
+fn hashes_as_other_tracers() {
+    assert_eq!(knuth_hash(1), 1111111111111111111);
+    assert_eq!(knuth_hash(1000), 4306466688538014040);
+    assert_eq!(knuth_hash(u64::MAX), 17335632962598440505);
+}
+
+#[test]
+fn samples_by_rate_as_other_tracers() {
+    for (trace_id, expected) in VECTORS.iter() {
+        for (rate, expected) in RATES.iter().zip(expected.iter()) {
+            assert_eq!(
+                sampled_by_rate(*trace_id, *rate),
+                *expected,
+                "trace {} at {}",
+                trace_id,
+                rate
+            );
+        }
+        assert!(sampled_by_rate(*trace_id, 1.0));
+        assert!(!sampled_by_rate(*trace_id, 0.0));
+    }
+}
+
+#[test]
+fn rules_sample_as_other_tracers() {
+    for (i, rate) in RATES.iter().enumerate() {
+        let sampler = RulesSampler::from_config("[]", *rate as f32).unwrap();
+        for (trace_id, expected) in VECTORS.iter() {
+            let root = SpanData {
+                trace_id: *trace_id,
+                ..Default::default()
+            };
+            let result = sampler.sample(&root).unwrap();
+            // The rules were configured as f32.
+            assert_eq!(
+                kept(&result.sampling_priority),
+                sampled_by_rate(*trace_id, *rate as f32 as f64)
+            );
+            assert_eq!(kept(&result.sampling_priority), expected[i]);
+        }
+    }
+}
+
+#[test]
+fn agent_rates_sample_as_other_tracers() {
+    for (i, rate) in RATES.iter().enumerate() {
+        let sampler = PrioritySampler::new();
+        sampler
+            .configure(&json!({ "service:service,env:": rate }))
+            .unwrap();
+        for (trace_id, expected) in VECTORS.iter() {
+            let result = sampler.sample("", "service", *trace_id).unwrap();
+            assert_eq!(kept(&result.sampling_priority), expected[i]);
+        }
+    }
+}
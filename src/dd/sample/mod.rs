@@ -1,7 +1,10 @@
+#[cfg(test)]
+mod consistency;
 mod priority_sampler;
 mod rules_sampler;
-mod sampling_priority;
+mod sampling_decision;
 
-pub(crate) use priority_sampler::*;
-pub(crate) use rules_sampler::*;
-pub(crate) use sampling_priority::*;
+pub(crate) use crate::propagation::SamplingPriority;
+pub use priority_sampler::*;
+pub use rules_sampler::*;
+pub use sampling_decision::*;
@@ -1,12 +1,15 @@
 use super::SamplingPriority;
-use crate::dd::utils::{max_id_from_sample_rate, CONSTANT_RATE_HASH_FACTOR};
+use crate::sampling::{knuth_hash, max_hash};
 use eyre::{eyre, Result};
 use serde_json::Value;
 use std::{collections::HashMap, sync::Mutex};
 
 const PRIORITY_SAMPLER_DEFAULT_RATE_KEY: &str = "service:,env:";
+/// Agent updates with more rates are rejected, bounding the memory used by
+/// services and environments churning between updates.
+const MAX_AGENT_SAMPLING_RATES: usize = 10_000;
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct SampleResult {
     pub rule_rate: f64,
     pub limiter_rate: f64,
@@ -17,9 +20,9 @@ pub struct SampleResult {
 impl SampleResult {
     pub fn new() -> SampleResult {
         Self {
-            rule_rate: std::f64::NAN,
-            limiter_rate: std::f64::NAN,
-            priority_rate: std::f32::NAN,
+            rule_rate: f64::NAN,
+            limiter_rate: f64::NAN,
+            priority_rate: f32::NAN,
             sampling_priority: None,
         }
     }
@@ -31,10 +34,28 @@ pub struct SamplingRate {
     pub max_hash: u64,
 }
 
+/// Counts the updates of the agent rates, e.g. to check that a misbehaving
+/// agent isn't overriding good rates.
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct RateUpdateStats {
+    pub applied: u64,
+    /// Updates that were malformed, out of range or too large.
+    pub rejected: u64,
+    /// How many rates the last applied update held.
+    pub rates: usize,
+}
+
+fn rate_key(service: &str, environment: &str) -> String {
+    format!("service:{},env:{}", service, environment)
+}
+
 #[derive(Debug)]
 struct PrioritySamplerData {
     pub agent_sampling_rates: HashMap<String, SamplingRate>,
     pub default_sampling_rate: SamplingRate,
+    /// Rates set by hand, taking precedence over the agent ones.
+    pub pinned_rates: HashMap<String, SamplingRate>,
+    pub update_stats: RateUpdateStats,
 }
 
 #[derive(Debug)]
@@ -48,8 +69,10 @@ impl PrioritySampler {
             agent_sampling_rates: HashMap::new(),
             default_sampling_rate: SamplingRate {
                 rate: 1.0,
-                max_hash: std::u64::MAX,
+                max_hash: u64::MAX,
             },
+            pinned_rates: HashMap::new(),
+            update_stats: RateUpdateStats::default(),
         };
         Self {
             data: Mutex::new(data),
@@ -61,16 +84,20 @@ impl PrioritySampler {
 
         let mut applied_rate = data.default_sampling_rate.clone();
 
-        let key = format!("service:{},env:{}", service, environment);
-        if let Some(rule) = data.agent_sampling_rates.get(&key) {
+        let key = rate_key(service, environment);
+        if let Some(rule) = data
+            .pinned_rates
+            .get(&key)
+            .or_else(|| data.agent_sampling_rates.get(&key))
+        {
             applied_rate = rule.clone();
         }
 
-        let hashed_id = trace_id as u128 * CONSTANT_RATE_HASH_FACTOR as u128;
-        let sampling_priority = if hashed_id as u64 >= applied_rate.max_hash {
-            Some(SamplingPriority::SamplerDrop)
-        } else {
+        let sampled = applied_rate.rate >= 1.0 || knuth_hash(trace_id) < applied_rate.max_hash;
+        let sampling_priority = if sampled {
             Some(SamplingPriority::SamplerKeep)
+        } else {
+            Some(SamplingPriority::SamplerDrop)
         };
 
         Ok(SampleResult {
@@ -80,39 +107,129 @@ impl PrioritySampler {
         })
     }
 
-    pub fn configure(&mut self, config: &Value) -> Result<()> {
-        let mut rates = HashMap::new();
-        let object = if let Value::Object(object) = config {
-            object
-        } else {
-            return Err(eyre!("Invalid json for config. Expected Object."));
-        };
+    /// Returns the rates received from the agent by `service:<service>,env:<env>`
+    /// key, the default one under `service:,env:`.
+    pub fn agent_sampling_rates(&self) -> Result<HashMap<String, f32>> {
+        let data = self.data.lock().map_err(|_| eyre!("mutex lock failed"))?;
+        let mut rates: HashMap<String, f32> = data
+            .agent_sampling_rates
+            .iter()
+            .map(|(key, rate)| (key.clone(), rate.rate))
+            .collect();
+        rates.insert(
+            String::from(PRIORITY_SAMPLER_DEFAULT_RATE_KEY),
+            data.default_sampling_rate.rate,
+        );
 
+        Ok(rates)
+    }
+
+    /// Samples the traces of `service` in `environment` at `rate` instead
+    /// of the agent rate, until `clear_pinned_rate` is called, e.g. while
+    /// the agent rates are wrong.
+    pub fn pin_rate(&self, service: &str, environment: &str, rate: f64) -> Result<()> {
+        if !(0.0..=1.0).contains(&rate) {
+            return Err(eyre!("Sampling rate should be between 0.0 and 1.0"));
+        }
+        self.data
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .pinned_rates
+            .insert(
+                rate_key(service, environment),
+                SamplingRate {
+                    rate: rate as f32,
+                    max_hash: max_hash(rate),
+                },
+            );
+
+        Ok(())
+    }
+
+    /// Goes back to the agent rate of `service` in `environment`.
+    pub fn clear_pinned_rate(&self, service: &str, environment: &str) -> Result<()> {
+        self.data
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .pinned_rates
+            .remove(&rate_key(service, environment));
+
+        Ok(())
+    }
+
+    pub fn update_stats(&self) -> Result<RateUpdateStats> {
+        Ok(self
+            .data
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .update_stats
+            .clone())
+    }
+
+    /// Replaces the rates received from the agent, dropping those of the
+    /// services it no longer reports. Pinned rates are kept. Invalid updates
+    /// are rejected as a whole, keeping the current rates.
+    pub fn configure(&self, config: &Value) -> Result<()> {
         let mut data = self.data.lock().map_err(|_| eyre!("mutex lock failed"))?;
+        match parse_rates(config) {
+            Ok((default_rate, rates)) => {
+                data.update_stats.applied += 1;
+                data.update_stats.rates = rates.len() + default_rate.is_some() as usize;
+                if let Some(default_rate) = default_rate {
+                    data.default_sampling_rate = default_rate;
+                }
+                data.agent_sampling_rates = rates;
+                Ok(())
+            }
+            Err(error) => {
+                data.update_stats.rejected += 1;
+                Err(error)
+            }
+        }
+    }
+}
+
+/// Parses the rates of an agent response into the default rate, if any, and
+/// the rates of the other keys.
+fn parse_rates(config: &Value) -> Result<(Option<SamplingRate>, HashMap<String, SamplingRate>)> {
+    let object = if let Value::Object(object) = config {
+        object
+    } else {
+        return Err(eyre!("Invalid json for config. Expected Object."));
+    };
+    if object.len() > MAX_AGENT_SAMPLING_RATES {
+        return Err(eyre!(
+            "Too many sampling rates: {}, at most {} are accepted",
+            object.len(),
+            MAX_AGENT_SAMPLING_RATES
+        ));
+    }
 
-        for (key, value) in object {
-            let rate = if let Value::Number(value) = value {
-                value.as_f64().ok_or(eyre!("No float"))?
-            } else {
+    let mut default_rate = None;
+    let mut rates = HashMap::new();
+    for (key, value) in object {
+        let rate = match value.as_f64() {
+            Some(rate) if (0.0..=1.0).contains(&rate) => rate,
+            _ => {
                 return Err(eyre!(
-                    "Invalid json for config. All values should be numbers."
-                ));
-            };
-
-            let new_rate = SamplingRate {
-                rate: rate as f32,
-                max_hash: max_id_from_sample_rate(rate),
-            };
-            if key == PRIORITY_SAMPLER_DEFAULT_RATE_KEY {
-                data.default_sampling_rate = new_rate;
-            } else {
-                rates.insert(key.clone(), new_rate);
+                    "Invalid json for config. Rate of '{}' should be a number between 0.0 and 1.0.",
+                    key
+                ))
             }
-        }
-        data.agent_sampling_rates = rates;
+        };
 
-        Ok(())
+        let new_rate = SamplingRate {
+            rate: rate as f32,
+            max_hash: max_hash(rate),
+        };
+        if key == PRIORITY_SAMPLER_DEFAULT_RATE_KEY {
+            default_rate = Some(new_rate);
+        } else {
+            rates.insert(key.clone(), new_rate);
+        }
     }
+
+    Ok((default_rate, rates))
 }
 
 #[cfg(test)]
@@ -150,7 +267,7 @@ mod tests {
         #[test]
         fn spans_dont_match() {
             let config: Value = serde_json::from_str(CONFIG_JSON).unwrap();
-            let mut sampler = PrioritySampler::new();
+            let sampler = PrioritySampler::new();
             sampler.configure(&config).unwrap();
             let result = sampler
                 .sample("different env", "different service", 1)
@@ -165,7 +282,7 @@ mod tests {
         fn sampled_test(environment: &str, service: &str) -> f32 {
             let mut rng: MersenneTwister = Default::default();
             let config: Value = serde_json::from_str(CONFIG_JSON).unwrap();
-            let mut sampler = PrioritySampler::new();
+            let sampler = PrioritySampler::new();
             sampler.configure(&config).unwrap();
 
             // Case 1, service:nginx,env: => 0.8
@@ -196,6 +313,70 @@ mod tests {
             assert!(sample_rate < 0.85 && sample_rate > 0.75);
         }
 
+        #[test]
+        fn pinned_rates_survive_agent_updates() {
+            let config: Value = serde_json::from_str(CONFIG_JSON).unwrap();
+            let sampler = PrioritySampler::new();
+            sampler.configure(&config).unwrap();
+            sampler.pin_rate("nginx", "prod", 1.0).unwrap();
+            assert!(sampler.pin_rate("nginx", "", 1.5).is_err());
+
+            sampler.configure(&config).unwrap();
+            let result = sampler.sample("prod", "nginx", 1).unwrap();
+            assert_eq!(result.priority_rate, 1.0);
+            assert_eq!(
+                sampler.agent_sampling_rates().unwrap()["service:nginx,env:prod"],
+                0.2
+            );
+            assert_eq!(
+                sampler.agent_sampling_rates().unwrap()["service:,env:"],
+                1.0
+            );
+
+            sampler.clear_pinned_rate("nginx", "prod").unwrap();
+            let result = sampler.sample("prod", "nginx", 1).unwrap();
+            assert_eq!(result.priority_rate, 0.2);
+        }
+
+        #[test]
+        fn rejects_invalid_updates() {
+            let config: Value = serde_json::from_str(CONFIG_JSON).unwrap();
+            let sampler = PrioritySampler::new();
+            sampler.configure(&config).unwrap();
+
+            let invalid = [
+                serde_json::json!([0.5]),
+                serde_json::json!({"service:,env:": 0.5, "service:nginx,env:": 1.5}),
+                serde_json::json!({"service:,env:": 0.5, "service:nginx,env:": "1"}),
+            ];
+            for config in invalid.iter() {
+                assert!(sampler.configure(config).is_err());
+            }
+            let too_many: serde_json::Map<String, Value> = (0..=MAX_AGENT_SAMPLING_RATES)
+                .map(|i| (rate_key(&i.to_string(), ""), Value::from(0.5)))
+                .collect();
+            assert!(sampler.configure(&Value::Object(too_many)).is_err());
+
+            let rates = sampler.agent_sampling_rates().unwrap();
+            assert_eq!(rates["service:,env:"], 1.0);
+            assert_eq!(rates["service:nginx,env:"], 0.8);
+            assert_eq!(
+                sampler.update_stats().unwrap(),
+                RateUpdateStats {
+                    applied: 1,
+                    rejected: 4,
+                    rates: 2,
+                }
+            );
+
+            sampler
+                .configure(&serde_json::json!({"service:web,env:": 0.5}))
+                .unwrap();
+            let rates = sampler.agent_sampling_rates().unwrap();
+            assert!(!rates.contains_key("service:nginx,env:"));
+            assert_eq!(sampler.update_stats().unwrap().rates, 1);
+        }
+
         #[test]
         fn spans_can_be_sampled_config_2() {
             // Case 2, service:nginx,env:prod => 0.2
@@ -1,7 +1,22 @@
 use super::{PrioritySampler, SampleResult, SamplingPriority};
-use crate::dd::utils::{max_id_from_sample_rate, Limiter, TimePoint, CONSTANT_RATE_HASH_FACTOR};
-use eyre::Result;
+use crate::{
+    dd::{
+        span::{duration_nanos, SpanData},
+        tags::ENVIRONMENT,
+        utils::{glob_match, Clock, Limiter, SystemClock},
+    },
+    sampling::sampled_by_rate,
+};
+use eyre::{eyre, Result};
 use serde_json::Value;
+use std::{
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+/// Traces kept per second by the sampling rules, as in dd-opentracing-cpp.
+const DEFAULT_RATE_LIMIT: f64 = 100.0;
 
 pub(crate) struct RuleResult {
     pub matched: bool,
@@ -12,66 +27,154 @@ impl RuleResult {
     pub fn new() -> RuleResult {
         Self {
             matched: false,
-            rate: std::f64::NAN,
+            rate: f64::NAN,
         }
     }
 }
 
-pub(crate) struct RulesSampler<TimeProvider, RuleFunc>
+/// Rule matching a root span, e.g. on its service and operation name.
+/// Closures taking the span are rules.
+pub(crate) trait SamplingRule: Send + Sync {
+    fn match_span(&self, span: &SpanData) -> RuleResult;
+}
+
+impl<F> SamplingRule for F
 where
-    TimeProvider: Fn() -> TimePoint,
-    RuleFunc: Fn(&str, &str) -> RuleResult,
+    F: Fn(&SpanData) -> RuleResult + Send + Sync,
 {
-    limiter: Limiter<TimeProvider>,
-    sampling_rules: Vec<RuleFunc>,
+    fn match_span(&self, span: &SpanData) -> RuleResult {
+        self(span)
+    }
+}
+
+/// Decides the sampling priority of a trace from its local root before the
+/// sampling rules, which apply if it returns None.
+pub type SamplerOverride = Arc<dyn Fn(&SpanData) -> Option<SamplingPriority> + Send + Sync>;
+
+/// Keeps the traces whose local root ran for at least `threshold`, as
+/// allowed by `limiter`.
+#[derive(Debug)]
+struct LatencyRule {
+    threshold: Duration,
+    limiter: Limiter,
+}
+
+pub(crate) struct RulesSampler {
+    clock: Arc<dyn Clock>,
+    limiter: Limiter,
+    sampling_rules: Mutex<Vec<Box<dyn SamplingRule>>>,
     priority_sampler: PrioritySampler,
+    sampler_override: Option<SamplerOverride>,
+    error_sampling: bool,
+    latency_rule: Option<LatencyRule>,
 }
 
-impl<TimeProvider, RuleFunc> RulesSampler<TimeProvider, RuleFunc>
-where
-    TimeProvider: Fn() -> TimePoint,
-    RuleFunc: Fn(&str, &str) -> RuleResult,
-{
+// Rules and overrides are closures, so only their number is shown.
+impl fmt::Debug for RulesSampler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RulesSampler")
+            .field("limiter", &self.limiter)
+            .field("sampling_rules", &self.rules().len())
+            .field("priority_sampler", &self.priority_sampler)
+            .field("sampler_override", &self.sampler_override.is_some())
+            .field("error_sampling", &self.error_sampling)
+            .field("latency_rule", &self.latency_rule)
+            .finish()
+    }
+}
+
+impl RulesSampler {
     pub fn new(
-        time_provider: TimeProvider,
+        clock: Arc<dyn Clock>,
         max_tokens: u64,
         refresh_rate: f64,
         tokens_per_refresh: u64,
     ) -> Self {
         Self {
-            limiter: Limiter::<TimeProvider>::new(
-                time_provider,
-                max_tokens,
-                refresh_rate,
-                tokens_per_refresh,
-            ),
-            sampling_rules: Vec::new(),
+            clock: clock.clone(),
+            limiter: Limiter::new(clock, max_tokens, refresh_rate, tokens_per_refresh),
+            sampling_rules: Mutex::new(Vec::new()),
             priority_sampler: PrioritySampler::new(),
+            sampler_override: None,
+            error_sampling: false,
+            latency_rule: None,
+        }
+    }
+
+    pub fn add_rule(&mut self, rule: impl SamplingRule + 'static) {
+        self.rules().push(Box::new(rule));
+    }
+
+    fn rules(&self) -> MutexGuard<'_, Vec<Box<dyn SamplingRule>>> {
+        self.sampling_rules
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn set_override(&mut self, sampler_override: Option<SamplerOverride>) {
+        self.sampler_override = sampler_override;
+    }
+
+    /// Keeps the traces with errors which would be dropped by the sampler,
+    /// see `TracerOptions::error_sampling`.
+    pub fn set_error_sampling(&mut self, error_sampling: bool) {
+        self.error_sampling = error_sampling;
+    }
+
+    pub fn error_sampling(&self) -> bool {
+        self.error_sampling
+    }
+
+    /// Keeps the traces dropped by the sampler whose local root ran for at
+    /// least `threshold`, as many as `limiter` allows.
+    pub fn set_latency_rule(&mut self, threshold: Duration, limiter: Limiter) {
+        self.latency_rule = Some(LatencyRule { threshold, limiter });
+    }
+
+    /// Returns whether the latency rule keeps the trace of the finished
+    /// local `root`, which counts against its limit.
+    pub fn keeps_slow(&self, root: &SpanData) -> Result<bool> {
+        match &self.latency_rule {
+            Some(rule) if root.duration >= duration_nanos(rule.threshold) => {
+                Ok(rule.limiter.allow(1)?.allowed)
+            }
+            _ => Ok(false),
         }
     }
 
-    pub fn add_rule(&mut self, rule: RuleFunc) {
-        self.sampling_rules.push(rule);
+    /// Samples the trace of the local `root` span, asking the override
+    /// first if there's one.
+    pub fn sample_root(&self, root: &SpanData) -> Result<SampleResult> {
+        let priority = self
+            .sampler_override
+            .as_ref()
+            .and_then(|sampler_override| sampler_override(root));
+        if let Some(priority) = priority {
+            return Ok(SampleResult {
+                sampling_priority: Some(priority),
+                ..SampleResult::new()
+            });
+        }
+
+        self.sample(root)
     }
 
-    pub fn sample(
-        &mut self,
-        environment: &str,
-        service: &str,
-        name: &str,
-        trace_id: u64,
-    ) -> Result<SampleResult> {
-        let rule_result = self.match_rule(service, name);
+    /// Samples the trace of the local `root` span with the first rule
+    /// matching it, or with the agent rates if none does.
+    pub fn sample(&self, root: &SpanData) -> Result<SampleResult> {
+        let rule_result = self.match_rule(root);
         if !rule_result.matched {
-            return self.priority_sampler.sample(environment, service, trace_id);
+            let environment = root.meta.get(ENVIRONMENT).map(String::as_str);
+            return self.priority_sampler.sample(
+                environment.unwrap_or_default(),
+                &root.service,
+                root.trace_id,
+            );
         }
 
         let mut result = SampleResult::new();
         result.rule_rate = rule_result.rate;
-        let max_hash = max_id_from_sample_rate(rule_result.rate);
-        let hashed_id = trace_id as u128 * CONSTANT_RATE_HASH_FACTOR as u128;
-
-        if hashed_id > max_hash as u128 {
+        if !sampled_by_rate(root.trace_id, rule_result.rate) {
             result.sampling_priority = Some(SamplingPriority::SamplerDrop);
             return Ok(result);
         }
@@ -86,9 +189,9 @@ where
         Ok(result)
     }
 
-    pub fn match_rule(&self, service: &str, name: &str) -> RuleResult {
-        for rule in &self.sampling_rules {
-            let result = rule(service, name);
+    pub fn match_rule(&self, span: &SpanData) -> RuleResult {
+        for rule in self.rules().iter() {
+            let result = rule.match_span(span);
             if result.matched {
                 return result;
             }
@@ -97,7 +200,251 @@ where
         RuleResult::new()
     }
 
-    pub fn update_priority_sampler(&mut self, config: &Value) -> Result<()> {
+    pub fn update_priority_sampler(&self, config: &Value) -> Result<()> {
         self.priority_sampler.configure(config)
     }
+
+    /// Samples the traces matching no rule with the agent rates.
+    pub fn priority_sampler(&self) -> &PrioritySampler {
+        &self.priority_sampler
+    }
+}
+
+/// Returns the value of the tag `key` of `span` as matched by the sampling
+/// rules. Metrics only match as integers.
+fn tag_value(span: &SpanData, key: &str) -> Option<String> {
+    if let Some(value) = span.meta.get(key) {
+        return Some(value.clone());
+    }
+    span.metrics
+        .get(key)
+        .filter(|value| value.fract() == 0.0)
+        .map(|value| format!("{}", *value as i64))
+}
+
+impl RulesSampler {
+    /// Creates the sampler of the `sampling_rules` JSON array, e.g.
+    /// `[{"service": "db", "name": "query", "sample_rate": 0.1}]`, followed by
+    /// a rule matching every span with `sample_rate` unless it's NaN. Rules
+    /// match the `service`, `name` and `resource` of spans, and the values of
+    /// their `tags`, with glob patterns. Rules without some of them match
+    /// any, e.g. `{"resource": "GET /health*", "sample_rate": 0}` drops
+    /// health checks.
+    pub fn from_config(sampling_rules: &str, sample_rate: f32) -> Result<Self> {
+        let rules: Value = serde_json::from_str(sampling_rules)?;
+        let rules = rules
+            .as_array()
+            .ok_or_else(|| eyre!("sampling_rules should be an array"))?;
+
+        let mut sampler = Self::new(
+            Arc::new(SystemClock),
+            DEFAULT_RATE_LIMIT as u64,
+            DEFAULT_RATE_LIMIT,
+            1,
+        );
+        for rule in rules {
+            let rate = rule
+                .get("sample_rate")
+                .and_then(Value::as_f64)
+                .filter(|rate| (0.0..=1.0).contains(rate))
+                .ok_or_else(|| eyre!("Sampling rule without valid sample_rate: {}", rule))?;
+            let pattern = |key: &str| -> Result<Option<String>> {
+                match rule.get(key) {
+                    Some(Value::String(value)) => Ok(Some(value.clone())),
+                    Some(_) => Err(eyre!("Sampling rule {} should be a string: {}", key, rule)),
+                    None => Ok(None),
+                }
+            };
+            let (service, name) = (pattern("service")?, pattern("name")?);
+            let resource = pattern("resource")?;
+            let mut tags = Vec::new();
+            match rule.get("tags") {
+                Some(Value::Object(patterns)) => {
+                    for (key, pattern) in patterns {
+                        let pattern = pattern.as_str().ok_or_else(|| {
+                            eyre!("Sampling rule tags should be strings: {}", rule)
+                        })?;
+                        tags.push((key.clone(), String::from(pattern)));
+                    }
+                }
+                Some(_) => return Err(eyre!("Sampling rule tags should be an object: {}", rule)),
+                None => {}
+            }
+            let matches = |pattern: &Option<String>, value: &str| {
+                pattern
+                    .as_ref()
+                    .is_none_or(|pattern| glob_match(pattern, value))
+            };
+            sampler.add_rule(move |span: &SpanData| RuleResult {
+                matched: matches(&service, &span.service)
+                    && matches(&name, &span.name)
+                    && matches(&resource, &span.resource)
+                    && tags.iter().all(|(key, pattern)| {
+                        tag_value(span, key).is_some_and(|value| glob_match(pattern, &value))
+                    }),
+                rate,
+            });
+        }
+        if !sample_rate.is_nan() {
+            sampler.add_rule(move |_: &SpanData| RuleResult {
+                matched: true,
+                rate: sample_rate as f64,
+            });
+        }
+
+        Ok(sampler)
+    }
+
+    /// Replaces the sampling rules with those of `sampling_rules` and
+    /// `sample_rate`, as read by `from_config`. The rates of the agent and
+    /// the state of the rate limiter are kept. On error, the rules are kept.
+    pub fn update_rules(&self, sampling_rules: &str, sample_rate: f32) -> Result<()> {
+        let rules = Self::from_config(sampling_rules, sample_rate)?.sampling_rules;
+        *self.rules() = rules
+            .into_inner()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        Ok(())
+    }
+
+    /// Keeps up to `per_second` traces per second among the ones dropped
+    /// whose local root ran for at least `threshold`.
+    pub fn keep_slow_traces(&mut self, threshold: Duration, per_second: f64) {
+        let limiter = Limiter::new(self.clock.clone(), per_second.ceil() as u64, per_second, 1);
+        self.set_latency_rule(threshold, limiter);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(service: &str, name: &str) -> SpanData {
+        SpanData {
+            service: Arc::from(service),
+            name: Arc::from(name),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn builds_rules_from_config() {
+        let sampler =
+            RulesSampler::from_config(r#"[{"service": "db", "sample_rate": 0.1}]"#, 0.5).unwrap();
+        assert_eq!(sampler.match_rule(&span("db", "query")).rate, 0.1);
+        assert_eq!(sampler.match_rule(&span("web", "request")).rate, 0.5);
+
+        let sampler =
+            RulesSampler::from_config(r#"[{"name": "query", "sample_rate": 1}]"#, f32::NAN)
+                .unwrap();
+        assert!(sampler.match_rule(&span("db", "query")).matched);
+        assert!(!sampler.match_rule(&span("db", "insert")).matched);
+
+        assert!(RulesSampler::from_config(r#"[{"service": "db"}]"#, 0.5).is_err());
+        assert!(RulesSampler::from_config(r#"{"sample_rate": 1}"#, 0.5).is_err());
+        assert!(RulesSampler::from_config(r#"[{"tags": ["a"], "sample_rate": 1}]"#, 0.5).is_err());
+    }
+
+    #[test]
+    fn matches_resources_and_tags() {
+        let sampler = RulesSampler::from_config(
+            r#"[
+                {"resource": "GET /health*", "sample_rate": 0},
+                {"tags": {"http.route": "/admin/*", "http.status_code": "5??"}, "sample_rate": 1}
+            ]"#,
+            0.5,
+        )
+        .unwrap();
+        let mut request = span("web", "request");
+        request.resource = Arc::from("GET /healthz");
+        assert_eq!(sampler.match_rule(&request).rate, 0.0);
+
+        request.resource = Arc::from("GET /admin/users");
+        request
+            .meta
+            .insert(String::from("http.route"), String::from("/admin/users"));
+        assert_eq!(sampler.match_rule(&request).rate, 0.5);
+        request
+            .metrics
+            .insert(String::from("http.status_code"), 503.0);
+        assert_eq!(sampler.match_rule(&request).rate, 1.0);
+    }
+
+    #[test]
+    fn mixes_rule_types() {
+        struct ServiceRule(&'static str);
+
+        impl SamplingRule for ServiceRule {
+            fn match_span(&self, span: &SpanData) -> RuleResult {
+                RuleResult {
+                    matched: &*span.service == self.0,
+                    rate: 0.25,
+                }
+            }
+        }
+
+        let mut sampler = RulesSampler::new(Arc::new(SystemClock), 100, 100.0, 1);
+        sampler.add_rule(ServiceRule("db"));
+        sampler.add_rule(|_: &SpanData| RuleResult {
+            matched: true,
+            rate: 1.0,
+        });
+        assert_eq!(sampler.match_rule(&span("db", "query")).rate, 0.25);
+        assert_eq!(sampler.match_rule(&span("web", "request")).rate, 1.0);
+    }
+
+    #[test]
+    fn samples_from_many_threads() {
+        let sampler =
+            Arc::new(RulesSampler::from_config(r#"[{"sample_rate": 1}]"#, f32::NAN).unwrap());
+        let threads: Vec<_> = (0..4)
+            .map(|thread| {
+                let sampler = Arc::clone(&sampler);
+                std::thread::spawn(move || {
+                    let root = SpanData {
+                        trace_id: thread,
+                        ..span("web", "request")
+                    };
+                    sampler.sample(&root).unwrap().rule_rate
+                })
+            })
+            .collect();
+        sampler
+            .update_rules(r#"[{"sample_rate": 0.5}]"#, f32::NAN)
+            .unwrap();
+
+        for thread in threads {
+            let rate = thread.join().unwrap();
+            assert!(rate == 1.0 || rate == 0.5);
+        }
+        assert_eq!(sampler.match_rule(&span("web", "request")).rate, 0.5);
+    }
+
+    #[test]
+    fn asks_override_first() {
+        let mut sampler = RulesSampler::from_config("[]", 0.0).unwrap();
+        sampler.set_override(Some(Arc::new(|root: &SpanData| {
+            match root.meta.get("customer.tier").map(String::as_str) {
+                Some("premium") => Some(SamplingPriority::UserKeep),
+                _ => None,
+            }
+        })));
+
+        let mut root = SpanData {
+            trace_id: 1,
+            ..Default::default()
+        };
+        let result = sampler.sample_root(&root).unwrap();
+        assert_eq!(
+            result.sampling_priority,
+            Some(SamplingPriority::SamplerDrop)
+        );
+        assert_eq!(result.rule_rate, 0.0);
+
+        root.meta
+            .insert(String::from("customer.tier"), String::from("premium"));
+        let result = sampler.sample_root(&root).unwrap();
+        assert_eq!(result.sampling_priority, Some(SamplingPriority::UserKeep));
+        assert!(result.rule_rate.is_nan());
+    }
 }
@@ -0,0 +1,44 @@
+use super::{SampleResult, SamplingPriority};
+use crate::dd::tags::{
+    AGENT_RATE_SAMPLING_MECHANISM, DEFAULT_SAMPLING_MECHANISM, MANUAL_SAMPLING_MECHANISM,
+    RULE_SAMPLING_MECHANISM,
+};
+
+/// SamplingDecision is the decision of the sampler for a trace which isn't
+/// started yet, see `Tracer::should_sample`. Starting the trace with it
+/// keeps the decision, as if it was made when its root span started.
+#[derive(Debug, Clone)]
+pub struct SamplingDecision {
+    pub trace_id: u64,
+    /// The priority and the rates it was decided with.
+    pub result: SampleResult,
+}
+
+impl SamplingDecision {
+    pub fn priority(&self) -> Option<SamplingPriority> {
+        self.result.sampling_priority.clone()
+    }
+
+    /// Whether the trace is kept. Traces are kept without priority
+    /// sampling.
+    pub fn keep(&self) -> bool {
+        self.result
+            .sampling_priority
+            .as_ref()
+            .is_none_or(|priority| priority.as_i32() > 0)
+    }
+
+    /// The mechanism which decided, as in the `_dd.p.dm` tag.
+    pub fn mechanism(&self) -> &'static str {
+        let result = &self.result;
+        if result.sampling_priority.is_none() {
+            DEFAULT_SAMPLING_MECHANISM
+        } else if !result.rule_rate.is_nan() {
+            RULE_SAMPLING_MECHANISM
+        } else if !result.priority_rate.is_nan() {
+            AGENT_RATE_SAMPLING_MECHANISM
+        } else {
+            MANUAL_SAMPLING_MECHANISM
+        }
+    }
+}
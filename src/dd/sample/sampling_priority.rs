@@ -1,7 +0,0 @@
-#[derive(Debug, PartialEq, Clone)]
-pub enum SamplingPriority {
-    UserDrop,
-    SamplerDrop,
-    SamplerKeep,
-    UserKeep,
-}
@@ -0,0 +1,63 @@
+use super::WritingSpanBuffer;
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+type Shared = Arc<(Mutex<bool>, Condvar)>;
+
+/// Heartbeat periodically writes partial snapshots of long-running root spans
+/// from a background thread until it's dropped.
+pub(crate) struct Heartbeat {
+    stop: Shared,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Heartbeat {
+    pub fn new(buffer: Arc<WritingSpanBuffer>, period: Duration) -> Self {
+        Self::every(period, move || {
+            let _ = buffer.write_heartbeats(period);
+        })
+    }
+
+    /// Runs `task` every `period` instead, e.g. to look for leaked spans.
+    pub fn every<F: Fn() + Send + 'static>(period: Duration, task: F) -> Self {
+        let stop: Shared = Arc::new((Mutex::new(false), Condvar::new()));
+        let worker_stop = stop.clone();
+        let worker = thread::spawn(move || {
+            let (lock, condvar) = &*worker_stop;
+            let mut stopped = match lock.lock() {
+                Ok(stopped) => stopped,
+                Err(_) => return,
+            };
+            while !*stopped {
+                stopped = match condvar.wait_timeout(stopped, period) {
+                    Ok((stopped, timeout)) if timeout.timed_out() => {
+                        task();
+                        stopped
+                    }
+                    Ok((stopped, _)) => stopped,
+                    Err(_) => return,
+                };
+            }
+        });
+
+        Self {
+            stop,
+            worker: Some(worker),
+        }
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        if let Ok(mut stopped) = self.stop.0.lock() {
+            *stopped = true;
+        }
+        self.stop.1.notify_all();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
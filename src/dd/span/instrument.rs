@@ -0,0 +1,162 @@
+use super::{activate, OwnedSpan, ScopedContext, SpanContext};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// SpanExt ties futures to spans: the span is active while the future is
+/// polled, whichever thread of the runtime polls it, so that the spans it
+/// starts are its children across `.await` points and tasks.
+pub trait SpanExt: Future + Sized {
+    /// Runs the future in `span`, finished when the future completes.
+    fn with_span(self, span: OwnedSpan) -> WithSpan<Self> {
+        WithSpan {
+            context: span.context().clone(),
+            future: Box::pin(self),
+            span: Some(span),
+            error_on_cancel: false,
+        }
+    }
+
+    /// Runs the future in the span context active when it's created, e.g.
+    /// a future passed to `tokio::spawn`.
+    fn in_current_context(self) -> WithContext<Self> {
+        WithContext {
+            future: Box::pin(self),
+            context: ScopedContext::capture(),
+        }
+    }
+}
+
+impl<F: Future> SpanExt for F {}
+
+/// The future of `SpanExt::with_span`.
+pub struct WithSpan<F> {
+    future: Pin<Box<F>>,
+    context: SpanContext,
+    /// None once the future completed.
+    span: Option<OwnedSpan>,
+    error_on_cancel: bool,
+}
+
+impl<F> WithSpan<F> {
+    /// Marks the span as a `cancellation` error when the future is dropped
+    /// before it completes, e.g. when the client disconnects or a timeout
+    /// elapses. Otherwise the span just finishes then.
+    pub fn error_on_cancel(mut self) -> Self {
+        self.error_on_cancel = true;
+        self
+    }
+}
+
+impl<F: Future> Future for WithSpan<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let output = {
+            let _scope = activate(this.context.clone());
+            match this.future.as_mut().poll(cx) {
+                Poll::Ready(output) => output,
+                Poll::Pending => return Poll::Pending,
+            }
+        };
+
+        if let Some(mut span) = this.span.take() {
+            span.finish();
+        }
+        Poll::Ready(output)
+    }
+}
+
+impl<F> Drop for WithSpan<F> {
+    fn drop(&mut self) {
+        match self.span.take() {
+            Some(mut span) if self.error_on_cancel => span.finish_cancelled(),
+            Some(mut span) => span.finish(),
+            None => {}
+        }
+    }
+}
+
+/// The future of `SpanExt::in_current_context`.
+pub struct WithContext<F> {
+    future: Pin<Box<F>>,
+    context: ScopedContext,
+}
+
+impl<F: Future> Future for WithContext<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let _scope = this.context.restore();
+        this.future.as_mut().poll(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dd::{agent::MockTransport, span::active_context, tags::ERROR_TYPE, Tracer, TracerOptions},
+        opentracing::StartSpanOptions,
+    };
+    use serde_json::Value;
+    use std::{sync::Arc, task::Waker, time::Duration};
+
+    /// Pending on its first poll, ready with the id of the active span
+    /// context on the next.
+    #[derive(Default)]
+    struct Yield(bool);
+
+    impl Future for Yield {
+        type Output = Option<u64>;
+
+        fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<u64>> {
+            if !self.0 {
+                self.0 = true;
+                return Poll::Pending;
+            }
+            Poll::Ready(active_context().map(|context| context.id()))
+        }
+    }
+
+    #[test]
+    fn keeps_spans_active_across_polls() {
+        let transport = Arc::new(MockTransport::default());
+        let tracer = Tracer::with_transport(TracerOptions::default(), transport.clone()).unwrap();
+        let mut cx = Context::from_waker(Waker::noop());
+
+        let span = tracer.start_owned_span("request", &StartSpanOptions::default());
+        let id = span.context().id();
+        let mut request = Yield::default().with_span(span);
+        assert!(Pin::new(&mut request).poll(&mut cx).is_pending());
+        assert!(active_context().is_none());
+        assert_eq!(Pin::new(&mut request).poll(&mut cx), Poll::Ready(Some(id)));
+
+        let scope = activate(SpanContext::new(42, 7, "", Default::default()));
+        let mut task = Yield::default().in_current_context();
+        drop(scope);
+        assert!(Pin::new(&mut task).poll(&mut cx).is_pending());
+        assert_eq!(Pin::new(&mut task).poll(&mut cx), Poll::Ready(Some(42)));
+
+        let span = tracer.start_owned_span("timed out", &StartSpanOptions::default());
+        let mut cancelled = Yield::default().with_span(span).error_on_cancel();
+        assert!(Pin::new(&mut cancelled).poll(&mut cx).is_pending());
+        drop(cancelled);
+
+        assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 2);
+        let posts = transport.posts.lock().unwrap();
+        let spans: Vec<Value> = posts
+            .iter()
+            .flat_map(|(_, body)| serde_json::from_slice::<Vec<Vec<Value>>>(body).unwrap())
+            .flatten()
+            .collect();
+        let span = |name: &str| spans.iter().find(|span| span["name"] == name).unwrap();
+        assert_eq!(span("request")["error"], 0);
+        assert_eq!(span("timed out")["error"], 1);
+        assert_eq!(span("timed out")["meta"][ERROR_TYPE], "cancellation");
+    }
+}
@@ -1,3 +1,27 @@
+#[cfg(feature = "threads")]
+mod heartbeat;
+mod instrument;
+mod owned_span;
+mod saved_trace;
+mod scope;
 mod span_buffer;
 mod span_context;
 mod span_data;
+mod trace_filter;
+mod trace_processor;
+mod trace_segment;
+mod user;
+
+#[cfg(feature = "threads")]
+pub(crate) use heartbeat::*;
+pub use instrument::*;
+pub use owned_span::*;
+pub use saved_trace::*;
+pub use scope::*;
+pub use span_buffer::*;
+pub use span_context::*;
+pub use span_data::*;
+pub use trace_filter::*;
+pub use trace_processor::*;
+pub(crate) use trace_segment::*;
+pub use user::*;
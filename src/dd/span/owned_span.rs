@@ -0,0 +1,785 @@
+use super::{duration_nanos, nanos_since_epoch, SpanBuffer, SpanContext, SpanData, UserInfo};
+use crate::{
+    dd::tags::{
+        ERROR, ERROR_MSG, ERROR_STACK, ERROR_TYPE, EVENTS, MEASURED, OPERATION_NAME, RESOURCE_NAME,
+        SERVICE_NAME, SPAN_LINKS, SPAN_TYPE,
+    },
+    dd::utils::{LogLevel, RateLimitedLogger},
+    opentracing::{self, FinishSpanOptions},
+};
+use serde_json::{json, Map, Value};
+use std::{
+    ops::{Deref, DerefMut},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Returns the metric value of a number, or None for integers beyond 2^53
+/// which f64 can't represent exactly, such as 64-bit ids.
+fn number_to_metric(number: &serde_json::Number) -> Option<f64> {
+    const MAX_EXACT_INTEGER: u64 = 1 << 53;
+    match (number.as_u64(), number.as_i64()) {
+        (Some(value), _) if value > MAX_EXACT_INTEGER => None,
+        (_, Some(value)) if value.unsigned_abs() > MAX_EXACT_INTEGER => None,
+        _ => number.as_f64(),
+    }
+}
+
+fn value_to_error(value: &Value) -> bool {
+    match value {
+        Value::Bool(value) => *value,
+        Value::Number(value) => value.as_f64().is_some_and(|value| value != 0.0),
+        Value::String(value) => value == "true" || value == "1",
+        _ => false,
+    }
+}
+
+/// Builds a span event from the fields of a log record. The `event` field
+/// names it, the other fields are its attributes. Span event attributes
+/// can't be objects, so these are kept as their JSON.
+fn log_to_event(timestamp: SystemTime, fields: &[(String, Value)]) -> Value {
+    let mut name = String::from("log");
+    let mut attributes = Map::new();
+    for (key, value) in fields {
+        match (key.as_str(), value) {
+            ("event", Value::String(event)) => name = event.clone(),
+            (_, Value::Object(_)) => {
+                attributes.insert(key.clone(), Value::String(value.to_string()));
+            }
+            _ => {
+                attributes.insert(key.clone(), value.clone());
+            }
+        }
+    }
+
+    json!({
+        "name": name,
+        "time_unix_nano": nanos_since_epoch(timestamp),
+        "attributes": attributes,
+    })
+}
+
+/// OwnedSpan holds the data of a span and the shared span buffer, without
+/// borrowing the tracer, so it can be moved to and finished on any thread.
+/// Its data is handed over to the span buffer when it's finished or dropped.
+pub struct OwnedSpan {
+    buffer: Arc<dyn SpanBuffer>,
+    context: SpanContext,
+    start_steady: Instant,
+    /// Duration of spans of work measured elsewhere, recorded whenever
+    /// they're finished.
+    recorded_duration: Option<Duration>,
+    /// None once the span is finished.
+    span: Option<SpanData>,
+    /// Logged span events, written as the `events` tag on finish.
+    events: Vec<Value>,
+    /// Receives the warnings about baggage beyond the limits.
+    logger: Option<Arc<RateLimitedLogger>>,
+}
+
+impl OwnedSpan {
+    pub(crate) fn new(
+        buffer: Arc<dyn SpanBuffer>,
+        mut context: SpanContext,
+        start_system: SystemTime,
+        start_steady: Instant,
+        mut span: SpanData,
+    ) -> OwnedSpan {
+        span.trace_id = context.trace_id();
+        span.span_id = context.id();
+        span.start = nanos_since_epoch(start_system);
+        if let Ok(segment) = buffer.register_span(&context, &span) {
+            context.set_trace_segment(segment);
+        }
+
+        Self {
+            buffer,
+            context,
+            start_steady,
+            recorded_duration: None,
+            span: Some(span),
+            events: Vec::new(),
+            logger: None,
+        }
+    }
+
+    /// Creates a span recording nothing, whose context continues the trace of
+    /// `context`, e.g. beyond the spans per trace of the tracer.
+    pub(crate) fn noop(buffer: Arc<dyn SpanBuffer>, context: SpanContext) -> Self {
+        Self {
+            buffer,
+            context,
+            start_steady: Instant::now(),
+            recorded_duration: None,
+            span: None,
+            events: Vec::new(),
+            logger: None,
+        }
+    }
+
+    /// Resumes a span saved by another process, already registered in the
+    /// segment of `context`. Its steady start is set so that its duration
+    /// covers the time it ran before being saved.
+    pub(crate) fn restore(
+        buffer: Arc<dyn SpanBuffer>,
+        context: SpanContext,
+        mut span: SpanData,
+    ) -> Self {
+        let elapsed = nanos_since_epoch(SystemTime::now()).saturating_sub(span.start);
+        let now = Instant::now();
+        let start_steady = now
+            .checked_sub(Duration::from_nanos(elapsed.max(0) as u64))
+            .unwrap_or(now);
+        let events = span
+            .meta
+            .remove(EVENTS)
+            .and_then(|events| serde_json::from_str(&events).ok())
+            .unwrap_or_default();
+
+        Self {
+            buffer,
+            context,
+            start_steady,
+            recorded_duration: None,
+            span: Some(span),
+            events,
+            logger: None,
+        }
+    }
+
+    pub(crate) fn set_logger(&mut self, logger: Arc<RateLimitedLogger>) {
+        self.logger = Some(logger);
+    }
+
+    /// Makes the span last `duration` however it's finished, for work
+    /// measured elsewhere.
+    pub fn set_recorded_duration(&mut self, duration: Duration) {
+        self.recorded_duration = Some(duration);
+    }
+
+    /// Moves the start of the span to `start`, e.g. when the true start of a
+    /// request is learned later from an upstream timestamp header. The
+    /// steady start moves as much, so the duration still covers the whole
+    /// request. Does nothing once the span is finished.
+    pub fn set_start_time(&mut self, start: SystemTime) {
+        let span = match self.span.as_mut() {
+            Some(span) => span,
+            None => return,
+        };
+
+        let start = nanos_since_epoch(start);
+        let shift = Duration::from_nanos(start.abs_diff(span.start));
+        let start_steady = if start < span.start {
+            self.start_steady.checked_sub(shift)
+        } else {
+            self.start_steady.checked_add(shift)
+        };
+        if let Some(start_steady) = start_steady {
+            self.start_steady = start_steady;
+            span.start = start;
+        }
+    }
+
+    /// Marks the span as measured so that trace metrics are computed for it
+    /// even if it isn't a service entry span.
+    pub fn set_measured(&mut self, measured: bool) {
+        if let Some(span) = self.span.as_mut() {
+            if measured {
+                span.metrics.insert(String::from(MEASURED), 1.0);
+            } else {
+                span.metrics.remove(MEASURED);
+            }
+        }
+    }
+
+    /// Runs `f` and records how long it took, in milliseconds, as the `name`
+    /// metric, e.g. `db.query.duration_ms`.
+    pub fn record_duration<T, F: FnOnce() -> T>(&mut self, name: &str, f: F) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.record_elapsed(name, start);
+        result
+    }
+
+    /// Starts a Timer recording the `name` metric when it's dropped.
+    pub fn timer(&mut self, name: &str) -> Timer<'_> {
+        Timer {
+            span: self,
+            name: String::from(name),
+            start: Instant::now(),
+        }
+    }
+
+    fn record_elapsed(&mut self, name: &str, start: Instant) {
+        if let Some(span) = self.span.as_mut() {
+            let elapsed = start.elapsed().as_secs_f64() * 1000.0;
+            span.metrics.insert(String::from(name), elapsed);
+        }
+    }
+
+    /// Finishes the span at `finish_steady`, measured on the steady clock so
+    /// that changes of the system time don't skew it. Finishing it again
+    /// does nothing.
+    pub fn finish_at(&mut self, finish_steady: Instant) {
+        let duration = self
+            .recorded_duration
+            .unwrap_or_else(|| finish_steady.saturating_duration_since(self.start_steady));
+        self.finish_with_duration(duration);
+    }
+
+    /// Finishes the span with an explicit duration, e.g. for spans rebuilt
+    /// from timings measured elsewhere such as the latency of a queue.
+    /// Spans last at least 1ns, as the agent drops empty ones.
+    pub fn finish_with_duration(&mut self, duration: Duration) {
+        let mut span = match self.span.take() {
+            Some(span) => span,
+            None => return,
+        };
+
+        span.duration = duration_nanos(duration).max(1);
+        if !self.events.is_empty() {
+            let events = Value::Array(std::mem::take(&mut self.events));
+            span.meta.insert(String::from(EVENTS), events.to_string());
+        }
+        let _ = self.buffer.finish_span(span);
+    }
+
+    pub fn finish(&mut self) {
+        self.finish_at(Instant::now());
+    }
+
+    /// Finishes the span of work which was abandoned before it completed,
+    /// e.g. a request whose client disconnected, as an error of type
+    /// `cancellation`.
+    pub fn finish_cancelled(&mut self) {
+        if self.span.is_none() {
+            return;
+        }
+        self.log(&[
+            (String::from("event"), Value::from("error")),
+            (String::from("error.kind"), Value::from("cancellation")),
+            (
+                String::from("message"),
+                Value::from("cancelled before completion"),
+            ),
+        ]);
+        self.finish();
+    }
+
+    /// Returns the data of the running span as it is now, its events kept
+    /// in the `events` tag, e.g. to save its trace. None once it's finished.
+    pub fn snapshot(&self) -> Option<SpanData> {
+        let mut span = self.span.clone()?;
+        if !self.events.is_empty() {
+            let events = Value::Array(self.events.clone());
+            span.meta.insert(String::from(EVENTS), events.to_string());
+        }
+
+        Some(span)
+    }
+
+    /// Drops the span without finishing it, once its trace is saved to be
+    /// finished by another process.
+    pub fn detach(mut self) {
+        self.span = None;
+    }
+
+    pub fn set_operation_name(&mut self, operation_name: &str) {
+        if let Some(span) = self.span.as_mut() {
+            span.name = Arc::from(operation_name);
+            span.resource = span.name.clone();
+        }
+    }
+
+    /// Sets a tag. The `service.name`, `span.type`, `resource.name`,
+    /// `operation` and `error` keys set the corresponding span fields, other
+    /// values are coerced:
+    /// - numbers become metrics, as with `set_metric`, except integers beyond
+    ///   2^53 which are kept as strings so that 64-bit ids stay exact,
+    /// - strings are kept as they are, even if they hold a number,
+    /// - booleans become "true" or "false", arrays, objects and null their
+    ///   JSON.
+    ///
+    /// A key is either a metric or a string tag: setting it as one removes
+    /// the other.
+    pub fn set_tag(&mut self, key: &str, value: &Value) {
+        let start = self.start_overhead();
+        self.tag(key, value);
+        self.add_overhead(start);
+    }
+
+    fn tag(&mut self, key: &str, value: &Value) {
+        let span = match self.span.as_mut() {
+            Some(span) => span,
+            None => return,
+        };
+
+        match key {
+            SERVICE_NAME => span.service = Arc::from(value_to_string(value)),
+            SPAN_TYPE => span.span_type = Arc::from(value_to_string(value)),
+            RESOURCE_NAME => span.resource = Arc::from(value_to_string(value)),
+            OPERATION_NAME => span.name = Arc::from(value_to_string(value)),
+            ERROR => span.error = value_to_error(value) as i32,
+            _ => match value {
+                Value::Number(number) => match number_to_metric(number) {
+                    Some(number) => self.set_metric(key, number),
+                    None => {
+                        span.metrics.remove(key);
+                        span.meta.insert(String::from(key), number.to_string());
+                    }
+                },
+                value => {
+                    span.metrics.remove(key);
+                    span.meta.insert(String::from(key), value_to_string(value));
+                }
+            },
+        }
+    }
+
+    /// Sets a numeric metric, such as `_dd.measured` or an analytics rate.
+    /// NaN and infinite values can't be sent and are ignored.
+    pub fn set_metric(&mut self, key: &str, value: f64) {
+        let span = match self.span.as_mut() {
+            Some(span) => span,
+            None => return,
+        };
+        if !value.is_finite() {
+            return;
+        }
+
+        span.meta.remove(key);
+        span.metrics.insert(String::from(key), value);
+    }
+
+    /// Records a log, as OpenTracing `Log` calls do. Logs whose `event` field
+    /// is "error" mark the span as an error, their `message` (or
+    /// `error.object`), `error.kind` and `stack` fields setting the
+    /// `error.msg`, `error.type` and `error.stack` tags. Other logs are
+    /// recorded as span events.
+    pub fn log_at(&mut self, timestamp: SystemTime, fields: &[(String, Value)]) {
+        let start = self.start_overhead();
+        self.record_log(timestamp, fields);
+        self.add_overhead(start);
+    }
+
+    fn record_log(&mut self, timestamp: SystemTime, fields: &[(String, Value)]) {
+        let span = match self.span.as_mut() {
+            Some(span) => span,
+            None => return,
+        };
+
+        let is_error = fields
+            .iter()
+            .any(|(key, value)| key == "event" && value.as_str() == Some("error"));
+        if !is_error {
+            self.events.push(log_to_event(timestamp, fields));
+            return;
+        }
+
+        span.error = 1;
+        for (key, value) in fields {
+            let tag = match key.as_str() {
+                "message" | "error.object" => ERROR_MSG,
+                "error.kind" => ERROR_TYPE,
+                "stack" => ERROR_STACK,
+                _ => continue,
+            };
+            // An explicit message wins over the description of the object.
+            if key == "error.object" && fields.iter().any(|(key, _)| key == "message") {
+                continue;
+            }
+            span.metrics.remove(tag);
+            span.meta.insert(String::from(tag), value_to_string(value));
+        }
+    }
+
+    pub fn log(&mut self, fields: &[(String, Value)]) {
+        self.log_at(SystemTime::now(), fields);
+    }
+
+    /// Sets a baggage item, see `SpanContext::set_baggage_item`. Items
+    /// beyond the baggage limits are logged as warnings.
+    pub fn set_baggage_item(&mut self, restricted_key: &str, value: &str) {
+        let warning = self.context.set_baggage_item(restricted_key, value);
+        if let (Ok(Some(warning)), Some(logger)) = (warning, &self.logger) {
+            logger.log(LogLevel::Warn, "baggage", &warning);
+        }
+    }
+
+    pub fn baggage_item(&self, restricted_key: &str) -> String {
+        self.context
+            .baggage_item(restricted_key)
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+    }
+
+    pub fn remove_baggage_item(&mut self, restricted_key: &str) -> Option<String> {
+        self.context
+            .remove_baggage_item(restricted_key)
+            .ok()
+            .flatten()
+    }
+
+    pub fn context(&self) -> &SpanContext {
+        &self.context
+    }
+
+    /// Adds `link`, e.g. `{"trace_id": ..., "span_id": ..., "attributes":
+    /// {...}}`, to the span links of the span, kept in the `_dd.span_links`
+    /// tag.
+    pub fn add_span_link(&mut self, link: Value) {
+        let span = match self.span.as_mut() {
+            Some(span) => span,
+            None => return,
+        };
+        let mut links = match span
+            .meta
+            .get(SPAN_LINKS)
+            .map(|links| serde_json::from_str(links))
+        {
+            Some(Ok(Value::Array(links))) => links,
+            _ => Vec::new(),
+        };
+        links.push(link);
+        span.meta
+            .insert(String::from(SPAN_LINKS), Value::Array(links).to_string());
+    }
+
+    /// Tags the local root span of the trace with the user it runs for, as
+    /// `usr.*` tags, e.g. from the middleware authenticating requests, so
+    /// that traces can be searched by user.
+    pub fn set_user(&mut self, user: &UserInfo) {
+        if let Some(segment) = self.context.trace_segment() {
+            for (key, value) in user.tags() {
+                let _ = segment.set_trace_tag(key, &value);
+            }
+        }
+    }
+
+    /// Returns when tracer work starts, if the overhead of the trace is
+    /// measured.
+    pub fn start_overhead(&self) -> Option<Instant> {
+        self.context
+            .trace_segment()
+            .and_then(|segment| segment.start_overhead())
+    }
+
+    /// Adds the time elapsed since `start` to the overhead of the trace.
+    pub fn add_overhead(&self, start: Option<Instant>) {
+        if let Some(segment) = self.context.trace_segment() {
+            segment.add_overhead(start);
+        }
+    }
+}
+
+impl Drop for OwnedSpan {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Timer times a phase of a span without creating a child span: the time
+/// elapsed until it's dropped is recorded as a metric in milliseconds. The
+/// span stays usable through the timer.
+pub struct Timer<'s> {
+    span: &'s mut OwnedSpan,
+    name: String,
+    start: Instant,
+}
+
+impl<'s> Deref for Timer<'s> {
+    type Target = OwnedSpan;
+
+    fn deref(&self) -> &OwnedSpan {
+        self.span
+    }
+}
+
+impl<'s> DerefMut for Timer<'s> {
+    fn deref_mut(&mut self) -> &mut OwnedSpan {
+        self.span
+    }
+}
+
+impl<'s> Drop for Timer<'s> {
+    fn drop(&mut self) {
+        self.span.record_elapsed(&self.name, self.start);
+    }
+}
+
+/// Span is the Datadog implementation of an OpenTracing span: an OwnedSpan
+/// borrowing the tracer that started it.
+pub struct Span<'a> {
+    tracer: &'a dyn opentracing::Tracer,
+    inner: OwnedSpan,
+}
+
+impl<'a> Span<'a> {
+    pub fn new(tracer: &'a dyn opentracing::Tracer, inner: OwnedSpan) -> Span<'a> {
+        Self { tracer, inner }
+    }
+
+    pub fn set_measured(&mut self, measured: bool) {
+        self.inner.set_measured(measured);
+    }
+
+    pub fn record_duration<T, F: FnOnce() -> T>(&mut self, name: &str, f: F) -> T {
+        self.inner.record_duration(name, f)
+    }
+
+    pub fn set_metric(&mut self, key: &str, value: f64) {
+        self.inner.set_metric(key, value);
+    }
+
+    pub fn set_user(&mut self, user: &UserInfo) {
+        self.inner.set_user(user);
+    }
+
+    pub fn timer(&mut self, name: &str) -> Timer<'_> {
+        self.inner.timer(name)
+    }
+
+    pub fn finish_with_duration(&mut self, duration: Duration) {
+        self.inner.finish_with_duration(duration);
+    }
+
+    pub fn set_start_time(&mut self, start: SystemTime) {
+        self.inner.set_start_time(start);
+    }
+
+    /// Releases the span from the tracer, e.g. to finish it on another
+    /// thread.
+    pub fn into_owned(self) -> OwnedSpan {
+        self.inner
+    }
+}
+
+impl<'a> opentracing::Span for Span<'a> {
+    fn finish_with_options(&mut self, finish_span_options: &FinishSpanOptions) {
+        for record in &finish_span_options.log_records {
+            self.inner.log_at(record.timestamp, &record.fields);
+        }
+        self.inner
+            .finish_at(finish_span_options.finish_steady_timestamp);
+    }
+
+    fn set_operation_name(&mut self, operation_name: &str) {
+        self.inner.set_operation_name(operation_name);
+    }
+
+    fn set_tag(&mut self, key: &str, value: &Value) {
+        self.inner.set_tag(key, value);
+    }
+
+    fn set_baggage_item(&mut self, restricted_key: &str, value: &str) {
+        self.inner.set_baggage_item(restricted_key, value);
+    }
+
+    fn baggage_item(&self, restricted_key: &str) -> String {
+        self.inner.baggage_item(restricted_key)
+    }
+
+    fn remove_baggage_item(&mut self, restricted_key: &str) -> Option<String> {
+        self.inner.remove_baggage_item(restricted_key)
+    }
+
+    fn log(&mut self, fields: &[(String, Value)]) {
+        self.inner.log(fields);
+    }
+
+    fn context(&self) -> &dyn opentracing::SpanContext {
+        self.inner.context()
+    }
+
+    fn tracer(&self) -> &dyn opentracing::Tracer {
+        self.tracer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dd::span::MockBuffer;
+    use std::{collections::HashMap, thread, time::UNIX_EPOCH};
+
+    #[test]
+    fn records_phase_durations() {
+        let buffer = Arc::new(MockBuffer::default());
+        let mut span = OwnedSpan::new(
+            buffer.clone(),
+            SpanContext::new(1, 1, "", HashMap::new()),
+            SystemTime::now(),
+            Instant::now(),
+            SpanData::default(),
+        );
+
+        let rows = span.record_duration("db.query.duration_ms", || {
+            thread::sleep(Duration::from_millis(5));
+            3
+        });
+        assert_eq!(rows, 3);
+        {
+            let mut timer = span.timer("render.duration_ms");
+            timer.set_tag("rows", &Value::from(rows));
+            thread::sleep(Duration::from_millis(5));
+        }
+        span.finish();
+
+        let finished = buffer.finished.lock().unwrap();
+        let metrics = &finished[0].metrics;
+        assert!(metrics["db.query.duration_ms"] >= 5.0);
+        assert!(metrics["render.duration_ms"] >= 5.0);
+        assert_eq!(metrics["rows"], 3.0);
+    }
+
+    #[test]
+    fn spans_last_at_least_a_nanosecond() {
+        let buffer = Arc::new(MockBuffer::default());
+        let start = Instant::now();
+        let new_span = || {
+            OwnedSpan::new(
+                buffer.clone(),
+                SpanContext::new(1, 1, "", HashMap::new()),
+                SystemTime::now(),
+                start,
+                SpanData::default(),
+            )
+        };
+
+        new_span().finish_at(start);
+        new_span().finish_at(start - Duration::from_millis(1));
+        let mut span = new_span();
+        span.finish_with_duration(Duration::from_millis(250));
+        span.finish_with_duration(Duration::from_millis(1));
+        new_span().finish_with_duration(Duration::from_secs(0));
+
+        let durations: Vec<i64> = buffer
+            .finished
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|span| span.duration)
+            .collect();
+        assert_eq!(durations, vec![1, 1, 250_000_000, 1]);
+    }
+
+    #[test]
+    fn moves_the_start_of_unfinished_spans() {
+        let buffer = Arc::new(MockBuffer::default());
+        let start = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let start_steady = Instant::now();
+        let mut span = OwnedSpan::new(
+            buffer.clone(),
+            SpanContext::new(1, 1, "", HashMap::new()),
+            start,
+            start_steady,
+            SpanData::default(),
+        );
+
+        span.set_start_time(start - Duration::from_millis(40));
+        span.finish_at(start_steady + Duration::from_millis(10));
+        span.set_start_time(start);
+
+        let finished = buffer.finished.lock().unwrap();
+        assert_eq!(finished[0].start, 1_599_999_999_960_000_000);
+        assert_eq!(finished[0].duration, 50_000_000);
+    }
+
+    #[test]
+    fn coerces_tags_to_metrics_and_meta() {
+        let buffer = Arc::new(MockBuffer::default());
+        let mut span = OwnedSpan::new(
+            buffer.clone(),
+            SpanContext::new(1, 1, "", HashMap::new()),
+            SystemTime::now(),
+            Instant::now(),
+            SpanData::default(),
+        );
+
+        span.set_tag("retries", &Value::from(2));
+        span.set_tag("port", &Value::from("8126"));
+        span.set_tag("cached", &Value::Bool(false));
+        span.set_metric("_dd1.sr.eausr", 0.5);
+        span.set_metric("ratio", f64::NAN);
+        span.set_tag("cached", &Value::from(1.5));
+        span.set_metric("port", 8126.0);
+        span.set_tag("retries", &Value::from("many"));
+        span.set_tag("user.id", &Value::from(u64::MAX - 1));
+        span.set_tag("offset", &Value::from(-(1i64 << 60)));
+        span.set_tag("count", &Value::from(1u64 << 53));
+        span.finish();
+
+        let finished = buffer.finished.lock().unwrap();
+        let span = &finished[0];
+        assert_eq!(span.metrics["_dd1.sr.eausr"], 0.5);
+        assert_eq!(span.metrics["cached"], 1.5);
+        assert_eq!(span.metrics["port"], 8126.0);
+        assert_eq!(span.meta["retries"], "many");
+        assert!(!span.metrics.contains_key("ratio"));
+        assert!(!span.meta.contains_key("cached") && !span.meta.contains_key("port"));
+        assert!(!span.metrics.contains_key("retries"));
+        assert_eq!(span.meta["user.id"], "18446744073709551614");
+        assert_eq!(span.meta["offset"], "-1152921504606846976");
+        assert_eq!(span.metrics["count"], (1u64 << 53) as f64);
+    }
+
+    #[test]
+    fn records_logs_as_error_tags_and_events() {
+        let buffer = Arc::new(MockBuffer::default());
+        let mut span = OwnedSpan::new(
+            buffer.clone(),
+            SpanContext::new(1, 1, "", HashMap::new()),
+            SystemTime::now(),
+            Instant::now(),
+            SpanData::default(),
+        );
+        let fields = |fields: &[(&str, Value)]| -> Vec<(String, Value)> {
+            fields
+                .iter()
+                .map(|(key, value)| (String::from(*key), value.clone()))
+                .collect()
+        };
+
+        span.log_at(
+            UNIX_EPOCH + Duration::from_secs(1),
+            &fields(&[
+                ("event", Value::from("cache miss")),
+                ("key", Value::from("user:1")),
+                ("hits", Value::from(0)),
+                ("extra", json!({"shard": 2})),
+            ]),
+        );
+        span.log(&fields(&[
+            ("event", Value::from("error")),
+            ("error.kind", Value::from("Timeout")),
+            ("error.object", Value::from("Error { .. }")),
+            ("message", Value::from("query timed out")),
+            ("stack", Value::from("at main.rs:1")),
+        ]));
+        span.finish();
+
+        let finished = buffer.finished.lock().unwrap();
+        let span = &finished[0];
+        assert_eq!(span.error, 1);
+        assert_eq!(span.meta[ERROR_MSG], "query timed out");
+        assert_eq!(span.meta[ERROR_TYPE], "Timeout");
+        assert_eq!(span.meta[ERROR_STACK], "at main.rs:1");
+        let events: Value = serde_json::from_str(&span.meta[EVENTS]).unwrap();
+        assert_eq!(
+            events,
+            json!([{
+                "name": "cache miss",
+                "time_unix_nano": 1_000_000_000,
+                "attributes": {"key": "user:1", "hits": 0, "extra": r#"{"shard":2}"#},
+            }])
+        );
+    }
+}
@@ -0,0 +1,230 @@
+use super::SpanData;
+use crate::dd::sample::{SampleResult, SamplingPriority};
+use eyre::{eyre, Result};
+use serde_json::{json, Value};
+use std::{collections::HashMap, sync::Arc};
+
+/// Version of the format of saved traces, which are refused by tracers
+/// reading another one.
+const VERSION: u64 = 1;
+
+/// SavedSpan is a span which was running when its trace was saved.
+#[derive(Clone)]
+pub struct SavedSpan {
+    pub data: SpanData,
+    pub baggage: HashMap<String, String>,
+}
+
+/// SavedTrace is the local state of a trace segment, saved by a process to
+/// be restored by another one, e.g. when a job is checkpointed or migrated
+/// mid-trace: the running spans, the spans waiting for them, and what was
+/// decided for the whole segment.
+#[derive(Clone, Default)]
+pub struct SavedTrace {
+    pub trace_id: u64,
+    pub origin: String,
+    pub sampling: Option<SampleResult>,
+    pub trace_tags: HashMap<String, String>,
+    /// Snapshot of the local root as it was when started.
+    pub root: Option<SpanData>,
+    pub partial_version: u32,
+    pub open_spans: Vec<SavedSpan>,
+    pub finished_spans: Vec<SpanData>,
+}
+
+fn encode_span(span: &SpanData) -> Value {
+    json!({
+        "type": &*span.span_type,
+        "service": &*span.service,
+        "resource": &*span.resource,
+        "name": &*span.name,
+        "span_id": span.span_id,
+        "parent_id": span.parent_id,
+        "start": span.start,
+        "duration": span.duration,
+        "error": span.error,
+        "meta": span.meta,
+        "metrics": span.metrics,
+    })
+}
+
+fn decode_span(trace_id: u64, span: &Value) -> Result<SpanData> {
+    let string = |key: &str| -> Arc<str> { Arc::from(span[key].as_str().unwrap_or_default()) };
+    let id = |key: &str| {
+        span[key]
+            .as_u64()
+            .ok_or_else(|| eyre!("Saved span without {}", key))
+    };
+
+    Ok(SpanData {
+        span_type: string("type"),
+        service: string("service"),
+        resource: string("resource"),
+        name: string("name"),
+        trace_id,
+        span_id: id("span_id")?,
+        parent_id: id("parent_id")?,
+        start: span["start"].as_i64().unwrap_or_default(),
+        duration: span["duration"].as_i64().unwrap_or_default(),
+        error: span["error"].as_i64().unwrap_or_default() as i32,
+        meta: serde_json::from_value(span["meta"].clone())?,
+        metrics: serde_json::from_value(span["metrics"].clone())?,
+    })
+}
+
+/// Rates are NaN until they're known, which JSON can't represent.
+fn encode_rate(rate: f64) -> Value {
+    if rate.is_nan() {
+        Value::Null
+    } else {
+        Value::from(rate)
+    }
+}
+
+fn decode_rate(rate: &Value) -> f64 {
+    rate.as_f64().unwrap_or(f64::NAN)
+}
+
+fn encode_sampling(sampling: &SampleResult) -> Value {
+    json!({
+        "priority": sampling.sampling_priority.as_ref().map(SamplingPriority::as_i32),
+        "rule_rate": encode_rate(sampling.rule_rate),
+        "limiter_rate": encode_rate(sampling.limiter_rate),
+        "priority_rate": encode_rate(sampling.priority_rate as f64),
+    })
+}
+
+fn decode_sampling(sampling: &Value) -> SampleResult {
+    SampleResult {
+        rule_rate: decode_rate(&sampling["rule_rate"]),
+        limiter_rate: decode_rate(&sampling["limiter_rate"]),
+        priority_rate: decode_rate(&sampling["priority_rate"]) as f32,
+        sampling_priority: sampling["priority"]
+            .as_i64()
+            .and_then(|priority| SamplingPriority::from_i32(priority as i32)),
+    }
+}
+
+impl SavedTrace {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let open_spans: Vec<Value> = self
+            .open_spans
+            .iter()
+            .map(|span| json!({"span": encode_span(&span.data), "baggage": span.baggage}))
+            .collect();
+        let saved = json!({
+            "version": VERSION,
+            "trace_id": self.trace_id,
+            "origin": self.origin,
+            "sampling": self.sampling.as_ref().map(encode_sampling),
+            "trace_tags": self.trace_tags,
+            "root": self.root.as_ref().map(encode_span),
+            "partial_version": self.partial_version,
+            "open_spans": open_spans,
+            "finished_spans": self.finished_spans.iter().map(encode_span).collect::<Vec<_>>(),
+        });
+
+        saved.to_string().into_bytes()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let saved: Value = serde_json::from_slice(bytes)?;
+        let version = saved.get("version").and_then(Value::as_u64);
+        if version != Some(VERSION) {
+            return Err(eyre!("Unsupported saved trace version {:?}", version));
+        }
+        let trace_id = saved
+            .get("trace_id")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| eyre!("Saved trace without trace id"))?;
+        let spans = |key: &str| -> Vec<Value> {
+            match saved.get(key) {
+                Some(Value::Array(spans)) => spans.clone(),
+                _ => Vec::new(),
+            }
+        };
+
+        Ok(Self {
+            trace_id,
+            origin: String::from(saved["origin"].as_str().unwrap_or_default()),
+            sampling: saved
+                .get("sampling")
+                .filter(|sampling| !sampling.is_null())
+                .map(decode_sampling),
+            trace_tags: serde_json::from_value(saved["trace_tags"].clone())?,
+            root: match saved.get("root") {
+                Some(root) if !root.is_null() => Some(decode_span(trace_id, root)?),
+                _ => None,
+            },
+            partial_version: saved["partial_version"].as_u64().unwrap_or_default() as u32,
+            open_spans: spans("open_spans")
+                .iter()
+                .map(|span| {
+                    Ok(SavedSpan {
+                        data: decode_span(trace_id, &span["span"])?,
+                        baggage: serde_json::from_value(span["baggage"].clone())?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+            finished_spans: spans("finished_spans")
+                .iter()
+                .map(|span| decode_span(trace_id, span))
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_saved_traces() {
+        let span = SpanData {
+            name: Arc::from("checkout"),
+            trace_id: 7,
+            span_id: 8,
+            start: 1_000,
+            meta: vec![(String::from("user"), String::from("42"))]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let saved = SavedTrace {
+            trace_id: 7,
+            origin: String::from("synthetics"),
+            sampling: Some(SampleResult {
+                rule_rate: 0.5,
+                sampling_priority: Some(SamplingPriority::UserKeep),
+                ..SampleResult::new()
+            }),
+            root: Some(span.clone()),
+            open_spans: vec![SavedSpan {
+                data: span,
+                baggage: vec![(String::from("tenant"), String::from("acme"))]
+                    .into_iter()
+                    .collect(),
+            }],
+            ..Default::default()
+        };
+
+        let restored = SavedTrace::from_bytes(&saved.to_bytes()).unwrap();
+        assert_eq!(restored.trace_id, 7);
+        assert_eq!(restored.origin, "synthetics");
+        let sampling = restored.sampling.unwrap();
+        assert_eq!(sampling.rule_rate, 0.5);
+        assert!(sampling.limiter_rate.is_nan());
+        assert_eq!(sampling.sampling_priority, Some(SamplingPriority::UserKeep));
+        assert_eq!(restored.root.unwrap().span_id, 8);
+        let open = &restored.open_spans[0];
+        assert_eq!(&*open.data.name, "checkout");
+        assert_eq!(open.data.trace_id, 7);
+        assert_eq!(open.data.start, 1_000);
+        assert_eq!(open.data.meta["user"], "42");
+        assert_eq!(open.baggage["tenant"], "acme");
+        assert!(restored.finished_spans.is_empty());
+
+        assert!(SavedTrace::from_bytes(b"{\"version\": 2, \"trace_id\": 7}").is_err());
+        assert!(SavedTrace::from_bytes(b"not json").is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use super::SpanContext;
+use std::{cell::RefCell, marker::PhantomData, thread};
+
+thread_local! {
+    /// The contexts of the spans activated on the thread, innermost last.
+    static ACTIVE: RefCell<Vec<SpanContext>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Scope keeps a span context active on the thread while it lives: spans
+/// started without parent nor reference are its children. Dropping it
+/// restores the context which was active before. It can't leave its thread.
+pub struct Scope {
+    depth: usize,
+    _thread: PhantomData<*const ()>,
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        // Scopes dropped out of order also end those activated after them.
+        let _ = ACTIVE.try_with(|active| active.borrow_mut().truncate(self.depth));
+    }
+}
+
+/// Makes `context` the active span context of the thread until the scope
+/// returned is dropped.
+pub fn activate(context: SpanContext) -> Scope {
+    let depth = ACTIVE.with(|active| {
+        let mut active = active.borrow_mut();
+        active.push(context);
+        active.len() - 1
+    });
+
+    Scope {
+        depth,
+        _thread: PhantomData,
+    }
+}
+
+/// Returns the active span context of the thread, if any.
+pub fn active_context() -> Option<SpanContext> {
+    ACTIVE
+        .try_with(|active| active.borrow().last().cloned())
+        .ok()
+        .flatten()
+}
+
+/// ScopedContext carries the active span context of a thread to another,
+/// e.g. from the thread handling a request to the workers of a thread pool,
+/// so that the spans of the work done there are children of the span of
+/// the request rather than roots of traces of their own.
+#[derive(Debug, Clone, Default)]
+pub struct ScopedContext(Option<SpanContext>);
+
+impl ScopedContext {
+    /// Captures the active span context of the calling thread.
+    pub fn capture() -> Self {
+        Self(active_context())
+    }
+
+    /// Makes the captured context active on the calling thread until the
+    /// scope returned is dropped. None if there was no active context.
+    pub fn restore(&self) -> Option<Scope> {
+        self.0.clone().map(activate)
+    }
+
+    /// Returns `work` running in the captured context, e.g. for
+    /// `rayon::spawn` or `ThreadPool::execute`.
+    pub fn wrap<T>(self, work: impl FnOnce() -> T) -> impl FnOnce() -> T {
+        move || {
+            let _scope = self.restore();
+            work()
+        }
+    }
+}
+
+/// Spawns a thread running `work` in the active span context of the
+/// calling thread, as `std::thread::spawn` does otherwise.
+pub fn spawn<T: Send + 'static>(
+    work: impl FnOnce() -> T + Send + 'static,
+) -> thread::JoinHandle<T> {
+    thread::spawn(ScopedContext::capture().wrap(work))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn carries_active_contexts_across_threads() {
+        let request = SpanContext::new(1, 7, "", HashMap::new());
+        let query = SpanContext::new(2, 7, "", HashMap::new());
+        assert!(active_context().is_none());
+
+        let request_scope = activate(request);
+        let query_scope = activate(query);
+        assert_eq!(active_context().unwrap().id(), 2);
+        drop(query_scope);
+        assert_eq!(active_context().unwrap().id(), 1);
+
+        let worker = spawn(|| active_context().map(|context| context.id()));
+        assert_eq!(worker.join().unwrap(), Some(1));
+        let captured = ScopedContext::capture();
+        drop(request_scope);
+        assert!(active_context().is_none());
+        let worker = thread::spawn(captured.wrap(|| active_context().map(|context| context.id())));
+        assert_eq!(worker.join().unwrap(), Some(1));
+        assert!(ScopedContext::capture().restore().is_none());
+    }
+}
@@ -1,5 +1,871 @@
-use crate::dd::span::span_context::SpanContext;
+use super::{
+    nanos_since_epoch, FilterProcessor, OpenSpan, SavedSpan, SavedTrace, SpanContext, SpanData,
+    TraceFilter, TraceProcessor, TraceSegment,
+};
+use crate::dd::{
+    sample::{PrioritySampler, RulesSampler, SampleResult, SamplingPriority},
+    tags::{COLD_START, PEER_SERVICE, PEER_SERVICE_REMAPPED_FROM, TRACER_OVERHEAD},
+    writer::AgentWriter,
+};
+use eyre::{eyre, Result};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
 
-pub(crate) trait SpanBuffer {
-    fn register_span(context: &SpanContext);
+/// How long the end of a serverless invocation waits for its trace to be sent.
+const SERVERLESS_FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn now_nanos() -> i64 {
+    nanos_since_epoch(SystemTime::now())
+}
+
+/// Receives each span as it finishes, before it's buffered, e.g. to add tags
+/// to all the spans of the application.
+pub type SpanFinishHook = Arc<dyn Fn(&mut SpanData) + Send + Sync>;
+
+/// AbandonedTrace is a trace with spans open for longer than expected,
+/// which most likely leaked and will never finish.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct AbandonedTrace {
+    pub trace_id: u64,
+    /// The spans open for too long, the oldest first.
+    pub spans: Vec<OpenSpan>,
+}
+
+impl fmt::Display for AbandonedTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Trace {} has spans which may never finish:",
+            self.trace_id
+        )?;
+        for (i, span) in self.spans.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            write!(f, "{}{} (open for {:?})", separator, span.name, span.age)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// SpanBuffer receives the spans of the tracer as they start and finish.
+pub(crate) trait SpanBuffer: Send + Sync {
+    /// Adds a started span to the segment of its trace, which is returned
+    /// to be shared through the span context.
+    fn register_span(&self, context: &SpanContext, span: &SpanData) -> Result<Arc<TraceSegment>>;
+
+    /// Adds a finished span, completing its trace segment once all of the
+    /// spans registered in it have finished.
+    fn finish_span(&self, span: SpanData) -> Result<()>;
+
+    /// Sends the completed traces, waiting up to `timeout`, and returns how
+    /// many were sent. Traces with unfinished spans stay buffered.
+    fn flush(&self, timeout: Duration) -> Result<usize>;
+
+    /// Returns the sampling priority of a trace segment, deciding it now if
+    /// it hasn't been yet, e.g. before its context is injected.
+    fn assign_sampling_priority(&self, segment: &TraceSegment) -> Result<Option<SamplingPriority>>;
+}
+
+/// MockBuffer records the finished spans, and has nothing to send when
+/// flushed. Traces get no sampling priority unless they inherit one.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct MockBuffer {
+    pub finished: Mutex<Vec<SpanData>>,
+}
+
+#[cfg(test)]
+impl SpanBuffer for MockBuffer {
+    fn register_span(&self, context: &SpanContext, span: &SpanData) -> Result<Arc<TraceSegment>> {
+        let segment = context.trace_segment().cloned().unwrap_or_else(|| {
+            Arc::new(TraceSegment::new(
+                context.trace_id(),
+                context.trace_id_high(),
+                context.origin(),
+                context.propagated_sampling_priority().clone(),
+            ))
+        });
+        segment.register(span)?;
+
+        Ok(segment)
+    }
+
+    fn finish_span(&self, span: SpanData) -> Result<()> {
+        self.finished
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .push(span);
+
+        Ok(())
+    }
+
+    fn flush(&self, _timeout: Duration) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn assign_sampling_priority(&self, segment: &TraceSegment) -> Result<Option<SamplingPriority>> {
+        segment.sample(None)
+    }
+}
+
+/// WritingSpanBuffer collects the spans of each local trace segment and
+/// hands the trace over to the writer once all of its spans have finished.
+///
+/// In serverless mode the trace is also flushed right away, since the
+/// function may be frozen as soon as the invocation returns, and the first
+/// trace is tagged as a cold start.
+///
+/// Spans finished while their local root is still running wait for it,
+/// unless `flush_orphans` is called: they are then written as a chunk of
+/// the trace of their own after a grace period.
+///
+/// Traces go through the processors before they're written, which may drop
+/// them. In propagation-only mode completed traces are dropped instead.
+///
+/// Each segment is sampled once, when its context is first injected or else
+/// when it completes. Segments continuing a propagated context inherit its
+/// sampling priority.
+pub(crate) struct WritingSpanBuffer {
+    /// Tells the segments of this buffer from those of other tracers.
+    id: u64,
+    writer: Arc<AgentWriter>,
+    segments: Mutex<HashMap<u64, Arc<TraceSegment>>>,
+    sampler: Option<Arc<RulesSampler>>,
+    serverless: bool,
+    propagation_only: bool,
+    processors: Vec<Box<dyn TraceProcessor>>,
+    on_span_finish: Option<SpanFinishHook>,
+    /// Services renamed as their spans finish.
+    service_mapping: HashMap<String, Arc<str>>,
+    cold_start: AtomicBool,
+    /// Total overhead of the traces written, if it's measured.
+    overhead: Option<AtomicU64>,
+}
+
+impl WritingSpanBuffer {
+    pub fn new(id: u64, writer: Arc<AgentWriter>, serverless: bool) -> Self {
+        Self {
+            id,
+            writer,
+            segments: Mutex::new(HashMap::new()),
+            sampler: None,
+            serverless,
+            propagation_only: false,
+            processors: Vec::new(),
+            on_span_finish: None,
+            service_mapping: HashMap::new(),
+            cold_start: AtomicBool::new(serverless),
+            overhead: None,
+        }
+    }
+
+    /// Samples the traces with `sampler`. Without one, traces get no
+    /// sampling priority unless they inherit it.
+    pub fn with_sampler(self, sampler: RulesSampler) -> Self {
+        Self {
+            sampler: Some(Arc::new(sampler)),
+            ..self
+        }
+    }
+
+    /// Passes each finished span to `hook` before buffering it.
+    pub fn on_span_finish(self, hook: SpanFinishHook) -> Self {
+        Self {
+            on_span_finish: Some(hook),
+            ..self
+        }
+    }
+
+    /// Renames the services of spans, and their `peer.service` tag, as they
+    /// finish, before the finish hook sees them.
+    pub fn with_service_mapping(self, mapping: &HashMap<String, String>) -> Self {
+        Self {
+            service_mapping: mapping
+                .iter()
+                .map(|(from, to)| (from.clone(), Arc::from(to.as_str())))
+                .collect(),
+            ..self
+        }
+    }
+
+    /// Drops the traces `filter` doesn't keep, after the other processors.
+    pub fn with_filter(self, filter: Arc<dyn TraceFilter>) -> Self {
+        self.with_processor(Box::new(FilterProcessor(filter)))
+    }
+
+    /// Passes the traces to `processor` after the other processors.
+    pub fn with_processor(mut self, processor: Box<dyn TraceProcessor>) -> Self {
+        self.processors.push(processor);
+        self
+    }
+
+    /// Measures the time spent in the tracer for each trace, see
+    /// `TraceSegment::measure_overhead`.
+    pub fn measure_overhead(self) -> Self {
+        Self {
+            overhead: Some(AtomicU64::new(0)),
+            ..self
+        }
+    }
+
+    /// Drops the traces once they complete instead of writing them.
+    pub fn propagation_only(self) -> Self {
+        Self {
+            propagation_only: true,
+            ..self
+        }
+    }
+}
+
+impl WritingSpanBuffer {
+    /// Drops all pending traces without writing them.
+    pub fn clear(&self) -> Result<()> {
+        self.segments
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .clear();
+
+        Ok(())
+    }
+
+    /// Whether `segment` was created by this buffer, rather than by another
+    /// tracer of the process.
+    pub fn owns(&self, segment: &TraceSegment) -> bool {
+        segment.owner() == self.id
+    }
+
+    /// Returns the nanoseconds spent in the tracer for the traces written so
+    /// far, if it's measured.
+    pub fn overhead(&self) -> Option<u64> {
+        self.overhead
+            .as_ref()
+            .map(|overhead| overhead.load(Ordering::Relaxed))
+    }
+
+    /// Returns how many traces have spans still running.
+    pub fn pending_traces(&self) -> Result<usize> {
+        Ok(self
+            .segments
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .len())
+    }
+
+    /// Takes the state of the trace of `segment` out of the buffer, to be
+    /// restored by another process, see `TraceSegment::save`.
+    pub fn save_segment(
+        &self,
+        segment: &TraceSegment,
+        spans: Vec<SavedSpan>,
+    ) -> Result<SavedTrace> {
+        let mut segments = self
+            .segments
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?;
+        let saved = segment.save(spans)?;
+        segments.remove(&segment.trace_id());
+
+        Ok(saved)
+    }
+
+    /// Adds the segment of a trace saved by another process. Fails if spans
+    /// of the trace are already running here.
+    pub fn restore_segment(&self, saved: SavedTrace) -> Result<Arc<TraceSegment>> {
+        let mut segments = self
+            .segments
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?;
+        if segments.contains_key(&saved.trace_id) {
+            return Err(eyre!("Trace {} is already running", saved.trace_id));
+        }
+        let segment = Arc::new(
+            TraceSegment::restore(saved)
+                .owned_by(self.id)
+                .measure_overhead(self.overhead.is_some()),
+        );
+        segments.insert(segment.trace_id(), segment.clone());
+
+        Ok(segment)
+    }
+
+    /// Writes a partial copy of every local root span that has been running
+    /// for at least `min_age`, so long-running work shows up before it
+    /// completes. Each heartbeat bumps the `_dd.partial_version` metric, and
+    /// goes through the processors like the chunks of the trace.
+    pub fn write_heartbeats(&self, min_age: Duration) -> Result<()> {
+        if self.propagation_only {
+            return Ok(());
+        }
+        let segments = self
+            .segments
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?;
+        let now = now_nanos();
+        let mut snapshots = Vec::new();
+        for segment in segments.values() {
+            if let Some(snapshot) = segment.heartbeat(now, min_age, self.sampler.as_deref())? {
+                snapshots.push(snapshot);
+            }
+        }
+        drop(segments);
+
+        for snapshot in snapshots {
+            let mut spans = vec![snapshot];
+            self.process(&mut spans);
+            if !spans.is_empty() {
+                self.writer.write(spans)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Updates the sampler with the rates by service the agent answered
+    /// with.
+    pub fn update_agent_rates(&self, rates: &Value) -> Result<()> {
+        match &self.sampler {
+            Some(sampler) => sampler.update_priority_sampler(rates),
+            None => Ok(()),
+        }
+    }
+
+    /// Replaces the sampling rules of the sampler, see
+    /// `RulesSampler::update_rules`. Fails if priority sampling is disabled.
+    pub fn update_sampling_rules(&self, sampling_rules: &str, sample_rate: f32) -> Result<()> {
+        self.sampler
+            .as_ref()
+            .ok_or_else(|| eyre!("Priority sampling is disabled"))?
+            .update_rules(sampling_rules, sample_rate)
+    }
+
+    /// Samples the trace of `root` before it's started. Without sampler,
+    /// the result has no priority.
+    pub fn sample_root(&self, root: &SpanData) -> Result<SampleResult> {
+        match &self.sampler {
+            Some(sampler) => sampler.sample_root(root),
+            None => Ok(SampleResult::new()),
+        }
+    }
+
+    /// Runs `f` with the sampler of the agent rates. Fails if priority
+    /// sampling is disabled.
+    pub fn with_priority_sampler<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&PrioritySampler) -> Result<T>,
+    {
+        let sampler = self
+            .sampler
+            .as_ref()
+            .ok_or_else(|| eyre!("Priority sampling is disabled"))?;
+
+        f(sampler.priority_sampler())
+    }
+
+    /// Writes the spans which finished at least `grace_period` ago and still
+    /// wait for other spans of their trace, e.g. for a local root which
+    /// never finishes or outlives them by far. They keep their `parent_id`.
+    pub fn flush_orphans(&self, grace_period: Duration) -> Result<()> {
+        let segments = self
+            .segments
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?;
+        let now = now_nanos();
+
+        let mut chunks = Vec::new();
+        for segment in segments.values() {
+            if let Some(chunk) =
+                segment.flush_orphans(now, grace_period, self.sampler.as_deref())?
+            {
+                chunks.push((segment.root_id()?, chunk));
+            }
+        }
+        drop(segments);
+
+        for (root_id, chunk) in chunks {
+            self.write_trace(chunk, root_id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the traces with spans open for at least `max_age`, each of
+    /// them once. With `finish`, all the open spans of these traces are
+    /// finished now, tagged with `_dd.abandoned`, and the traces written;
+    /// finishing the spans afterwards does nothing.
+    pub fn check_abandoned(&self, max_age: Duration, finish: bool) -> Result<Vec<AbandonedTrace>> {
+        let mut segments = self
+            .segments
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?;
+        let now = now_nanos();
+
+        let mut abandoned = Vec::new();
+        let mut completed = Vec::new();
+        for (trace_id, segment) in segments.iter() {
+            let spans = segment.abandoned_spans(now, max_age)?;
+            if spans.is_empty() {
+                continue;
+            }
+            abandoned.push(AbandonedTrace {
+                trace_id: *trace_id,
+                spans,
+            });
+            if finish {
+                let root_id = segment.root_id()?;
+                if let Some(spans) = segment.finish_abandoned(now, self.sampler.as_deref())? {
+                    completed.push((*trace_id, root_id, spans));
+                }
+            }
+        }
+        for (trace_id, _, _) in &completed {
+            segments.remove(trace_id);
+        }
+        drop(segments);
+
+        for (_, root_id, spans) in completed {
+            self.write_trace(spans, root_id)?;
+        }
+
+        Ok(abandoned)
+    }
+
+    fn process(&self, spans: &mut Vec<SpanData>) {
+        for processor in &self.processors {
+            processor.process(spans);
+        }
+    }
+
+    fn write_trace(&self, mut spans: Vec<SpanData>, root_id: Option<u64>) -> Result<()> {
+        if self.propagation_only {
+            return Ok(());
+        }
+        if let Some(overhead) = &self.overhead {
+            let trace_overhead: f64 = spans
+                .iter()
+                .filter_map(|span| span.metrics.get(TRACER_OVERHEAD))
+                .sum();
+            overhead.fetch_add(trace_overhead as u64, Ordering::Relaxed);
+        }
+        self.process(&mut spans);
+        if spans.is_empty() {
+            return Ok(());
+        }
+
+        if self.cold_start.swap(false, Ordering::Relaxed) {
+            if let Some(root) = spans.iter_mut().find(|span| Some(span.span_id) == root_id) {
+                root.metrics.insert(String::from(COLD_START), 1.0);
+            }
+        }
+        self.writer.write(spans)?;
+        if self.serverless {
+            self.writer.flush(SERVERLESS_FLUSH_TIMEOUT)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl SpanBuffer for WritingSpanBuffer {
+    fn register_span(&self, context: &SpanContext, span: &SpanData) -> Result<Arc<TraceSegment>> {
+        let mut segments = self
+            .segments
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?;
+        let segment = segments
+            .entry(context.trace_id())
+            .or_insert_with(|| {
+                // Contexts of local spans share their segment, others were
+                // propagated from another process or tracer.
+                context
+                    .trace_segment()
+                    .filter(|segment| self.owns(segment))
+                    .cloned()
+                    .unwrap_or_else(|| {
+                        Arc::new(
+                            TraceSegment::new(
+                                context.trace_id(),
+                                context.trace_id_high(),
+                                context.origin(),
+                                context.propagated_sampling_priority().clone(),
+                            )
+                            .owned_by(self.id)
+                            .measure_overhead(self.overhead.is_some()),
+                        )
+                    })
+            })
+            .clone();
+        segment.register(span)?;
+
+        Ok(segment)
+    }
+
+    fn finish_span(&self, mut span: SpanData) -> Result<()> {
+        if let Some(service) = self.service_mapping.get(&*span.service) {
+            span.service = service.clone();
+        }
+        let peer_service = span
+            .meta
+            .get(PEER_SERVICE)
+            .and_then(|peer_service| self.service_mapping.get(peer_service));
+        if let Some(peer_service) = peer_service {
+            let peer_service = String::from(&**peer_service);
+            if let Some(from) = span.meta.insert(String::from(PEER_SERVICE), peer_service) {
+                span.meta
+                    .insert(String::from(PEER_SERVICE_REMAPPED_FROM), from);
+            }
+        }
+        if let Some(hook) = &self.on_span_finish {
+            hook(&mut span);
+        }
+        let mut segments = self
+            .segments
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?;
+        let trace_id = span.trace_id;
+        let segment = segments
+            .get(&trace_id)
+            .cloned()
+            .ok_or_else(|| eyre!("Missing trace for finished span"))?;
+        let root_id = segment.root_id()?;
+        let spans = match segment.finish(span, self.sampler.as_deref())? {
+            Some(spans) => spans,
+            None => return Ok(()),
+        };
+        segments.remove(&trace_id);
+        drop(segments);
+
+        self.write_trace(spans, root_id)
+    }
+
+    fn flush(&self, timeout: Duration) -> Result<usize> {
+        self.writer.flush(timeout)
+    }
+
+    fn assign_sampling_priority(&self, segment: &TraceSegment) -> Result<Option<SamplingPriority>> {
+        segment.sample(self.sampler.as_deref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dd::{
+        agent::MockTransport,
+        span::UrlFilter,
+        tags::{ABANDONED, PARTIAL_VERSION, RULE_SAMPLE_RATE, SAMPLING_PRIORITY},
+        writer::Destination,
+    };
+    use serde_json::Value;
+
+    fn run_trace(buffer: &WritingSpanBuffer, trace_id: u64) {
+        let spans = [(trace_id, 0), (trace_id + 1, trace_id)];
+        for (span_id, parent_id) in spans.iter() {
+            let context = SpanContext::new(*span_id, trace_id, "", HashMap::new());
+            let span = SpanData {
+                trace_id,
+                span_id: *span_id,
+                parent_id: *parent_id,
+                ..Default::default()
+            };
+            buffer.register_span(&context, &span).unwrap();
+        }
+        for (span_id, parent_id) in spans.iter().rev() {
+            let span = SpanData {
+                trace_id,
+                span_id: *span_id,
+                parent_id: *parent_id,
+                ..Default::default()
+            };
+            buffer.finish_span(span).unwrap();
+        }
+    }
+
+    #[test]
+    fn serverless_flushes_each_trace_and_tags_cold_start() {
+        let transport = Arc::new(MockTransport::default());
+        let writer = Arc::new(AgentWriter::new(
+            transport.clone(),
+            Destination::Agent,
+            Duration::from_secs(3600),
+        ));
+        let buffer = WritingSpanBuffer::new(1, writer, true);
+
+        run_trace(&buffer, 10);
+        run_trace(&buffer, 20);
+
+        let posts = transport.posts.lock().unwrap();
+        assert_eq!(posts.len(), 2);
+        let cold_starts: Vec<Vec<bool>> = posts
+            .iter()
+            .map(|(_, body)| {
+                let payload: Value = serde_json::from_slice(body).unwrap();
+                payload[0]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|span| span["metrics"].get(COLD_START).is_some())
+                    .collect()
+            })
+            .collect();
+        // Spans are written in finishing order: child first.
+        assert_eq!(cold_starts, vec![vec![false, true], vec![false, false]]);
+    }
+
+    #[test]
+    fn passes_finished_spans_to_hook() {
+        let transport = Arc::new(MockTransport::default());
+        let writer = Arc::new(AgentWriter::new(
+            transport.clone(),
+            Destination::Agent,
+            Duration::from_secs(3600),
+        ));
+        let buffer = WritingSpanBuffer::new(1, writer.clone(), false).on_span_finish(Arc::new(
+            |span: &mut SpanData| {
+                span.meta
+                    .insert(String::from("region"), String::from("eu-west-1"));
+            },
+        ));
+
+        run_trace(&buffer, 10);
+        writer.flush(Duration::from_secs(5)).unwrap();
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+        assert!(traces[0]
+            .iter()
+            .all(|span| span["meta"]["region"] == "eu-west-1"));
+    }
+
+    #[test]
+    fn maps_services_as_spans_finish() {
+        let transport = Arc::new(MockTransport::default());
+        let writer = Arc::new(AgentWriter::new(
+            transport.clone(),
+            Destination::Agent,
+            Duration::from_secs(3600),
+        ));
+        let mapping = vec![(String::from("postgres"), String::from("orders-db"))]
+            .into_iter()
+            .collect();
+        let buffer = WritingSpanBuffer::new(1, writer.clone(), false)
+            .with_service_mapping(&mapping)
+            .on_span_finish(Arc::new(|span: &mut SpanData| {
+                let service = String::from(span.service());
+                span.set_tag("hook.service", &service);
+            }));
+
+        let spans: Vec<SpanData> = [(1, 0, "web"), (2, 1, "postgres")]
+            .iter()
+            .map(|(span_id, parent_id, service)| {
+                let mut span = SpanData {
+                    trace_id: 1,
+                    span_id: *span_id,
+                    parent_id: *parent_id,
+                    service: Arc::from(*service),
+                    ..Default::default()
+                };
+                span.set_tag(PEER_SERVICE, "postgres");
+                let context = SpanContext::new(*span_id, 1, "", HashMap::new());
+                buffer.register_span(&context, &span).unwrap();
+                span
+            })
+            .collect();
+        for span in spans {
+            buffer.finish_span(span).unwrap();
+        }
+        writer.flush(Duration::from_secs(5)).unwrap();
+
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+        let services: Vec<_> = traces[0]
+            .iter()
+            .map(|span| (&span["service"], &span["meta"]["hook.service"]))
+            .collect();
+        assert_eq!(
+            services,
+            vec![
+                (&Value::from("web"), &Value::from("web")),
+                (&Value::from("orders-db"), &Value::from("orders-db"))
+            ]
+        );
+        assert!(traces[0]
+            .iter()
+            .all(|span| span["meta"][PEER_SERVICE] == "orders-db"
+                && span["meta"][PEER_SERVICE_REMAPPED_FROM] == "postgres"));
+    }
+
+    #[test]
+    fn samples_each_trace_once() {
+        let transport = Arc::new(MockTransport::default());
+        let writer = Arc::new(AgentWriter::new(
+            transport.clone(),
+            Destination::Agent,
+            Duration::from_secs(3600),
+        ));
+        let sampler = RulesSampler::from_config("[]", 0.0).unwrap();
+        let buffer = WritingSpanBuffer::new(1, writer.clone(), false).with_sampler(sampler);
+
+        let root = SpanContext::new(1, 1, "", HashMap::new());
+        let span = SpanData {
+            trace_id: 1,
+            span_id: 1,
+            ..Default::default()
+        };
+        let segment = buffer.register_span(&root, &span).unwrap();
+        for _ in 0..2 {
+            assert_eq!(
+                buffer.assign_sampling_priority(&segment).unwrap(),
+                Some(SamplingPriority::SamplerDrop)
+            );
+        }
+
+        let mut inherited = SpanContext::new(2, 2, "", HashMap::new());
+        inherited.set_propagated_sampling_priority(Some(SamplingPriority::UserKeep));
+        let segment = buffer
+            .register_span(&inherited, &SpanData::default())
+            .unwrap();
+        assert_eq!(
+            buffer.assign_sampling_priority(&segment).unwrap(),
+            Some(SamplingPriority::UserKeep)
+        );
+
+        run_trace(&buffer, 10);
+        writer.flush(Duration::from_secs(5)).unwrap();
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+        let priorities: Vec<Option<f64>> = traces[0]
+            .iter()
+            .map(|span| span["metrics"][SAMPLING_PRIORITY].as_f64())
+            .collect();
+        assert_eq!(priorities, vec![None, Some(0.0)]);
+        assert_eq!(traces[0][1]["metrics"][RULE_SAMPLE_RATE], 0.0);
+    }
+
+    #[test]
+    fn filters_and_samples_heartbeats() {
+        let transport = Arc::new(MockTransport::default());
+        let writer = Arc::new(AgentWriter::new(
+            transport.clone(),
+            Destination::Agent,
+            Duration::from_secs(3600),
+        ));
+        let sampler = RulesSampler::from_config("[]", 0.0).unwrap();
+        let buffer = WritingSpanBuffer::new(1, writer.clone(), false)
+            .with_sampler(sampler)
+            .with_filter(Arc::new(UrlFilter::new(vec![String::from("/healthz")])));
+
+        for (trace_id, url) in [(1, "http://app/orders"), (2, "http://app/healthz")].iter() {
+            let context = SpanContext::new(*trace_id, *trace_id, "", HashMap::new());
+            let mut span = SpanData {
+                trace_id: *trace_id,
+                span_id: *trace_id,
+                start: 1,
+                ..Default::default()
+            };
+            span.set_tag("http.url", url);
+            buffer.register_span(&context, &span).unwrap();
+        }
+        buffer.write_heartbeats(Duration::from_secs(1)).unwrap();
+        writer.flush(Duration::from_secs(5)).unwrap();
+
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+        assert_eq!(traces.len(), 1);
+        let heartbeat = &traces[0][0];
+        assert_eq!(heartbeat["trace_id"], 1);
+        assert_eq!(heartbeat["metrics"][PARTIAL_VERSION], 1.0);
+        assert_eq!(heartbeat["metrics"][SAMPLING_PRIORITY], 0.0);
+    }
+
+    #[test]
+    fn writes_orphans_as_chunks() {
+        let transport = Arc::new(MockTransport::default());
+        let writer = Arc::new(AgentWriter::new(
+            transport.clone(),
+            Destination::Agent,
+            Duration::from_secs(3600),
+        ));
+        let buffer = WritingSpanBuffer::new(1, writer.clone(), false);
+
+        let context = SpanContext::new(1, 1, "", HashMap::new());
+        let started = |span_id: u64, parent_id: u64| SpanData {
+            trace_id: 1,
+            span_id,
+            parent_id,
+            start: now_nanos() - 60_000_000_000,
+            ..Default::default()
+        };
+        buffer.register_span(&context, &started(1, 0)).unwrap();
+        buffer.register_span(&context, &started(2, 1)).unwrap();
+        buffer.finish_span(started(2, 1)).unwrap();
+
+        buffer.flush_orphans(Duration::from_secs(120)).unwrap();
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 0);
+        buffer.flush_orphans(Duration::from_secs(30)).unwrap();
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 1);
+        buffer.finish_span(started(1, 0)).unwrap();
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 1);
+
+        let posts = transport.posts.lock().unwrap();
+        let chunks: Vec<(u64, u64)> = posts
+            .iter()
+            .flat_map(|(_, body)| serde_json::from_slice::<Vec<Vec<Value>>>(body).unwrap())
+            .flatten()
+            .map(|span| {
+                (
+                    span["span_id"].as_u64().unwrap(),
+                    span["parent_id"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(chunks, vec![(2, 1), (1, 0)]);
+    }
+
+    #[test]
+    fn finishes_abandoned_traces() {
+        let transport = Arc::new(MockTransport::default());
+        let writer = Arc::new(AgentWriter::new(
+            transport.clone(),
+            Destination::Agent,
+            Duration::from_secs(3600),
+        ));
+        let buffer = WritingSpanBuffer::new(1, writer.clone(), false);
+
+        let context = SpanContext::new(1, 1, "", HashMap::new());
+        let span = SpanData {
+            name: Arc::from("request"),
+            trace_id: 1,
+            span_id: 1,
+            start: now_nanos() - 60_000_000_000,
+            ..Default::default()
+        };
+        buffer.register_span(&context, &span).unwrap();
+        assert!(buffer
+            .check_abandoned(Duration::from_secs(120), true)
+            .unwrap()
+            .is_empty());
+
+        let abandoned = buffer
+            .check_abandoned(Duration::from_secs(30), true)
+            .unwrap();
+        assert_eq!(abandoned.len(), 1);
+        assert!(abandoned[0]
+            .to_string()
+            .starts_with("Trace 1 has spans which may never finish: request (open for 60"));
+        // Finishing the span late doesn't write it again.
+        let _ = buffer.finish_span(span);
+
+        writer.flush(Duration::from_secs(5)).unwrap();
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+        assert_eq!(traces.len(), 1);
+        assert_eq!(traces[0][0]["meta"][ABANDONED], "true");
+    }
 }
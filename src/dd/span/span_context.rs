@@ -1,15 +1,50 @@
-use crate::{dd::sample::SamplingPriority, opentracing};
+use super::TraceSegment;
+use crate::{
+    dd::sample::SamplingPriority,
+    opentracing,
+    propagation::{PropagatedContext, PROPAGATED_TAG_PREFIX},
+};
 use eyre::{eyre, Result};
-use std::{collections::HashMap, sync::Mutex};
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
 
-pub(crate) struct SpanContext {
+/// BaggageLimits bound the baggage of a trace, which is sent with every
+/// request made within it. Items beyond them are dropped, and values longer
+/// than `max_value_length` bytes are truncated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BaggageLimits {
+    pub max_items: usize,
+    pub max_key_length: usize,
+    pub max_value_length: usize,
+}
+
+impl Default for BaggageLimits {
+    fn default() -> Self {
+        Self {
+            max_items: 64,
+            max_key_length: 128,
+            max_value_length: 1024,
+        }
+    }
+}
+
+pub struct SpanContext {
     nginx_opentracing_compatibility_hack: bool,
     propagated_sampling_priority: Option<SamplingPriority>,
     id: u64,
     trace_id: u64,
+    /// Upper 64 bits of 128-bit trace ids, 0 for 64-bit ones.
+    trace_id_high: u64,
     origin: String,
+    /// Segment of the local span, None for propagated contexts.
+    trace_segment: Option<Arc<TraceSegment>>,
 
     baggage: Mutex<HashMap<String, String>>,
+    baggage_limits: BaggageLimits,
 }
 
 impl SpanContext {
@@ -24,8 +59,11 @@ impl SpanContext {
             propagated_sampling_priority: None,
             id,
             trace_id,
+            trace_id_high: 0,
             origin: String::from(origin),
+            trace_segment: None,
             baggage: Mutex::new(baggage),
+            baggage_limits: BaggageLimits::default(),
         }
     }
 
@@ -40,6 +78,69 @@ impl SpanContext {
         }
     }
 
+    /// Creates the context of a span continuing a propagated trace; its id is
+    /// the caller's until the child span is started.
+    pub fn from_propagated(context: PropagatedContext) -> SpanContext {
+        let mut span_context = SpanContext::new(
+            context.parent_id,
+            context.trace_id,
+            &context.origin,
+            context.baggage.into_iter().collect(),
+        );
+        span_context.propagated_sampling_priority = context.sampling_priority;
+        span_context.trace_id_high = context.trace_id_high;
+
+        span_context
+    }
+
+    /// Returns what has to be propagated to continue the trace from this
+    /// span, along with the `_dd.p.*` tags of its trace segment.
+    pub fn to_propagated(
+        &self,
+        sampling_priority: Option<&SamplingPriority>,
+    ) -> Result<PropagatedContext> {
+        let trace_tags = match &self.trace_segment {
+            Some(segment) => segment
+                .trace_tags()?
+                .into_iter()
+                .filter(|(key, _)| key.starts_with(PROPAGATED_TAG_PREFIX))
+                .collect(),
+            None => Default::default(),
+        };
+        let data = self
+            .baggage
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?;
+
+        Ok(PropagatedContext {
+            trace_id: self.trace_id,
+            trace_id_high: self.trace_id_high,
+            parent_id: self.id,
+            sampling_priority: sampling_priority.cloned(),
+            origin: self.origin.clone(),
+            baggage: data
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+            trace_tags,
+        })
+    }
+
+    /// Encodes the context as a single string, see
+    /// `PropagatedContext::to_token`.
+    pub fn to_string_token(&self) -> Result<String> {
+        Ok(self
+            .to_propagated(self.propagated_sampling_priority.as_ref())?
+            .to_token())
+    }
+
+    /// Decodes a context encoded by `to_string_token`.
+    pub fn from_string_token(token: &str) -> Result<SpanContext> {
+        PropagatedContext::from_token(token)
+            .map(SpanContext::from_propagated)
+            .map_err(|error| eyre!("{}", error))
+    }
+
     pub fn id(&self) -> u64 {
         self.id
     }
@@ -48,23 +149,79 @@ impl SpanContext {
         self.trace_id
     }
 
+    pub fn trace_id_high(&self) -> u64 {
+        self.trace_id_high
+    }
+
+    pub fn set_trace_id_high(&mut self, trace_id_high: u64) {
+        self.trace_id_high = trace_id_high;
+    }
+
     pub fn propagated_sampling_priority(&self) -> &Option<SamplingPriority> {
         &self.propagated_sampling_priority
     }
 
+    pub fn set_propagated_sampling_priority(&mut self, priority: Option<SamplingPriority>) {
+        self.propagated_sampling_priority = priority;
+    }
+
     pub fn origin(&self) -> &str {
         &self.origin
     }
 
-    pub fn set_baggage_item(&mut self, key: &str, value: &str) -> Result<()> {
+    pub(crate) fn trace_segment(&self) -> Option<&Arc<TraceSegment>> {
+        self.trace_segment.as_ref()
+    }
+
+    pub(crate) fn set_trace_segment(&mut self, trace_segment: Arc<TraceSegment>) {
+        self.trace_segment = Some(trace_segment);
+    }
+
+    pub fn set_baggage_limits(&mut self, limits: BaggageLimits) {
+        self.baggage_limits = limits;
+    }
+
+    /// Sets a baggage item within the baggage limits. Keys must be printable
+    /// ASCII, as they're sent as header names. Returns a warning if the item
+    /// was dropped or its value truncated.
+    pub fn set_baggage_item(&mut self, key: &str, value: &str) -> Result<Option<String>> {
+        let limits = &self.baggage_limits;
         let mut data = self
             .baggage
             .lock()
             .map_err(|_| eyre!("mutex lock failed"))?;
 
-        data.insert(String::from(key), String::from(value));
+        if key.is_empty() || !key.bytes().all(|byte| byte.is_ascii_graphic()) {
+            return Ok(Some(format!(
+                "Dropped baggage item {:?}: keys must be printable ASCII",
+                key
+            )));
+        }
+        if key.len() > limits.max_key_length {
+            return Ok(Some(format!(
+                "Dropped baggage item {:?}: keys are limited to {} bytes",
+                key, limits.max_key_length
+            )));
+        }
+        if !data.contains_key(key) && data.len() >= limits.max_items {
+            return Ok(Some(format!(
+                "Dropped baggage item {:?}: baggage is limited to {} items",
+                key, limits.max_items
+            )));
+        }
 
-        Ok(())
+        let mut length = value.len().min(limits.max_value_length);
+        while !value.is_char_boundary(length) {
+            length -= 1;
+        }
+        data.insert(String::from(key), String::from(&value[..length]));
+
+        Ok((length < value.len()).then(|| {
+            format!(
+                "Truncated baggage item {:?}: values are limited to {} bytes",
+                key, limits.max_value_length
+            )
+        }))
     }
 
     pub fn baggage_item(&self, key: &str) -> Result<Option<String>> {
@@ -73,10 +230,29 @@ impl SpanContext {
             .lock()
             .map_err(|_| eyre!("mutex lock failed"))?;
 
-        Ok(match data.get(key) {
-            Some(str) => Some(str.clone()),
-            None => None,
-        })
+        Ok(data.get(key).cloned())
+    }
+
+    pub fn remove_baggage_item(&mut self, key: &str) -> Result<Option<String>> {
+        let mut data = self
+            .baggage
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?;
+
+        Ok(data.remove(key))
+    }
+
+    /// Returns a copy of the baggage items.
+    pub fn baggage_items(&self) -> Result<Vec<(String, String)>> {
+        let data = self
+            .baggage
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?;
+
+        Ok(data
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
     }
 
     pub fn with_id(&self, id: u64) -> Result<SpanContext> {
@@ -88,16 +264,60 @@ impl SpanContext {
         let baggage = data.clone();
         let mut context = SpanContext::new(id, self.trace_id, &self.origin, baggage);
         context.propagated_sampling_priority = self.propagated_sampling_priority.clone();
+        context.trace_id_high = self.trace_id_high;
+        context.trace_segment = self.trace_segment.clone();
+        context.baggage_limits = self.baggage_limits;
 
         Ok(context)
     }
 }
 
+impl Clone for SpanContext {
+    fn clone(&self) -> SpanContext {
+        let baggage = self
+            .baggage
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone();
+
+        Self {
+            nginx_opentracing_compatibility_hack: self.nginx_opentracing_compatibility_hack,
+            propagated_sampling_priority: self.propagated_sampling_priority.clone(),
+            id: self.id,
+            trace_id: self.trace_id,
+            trace_id_high: self.trace_id_high,
+            origin: self.origin.clone(),
+            trace_segment: self.trace_segment.clone(),
+            baggage: Mutex::new(baggage),
+            baggage_limits: self.baggage_limits,
+        }
+    }
+}
+
+// The trace segment is left out: it holds the spans of the whole trace.
+impl fmt::Debug for SpanContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let baggage = self
+            .baggage
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        f.debug_struct("SpanContext")
+            .field("id", &self.id)
+            .field("trace_id", &self.trace_id)
+            .field("trace_id_high", &self.trace_id_high)
+            .field("origin", &self.origin)
+            .field(
+                "propagated_sampling_priority",
+                &self.propagated_sampling_priority,
+            )
+            .field("local", &self.trace_segment.is_some())
+            .field("baggage", &*baggage)
+            .finish_non_exhaustive()
+    }
+}
+
 impl opentracing::SpanContext for SpanContext {
-    fn foreach_baggage_item<F>(&self, f: F) -> Result<()>
-    where
-        F: Fn(&str, &str) -> bool,
-    {
+    fn foreach_baggage_item(&self, f: &mut dyn FnMut(&str, &str) -> bool) -> Result<()> {
         let data = self
             .baggage
             .lock()
@@ -111,4 +331,101 @@ impl opentracing::SpanContext for SpanContext {
 
         Ok(())
     }
+
+    fn baggage_len(&self) -> usize {
+        self.baggage.lock().map_or(0, |data| data.len())
+    }
+
+    fn iter(&self) -> std::vec::IntoIter<(String, String)> {
+        self.baggage_items().unwrap_or_default().into_iter()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn string_token_round_trip() {
+        let mut context = SpanContext::new(0xabc, 0x1234, "synthetics", HashMap::new());
+        context.set_propagated_sampling_priority(Some(SamplingPriority::UserKeep));
+        context.set_baggage_item("user", "42").unwrap();
+
+        let token = context.to_string_token().unwrap();
+        assert_eq!(token, "1234:abc:2:synthetics;user=42");
+        let decoded = SpanContext::from_string_token(&token).unwrap();
+        assert_eq!((decoded.trace_id(), decoded.id()), (0x1234, 0xabc));
+        assert_eq!(
+            decoded.propagated_sampling_priority(),
+            &Some(SamplingPriority::UserKeep)
+        );
+        assert_eq!(decoded.origin(), "synthetics");
+        assert_eq!(
+            decoded.baggage_item("user").unwrap(),
+            Some(String::from("42"))
+        );
+
+        assert!(SpanContext::from_string_token("not a token").is_err());
+    }
+
+    #[test]
+    fn iterates_and_removes_baggage() {
+        use opentracing::SpanContext as _;
+
+        let mut context = SpanContext::new(1, 1, "", HashMap::new());
+        context.set_baggage_item("user", "42").unwrap();
+        context.set_baggage_item("team", "apm").unwrap();
+        let dyn_context: &dyn opentracing::SpanContext = &context;
+        assert_eq!(dyn_context.baggage_len(), 2);
+        let mut items: Vec<_> = dyn_context.iter().collect();
+        items.sort();
+        assert_eq!(
+            items,
+            vec![
+                (String::from("team"), String::from("apm")),
+                (String::from("user"), String::from("42"))
+            ]
+        );
+
+        assert_eq!(
+            context.remove_baggage_item("user").unwrap(),
+            Some(String::from("42"))
+        );
+        assert_eq!(context.remove_baggage_item("user").unwrap(), None);
+        assert_eq!(context.baggage_len(), 1);
+    }
+
+    #[test]
+    fn limits_baggage() {
+        let mut context = SpanContext::new(1, 1, "", HashMap::new());
+        context.set_baggage_limits(BaggageLimits {
+            max_items: 2,
+            max_key_length: 8,
+            max_value_length: 4,
+        });
+
+        assert_eq!(context.set_baggage_item("user", "42").unwrap(), None);
+        assert!(context.set_baggage_item("usér", "1").unwrap().is_some());
+        assert!(context.set_baggage_item("a b", "1").unwrap().is_some());
+        assert!(context.set_baggage_item("", "1").unwrap().is_some());
+        assert!(context
+            .set_baggage_item("much_too_long", "1")
+            .unwrap()
+            .is_some());
+        assert!(context.set_baggage_item("team", "apmé").unwrap().is_some());
+        assert_eq!(
+            context.baggage_item("team").unwrap(),
+            Some(String::from("apm"))
+        );
+        assert!(context.set_baggage_item("region", "eu").unwrap().is_some());
+        assert_eq!(context.baggage_item("region").unwrap(), None);
+
+        let child = context.with_id(2).unwrap();
+        assert_eq!(child.baggage_limits, context.baggage_limits);
+        assert_eq!(context.set_baggage_item("user", "43").unwrap(), None);
+    }
 }
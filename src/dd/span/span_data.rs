@@ -1,20 +1,45 @@
 use crate::dd::tags::ENVIRONMENT;
-use std::collections::HashMap;
-
-#[derive(Default)]
-pub(crate) struct SpanData {
-    pub span_type: String,
-    pub service: String,
-    pub resource: String,
-    pub name: String,
-    pub trace_id: u64,
-    pub span_id: u64,
-    pub parent_id: u64,
-    pub start_id: i64,
-    pub duration: i64,
-    pub error: i32,
-    pub meta: HashMap<String, String>,
-    pub metrics: HashMap<String, f64>,
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Converts a time to nanoseconds since the Unix epoch, as span starts are
+/// sent. Times before the epoch are 0, and times after 2262 saturate.
+pub(crate) fn nanos_since_epoch(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(duration_nanos)
+        .unwrap_or_default()
+}
+
+/// Converts a duration to nanoseconds, as span durations are sent.
+/// Durations beyond 292 years saturate.
+pub(crate) fn duration_nanos(duration: Duration) -> i64 {
+    i64::try_from(duration.as_nanos()).unwrap_or(i64::MAX)
+}
+
+/// SpanData is a span as sent to the agent. Filters, processors and finish
+/// hooks see it through its accessors: they may change its resource,
+/// service, tags and error, but not its ids or timing.
+#[derive(Default, Clone)]
+#[non_exhaustive]
+pub struct SpanData {
+    pub(crate) span_type: Arc<str>,
+    pub(crate) service: Arc<str>,
+    pub(crate) resource: Arc<str>,
+    pub(crate) name: Arc<str>,
+    pub(crate) trace_id: u64,
+    pub(crate) span_id: u64,
+    pub(crate) parent_id: u64,
+    /// Nanoseconds since the Unix epoch.
+    pub(crate) start: i64,
+    /// Nanoseconds.
+    pub(crate) duration: i64,
+    pub(crate) error: i32,
+    pub(crate) meta: HashMap<String, String>,
+    pub(crate) metrics: HashMap<String, f64>,
 }
 
 impl SpanData {
@@ -24,4 +49,130 @@ impl SpanData {
             None => "".to_owned(),
         }
     }
+
+    pub fn span_type(&self) -> &str {
+        &self.span_type
+    }
+
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    pub fn resource(&self) -> &str {
+        &self.resource
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn trace_id(&self) -> u64 {
+        self.trace_id
+    }
+
+    pub fn span_id(&self) -> u64 {
+        self.span_id
+    }
+
+    /// 0 for the roots of traces.
+    pub fn parent_id(&self) -> u64 {
+        self.parent_id
+    }
+
+    /// Returns the start of the span in nanoseconds since the Unix epoch.
+    pub fn start(&self) -> i64 {
+        self.start
+    }
+
+    /// Returns the duration of the span in nanoseconds.
+    pub fn duration(&self) -> i64 {
+        self.duration
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.error != 0
+    }
+
+    /// Returns the string tags of the span.
+    pub fn meta(&self) -> &HashMap<String, String> {
+        &self.meta
+    }
+
+    /// Returns the numeric tags of the span.
+    pub fn metrics(&self) -> &HashMap<String, f64> {
+        &self.metrics
+    }
+
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.meta.get(key).map(String::as_str)
+    }
+
+    pub fn metric(&self, key: &str) -> Option<f64> {
+        self.metrics.get(key).copied()
+    }
+
+    pub fn set_service(&mut self, service: &str) {
+        self.service = Arc::from(service);
+    }
+
+    pub fn set_resource(&mut self, resource: &str) {
+        self.resource = Arc::from(resource);
+    }
+
+    pub fn set_error(&mut self, error: bool) {
+        self.error = error as i32;
+    }
+
+    /// Sets a string tag, replacing the metric of the same key if any.
+    pub fn set_tag(&mut self, key: &str, value: &str) {
+        self.metrics.remove(key);
+        self.meta.insert(String::from(key), String::from(value));
+    }
+
+    /// Sets a metric, replacing the string tag of the same key if any.
+    pub fn set_metric(&mut self, key: &str, value: f64) {
+        self.meta.remove(key);
+        self.metrics.insert(String::from(key), value);
+    }
+
+    /// Removes the string tag or metric `key`.
+    pub fn remove_tag(&mut self, key: &str) {
+        self.meta.remove(key);
+        self.metrics.remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_are_either_strings_or_metrics() {
+        let mut span = SpanData::default();
+        span.set_tag("http.status_code", "200");
+        assert_eq!(span.tag("http.status_code"), Some("200"));
+
+        span.set_metric("http.status_code", 200.0);
+        assert_eq!(span.tag("http.status_code"), None);
+        assert_eq!(span.metric("http.status_code"), Some(200.0));
+
+        span.set_tag("http.status_code", "200");
+        assert_eq!(span.metric("http.status_code"), None);
+        span.remove_tag("http.status_code");
+        assert!(span.meta().is_empty() && span.metrics().is_empty());
+    }
+
+    #[test]
+    fn converts_times_at_the_epoch_boundaries() {
+        let nanosecond = Duration::from_nanos(1);
+        assert_eq!(nanos_since_epoch(UNIX_EPOCH), 0);
+        assert_eq!(nanos_since_epoch(UNIX_EPOCH + nanosecond), 1);
+        assert_eq!(nanos_since_epoch(UNIX_EPOCH - nanosecond), 0);
+        let last = UNIX_EPOCH + Duration::from_nanos(i64::MAX as u64);
+        assert_eq!(nanos_since_epoch(last), i64::MAX);
+        assert_eq!(nanos_since_epoch(last + nanosecond), i64::MAX);
+
+        assert_eq!(duration_nanos(Duration::from_secs(2)), 2_000_000_000);
+        assert_eq!(duration_nanos(Duration::from_secs(u64::MAX)), i64::MAX);
+    }
 }
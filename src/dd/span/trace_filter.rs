@@ -0,0 +1,111 @@
+use super::{SpanData, TraceProcessor};
+use crate::dd::utils::glob_match;
+use std::sync::Arc;
+
+/// TraceFilter decides which traces are sent, after sampling, e.g. to drop
+/// the health checks of a load balancer which would use up the ingestion
+/// quota. Filters see each chunk of a trace once it's written.
+pub trait TraceFilter: Send + Sync {
+    /// Returns false to drop `spans`.
+    fn keep(&self, spans: &[SpanData]) -> bool;
+}
+
+/// Runs a TraceFilter as a TraceProcessor.
+pub(crate) struct FilterProcessor(pub Arc<dyn TraceFilter>);
+
+impl TraceProcessor for FilterProcessor {
+    fn process(&self, spans: &mut Vec<SpanData>) {
+        if !self.0.keep(spans) {
+            spans.clear();
+        }
+    }
+}
+
+const HTTP_URL: &str = "http.url";
+
+/// Returns the path of an `http.url` tag, without scheme, host or query.
+fn url_path(url: &str) -> &str {
+    let path = match url.find("://") {
+        Some(scheme_end) => {
+            let host = &url[scheme_end + 3..];
+            host.find('/').map_or("/", |path_start| &host[path_start..])
+        }
+        None => url,
+    };
+
+    path.split(['?', '#']).next().unwrap_or(path)
+}
+
+/// UrlFilter drops the traces whose local root span succeeded and either
+/// has the path of its `http.url` tag or its resource matching one of its
+/// glob patterns, e.g. `/healthz` or `GET /health*`. Failed requests are
+/// kept.
+pub struct UrlFilter {
+    patterns: Vec<String>,
+}
+
+impl UrlFilter {
+    pub fn new(patterns: Vec<String>) -> Self {
+        Self { patterns }
+    }
+
+    fn matches(&self, value: &str) -> bool {
+        self.patterns
+            .iter()
+            .any(|pattern| glob_match(pattern, value))
+    }
+}
+
+impl TraceFilter for UrlFilter {
+    fn keep(&self, spans: &[SpanData]) -> bool {
+        // The local root, or the first span of chunks of orphans.
+        let root = spans
+            .iter()
+            .find(|span| !spans.iter().any(|parent| parent.span_id == span.parent_id));
+        let root = match root {
+            Some(root) if root.error == 0 => root,
+            _ => return true,
+        };
+
+        let url_matches = root
+            .meta
+            .get(HTTP_URL)
+            .is_some_and(|url| self.matches(url_path(url)));
+        !url_matches && !self.matches(&root.resource)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_matching_urls_and_resources() {
+        let filter = UrlFilter::new(vec![String::from("/health*"), String::from("GET /ping")]);
+        let root = |url: &str, resource: &str| {
+            let mut root = SpanData {
+                span_id: 1,
+                resource: Arc::from(resource),
+                ..Default::default()
+            };
+            root.meta.insert(String::from(HTTP_URL), String::from(url));
+            root
+        };
+
+        assert!(!filter.keep(&[root("http://web:8080/healthz?full=1", "GET")]));
+        assert!(!filter.keep(&[root("", "GET /ping")]));
+        assert!(filter.keep(&[root("https://web/users", "GET /users")]));
+        assert!(filter.keep(&[SpanData {
+            error: 1,
+            ..root("/healthz", "GET")
+        }]));
+
+        let child = SpanData {
+            span_id: 2,
+            parent_id: 1,
+            ..root("/healthz", "GET /healthz")
+        };
+        assert!(filter.keep(&[child.clone(), root("/users", "GET /users")]));
+        assert!(!filter.keep(&[child, root("/health", "GET")]));
+    }
+}
@@ -0,0 +1,9 @@
+use super::SpanData;
+
+/// TraceProcessor receives the spans of each chunk of a trace once it's
+/// written, after sampling and before encoding, and may change or remove
+/// them, e.g. to scrub sensitive tags or add tags known once the trace
+/// completes. Removing all the spans drops the chunk.
+pub trait TraceProcessor: Send + Sync {
+    fn process(&self, spans: &mut Vec<SpanData>);
+}
@@ -0,0 +1,713 @@
+use super::{duration_nanos, SavedSpan, SavedTrace, SpanData};
+use crate::{
+    dd::{
+        sample::{RulesSampler, SampleResult, SamplingPriority},
+        tags::{
+            ABANDONED, AGENT_SAMPLE_RATE, DECISION_MAKER, ERROR_SAMPLING_MECHANISM,
+            LATENCY_SAMPLING_MECHANISM, LIMIT_SAMPLE_RATE, ORIGIN, PARTIAL_VERSION,
+            RULE_SAMPLE_RATE, SAMPLING_PRIORITY, TRACER_OVERHEAD,
+        },
+    },
+    propagation::TRACE_ID_HIGH_TAG,
+};
+use eyre::{eyre, Result};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+#[derive(Default)]
+struct TraceSegmentData {
+    /// Snapshots of the spans started and not finished yet.
+    open_spans: HashMap<u64, SpanData>,
+    finished_spans: Vec<SpanData>,
+    /// Snapshot of the local root as it was when started, used for heartbeats.
+    root: Option<SpanData>,
+    partial_version: u32,
+    /// Made once for the whole segment, see `TraceSegment::sample`.
+    sampling: Option<SampleResult>,
+    trace_tags: HashMap<String, String>,
+    /// Number of spans registered, including the finished ones.
+    span_count: usize,
+    /// Whether the segment was reported as abandoned.
+    abandoned: bool,
+}
+
+impl TraceSegmentData {
+    fn root_finished(&self, root: &SpanData) -> bool {
+        !self.open_spans.contains_key(&root.span_id)
+    }
+
+    fn sample(
+        &mut self,
+        sampler: Option<&RulesSampler>,
+        root: &SpanData,
+    ) -> Result<Option<SamplingPriority>> {
+        if self.sampling.is_none() {
+            if let Some(sampler) = sampler {
+                self.sampling = Some(sampler.sample_root(root)?);
+            }
+        }
+
+        Ok(self
+            .sampling
+            .as_ref()
+            .and_then(|sampling| sampling.sampling_priority.clone()))
+    }
+
+    /// Turns a sampler drop into a keep if `spans` have errors and the
+    /// sampler keeps them, or if their finished local `root` is slow enough
+    /// for the latency rule.
+    fn keep_dropped(
+        &mut self,
+        sampler: Option<&RulesSampler>,
+        spans: &[SpanData],
+        root: Option<&SpanData>,
+    ) -> Result<()> {
+        let (sampler, sampling) = match (sampler, self.sampling.as_mut()) {
+            (Some(sampler), Some(sampling))
+                if sampling.sampling_priority == Some(SamplingPriority::SamplerDrop) =>
+            {
+                (sampler, sampling)
+            }
+            _ => return Ok(()),
+        };
+        let mechanism = if sampler.error_sampling() && spans.iter().any(|span| span.error != 0) {
+            ERROR_SAMPLING_MECHANISM
+        } else if root.map_or(Ok(false), |root| sampler.keeps_slow(root))? {
+            LATENCY_SAMPLING_MECHANISM
+        } else {
+            return Ok(());
+        };
+
+        sampling.sampling_priority = Some(SamplingPriority::SamplerKeep);
+        self.trace_tags
+            .insert(String::from(DECISION_MAKER), String::from(mechanism));
+
+        Ok(())
+    }
+
+    /// Adds a finished span, returning whether the segment is complete.
+    /// Spans which aren't open, e.g. finished as abandoned, are dropped.
+    fn finish(&mut self, span: SpanData) -> bool {
+        if self.open_spans.remove(&span.span_id).is_none() {
+            return false;
+        }
+        self.finished_spans.push(span);
+
+        self.open_spans.is_empty()
+    }
+}
+
+/// OpenSpan is a span of a segment which hasn't finished, as reported by
+/// `TraceSegment::abandoned_spans`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct OpenSpan {
+    pub name: String,
+    pub age: Duration,
+}
+
+/// TraceSegment is the part of a trace running in this process. It's shared
+/// by all of its local spans through their contexts, and holds what is
+/// decided once for all of them: the sampling decision, the origin and the
+/// trace tags, along with the spans waiting for the segment to complete.
+pub(crate) struct TraceSegment {
+    trace_id: u64,
+    origin: String,
+    /// Id of the span buffer which created the segment: spans of other
+    /// tracers of the process start segments of their own.
+    owner: u64,
+    /// Nanoseconds spent in the tracer for the spans of the current chunk,
+    /// if it's measured.
+    overhead: Option<AtomicU64>,
+    data: Mutex<TraceSegmentData>,
+}
+
+impl TraceSegment {
+    /// Creates the segment of a new trace, or of a trace continued from a
+    /// propagated context, whose sampling priority is inherited. The upper
+    /// bits of 128-bit trace ids are kept as the `_dd.p.tid` trace tag.
+    pub fn new(
+        trace_id: u64,
+        trace_id_high: u64,
+        origin: &str,
+        sampling_priority: Option<SamplingPriority>,
+    ) -> TraceSegment {
+        let sampling = sampling_priority.map(|priority| SampleResult {
+            sampling_priority: Some(priority),
+            ..SampleResult::new()
+        });
+        let mut trace_tags = HashMap::new();
+        if trace_id_high != 0 {
+            trace_tags.insert(
+                String::from(TRACE_ID_HIGH_TAG),
+                format!("{:016x}", trace_id_high),
+            );
+        }
+
+        Self {
+            trace_id,
+            origin: String::from(origin),
+            owner: 0,
+            overhead: None,
+            data: Mutex::new(TraceSegmentData {
+                sampling,
+                trace_tags,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Creates the segment of a trace saved by another process, whose
+    /// running spans are registered again.
+    pub fn restore(saved: SavedTrace) -> TraceSegment {
+        let open_spans = saved
+            .open_spans
+            .into_iter()
+            .map(|span| (span.data.span_id, span.data))
+            .collect::<HashMap<_, _>>();
+        let span_count = open_spans.len() + saved.finished_spans.len();
+
+        Self {
+            trace_id: saved.trace_id,
+            origin: saved.origin,
+            owner: 0,
+            overhead: None,
+            data: Mutex::new(TraceSegmentData {
+                open_spans,
+                finished_spans: saved.finished_spans,
+                root: saved.root,
+                partial_version: saved.partial_version,
+                sampling: saved.sampling,
+                trace_tags: saved.trace_tags,
+                span_count,
+                abandoned: false,
+            }),
+        }
+    }
+
+    pub fn owned_by(self, owner: u64) -> Self {
+        Self { owner, ..self }
+    }
+
+    /// Measures the time spent in the tracer for the spans of the segment,
+    /// reported as the `_dd.tracer_overhead_ns` metric of each chunk.
+    pub fn measure_overhead(self, measure: bool) -> Self {
+        Self {
+            overhead: measure.then(|| AtomicU64::new(0)),
+            ..self
+        }
+    }
+
+    /// Returns when tracer work starts, if the overhead is measured.
+    pub fn start_overhead(&self) -> Option<Instant> {
+        self.overhead.as_ref().map(|_| Instant::now())
+    }
+
+    /// Adds the time elapsed since `start` to the overhead of the segment.
+    pub fn add_overhead(&self, start: Option<Instant>) {
+        if let (Some(overhead), Some(start)) = (&self.overhead, start) {
+            overhead.fetch_add(duration_nanos(start.elapsed()) as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub fn owner(&self) -> u64 {
+        self.owner
+    }
+
+    pub fn trace_id(&self) -> u64 {
+        self.trace_id
+    }
+
+    pub fn origin(&self) -> &str {
+        &self.origin
+    }
+
+    /// Returns the span id of the local root, once it's started.
+    pub fn root_id(&self) -> Result<Option<u64>> {
+        Ok(self
+            .data
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .root
+            .as_ref()
+            .map(|root| root.span_id))
+    }
+
+    /// Adds a started span, the first one being the local root.
+    pub fn register(&self, span: &SpanData) -> Result<()> {
+        let mut data = self.data.lock().map_err(|_| eyre!("mutex lock failed"))?;
+        if data.root.is_none() {
+            data.root = Some(span.clone());
+        }
+        data.open_spans.insert(span.span_id, span.clone());
+        data.span_count += 1;
+
+        Ok(())
+    }
+
+    /// Returns how many spans were started in the segment so far.
+    pub fn span_count(&self) -> Result<usize> {
+        Ok(self
+            .data
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .span_count)
+    }
+
+    /// Adds a finished span. Once all the spans have finished, the segment is
+    /// sampled if it hasn't been yet and returns them, the local root tagged
+    /// with the trace-level data.
+    pub fn finish(
+        &self,
+        span: SpanData,
+        sampler: Option<&RulesSampler>,
+    ) -> Result<Option<Vec<SpanData>>> {
+        let mut data = self.data.lock().map_err(|_| eyre!("mutex lock failed"))?;
+        if !data.finish(span) {
+            return Ok(None);
+        }
+
+        Ok(Some(self.complete(&mut data, sampler)?))
+    }
+
+    /// Takes the state of the segment to restore it in another process.
+    /// `spans` are the running spans as they are now, which must be all of
+    /// the open spans of the segment. The segment is left empty: finishing
+    /// its spans afterwards does nothing.
+    pub fn save(&self, spans: Vec<SavedSpan>) -> Result<SavedTrace> {
+        let mut data = self.data.lock().map_err(|_| eyre!("mutex lock failed"))?;
+        let all_open = spans.len() == data.open_spans.len()
+            && spans
+                .iter()
+                .all(|span| data.open_spans.contains_key(&span.data.span_id));
+        if !all_open {
+            return Err(eyre!(
+                "Trace {} has running spans which aren't saved",
+                self.trace_id
+            ));
+        }
+        data.open_spans.clear();
+
+        Ok(SavedTrace {
+            trace_id: self.trace_id,
+            origin: self.origin.clone(),
+            sampling: data.sampling.clone(),
+            trace_tags: data.trace_tags.clone(),
+            root: data.root.take(),
+            partial_version: data.partial_version,
+            open_spans: spans,
+            finished_spans: std::mem::take(&mut data.finished_spans),
+        })
+    }
+
+    /// Returns the spans open for at least `max_age` at `now` (in
+    /// nanoseconds since the epoch), the oldest first, if the segment
+    /// hasn't been reported yet. Each segment is reported once.
+    pub fn abandoned_spans(&self, now: i64, max_age: Duration) -> Result<Vec<OpenSpan>> {
+        let mut data = self.data.lock().map_err(|_| eyre!("mutex lock failed"))?;
+        if data.abandoned {
+            return Ok(Vec::new());
+        }
+
+        let mut spans: Vec<&SpanData> = data
+            .open_spans
+            .values()
+            .filter(|span| now - span.start >= duration_nanos(max_age))
+            .collect();
+        spans.sort_by_key(|span| span.start);
+        let spans: Vec<OpenSpan> = spans
+            .into_iter()
+            .map(|span| OpenSpan {
+                name: span.name.to_string(),
+                age: Duration::from_nanos((now - span.start) as u64),
+            })
+            .collect();
+        data.abandoned = !spans.is_empty();
+
+        Ok(spans)
+    }
+
+    /// Finishes the open spans at `now` from their snapshots, tagged with
+    /// `_dd.abandoned`, and returns the completed segment. Finishing these
+    /// spans later does nothing.
+    pub fn finish_abandoned(
+        &self,
+        now: i64,
+        sampler: Option<&RulesSampler>,
+    ) -> Result<Option<Vec<SpanData>>> {
+        let mut data = self.data.lock().map_err(|_| eyre!("mutex lock failed"))?;
+        if data.open_spans.is_empty() {
+            return Ok(None);
+        }
+
+        let spans: Vec<SpanData> = data.open_spans.values().cloned().collect();
+        for mut span in spans {
+            span.duration = (now - span.start).max(1);
+            span.meta
+                .insert(String::from(ABANDONED), String::from("true"));
+            data.finish(span);
+        }
+
+        Ok(Some(self.complete(&mut data, sampler)?))
+    }
+
+    /// Finishes the segment with its last chunk. Spans started later from
+    /// the contexts of this segment start a new chunk, with the same
+    /// sampling decision.
+    fn complete(
+        &self,
+        data: &mut TraceSegmentData,
+        sampler: Option<&RulesSampler>,
+    ) -> Result<Vec<SpanData>> {
+        let spans = self.take_chunk(data, sampler)?;
+        data.root = None;
+
+        Ok(spans)
+    }
+
+    /// Takes the finished spans as a chunk of the trace. The local root, or
+    /// the first span of chunks without it, carries the trace-level data.
+    fn take_chunk(
+        &self,
+        data: &mut TraceSegmentData,
+        sampler: Option<&RulesSampler>,
+    ) -> Result<Vec<SpanData>> {
+        let start = self.start_overhead();
+        let mut spans = std::mem::take(&mut data.finished_spans);
+        let root_id = data.root.as_ref().map(|root| root.span_id);
+        let root = spans.iter().position(|span| Some(span.span_id) == root_id);
+        match root {
+            Some(root) => data.sample(sampler, &spans[root])?,
+            None => {
+                let root = data.root.clone().unwrap_or_else(|| spans[0].clone());
+                data.sample(sampler, &root)?
+            }
+        };
+        data.keep_dropped(sampler, &spans, root.map(|root| &spans[root]))?;
+
+        let root = &mut spans[root.unwrap_or_default()];
+        if let Some(sampling) = &data.sampling {
+            tag_sampling(root, sampling);
+        }
+        if !self.origin.is_empty() {
+            root.meta.insert(String::from(ORIGIN), self.origin.clone());
+        }
+        for (key, value) in &data.trace_tags {
+            root.meta.insert(key.clone(), value.clone());
+        }
+        self.add_overhead(start);
+        if let Some(overhead) = &self.overhead {
+            let overhead = overhead.swap(0, Ordering::Relaxed);
+            root.metrics
+                .insert(String::from(TRACER_OVERHEAD), overhead as f64);
+        }
+
+        Ok(spans)
+    }
+
+    /// Takes the finished spans as a chunk if the first of them finished at
+    /// least `grace_period` before `now` (in nanoseconds since the epoch)
+    /// and other spans are still open, e.g. children of a local root which
+    /// never finishes. Their `parent_id` is kept, pointing at spans of other
+    /// chunks.
+    pub fn flush_orphans(
+        &self,
+        now: i64,
+        grace_period: Duration,
+        sampler: Option<&RulesSampler>,
+    ) -> Result<Option<Vec<SpanData>>> {
+        let mut data = self.data.lock().map_err(|_| eyre!("mutex lock failed"))?;
+        let first_finished = data
+            .finished_spans
+            .iter()
+            .map(|span| span.start + span.duration)
+            .min();
+        match first_finished {
+            Some(finished) if now - finished >= duration_nanos(grace_period) => {}
+            _ => return Ok(None),
+        }
+
+        Ok(Some(self.take_chunk(&mut data, sampler)?))
+    }
+
+    /// Returns the sampling priority of the segment, sampling its root as
+    /// started if it hasn't been decided yet, e.g. before injecting it.
+    /// Deciding once keeps the spans of the trace and the services it calls
+    /// consistent, and has the rate limiter count the trace once.
+    pub fn sample(&self, sampler: Option<&RulesSampler>) -> Result<Option<SamplingPriority>> {
+        let mut data = self.data.lock().map_err(|_| eyre!("mutex lock failed"))?;
+        let root = match data.root.clone() {
+            Some(root) => root,
+            None => return Ok(None),
+        };
+
+        data.sample(sampler, &root)
+    }
+
+    /// Sets the sampling decision of the trace made before its root span
+    /// started, unless it was already decided.
+    pub fn set_sampling(&self, sampling: SampleResult) -> Result<()> {
+        self.data
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .sampling
+            .get_or_insert(sampling);
+
+        Ok(())
+    }
+
+    /// Sets a tag written on the local root span, e.g. a `_dd.p.*` tag
+    /// propagated with the trace.
+    pub fn set_trace_tag(&self, key: &str, value: &str) -> Result<()> {
+        self.data
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .trace_tags
+            .insert(String::from(key), String::from(value));
+
+        Ok(())
+    }
+
+    pub fn trace_tags(&self) -> Result<HashMap<String, String>> {
+        Ok(self
+            .data
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .trace_tags
+            .clone())
+    }
+
+    /// Returns a partial copy of the local root if it has been running for
+    /// at least `min_age` at `now` (in nanoseconds since the epoch), bumping
+    /// its `_dd.partial_version` metric. The segment is sampled first if it
+    /// hasn't been yet, so the copy carries the decision of the trace like
+    /// its chunks do.
+    pub fn heartbeat(
+        &self,
+        now: i64,
+        min_age: Duration,
+        sampler: Option<&RulesSampler>,
+    ) -> Result<Option<SpanData>> {
+        let mut data = self.data.lock().map_err(|_| eyre!("mutex lock failed"))?;
+        let root = match data.root.as_ref() {
+            Some(root) if !data.root_finished(root) => root,
+            _ => return Ok(None),
+        };
+        let age = now - root.start;
+        if age < duration_nanos(min_age) {
+            return Ok(None);
+        }
+
+        let mut snapshot = root.clone();
+        data.sample(sampler, &snapshot)?;
+        if let Some(sampling) = &data.sampling {
+            tag_sampling(&mut snapshot, sampling);
+        }
+        if !self.origin.is_empty() {
+            snapshot
+                .meta
+                .insert(String::from(ORIGIN), self.origin.clone());
+        }
+        for (key, value) in &data.trace_tags {
+            snapshot.meta.insert(key.clone(), value.clone());
+        }
+        data.partial_version += 1;
+        snapshot.duration = age;
+        snapshot
+            .metrics
+            .insert(String::from(PARTIAL_VERSION), data.partial_version as f64);
+
+        Ok(Some(snapshot))
+    }
+}
+
+/// Records the sampling decision on the local root span, the only span the
+/// agent reads it from.
+fn tag_sampling(root: &mut SpanData, sampling: &SampleResult) {
+    let priority = match &sampling.sampling_priority {
+        Some(priority) => priority,
+        None => return,
+    };
+    root.metrics
+        .insert(String::from(SAMPLING_PRIORITY), priority.as_i32() as f64);
+    let rates = [
+        (RULE_SAMPLE_RATE, sampling.rule_rate),
+        (LIMIT_SAMPLE_RATE, sampling.limiter_rate),
+        (AGENT_SAMPLE_RATE, sampling.priority_rate as f64),
+    ];
+    for (key, rate) in rates.iter() {
+        if !rate.is_nan() {
+            root.metrics.insert(String::from(*key), *rate);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn span(span_id: u64) -> SpanData {
+        SpanData {
+            trace_id: 1,
+            span_id,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn completes_with_tagged_root() {
+        let segment = TraceSegment::new(1, 2, "synthetics", Some(SamplingPriority::UserKeep));
+        segment.register(&span(1)).unwrap();
+        segment.register(&span(2)).unwrap();
+        segment.set_trace_tag("_dd.p.dm", "-4").unwrap();
+
+        assert!(segment.finish(span(1), None).unwrap().is_none());
+        let spans = segment.finish(span(2), None).unwrap().unwrap();
+        let root = &spans[0];
+        assert_eq!(root.metrics[SAMPLING_PRIORITY], 2.0);
+        assert_eq!(root.meta[ORIGIN], "synthetics");
+        assert_eq!(root.meta["_dd.p.dm"], "-4");
+        assert_eq!(root.meta[TRACE_ID_HIGH_TAG], "0000000000000002");
+        assert!(spans[1].metrics.is_empty() && spans[1].meta.is_empty());
+    }
+
+    #[test]
+    fn keeps_dropped_traces_with_errors() {
+        let mut sampler = RulesSampler::from_config(r#"[{"sample_rate": 0.0}]"#, 1.0).unwrap();
+        sampler.set_error_sampling(true);
+
+        let segment = TraceSegment::new(1, 0, "", None);
+        segment.register(&span(1)).unwrap();
+        segment.register(&span(2)).unwrap();
+        assert!(segment.finish(span(2), Some(&sampler)).unwrap().is_none());
+        let spans = segment.finish(span(1), Some(&sampler)).unwrap().unwrap();
+        assert_eq!(spans[1].metrics[SAMPLING_PRIORITY], 0.0);
+
+        let segment = TraceSegment::new(2, 0, "", None);
+        segment.register(&span(1)).unwrap();
+        let failed = SpanData {
+            error: 1,
+            ..span(1)
+        };
+        let spans = segment.finish(failed, Some(&sampler)).unwrap().unwrap();
+        assert_eq!(spans[0].metrics[SAMPLING_PRIORITY], 1.0);
+        assert_eq!(spans[0].meta[DECISION_MAKER], ERROR_SAMPLING_MECHANISM);
+
+        // Decisions of the user are kept.
+        let segment = TraceSegment::new(3, 0, "", Some(SamplingPriority::UserDrop));
+        segment.register(&span(1)).unwrap();
+        let failed = SpanData {
+            error: 1,
+            ..span(1)
+        };
+        let spans = segment.finish(failed, Some(&sampler)).unwrap().unwrap();
+        assert_eq!(spans[0].metrics[SAMPLING_PRIORITY], -1.0);
+    }
+
+    #[test]
+    fn keeps_slow_traces() {
+        let mut sampler = RulesSampler::from_config("[]", 0.0).unwrap();
+        sampler.keep_slow_traces(Duration::from_nanos(100), 1.0);
+        let finished = |trace_id: u64, duration: i64| {
+            let segment = TraceSegment::new(trace_id, 0, "", None);
+            segment.register(&span(1)).unwrap();
+            let root = SpanData {
+                duration,
+                ..span(1)
+            };
+            segment.finish(root, Some(&sampler)).unwrap().unwrap()
+        };
+
+        assert_eq!(finished(1, 50)[0].metrics[SAMPLING_PRIORITY], 0.0);
+        let spans = finished(2, 150);
+        assert_eq!(spans[0].metrics[SAMPLING_PRIORITY], 1.0);
+        assert_eq!(spans[0].meta[DECISION_MAKER], LATENCY_SAMPLING_MECHANISM);
+        // Over the limit of the rule.
+        assert_eq!(finished(3, 150)[0].metrics[SAMPLING_PRIORITY], 0.0);
+    }
+
+    #[test]
+    fn flushes_orphans_after_grace_period() {
+        let segment = TraceSegment::new(1, 0, "", Some(SamplingPriority::SamplerKeep));
+        segment.register(&span(1)).unwrap();
+        segment.register(&span(2)).unwrap();
+        segment.register(&span(3)).unwrap();
+        let finished = |span_id: u64, duration: i64| SpanData {
+            parent_id: 1,
+            duration,
+            ..span(span_id)
+        };
+        assert!(segment.finish(finished(2, 10), None).unwrap().is_none());
+
+        let grace_period = Duration::from_nanos(100);
+        assert!(segment
+            .flush_orphans(50, grace_period, None)
+            .unwrap()
+            .is_none());
+        let chunk = segment
+            .flush_orphans(110, grace_period, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(chunk.len(), 1);
+        assert_eq!(chunk[0].parent_id, 1);
+        assert_eq!(chunk[0].metrics[SAMPLING_PRIORITY], 1.0);
+        assert!(segment
+            .flush_orphans(500, grace_period, None)
+            .unwrap()
+            .is_none());
+        let heartbeat = segment.heartbeat(500, grace_period, None).unwrap().unwrap();
+        assert_eq!(heartbeat.metrics[SAMPLING_PRIORITY], 1.0);
+
+        assert!(segment.finish(finished(3, 20), None).unwrap().is_none());
+        let spans = segment.finish(span(1), None).unwrap().unwrap();
+        let ids: Vec<u64> = spans.iter().map(|span| span.span_id).collect();
+        assert_eq!(ids, [3, 1]);
+        assert_eq!(spans[1].metrics[SAMPLING_PRIORITY], 1.0);
+    }
+
+    #[test]
+    fn reports_and_finishes_abandoned_spans() {
+        let segment = TraceSegment::new(1, 0, "", None);
+        let started = |span_id: u64, name: &str, start: i64| SpanData {
+            name: Arc::from(name),
+            start,
+            ..span(span_id)
+        };
+        segment.register(&started(1, "request", 0)).unwrap();
+        segment.register(&started(2, "db.query", 10)).unwrap();
+        segment.register(&started(3, "render", 90)).unwrap();
+        assert!(segment.finish(span(3), None).unwrap().is_none());
+
+        assert!(segment
+            .abandoned_spans(50, Duration::from_nanos(100))
+            .unwrap()
+            .is_empty());
+        let open = segment
+            .abandoned_spans(100, Duration::from_nanos(90))
+            .unwrap();
+        let names: Vec<&str> = open.iter().map(|span| span.name.as_str()).collect();
+        assert_eq!(names, ["request", "db.query"]);
+        assert_eq!(open[0].age, Duration::from_nanos(100));
+        // Segments are reported once.
+        assert!(segment
+            .abandoned_spans(200, Duration::from_nanos(90))
+            .unwrap()
+            .is_empty());
+
+        let spans = segment.finish_abandoned(100, None).unwrap().unwrap();
+        assert_eq!(spans.len(), 3);
+        let abandoned: Vec<(u64, i64)> = spans
+            .iter()
+            .filter(|span| span.meta.contains_key(ABANDONED))
+            .map(|span| (span.span_id, span.duration))
+            .collect();
+        assert!(abandoned.contains(&(1, 100)) && abandoned.contains(&(2, 90)));
+        assert!(segment.finish(span(2), None).unwrap().is_none());
+    }
+}
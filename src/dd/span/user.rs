@@ -0,0 +1,51 @@
+use crate::dd::utils::base64;
+
+const USER_ID: &str = "usr.id";
+const USER_EMAIL: &str = "usr.email";
+const USER_NAME: &str = "usr.name";
+const USER_SESSION_ID: &str = "usr.session_id";
+const USER_ROLE: &str = "usr.role";
+const USER_SCOPE: &str = "usr.scope";
+/// The id of the user propagated to the services called in the trace,
+/// base64 encoded.
+const PROPAGATED_USER_ID: &str = "_dd.p.usr.id";
+
+/// UserInfo is the user on whose behalf a trace runs, see
+/// `OwnedSpan::set_user`. Empty fields aren't tagged.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UserInfo {
+    pub id: String,
+    pub email: String,
+    pub name: String,
+    pub session_id: String,
+    pub role: String,
+    /// The permissions granted to the user, e.g. the scopes of its token.
+    pub scope: String,
+    /// Also propagates the id as the `_dd.p.usr.id` trace tag, so that the
+    /// traces of the services called can be searched by user too.
+    pub propagate: bool,
+}
+
+impl UserInfo {
+    /// Returns the `usr.*` tags of the user, and `_dd.p.usr.id` if it's
+    /// propagated.
+    pub fn tags(&self) -> Vec<(&'static str, String)> {
+        let mut tags: Vec<(&'static str, String)> = [
+            (USER_ID, &self.id),
+            (USER_EMAIL, &self.email),
+            (USER_NAME, &self.name),
+            (USER_SESSION_ID, &self.session_id),
+            (USER_ROLE, &self.role),
+            (USER_SCOPE, &self.scope),
+        ]
+        .iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(key, value)| (*key, String::clone(value)))
+        .collect();
+        if self.propagate && !self.id.is_empty() {
+            tags.push((PROPAGATED_USER_ID, base64(self.id.as_bytes())));
+        }
+
+        tags
+    }
+}
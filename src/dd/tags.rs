@@ -3,7 +3,58 @@ pub(crate) const SERVICE_NAME: &str = "service.name";
 pub(crate) const SPAN_TYPE: &str = "span.type";
 pub(crate) const OPERATION_NAME: &str = "operation";
 pub(crate) const RESOURCE_NAME: &str = "resource.name";
-pub(crate) const ANALYTICS_EVENT: &str = "analytics.event";
-pub(crate) const MANUAL_KEEP: &str = "manual.keep";
-pub(crate) const MANUAL_DROP: &str = "manual.drop";
 pub(crate) const VERSION: &str = "version";
+pub(crate) const RUNTIME_ID: &str = "runtime-id";
+pub(crate) const LANGUAGE: &str = "language";
+pub(crate) const PROCESS_ID: &str = "process_id";
+pub(crate) const PEER_SERVICE: &str = "peer.service";
+#[cfg(feature = "contrib")]
+pub(crate) const PEER_HOSTNAME: &str = "out.host";
+#[cfg(feature = "contrib")]
+pub(crate) const PEER_PORT: &str = "out.port";
+#[cfg(feature = "contrib")]
+pub(crate) const SPAN_KIND: &str = "span.kind";
+#[cfg(feature = "contrib")]
+pub(crate) const SPAN_KIND_CLIENT: &str = "client";
+pub(crate) const PEER_SERVICE_REMAPPED_FROM: &str = "_dd.peer.service.remapped_from";
+
+pub(crate) const ERROR: &str = "error";
+pub(crate) const ERROR_MSG: &str = "error.msg";
+pub(crate) const ERROR_TYPE: &str = "error.type";
+pub(crate) const ERROR_STACK: &str = "error.stack";
+pub(crate) const EVENTS: &str = "events";
+/// Span links of spans, as JSON, for agents reading them from tags.
+pub(crate) const SPAN_LINKS: &str = "_dd.span_links";
+pub(crate) const MEASURED: &str = "_dd.measured";
+pub(crate) const TOP_LEVEL: &str = "_dd.top_level";
+pub(crate) const PARTIAL_VERSION: &str = "_dd.partial_version";
+pub(crate) const COLD_START: &str = "_dd.cold_start";
+pub(crate) const ABANDONED: &str = "_dd.abandoned";
+pub(crate) const SPAN_LIMIT_REACHED: &str = "_dd.span_limit_reached";
+pub(crate) const INFERRED_SPAN: &str = "_dd.inferred_span";
+pub(crate) const GIT_COMMIT_SHA: &str = "_dd.git.commit.sha";
+pub(crate) const GIT_REPOSITORY_URL: &str = "_dd.git.repository_url";
+pub(crate) const SAMPLING_PRIORITY: &str = "_sampling_priority_v1";
+pub(crate) const RULE_SAMPLE_RATE: &str = "_dd.rule_psr";
+pub(crate) const LIMIT_SAMPLE_RATE: &str = "_dd.limit_psr";
+pub(crate) const AGENT_SAMPLE_RATE: &str = "_dd.agent_psr";
+pub(crate) const ORIGIN: &str = "_dd.origin";
+/// Trace tag of the mechanism which made the sampling decision, as `-<id>`.
+pub(crate) const DECISION_MAKER: &str = "_dd.p.dm";
+/// Mechanism of traces sampled by the default rate.
+pub(crate) const DEFAULT_SAMPLING_MECHANISM: &str = "-0";
+/// Mechanism of traces sampled by the rates of the agent.
+pub(crate) const AGENT_RATE_SAMPLING_MECHANISM: &str = "-1";
+/// Mechanism of traces sampled by a sampling rule.
+pub(crate) const RULE_SAMPLING_MECHANISM: &str = "-3";
+/// Mechanism of traces sampled by hand, e.g. by a sampler override.
+pub(crate) const MANUAL_SAMPLING_MECHANISM: &str = "-4";
+/// Mechanism of traces kept by error sampling, unused by other tracers.
+pub(crate) const ERROR_SAMPLING_MECHANISM: &str = "-13";
+/// Mechanism of slow traces kept by the latency rule.
+pub(crate) const LATENCY_SAMPLING_MECHANISM: &str = "-14";
+/// Metric of the nanoseconds spent in the tracer for the spans of a trace
+/// chunk, on its root.
+pub(crate) const TRACER_OVERHEAD: &str = "_dd.tracer_overhead_ns";
+/// Metric of spans kept by span sampling although their trace is dropped.
+pub(crate) const SPAN_SAMPLING_MECHANISM: &str = "_dd.span_sampling.mechanism";
@@ -0,0 +1,155 @@
+use serde_json::{json, Value};
+use std::{collections::HashMap, env};
+
+/// The environment variables of settings, by the key of the setting in the
+/// JSON configuration. Propagation styles are recorded as they're read, see
+/// `TracerOptions::read_propagation_env`.
+const ENV_SETTINGS: &[(&str, &str)] = &[
+    ("enabled", "DD_TRACE_ENABLED"),
+    ("propagation_only", "DD_APM_TRACING_ENABLED"),
+    ("error_sampling", "DD_TRACE_ERROR_SAMPLING"),
+    ("service_mapping", "DD_SERVICE_MAPPING"),
+    ("header_tags", "DD_TRACE_HEADER_TAGS"),
+    ("git_repository_url", "DD_GIT_REPOSITORY_URL"),
+    ("git_commit_sha", "DD_GIT_COMMIT_SHA"),
+    ("agent_pipe_name", "DD_TRACE_PIPE_NAME"),
+    ("serverless", "AWS_LAMBDA_FUNCTION_NAME"),
+    ("agentless", "DD_TRACE_AGENTLESS"),
+    ("site", "DD_SITE"),
+    (
+        "trace_id_128bit_generation",
+        "DD_TRACE_128_BIT_TRACEID_GENERATION_ENABLED",
+    ),
+    ("baggage_max_items", "DD_TRACE_BAGGAGE_MAX_ITEMS"),
+    ("filter_urls", "DD_TRACE_FILTER_URLS"),
+    ("debug", "DD_TRACE_DEBUG"),
+    ("request_queuing", "DD_TRACE_REQUEST_QUEUING_ENABLED"),
+    (
+        "inferred_proxy_services",
+        "DD_TRACE_INFERRED_PROXY_SERVICES_ENABLED",
+    ),
+    ("client_drop_p0s", "DD_TRACE_CLIENT_DROP_P0S_ENABLED"),
+    ("overhead_metrics", "DD_TRACE_OVERHEAD_METRICS_ENABLED"),
+    (
+        "http_server_error_statuses",
+        "DD_TRACE_HTTP_SERVER_ERROR_STATUSES",
+    ),
+    (
+        "http_client_error_statuses",
+        "DD_TRACE_HTTP_CLIENT_ERROR_STATUSES",
+    ),
+];
+
+/// ConfigSource is where the value of a setting comes from. The
+/// environment overrides the code, and remote configuration both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigSource {
+    Default,
+    /// Set by the application, in code or in the JSON configuration.
+    Code,
+    EnvVar,
+    /// Changed while the tracer runs, see `Tracer::reconfigure`.
+    RemoteConfig,
+}
+
+impl ConfigSource {
+    /// The name of the source in telemetry.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConfigSource::Default => "default",
+            ConfigSource::Code => "code",
+            ConfigSource::EnvVar => "env_var",
+            ConfigSource::RemoteConfig => "remote_config",
+        }
+    }
+}
+
+/// ConfigOrigins records where the settings come from, by their key in the
+/// JSON configuration. Settings without origin have their default value.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigOrigins(HashMap<String, ConfigSource>);
+
+impl ConfigOrigins {
+    /// Returns the origins of the settings read from the environment.
+    pub fn from_env() -> Self {
+        let mut origins = Self::default();
+        for (key, name) in ENV_SETTINGS {
+            if env::var_os(name).is_some() {
+                origins.set(key, ConfigSource::EnvVar);
+            }
+        }
+
+        origins
+    }
+
+    pub fn set(&mut self, key: &str, source: ConfigSource) {
+        self.0.insert(String::from(key), source);
+    }
+
+    pub fn get(&self, key: &str) -> ConfigSource {
+        self.0.get(key).copied().unwrap_or(ConfigSource::Default)
+    }
+
+    /// Records the settings of `config` which differ from those of
+    /// `defaults` and have no origin yet as set in code.
+    pub fn detect_code(&mut self, config: &Value, defaults: &Value) {
+        if let (Value::Object(config), Value::Object(defaults)) = (config, defaults) {
+            for (key, value) in config {
+                if !self.0.contains_key(key) && defaults.get(key) != Some(value) {
+                    self.set(key, ConfigSource::Code);
+                }
+            }
+        }
+    }
+
+    /// Returns the settings of `config` with their origin, as in the
+    /// `configuration` of telemetry payloads and in startup logs:
+    /// `[{"name": "debug", "value": true, "origin": "env_var"}, ...]`,
+    /// sorted by name.
+    pub fn describe(&self, config: &Value) -> Value {
+        let mut settings: Vec<(&String, &Value)> = match config {
+            Value::Object(config) => config.iter().collect(),
+            _ => Vec::new(),
+        };
+        settings.sort_by_key(|(key, _)| *key);
+
+        settings
+            .into_iter()
+            .map(|(key, value)| {
+                json!({
+                    "name": key,
+                    "value": value,
+                    "origin": self.get(key).name(),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_the_origin_of_settings() {
+        let mut origins = ConfigOrigins::default();
+        origins.set("debug", ConfigSource::EnvVar);
+        origins.set("service", ConfigSource::Code);
+        origins.detect_code(
+            &json!({"service": "web", "debug": true, "sample_rate": 0.5, "site": "datadoghq.com"}),
+            &json!({"service": "", "debug": false, "sample_rate": null, "site": "datadoghq.com"}),
+        );
+        assert_eq!(origins.get("sample_rate"), ConfigSource::Code);
+        assert_eq!(origins.get("site"), ConfigSource::Default);
+
+        let config = json!({"site": "datadoghq.com", "debug": true, "sample_rate": 0.5});
+        assert_eq!(
+            origins.describe(&config),
+            json!([
+                {"name": "debug", "value": true, "origin": "env_var"},
+                {"name": "sample_rate", "value": 0.5, "origin": "code"},
+                {"name": "site", "value": "datadoghq.com", "origin": "default"},
+            ])
+        );
+    }
+}
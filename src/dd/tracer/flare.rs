@@ -0,0 +1,74 @@
+use crate::dd::utils::zip;
+use serde_json::Value;
+
+/// Multipart boundary of flare uploads, which can't appear in their fields.
+const BOUNDARY: &str = "83CAD6AA-8A24-462C-8B3D-FF9CC683B51B";
+
+/// Flare is the diagnostic bundle sent to Datadog support through the agent
+/// for a support case: the resolved configuration of the tracer, its last
+/// messages, and the state of its buffer and writer.
+pub(crate) struct Flare {
+    pub config: Value,
+    pub logs: Vec<String>,
+    pub stats: Value,
+}
+
+impl Flare {
+    /// Returns the zip archive of the flare.
+    pub fn archive(&self) -> Vec<u8> {
+        let config = serde_json::to_vec_pretty(&self.config).unwrap_or_default();
+        let stats = serde_json::to_vec_pretty(&self.stats).unwrap_or_default();
+        let mut logs = self.logs.join("\n");
+        logs.push('\n');
+
+        zip(&[
+            ("tracer_config.json", &config),
+            ("tracer_stats.json", &stats),
+            ("tracer.log", logs.as_bytes()),
+        ])
+    }
+
+    /// Returns the headers and body of the upload of the flare for
+    /// `case_id`, as expected by the agent flare endpoint.
+    pub fn upload(
+        &self,
+        case_id: &str,
+        email: &str,
+        hostname: &str,
+        runtime_id: &str,
+    ) -> (Vec<(&'static str, String)>, Vec<u8>) {
+        let mut body = Vec::new();
+        let fields = [
+            ("source", "tracer_rust"),
+            ("case_id", case_id),
+            ("email", email),
+            ("hostname", hostname),
+        ];
+        for (name, value) in fields.iter() {
+            body.extend_from_slice(
+                format!(
+                    "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                    BOUNDARY, name, value
+                )
+                .as_bytes(),
+            );
+        }
+        body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"flare_file\"; \
+                 filename=\"tracer-rust-{}-debug.zip\"\r\n\
+                 Content-Type: application/octet-stream\r\n\r\n",
+                BOUNDARY, runtime_id
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(&self.archive());
+        body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+
+        let headers = vec![(
+            "Content-Type",
+            format!("multipart/form-data; boundary={}", BOUNDARY),
+        )];
+        (headers, body)
+    }
+}
@@ -0,0 +1,179 @@
+use crate::{
+    dd::utils::{base64, base64_decode},
+    opentracing::{PropagationError, TextMapReader, TextMapWriter},
+};
+use eyre::Result;
+use serde_json::{json, Map, Value};
+
+/// Name of the attribute of AWS messages holding the span context.
+const DATADOG_ATTRIBUTE: &str = "_datadog";
+/// Attributes SQS accepts per message; no room is made for the context.
+const MAX_MESSAGE_ATTRIBUTES: usize = 10;
+
+/// MessageCarrier propagates span contexts through AWS messages, as the
+/// other Datadog tracers do: the headers are the JSON object of a
+/// `_datadog` message attribute for SQS and SNS, or field of the detail of
+/// EventBridge events. Spans consuming messages continue the trace of the
+/// span which sent them.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MessageCarrier(Map<String, Value>);
+
+impl MessageCarrier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the carrier from the message attributes of an SQS or SNS
+    /// message, as returned by the SDK (`StringValue`), in Lambda events
+    /// (`stringValue`) or in the body of SNS notifications delivered to SQS
+    /// (`Value`). Binary attributes are base64 encoded.
+    pub fn from_message_attributes(attributes: &Value) -> Option<Self> {
+        let attribute = attributes.get(DATADOG_ATTRIBUTE)?;
+        let field = |names: &[&str]| names.iter().find_map(|name| attribute.get(name)?.as_str());
+        let data_type = field(&["DataType", "dataType", "Type"])?;
+        let value = field(&[
+            "StringValue",
+            "stringValue",
+            "BinaryValue",
+            "binaryValue",
+            "Value",
+        ])?;
+        let json = if data_type == "Binary" {
+            base64_decode(value)?
+        } else {
+            value.as_bytes().to_vec()
+        };
+
+        serde_json::from_slice(&json).ok().map(Self)
+    }
+
+    /// Reads the carrier from the `detail` of an EventBridge event.
+    pub fn from_event_detail(detail: &Value) -> Option<Self> {
+        detail
+            .get(DATADOG_ATTRIBUTE)?
+            .as_object()
+            .cloned()
+            .map(Self)
+    }
+
+    /// Adds the carrier to the message attributes of an SQS or SNS message,
+    /// as a string attribute, or as a binary attribute if `binary` (SNS
+    /// topics with subscription filter policies). Returns false if the
+    /// message already has all the attributes SQS accepts.
+    pub fn add_to_message_attributes(
+        &self,
+        attributes: &mut Map<String, Value>,
+        binary: bool,
+    ) -> bool {
+        if attributes.len() >= MAX_MESSAGE_ATTRIBUTES && !attributes.contains_key(DATADOG_ATTRIBUTE)
+        {
+            return false;
+        }
+
+        let value = Value::Object(self.0.clone()).to_string();
+        let attribute = if binary {
+            json!({"DataType": "Binary", "BinaryValue": base64(value.as_bytes())})
+        } else {
+            json!({"DataType": "String", "StringValue": value})
+        };
+        attributes.insert(String::from(DATADOG_ATTRIBUTE), attribute);
+        true
+    }
+
+    /// Adds the carrier to the `detail` of an EventBridge event.
+    pub fn add_to_event_detail(&self, detail: &mut Map<String, Value>) {
+        detail.insert(
+            String::from(DATADOG_ATTRIBUTE),
+            Value::Object(self.0.clone()),
+        );
+    }
+}
+
+impl TextMapReader for MessageCarrier {
+    fn lookup_key(&self, key: &str) -> Result<String, PropagationError> {
+        self.0
+            .get(key)
+            .and_then(Value::as_str)
+            .map(String::from)
+            .ok_or(PropagationError::KeyNotFound)
+    }
+
+    fn foreach_key(&self, f: &mut dyn FnMut(&str, &str) -> Result<()>) -> Result<()> {
+        for (key, value) in self.0.iter() {
+            if let Some(value) = value.as_str() {
+                f(key, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TextMapWriter for MessageCarrier {
+    fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        self.0.insert(String::from(key), Value::from(value));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dd::{agent::MockTransport, Tracer, TracerOptions},
+        opentracing::{StartSpanOptions, Tracer as _},
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn propagates_contexts_through_messages() {
+        let tracer =
+            Tracer::with_transport(TracerOptions::default(), Arc::new(MockTransport::default()))
+                .unwrap();
+        let span = tracer.start_owned_span("sqs.send", &StartSpanOptions::default());
+        let mut carrier = MessageCarrier::new();
+        tracer.inject(span.context(), &mut carrier).unwrap();
+
+        for binary in [false, true] {
+            let mut attributes = Map::new();
+            assert!(carrier.add_to_message_attributes(&mut attributes, binary));
+            let received = MessageCarrier::from_message_attributes(&Value::from(attributes));
+            assert_eq!(received.as_ref(), Some(&carrier));
+            let context = tracer.extract_or_new(&received.unwrap());
+            assert_eq!(context.trace_id(), span.context().trace_id());
+            assert_eq!(context.id(), span.context().id());
+        }
+
+        let value = Value::Object(carrier.0.clone()).to_string();
+        let lambda = json!({"_datadog": {"dataType": "String", "stringValue": value}});
+        let notification =
+            json!({"_datadog": {"Type": "Binary", "Value": base64(value.as_bytes())}});
+        assert_eq!(
+            MessageCarrier::from_message_attributes(&lambda).as_ref(),
+            Some(&carrier)
+        );
+        assert_eq!(
+            MessageCarrier::from_message_attributes(&notification).as_ref(),
+            Some(&carrier)
+        );
+        assert!(MessageCarrier::from_message_attributes(&json!({})).is_none());
+
+        let mut detail = Map::new();
+        carrier.add_to_event_detail(&mut detail);
+        let detail = Value::from(detail);
+        assert_eq!(
+            MessageCarrier::from_event_detail(&detail),
+            Some(carrier.clone())
+        );
+
+        let mut attributes: Map<String, Value> = (0..MAX_MESSAGE_ATTRIBUTES)
+            .map(|i| {
+                (
+                    i.to_string(),
+                    json!({"DataType": "String", "StringValue": "v"}),
+                )
+            })
+            .collect();
+        assert!(!carrier.add_to_message_attributes(&mut attributes, false));
+        assert!(!attributes.contains_key(DATADOG_ATTRIBUTE));
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{
+    dd::utils::base64_decode,
+    opentracing::{PropagationError, TextMapReader},
+};
+use eyre::Result;
+use std::collections::HashMap;
+
+/// Suffix of the names of binary gRPC metadata, whose values are base64.
+const BINARY_SUFFIX: &str = "-bin";
+
+/// MetadataCarrier reads span contexts from gRPC metadata or HTTP/2 headers,
+/// which don't read as HTTP/1 headers do:
+/// - names are lower case, and pseudo-headers (`:path`, `:authority`...)
+///   aren't metadata,
+/// - binary metadata, whose names end with `-bin`, is base64 encoded, e.g.
+///   by gRPC-Web clients,
+/// - a header may be repeated, its values are then joined with commas as
+///   for HTTP/1 lists such as `tracestate`.
+pub struct MetadataCarrier(HashMap<String, String>);
+
+impl MetadataCarrier {
+    /// Reads the `(name, value)` pairs of the metadata, in their order.
+    /// Binary values which aren't valid base64 or UTF-8 are ignored.
+    pub fn new<'a>(metadata: impl IntoIterator<Item = (&'a str, &'a [u8])>) -> Self {
+        let mut headers: HashMap<String, String> = HashMap::new();
+        for (name, value) in metadata {
+            if name.starts_with(':') {
+                continue;
+            }
+            let mut name = name.to_ascii_lowercase();
+            let value = match name.strip_suffix(BINARY_SUFFIX) {
+                Some(stripped) => {
+                    let decoded = std::str::from_utf8(value)
+                        .ok()
+                        .and_then(|value| base64_decode(value.trim()))
+                        .and_then(|value| String::from_utf8(value).ok());
+                    name.truncate(stripped.len());
+                    match decoded {
+                        Some(decoded) => decoded,
+                        None => continue,
+                    }
+                }
+                None => String::from_utf8_lossy(value).trim().to_string(),
+            };
+
+            headers
+                .entry(name)
+                .and_modify(|joined| {
+                    joined.push(',');
+                    joined.push_str(&value);
+                })
+                .or_insert(value);
+        }
+
+        Self(headers)
+    }
+}
+
+impl TextMapReader for MetadataCarrier {
+    fn lookup_key(&self, key: &str) -> Result<String, PropagationError> {
+        self.0
+            .get(&key.to_ascii_lowercase())
+            .cloned()
+            .ok_or(PropagationError::KeyNotFound)
+    }
+
+    fn foreach_key(&self, f: &mut dyn FnMut(&str, &str) -> Result<()>) -> Result<()> {
+        for (key, value) in self.0.iter() {
+            f(key, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dd::{agent::MockTransport, utils::base64, Tracer, TracerOptions},
+        propagation::PropagationStyle,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn reads_grpc_metadata() {
+        let origin = base64(b"synthetics");
+        let metadata: Vec<(&str, &[u8])> = vec![
+            (":method", b"POST"),
+            (":path", b"/billing.Invoices/Send"),
+            ("content-type", b"application/grpc-web+proto"),
+            (
+                "traceparent",
+                b"00-0000000000000000000000000000007b-00000000000001c8-01",
+            ),
+            ("tracestate", b"dd=s:1;o:synthetics"),
+            ("TraceState", b"other=vendor"),
+            (
+                "x-datadog-origin-bin",
+                origin.trim_end_matches('=').as_bytes(),
+            ),
+            ("x-broken-bin", b"not base64!"),
+        ];
+        let carrier = MetadataCarrier::new(metadata);
+        assert!(carrier.lookup_key(":path").is_err());
+        assert_eq!(
+            carrier.lookup_key("X-Datadog-Origin").unwrap(),
+            "synthetics"
+        );
+        assert_eq!(
+            carrier.lookup_key("tracestate").unwrap(),
+            "dd=s:1;o:synthetics,other=vendor"
+        );
+        assert!(carrier.lookup_key("x-broken").is_err());
+
+        let options = TracerOptions {
+            extract: vec![PropagationStyle::W3C].into_iter().collect(),
+            ..Default::default()
+        };
+        let tracer = Tracer::with_transport(options, Arc::new(MockTransport::default())).unwrap();
+        let context = tracer.extract_or_new(&carrier);
+        assert_eq!(context.trace_id(), 123);
+        assert_eq!(context.id(), 456);
+        assert_eq!(context.origin(), "synthetics");
+    }
+}
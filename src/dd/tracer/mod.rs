@@ -1,5 +1,27 @@
-mod propagation_style;
+mod config_source;
+mod flare;
+mod message_carrier;
+mod metadata_carrier;
+#[cfg(feature = "http-client")]
+mod noop;
+mod propagation;
+mod status_codes;
+#[allow(clippy::module_inception)]
 mod tracer;
+mod tracer_config;
+mod tracer_factory;
 mod tracer_options;
 
-use propagation_style::*;
+pub(crate) use crate::propagation::PropagationStyle;
+pub use config_source::*;
+pub use message_carrier::*;
+pub use metadata_carrier::*;
+#[cfg(feature = "http-client")]
+pub(crate) use noop::*;
+#[cfg(feature = "http-client")]
+pub(crate) use propagation::{extract as extract_context, inject as inject_context};
+pub use status_codes::*;
+pub use tracer::*;
+pub use tracer_config::*;
+pub use tracer_factory::*;
+pub use tracer_options::*;
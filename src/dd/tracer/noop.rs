@@ -0,0 +1,156 @@
+use std::{any::Any, collections::HashSet};
+
+use crate::{
+    dd::{self, PropagationStyle},
+    opentracing::{
+        ExtractionError, FinishSpanOptions, Span, SpanContext, StartSpanOptions, TextMapReader,
+        TextMapWriter, Tracer,
+    },
+    propagation::InjectOptions,
+};
+use eyre::Result;
+
+/// NoopSpanContext holds the span context a NoopTracer extracted, if any,
+/// so that it can be injected again unchanged.
+#[derive(Clone, Default)]
+pub(crate) struct NoopSpanContext {
+    propagated: Option<dd::SpanContext>,
+}
+
+impl SpanContext for NoopSpanContext {
+    fn foreach_baggage_item(&self, f: &mut dyn FnMut(&str, &str) -> bool) -> Result<()> {
+        match &self.propagated {
+            Some(context) => context.foreach_baggage_item(f),
+            None => Ok(()),
+        }
+    }
+
+    fn baggage_len(&self) -> usize {
+        self.propagated
+            .as_ref()
+            .map_or(0, |context| context.baggage_len())
+    }
+
+    fn iter(&self) -> std::vec::IntoIter<(String, String)> {
+        match &self.propagated {
+            Some(context) => context.iter(),
+            None => Vec::new().into_iter(),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub(crate) struct NoopSpan<'a> {
+    tracer: &'a dyn Tracer,
+    span_context: NoopSpanContext,
+}
+
+impl<'a> NoopSpan<'a> {
+    pub fn new(tracer: &'a dyn Tracer, span_context: NoopSpanContext) -> NoopSpan<'a> {
+        Self {
+            tracer,
+            span_context,
+        }
+    }
+}
+
+impl<'a> Span for NoopSpan<'a> {
+    fn finish_with_options(&mut self, _finish_span_options: &FinishSpanOptions) {}
+
+    fn set_operation_name(&mut self, _operation_name: &str) {}
+
+    fn set_tag(&mut self, _key: &str, _value: &serde_json::Value) {}
+
+    fn set_baggage_item(&mut self, _restricted_key: &str, _value: &str) {}
+
+    fn baggage_item(&self, _restricted_key: &str) -> String {
+        String::new()
+    }
+
+    fn remove_baggage_item(&mut self, _restricted_key: &str) -> Option<String> {
+        None
+    }
+
+    fn log(&mut self, _fields: &[(String, serde_json::Value)]) {}
+
+    fn context(&self) -> &dyn SpanContext {
+        &self.span_context
+    }
+
+    fn tracer(&self) -> &dyn Tracer {
+        self.tracer
+    }
+}
+
+/// NoopTracer records nothing, e.g. when tracing is disabled. It still
+/// passes the incoming span context through: the spans started from an
+/// extracted context inject it unchanged, so the services called keep
+/// continuing the trace of the caller.
+#[derive(Default)]
+pub(crate) struct NoopTracer {
+    extract: HashSet<PropagationStyle>,
+    inject: HashSet<PropagationStyle>,
+}
+
+impl NoopTracer {
+    /// Creates a tracer passing through the span contexts of the `extract`
+    /// styles, injected in the `inject` styles.
+    pub fn new(extract: HashSet<PropagationStyle>, inject: HashSet<PropagationStyle>) -> Self {
+        Self { extract, inject }
+    }
+}
+
+impl Tracer for NoopTracer {
+    fn start_span_with_options(
+        &self,
+        _operation_name: &str,
+        options: &StartSpanOptions,
+    ) -> Box<dyn Span + '_> {
+        let parent = options
+            .parent_context
+            .iter()
+            .chain(options.references.iter().map(|(_, context)| context))
+            .find_map(|context| {
+                let context = context.as_any();
+                context
+                    .downcast_ref::<NoopSpanContext>()
+                    .and_then(|context| context.propagated.clone())
+                    .or_else(|| context.downcast_ref::<dd::SpanContext>().cloned())
+            });
+
+        Box::new(NoopSpan::new(self, NoopSpanContext { propagated: parent }))
+    }
+
+    fn inject(&self, sc: &dyn SpanContext, writer: &mut dyn TextMapWriter) -> Result<()> {
+        let context = match sc.as_any().downcast_ref::<NoopSpanContext>() {
+            Some(NoopSpanContext {
+                propagated: Some(context),
+            }) => context,
+            _ => return Ok(()),
+        };
+
+        dd::inject_context(
+            context,
+            context.propagated_sampling_priority().as_ref(),
+            &self.inject,
+            &InjectOptions::default(),
+            writer,
+        )
+    }
+
+    fn extract(
+        &self,
+        reader: &dyn TextMapReader,
+    ) -> Result<Option<Box<dyn SpanContext>>, ExtractionError> {
+        Ok(dd::extract_context(reader, &self.extract)?.map(|context| {
+            Box::new(NoopSpanContext {
+                propagated: Some(context),
+            }) as Box<dyn SpanContext>
+        }))
+    }
+
+    fn close(&mut self) {}
+}
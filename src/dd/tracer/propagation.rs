@@ -0,0 +1,222 @@
+use super::PropagationStyle;
+use crate::{
+    dd::{sample::SamplingPriority, span::SpanContext},
+    opentracing::{ExtractionError, PropagationError, TextMapReader, TextMapWriter},
+    propagation::{self, InjectOptions, ParseStyleError, BAGGAGE_PREFIX, STYLES},
+};
+use eyre::{eyre, Result};
+use std::{cell::RefCell, collections::HashSet};
+
+/// Parses a propagation style name, ignoring case. Returns `None` for
+/// `none`, which disables propagation.
+pub(crate) fn parse_propagation_style(name: &str) -> Result<Option<PropagationStyle>> {
+    if name.trim().eq_ignore_ascii_case("none") {
+        return Ok(None);
+    }
+    name.parse()
+        .map(Some)
+        .map_err(|error: ParseStyleError| eyre!("{}, or none", error))
+}
+
+/// Parses a comma separated list of propagation styles, as in
+/// `DD_TRACE_PROPAGATION_STYLE`.
+pub(crate) fn parse_propagation_styles(value: &str) -> Result<HashSet<PropagationStyle>> {
+    let mut styles = HashSet::new();
+    for name in value.split(',').filter(|name| !name.trim().is_empty()) {
+        styles.extend(parse_propagation_style(name)?);
+    }
+    Ok(styles)
+}
+
+/// Writes the headers of every requested style for `context` to `writer`.
+pub(crate) fn inject(
+    context: &SpanContext,
+    sampling_priority: Option<&SamplingPriority>,
+    styles: &HashSet<PropagationStyle>,
+    options: &InjectOptions,
+    writer: &mut dyn TextMapWriter,
+) -> Result<()> {
+    let mut propagated = context.to_propagated(sampling_priority)?;
+    for style in STYLES.iter().filter(|style| styles.contains(style)) {
+        propagation::inject_with_options(style, &propagated, options, &mut |key, value| {
+            writer.set(key, value)
+        })?;
+        // Baggage only needs to be written once.
+        propagated.baggage.clear();
+    }
+
+    Ok(())
+}
+
+/// Reads a span context from `reader`, trying every requested style. Returns
+/// `None` if none of them is present.
+pub(crate) fn extract(
+    reader: &dyn TextMapReader,
+    styles: &HashSet<PropagationStyle>,
+) -> Result<Option<SpanContext>, ExtractionError> {
+    let lookup_error = RefCell::new(None);
+    let mut lookup = |key: &str| match reader.lookup_key(key) {
+        Ok(value) => Some(value),
+        Err(PropagationError::KeyNotFound) => None,
+        Err(error) => {
+            lookup_error
+                .borrow_mut()
+                .get_or_insert(ExtractionError::Carrier {
+                    key: String::from(key),
+                    error,
+                });
+            None
+        }
+    };
+
+    for style in STYLES.iter().filter(|style| styles.contains(style)) {
+        let propagated = propagation::extract(style, &mut lookup);
+        if let Some(error) = lookup_error.borrow_mut().take() {
+            return Err(error);
+        }
+        let propagated =
+            propagated.map_err(|error| ExtractionError::Corrupted(error.to_string()))?;
+        if let Some(mut propagated) = propagated {
+            // Baggage is optional: carriers which can't be iterated, such as
+            // the lookups of the C API, only lose it.
+            let _ = reader.foreach_key(&mut |key, value| {
+                let (prefix, name) = match key.get(..BAGGAGE_PREFIX.len()) {
+                    Some(prefix) => (prefix, &key[BAGGAGE_PREFIX.len()..]),
+                    None => return Ok(()),
+                };
+                if prefix.eq_ignore_ascii_case(BAGGAGE_PREFIX) && !name.is_empty() {
+                    propagated
+                        .baggage
+                        .insert(String::from(name), String::from(value));
+                }
+                Ok(())
+            });
+            return Ok(Some(SpanContext::from_propagated(propagated)));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct Carrier {
+        headers: HashMap<String, String>,
+    }
+
+    impl TextMapWriter for Carrier {
+        fn set(&mut self, key: &str, value: &str) -> Result<()> {
+            self.headers.insert(String::from(key), String::from(value));
+            Ok(())
+        }
+    }
+
+    impl TextMapReader for Carrier {
+        fn lookup_key(&self, key: &str) -> Result<String, PropagationError> {
+            self.headers
+                .get(key)
+                .cloned()
+                .ok_or(PropagationError::KeyNotFound)
+        }
+
+        fn foreach_key(&self, f: &mut dyn FnMut(&str, &str) -> Result<()>) -> Result<()> {
+            for (key, value) in &self.headers {
+                f(key, value)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn injects_and_extracts_every_style() {
+        let mut context = SpanContext::new(123, 456, "synthetics", HashMap::new());
+        context.set_baggage_item("user", "42").unwrap();
+        let mut carrier = Carrier::default();
+        let all: HashSet<PropagationStyle> = STYLES.iter().cloned().collect();
+        inject(
+            &context,
+            Some(&SamplingPriority::UserKeep),
+            &all,
+            &InjectOptions::default(),
+            &mut carrier,
+        )
+        .unwrap();
+
+        assert_eq!(carrier.headers["x-datadog-trace-id"], "456");
+        assert_eq!(carrier.headers["x-b3-traceid"], "00000000000001c8");
+        assert!(carrier.headers.contains_key("traceparent"));
+        assert_eq!(carrier.headers["ot-baggage-user"], "42");
+
+        carrier.headers.remove("x-datadog-trace-id");
+        carrier.headers.remove("x-datadog-parent-id");
+        let extracted = extract(&carrier, &all).unwrap().unwrap();
+        assert_eq!(extracted.trace_id(), 456);
+        assert_eq!(extracted.id(), 123);
+        assert_eq!(
+            extracted.baggage_item("user").unwrap(),
+            Some(String::from("42"))
+        );
+        assert_eq!(
+            extracted.propagated_sampling_priority(),
+            &Some(SamplingPriority::SamplerKeep)
+        );
+
+        carrier
+            .headers
+            .insert(String::from("x-b3-sampled"), String::from("maybe"));
+        assert!(extract(&carrier, &all).is_err());
+    }
+
+    #[test]
+    fn injects_128bit_trace_ids_as_configured() {
+        let mut context = SpanContext::new(2, 1, "", HashMap::new());
+        context.set_trace_id_high(0x640cfd8d00000000);
+        let styles: HashSet<PropagationStyle> = [PropagationStyle::Datadog, PropagationStyle::W3C]
+            .iter()
+            .cloned()
+            .collect();
+        let inject_with = |options: InjectOptions| {
+            let mut carrier = Carrier::default();
+            inject(&context, None, &styles, &options, &mut carrier).unwrap();
+            carrier.headers
+        };
+
+        let headers = inject_with(InjectOptions::default());
+        assert_eq!(headers["x-datadog-trace-id"], "1");
+        assert_eq!(headers["x-datadog-tags"], "_dd.p.tid=640cfd8d00000000");
+        assert_eq!(
+            headers["traceparent"],
+            "00-640cfd8d000000000000000000000001-0000000000000002-00"
+        );
+
+        let headers = inject_with(InjectOptions {
+            datadog_trace_id_128: false,
+            w3c_trace_id_128: false,
+        });
+        assert!(!headers.contains_key("x-datadog-tags"));
+        assert_eq!(
+            headers["traceparent"],
+            "00-00000000000000000000000000000001-0000000000000002-00"
+        );
+    }
+
+    #[test]
+    fn parses_style_lists() {
+        let styles =
+            parse_propagation_styles("Datadog, b3multi,B3 single header,TRACECONTEXT").unwrap();
+        assert_eq!(styles.len(), 4);
+        assert!(styles.contains(&PropagationStyle::B3Single));
+        assert!(parse_propagation_styles("none").unwrap().is_empty());
+        assert_eq!(
+            parse_propagation_styles("datadog,jaeger")
+                .unwrap_err()
+                .to_string(),
+            "Unknown propagation style 'jaeger', expected Datadog, B3, B3 single header or \
+             tracecontext, or none"
+        );
+    }
+}
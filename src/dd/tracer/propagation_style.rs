@@ -1,4 +0,0 @@
-pub enum PropagationStyle {
-    Datadog,
-    B3
-}
\ No newline at end of file
@@ -0,0 +1,115 @@
+use eyre::{eyre, Result};
+use std::{fmt, ops::RangeInclusive};
+
+/// StatusCodes is a set of HTTP status codes, written as codes and ranges
+/// separated by commas, e.g. `400-499,501`, as in
+/// `DD_TRACE_HTTP_SERVER_ERROR_STATUSES`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusCodes(Vec<RangeInclusive<u16>>);
+
+impl StatusCodes {
+    /// The statuses of server spans which are errors by default: 5xx.
+    pub fn server_errors() -> Self {
+        Self(vec![500..=599])
+    }
+
+    /// The statuses of client spans which are errors by default: 4xx and
+    /// 5xx.
+    pub fn client_errors() -> Self {
+        Self(vec![400..=599])
+    }
+
+    /// Parses codes and ranges separated by commas. Blanks are ignored, and
+    /// an empty value is the empty set.
+    pub fn parse(value: &str) -> Result<Self> {
+        let code = |code: &str| {
+            code.trim()
+                .parse::<u16>()
+                .ok()
+                .filter(|code| (100..=999).contains(code))
+                .ok_or_else(|| eyre!("invalid HTTP status code '{}'", code.trim()))
+        };
+
+        value
+            .split(',')
+            .filter(|item| !item.trim().is_empty())
+            .map(|item| match item.split_once('-') {
+                Some((from, to)) => {
+                    let (from, to) = (code(from)?, code(to)?);
+                    if from > to {
+                        return Err(eyre!("invalid HTTP status range '{}'", item.trim()));
+                    }
+                    Ok(from..=to)
+                }
+                None => code(item).map(|code| code..=code),
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+
+    /// Parses the variable `name`, None if it's unset.
+    pub(crate) fn from_env(name: &str) -> Result<Option<Self>> {
+        match std::env::var(name) {
+            Ok(value) => Self::parse(&value)
+                .map(Some)
+                .map_err(|error| eyre!("{}: {}", name, error)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    pub fn contains(&self, status: u16) -> bool {
+        self.0.iter().any(|range| range.contains(&status))
+    }
+}
+
+impl fmt::Display for StatusCodes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, range) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            match (range.start(), range.end()) {
+                (from, to) if from == to => write!(f, "{}", from)?,
+                (from, to) => write!(f, "{}-{}", from, to)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_codes_and_ranges() {
+        let statuses = StatusCodes::parse(" 400-499, 501,").unwrap();
+        assert!(statuses.contains(400));
+        assert!(statuses.contains(499));
+        assert!(statuses.contains(501));
+        assert!(!statuses.contains(500));
+        assert!(!statuses.contains(503));
+        assert_eq!(statuses.to_string(), "400-499,501");
+
+        assert!(!StatusCodes::parse("").unwrap().contains(500));
+        assert!(StatusCodes::server_errors().contains(503));
+        assert!(!StatusCodes::server_errors().contains(404));
+        assert!(StatusCodes::client_errors().contains(404));
+        for invalid in ["5xx", "499-400", "400-", "42", "500-1000"] {
+            assert!(StatusCodes::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn reports_invalid_variables() {
+        let name = "DD_TEST_STATUS_CODES_FROM_ENV";
+        assert_eq!(StatusCodes::from_env(name).unwrap(), None);
+        std::env::set_var(name, "5xx");
+        let error = StatusCodes::from_env(name).unwrap_err();
+        assert!(error.to_string().starts_with(name), "{}", error);
+        std::env::set_var(name, "500,502");
+        assert!(StatusCodes::from_env(name).unwrap().unwrap().contains(502));
+        std::env::remove_var(name);
+    }
+}
@@ -1,3 +1,2099 @@
+use super::{flare::Flare, propagation, ConfigSnapshot, TracerOptions, TracerOptionsDelta};
+#[cfg(feature = "http-client")]
+use crate::dd::agent::HttpClient;
+#[cfg(feature = "tls")]
+use crate::dd::agent::HttpsClient;
+#[cfg(all(windows, feature = "windows-pipes"))]
+use crate::dd::agent::PipeClient;
+#[cfg(feature = "contrib")]
+use crate::dd::contrib::Registry;
+#[cfg(feature = "threads")]
+use crate::dd::span::Heartbeat;
+#[cfg(feature = "agentless")]
+use crate::dd::writer::{intake_host, Intake, INTAKE_PORT};
+use crate::{
+    dd::{
+        agent::{AgentInfo, NullTransport, Transport, FLARE_ENDPOINT},
+        sample::{RateUpdateStats, RulesSampler, SamplingDecision},
+        span::{
+            active_context, OwnedSpan, SavedSpan, SavedTrace, Span, SpanBuffer, SpanContext,
+            SpanData, UrlFilter, WritingSpanBuffer,
+        },
+        tags::{
+            ENVIRONMENT, GIT_COMMIT_SHA, GIT_REPOSITORY_URL, INFERRED_SPAN, LANGUAGE, PROCESS_ID,
+            RESOURCE_NAME, RUNTIME_ID, SERVICE_NAME, SPAN_LIMIT_REACHED, SPAN_TYPE, VERSION,
+        },
+        utils::{trace_url, IdGenerator, Interner, LogLevel, RateLimitedLogger},
+        writer::{
+            AgentWriter, CircuitBreaker, Destination, PayloadCompression, SelfTracing,
+            StatsConcentrator,
+        },
+    },
+    opentracing::{
+        self, ExtractionError, PropagationError, StartSpanOptions, TextMapReader, TextMapWriter,
+    },
+    propagation::{InjectOptions, TRACE_ID_HIGH_TAG},
+};
+use eyre::{eyre, Result};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Bounds the operation names shared by the spans of a tracer.
+const MAX_INTERNED_OPERATION_NAMES: usize = 1024;
+
+#[cfg(feature = "http-client")]
+const AGENT_TIMEOUT: Duration = Duration::from_secs(2);
+
 pub struct Tracer {
-    
-}
\ No newline at end of file
+    options: TracerOptions,
+    /// The options changed since the tracer was created, see `reconfigure`.
+    config: Mutex<Arc<ConfigSnapshot>>,
+    writer: Arc<AgentWriter>,
+    buffer: Arc<WritingSpanBuffer>,
+    logger: Arc<RateLimitedLogger>,
+    #[cfg(feature = "threads")]
+    heartbeat: Option<Heartbeat>,
+    #[cfg(feature = "threads")]
+    watchdog: Option<Heartbeat>,
+    #[cfg(feature = "threads")]
+    orphans: Option<Heartbeat>,
+    #[cfg(feature = "contrib")]
+    integrations: Registry,
+    ids: IdGenerator,
+    /// Identifies the process to the agent, tagged on local root spans.
+    runtime_id: String,
+    /// Shared by the spans of the tracer, as are its service and type.
+    operation_names: Interner,
+    service: Arc<str>,
+    service_type: Arc<str>,
+}
+
+impl Tracer {
+    #[cfg(feature = "http-client")]
+    pub fn new(options: TracerOptions) -> Result<Tracer> {
+        #[cfg(feature = "agentless")]
+        {
+            if options.agentless {
+                let host = intake_host(&options.site);
+                let client = HttpsClient::new(&host, INTAKE_PORT, AGENT_TIMEOUT)
+                    .with_proxy(&options.proxy_url)?;
+                return Self::with_transport(options, Arc::new(client));
+            }
+        }
+
+        if !options.agent_pipe_name.is_empty() {
+            let client = pipe_agent_client(&options)?;
+            return Self::with_transport(options, client);
+        }
+        if options.agent_url.starts_with("https://") {
+            let client = https_agent_client(&options)?;
+            return Self::with_transport(options, client);
+        }
+
+        let client = if options.agent_url.is_empty() {
+            HttpClient::new(&options.agent_host, options.agent_port, AGENT_TIMEOUT)
+        } else {
+            HttpClient::from_url(&options.agent_url, AGENT_TIMEOUT)?
+        }
+        .with_proxy(&options.proxy_url)?;
+
+        Self::with_transport(options, Arc::new(client))
+    }
+
+    /// Creates a tracer sending traces through `transport` instead of the
+    /// built-in HTTP clients. Nothing is sent in propagation-only mode.
+    pub fn with_transport(
+        mut options: TracerOptions,
+        transport: Arc<dyn Transport>,
+    ) -> Result<Tracer> {
+        let transport: Arc<dyn Transport> = if options.propagation_only {
+            Arc::new(NullTransport)
+        } else {
+            transport
+        };
+        let destination = if options.agentless {
+            agentless_destination(&options)?
+        } else {
+            Destination::Agent
+        };
+        let writer = Arc::new(AgentWriter::new(
+            transport,
+            destination,
+            Duration::from_millis(options.write_perios_ms as u64),
+        ));
+        let logger = Arc::new(RateLimitedLogger::new(options.log_func.clone()));
+        logger.set_debug(options.debug);
+        writer.set_logger(logger.clone())?;
+        writer.set_queue_limit(options.trace_queue_capacity, options.trace_queue_policy)?;
+        writer.set_client_drop_p0s(
+            options
+                .client_drop_p0s
+                .then(|| StatsConcentrator::new(&options.environment, &options.version)),
+        )?;
+        writer.set_circuit_breaker((options.circuit_breaker_failures > 0).then(|| {
+            CircuitBreaker {
+                failures: options.circuit_breaker_failures,
+                cooldown: Duration::from_millis(options.circuit_breaker_cooldown_ms as u64),
+                policy: options.circuit_breaker_policy,
+            }
+        }))?;
+        if options.self_tracing {
+            writer.set_self_tracing(Some(SelfTracing::new(
+                &options.service,
+                &options.environment,
+            )))?;
+        }
+        if let Some(compression) = options.compression {
+            if !compression.is_supported() {
+                return Err(eyre!(
+                    "{} compression requires the {} feature",
+                    compression.content_encoding(),
+                    compression.feature()
+                ));
+            }
+            writer.set_compression(Some(PayloadCompression {
+                compression,
+                level: options.compression_level,
+                threshold: options.compression_threshold,
+            }))?;
+        }
+        let config = options.to_json();
+        options
+            .origins
+            .detect_code(&config, &TracerOptions::default().to_json());
+        let ids = IdGenerator::new();
+        let mut buffer = WritingSpanBuffer::new(ids.next_id(), writer.clone(), options.serverless);
+        if options.overhead_metrics {
+            buffer = buffer.measure_overhead();
+        }
+        if options.priority_sampling {
+            let mut sampler =
+                RulesSampler::from_config(&options.sampling_rules, options.sample_rate)?;
+            sampler.set_override(options.sampler_override.clone());
+            sampler.set_error_sampling(options.error_sampling);
+            if options.latency_keep_threshold_ms > 0 {
+                sampler.keep_slow_traces(
+                    Duration::from_millis(options.latency_keep_threshold_ms as u64),
+                    options.latency_keep_rate_limit,
+                );
+            }
+            buffer = buffer.with_sampler(sampler);
+        }
+        if !options.service_mapping.is_empty() {
+            buffer = buffer.with_service_mapping(&options.service_mapping);
+        }
+        if let Some(hook) = options.on_span_finish.clone() {
+            buffer = buffer.on_span_finish(hook);
+        }
+        if !options.filter_urls.is_empty() {
+            buffer = buffer.with_filter(Arc::new(UrlFilter::new(options.filter_urls.clone())));
+        }
+        for filter in &options.trace_filters {
+            buffer = buffer.with_filter(filter.clone());
+        }
+        for processor in std::mem::take(&mut options.trace_processors) {
+            buffer = buffer.with_processor(processor);
+        }
+        if options.propagation_only {
+            buffer = buffer.propagation_only();
+        }
+        let buffer = Arc::new(buffer);
+        // The writer outlives the buffer holding it, don't keep it alive.
+        let rates_buffer = Arc::downgrade(&buffer);
+        let rates_logger = logger.clone();
+        writer.set_rates_handler(Arc::new(move |rates| {
+            if let Some(buffer) = rates_buffer.upgrade() {
+                if let Err(error) = buffer.update_agent_rates(rates) {
+                    rates_logger.log(
+                        LogLevel::Error,
+                        "sampling",
+                        &format!("Ignoring the agent sampling rates: {}", error),
+                    );
+                }
+            }
+        }))?;
+
+        #[cfg(feature = "contrib")]
+        let integrations = Registry::new(logger.clone());
+        let mut tracer = Self {
+            writer,
+            buffer,
+            logger,
+            #[cfg(feature = "threads")]
+            heartbeat: None,
+            #[cfg(feature = "threads")]
+            watchdog: None,
+            #[cfg(feature = "threads")]
+            orphans: None,
+            #[cfg(feature = "contrib")]
+            integrations,
+            ids,
+            runtime_id: String::new(),
+            operation_names: Interner::new(MAX_INTERNED_OPERATION_NAMES),
+            service: Arc::from(options.service.as_str()),
+            service_type: Arc::from(options.service_type.as_str()),
+            config: Mutex::new(Arc::new(ConfigSnapshot::new(&options))),
+            options,
+        };
+        tracer.runtime_id = tracer.ids.next_uuid();
+        tracer.start_heartbeat();
+        let configuration = tracer.config()?.describe(&tracer.options);
+        tracer.logger.log(
+            LogLevel::Debug,
+            "startup",
+            &format!("DATADOG TRACER CONFIGURATION - {}", configuration),
+        );
+
+        Ok(tracer)
+    }
+
+    #[cfg(feature = "threads")]
+    fn start_heartbeat(&mut self) {
+        self.heartbeat = match self.options.heartbeat_period_ms {
+            0 => None,
+            period => Some(Heartbeat::new(
+                self.buffer.clone(),
+                Duration::from_millis(period as u64),
+            )),
+        };
+        self.watchdog = match self.options.abandoned_span_timeout_ms {
+            0 => None,
+            timeout => {
+                let buffer = self.buffer.clone();
+                let logger = self.logger.clone();
+                let timeout = Duration::from_millis(timeout as u64);
+                let finish = self.options.finish_abandoned_spans;
+                Some(Heartbeat::every(timeout, move || {
+                    report_abandoned(&buffer, &logger, timeout, finish)
+                }))
+            }
+        };
+        self.orphans = match self.options.orphan_grace_period_ms {
+            0 => None,
+            grace_period => {
+                let buffer = self.buffer.clone();
+                let grace_period = Duration::from_millis(grace_period as u64);
+                Some(Heartbeat::every(grace_period, move || {
+                    let _ = buffer.flush_orphans(grace_period);
+                }))
+            }
+        };
+    }
+
+    #[cfg(not(feature = "threads"))]
+    fn start_heartbeat(&mut self) {}
+
+    /// Does the work of the background threads: writes heartbeats and
+    /// orphaned spans, reports abandoned traces and sends the buffered
+    /// traces. Without the `threads` feature the host has to
+    /// call it every write period, e.g. from a proxy-wasm tick.
+    #[cfg(not(feature = "threads"))]
+    pub fn tick(&self) -> Result<()> {
+        if self.options.heartbeat_period_ms > 0 {
+            self.buffer.write_heartbeats(Duration::from_millis(
+                self.options.heartbeat_period_ms as u64,
+            ))?;
+        }
+        if self.options.orphan_grace_period_ms > 0 {
+            self.buffer.flush_orphans(Duration::from_millis(
+                self.options.orphan_grace_period_ms as u64,
+            ))?;
+        }
+        if self.options.abandoned_span_timeout_ms > 0 {
+            report_abandoned(
+                &self.buffer,
+                &self.logger,
+                Duration::from_millis(self.options.abandoned_span_timeout_ms as u64),
+                self.options.finish_abandoned_spans,
+            );
+        }
+        self.writer
+            .flush(Duration::from_millis(self.options.write_perios_ms as u64))
+            .map(|_| ())
+    }
+
+    /// Returns the sampling rates received from the agent, by
+    /// `service:<service>,env:<env>` key. The default rate is under
+    /// `service:,env:`.
+    pub fn agent_sampling_rates(&self) -> Result<HashMap<String, f32>> {
+        self.buffer
+            .with_priority_sampler(|sampler| sampler.agent_sampling_rates())
+    }
+
+    /// Samples the traces of `service` in `environment` at `rate` instead of
+    /// the rate received from the agent, until it's cleared, e.g. during an
+    /// incident while the agent rates are wrong. Sampling rules still apply
+    /// first.
+    pub fn pin_sampling_rate(&self, service: &str, environment: &str, rate: f64) -> Result<()> {
+        self.buffer
+            .with_priority_sampler(|sampler| sampler.pin_rate(service, environment, rate))
+    }
+
+    pub fn clear_pinned_sampling_rate(&self, service: &str, environment: &str) -> Result<()> {
+        self.buffer
+            .with_priority_sampler(|sampler| sampler.clear_pinned_rate(service, environment))
+    }
+
+    /// Returns how many updates of the agent sampling rates were applied and
+    /// rejected.
+    pub fn sampling_rate_updates(&self) -> Result<RateUpdateStats> {
+        self.buffer
+            .with_priority_sampler(|sampler| sampler.update_stats())
+    }
+
+    /// Returns the health metrics of the tracer by name, e.g. to report them
+    /// with the metrics of the application.
+    pub fn health_metrics(&self) -> Result<HashMap<&'static str, u64>> {
+        let mut metrics = HashMap::new();
+        metrics.insert("tracer.queue_full", self.writer.queue_full()?);
+        if let Some(overhead) = self.buffer.overhead() {
+            metrics.insert("tracer.overhead_ns", overhead);
+            metrics.insert("tracer.encoding_ns", self.writer.encoding_time()?);
+        }
+        Ok(metrics)
+    }
+
+    /// Sends every finished trace now and returns how many were sent, e.g.
+    /// before the process exits. Traces with unfinished spans stay buffered.
+    pub fn flush(&self, timeout: Duration) -> Result<usize> {
+        self.buffer.flush(timeout)
+    }
+
+    /// Same as `flush`, waiting on a blocking thread of the tokio runtime so
+    /// that its other tasks keep running. The future doesn't borrow the
+    /// tracer: it can be spawned.
+    #[cfg(feature = "tokio")]
+    pub fn flush_async(
+        &self,
+        timeout: Duration,
+    ) -> impl std::future::Future<Output = Result<usize>> + Send + 'static {
+        let buffer = self.buffer.clone();
+        async move { tokio::task::spawn_blocking(move || buffer.flush(timeout)).await? }
+    }
+
+    /// Starts a span which doesn't borrow the tracer: it can be sent to and
+    /// finished on another thread, even after the tracer is dropped.
+    pub fn start_owned_span(&self, operation_name: &str, options: &StartSpanOptions) -> OwnedSpan {
+        let start = self.options.overhead_metrics.then(Instant::now);
+        let span_id = self.ids.next_id();
+        // Spans without parent nor reference are children of the active
+        // span of the thread, if any.
+        let active = (options.parent_context.is_none() && options.references.is_empty())
+            .then(active_context)
+            .flatten();
+        // References created by other tracers are ignored.
+        let parent = options
+            .parent_context
+            .iter()
+            .chain(options.references.iter().map(|(_, context)| context))
+            .find_map(|context| context.as_any().downcast_ref::<SpanContext>())
+            .or(active.as_ref());
+        let (mut context, parent_id) =
+            match parent.map(|parent| (parent.with_id(span_id), parent.id())) {
+                Some((Ok(context), parent_id)) => (context, parent_id),
+                _ => (self.new_trace_context(span_id, span_id), 0),
+            };
+        // Spans continuing a propagated context, or a span of another
+        // tracer, are local roots too.
+        let local_root = parent.is_none_or(|parent| {
+            parent
+                .trace_segment()
+                .is_none_or(|segment| !self.buffer.owns(segment))
+        }) || parent_id == 0;
+        if let (Some(parent), false) = (parent, local_root) {
+            if self.span_limit_reached(parent) {
+                // The context of the parent, so that the spans of the
+                // services called are children of the last span recorded.
+                let context = parent.with_id(parent_id).unwrap_or(context);
+                return OwnedSpan::noop(self.buffer.clone(), context);
+            }
+        }
+
+        let resource = self.operation_names.intern(operation_name);
+        let mut data = SpanData {
+            service: self.service.clone(),
+            span_type: self.service_type.clone(),
+            name: if self.options.operation_name_override.is_empty() {
+                resource.clone()
+            } else {
+                self.operation_names
+                    .intern(&self.options.operation_name_override)
+            },
+            resource,
+            parent_id,
+            meta: self
+                .config()
+                .map(|config| config.tags.clone())
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+        if !self.options.environment.is_empty() {
+            data.meta
+                .insert(String::from(ENVIRONMENT), self.options.environment.clone());
+        }
+        if !self.options.version.is_empty() {
+            data.meta
+                .insert(String::from(VERSION), self.options.version.clone());
+        }
+        if local_root {
+            data.meta
+                .insert(String::from(RUNTIME_ID), self.runtime_id.clone());
+            data.meta
+                .insert(String::from(LANGUAGE), String::from("rust"));
+            data.metrics
+                .insert(String::from(PROCESS_ID), std::process::id() as f64);
+            if !self.options.git_repository_url.is_empty() {
+                data.meta.insert(
+                    String::from(GIT_REPOSITORY_URL),
+                    self.options.git_repository_url.clone(),
+                );
+            }
+            if !self.options.git_commit_sha.is_empty() {
+                data.meta.insert(
+                    String::from(GIT_COMMIT_SHA),
+                    self.options.git_commit_sha.clone(),
+                );
+            }
+        }
+
+        context.set_baggage_limits(self.options.baggage_limits);
+        let mut span = OwnedSpan::new(
+            self.buffer.clone(),
+            context,
+            options.start_system_time,
+            options.start_steady_time,
+            data,
+        );
+        span.set_logger(self.logger.clone());
+        for (key, value) in &options.tags {
+            span.set_tag(key, value);
+        }
+        span.add_overhead(start);
+
+        span
+    }
+
+    /// Whether the trace of `parent` has as many spans as allowed by
+    /// `max_spans_per_trace`, which is then tagged on its local root.
+    fn span_limit_reached(&self, parent: &SpanContext) -> bool {
+        let limit = self.options.max_spans_per_trace;
+        let segment = match parent.trace_segment() {
+            Some(segment) if limit > 0 => segment,
+            _ => return false,
+        };
+        if segment.span_count().unwrap_or_default() < limit {
+            return false;
+        }
+
+        let _ = segment.set_trace_tag(SPAN_LIMIT_REACHED, "true");
+        true
+    }
+
+    /// Starts a span of work measured elsewhere, e.g. a job of a batch
+    /// processor read from its metadata, which started at `start` and
+    /// lasted `duration`: it lasts that long however it's finished. The
+    /// start time of `options` is ignored. Returns an error if the work
+    /// started before the epoch or ends in the future.
+    pub fn start_span_at(
+        &self,
+        operation_name: &str,
+        start: SystemTime,
+        duration: Duration,
+        options: &StartSpanOptions,
+    ) -> Result<OwnedSpan> {
+        if start < UNIX_EPOCH {
+            return Err(eyre!("span {} starts before the epoch", operation_name));
+        }
+        match start.checked_add(duration) {
+            Some(end) if end <= SystemTime::now() => {}
+            _ => return Err(eyre!("span {} ends in the future", operation_name)),
+        }
+
+        let options = StartSpanOptions {
+            start_system_time: start,
+            start_steady_time: Instant::now(),
+            references: options.references.clone(),
+            parent_context: options.parent_context.clone(),
+            tags: options.tags.clone(),
+        };
+        let mut span = self.start_owned_span(operation_name, &options);
+        span.set_recorded_duration(duration);
+        Ok(span)
+    }
+
+    /// Starts the `http.queue` span of the time a request waited in the
+    /// queue of the load balancer, from its `X-Request-Start` or
+    /// `X-Queue-Start` header, if `request_queuing` is on. Server
+    /// middlewares start their span as its child, then finish it.
+    pub fn start_queue_span(
+        &self,
+        reader: &dyn TextMapReader,
+        options: &StartSpanOptions,
+    ) -> Option<OwnedSpan> {
+        if !self.options.request_queuing {
+            return None;
+        }
+
+        let start = [REQUEST_START, QUEUE_START]
+            .iter()
+            .find_map(|header| parse_request_start(&reader.lookup_key(header).ok()?))?;
+        let duration = SystemTime::now().duration_since(start).ok()?;
+        self.start_span_at(QUEUE_OPERATION, start, duration, options)
+            .ok()
+    }
+
+    /// Starts the span of the API gateway which received a request before
+    /// the server, from the `X-Dd-Proxy` headers it added, if
+    /// `inferred_proxy_services` is on. The span starts when the gateway
+    /// received the request: server middlewares start their span as its
+    /// child and finish it after theirs.
+    pub fn start_proxy_span(
+        &self,
+        reader: &dyn TextMapReader,
+        options: &StartSpanOptions,
+    ) -> Option<OwnedSpan> {
+        if !self.options.inferred_proxy_services {
+            return None;
+        }
+
+        let header = |name: &str| reader.lookup_key(name).ok();
+        let proxy = header(PROXY)?;
+        let (_, operation_name) = PROXIES.iter().find(|(name, _)| *name == proxy)?;
+        let start: u64 = header(PROXY_REQUEST_TIME)?.trim().parse().ok()?;
+        let start = UNIX_EPOCH.checked_add(Duration::from_millis(start))?;
+        if start > SystemTime::now() {
+            return None;
+        }
+
+        let mut span = self.start_owned_span(operation_name, options);
+        span.set_start_time(start);
+        let domain = header(PROXY_DOMAIN_NAME).unwrap_or_default();
+        let method = header(PROXY_METHOD).unwrap_or_default();
+        let path = header(PROXY_PATH).unwrap_or_default();
+        if !domain.is_empty() {
+            span.set_tag(SERVICE_NAME, &Value::from(domain.as_str()));
+        }
+        span.set_tag(RESOURCE_NAME, &Value::from(format!("{} {}", method, path)));
+        span.set_tag(SPAN_TYPE, &Value::from("web"));
+        span.set_tag("component", &Value::from(proxy));
+        span.set_tag("http.method", &Value::from(method));
+        span.set_tag("http.url", &Value::from(domain + &path));
+        if let Some(stage) = header(PROXY_STAGE) {
+            span.set_tag("stage", &Value::from(stage));
+        }
+        span.set_metric(INFERRED_SPAN, 1.0);
+        Some(span)
+    }
+
+    /// Tags `span` with the request headers of `reader` set as header tags,
+    /// e.g. by server middlewares on the span of each request. The header
+    /// tags are the current ones, see `reconfigure`.
+    pub fn tag_request_headers(&self, reader: &dyn TextMapReader, span: &mut OwnedSpan) {
+        let config = match self.config() {
+            Ok(config) => config,
+            Err(_) => return,
+        };
+        for (header, tag) in &config.header_tags {
+            if let Ok(value) = reader.lookup_key(header) {
+                span.set_tag(tag, &Value::from(value));
+            }
+        }
+    }
+
+    /// Decides whether to keep a new trace before starting any span, e.g. so
+    /// that hot paths skip creating the spans of dropped traces. The root of
+    /// the trace would be a span of `service` named `name` with `resource`,
+    /// as sampling rules match it. Start the trace with
+    /// `start_decided_span` to keep the decision: the trace is sampled once.
+    pub fn should_sample(
+        &self,
+        service: &str,
+        name: &str,
+        resource: &str,
+    ) -> Result<SamplingDecision> {
+        let mut root = SpanData {
+            trace_id: self.ids.next_id(),
+            service: Arc::from(service),
+            name: Arc::from(name),
+            resource: Arc::from(resource),
+            ..Default::default()
+        };
+        root.span_id = root.trace_id;
+        if !self.options.environment.is_empty() {
+            root.meta
+                .insert(String::from(ENVIRONMENT), self.options.environment.clone());
+        }
+
+        Ok(SamplingDecision {
+            trace_id: root.trace_id,
+            result: self.buffer.sample_root(&root)?,
+        })
+    }
+
+    /// Starts the root span of the trace `decision` was made for, which
+    /// keeps it. The parent of `options` is ignored.
+    pub fn start_decided_span(
+        &self,
+        decision: &SamplingDecision,
+        operation_name: &str,
+        options: &StartSpanOptions,
+    ) -> OwnedSpan {
+        let options = StartSpanOptions {
+            start_system_time: options.start_system_time,
+            start_steady_time: options.start_steady_time,
+            references: Vec::new(),
+            parent_context: Some(Rc::new(self.new_trace_context(0, decision.trace_id))),
+            tags: options.tags.clone(),
+        };
+        let span = self.start_owned_span(operation_name, &options);
+        if let Some(segment) = span.context().trace_segment() {
+            let _ = segment.set_sampling(decision.result.clone());
+        }
+
+        span
+    }
+
+    /// Saves the state of the trace of `spans` to be restored by another
+    /// process with `restore_trace`, e.g. to checkpoint a job or migrate it
+    /// mid-trace: the running spans, the spans of the trace waiting for
+    /// them, its sampling decision and trace tags. `spans` must be all of
+    /// the running spans of the trace; they're detached without finishing
+    /// them. On error, the spans are finished here.
+    pub fn save_trace(&self, spans: Vec<OwnedSpan>) -> Result<Vec<u8>> {
+        let segment = spans
+            .first()
+            .and_then(|span| span.context().trace_segment())
+            .filter(|segment| self.buffer.owns(segment))
+            .cloned()
+            .ok_or_else(|| eyre!("No running span of this tracer to save"))?;
+        let mut saved_spans = Vec::with_capacity(spans.len());
+        for span in &spans {
+            let same_trace = span
+                .context()
+                .trace_segment()
+                .is_some_and(|other| Arc::ptr_eq(other, &segment));
+            match span.snapshot() {
+                Some(data) if same_trace => saved_spans.push(SavedSpan {
+                    data,
+                    baggage: span.context().baggage_items()?.into_iter().collect(),
+                }),
+                _ => return Err(eyre!("Spans to save must be running spans of one trace")),
+            }
+        }
+
+        let saved = self.buffer.save_segment(&segment, saved_spans)?;
+        for span in spans {
+            span.detach();
+        }
+
+        Ok(saved.to_bytes())
+    }
+
+    /// Restores a trace saved by `save_trace`, returning its running spans
+    /// in the order they were saved. They finish in this process, the trace
+    /// being sent once all of them have.
+    pub fn restore_trace(&self, saved: &[u8]) -> Result<Vec<OwnedSpan>> {
+        let saved = SavedTrace::from_bytes(saved)?;
+        let trace_id_high = saved
+            .trace_tags
+            .get(TRACE_ID_HIGH_TAG)
+            .and_then(|high| u64::from_str_radix(high, 16).ok())
+            .unwrap_or_default();
+        let open_spans = saved.open_spans.clone();
+        let segment = self.buffer.restore_segment(saved)?;
+
+        Ok(open_spans
+            .into_iter()
+            .map(|span| {
+                let mut context = SpanContext::new(
+                    span.data.span_id,
+                    segment.trace_id(),
+                    segment.origin(),
+                    span.baggage,
+                );
+                context.set_trace_id_high(trace_id_high);
+                context.set_baggage_limits(self.options.baggage_limits);
+                context.set_trace_segment(segment.clone());
+                let mut span = OwnedSpan::restore(self.buffer.clone(), context, span.data);
+                span.set_logger(self.logger.clone());
+                span
+            })
+            .collect())
+    }
+
+    /// Extracts the span context propagated in `reader`, or creates the
+    /// context of a new trace if there's none or it can't be read, which is
+    /// logged. Spans started with it as `StartSpanOptions::parent_context`
+    /// are siblings, children of the caller or roots of the new trace.
+    pub fn extract_or_new(&self, reader: &dyn TextMapReader) -> SpanContext {
+        match propagation::extract(reader, &self.options.extract) {
+            Ok(Some(context)) => return context,
+            Ok(None) => {}
+            Err(error) => self.logger.log(
+                LogLevel::Error,
+                "extract",
+                &format!("Starting a new trace: {}", error),
+            ),
+        }
+        // Id 0 leaves the spans without parent, as in Synthetics requests.
+        self.new_trace_context(0, self.ids.next_id())
+    }
+
+    /// Creates the context of a new trace, with a 128-bit trace id if
+    /// they're generated: the upper 64 bits are the creation time in seconds
+    /// followed by zeros, as in the other Datadog tracers.
+    fn new_trace_context(&self, span_id: u64, trace_id: u64) -> SpanContext {
+        let mut context = SpanContext::new(span_id, trace_id, "", HashMap::new());
+        if self.options.trace_id_128bit_generation {
+            let seconds = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since_epoch| since_epoch.as_secs())
+                .unwrap_or_default();
+            context.set_trace_id_high((seconds as u32 as u64) << 32);
+        }
+
+        context
+    }
+
+    pub fn options(&self) -> &TracerOptions {
+        &self.options
+    }
+
+    /// Returns the current configuration of the options which can change
+    /// while the tracer runs.
+    pub(crate) fn config(&self) -> Result<Arc<ConfigSnapshot>> {
+        Ok(self
+            .config
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .clone())
+    }
+
+    /// Changes the sample rate, sampling rules, tags, header tags or log
+    /// level while the tracer runs, e.g. from an admin endpoint, and returns
+    /// the version of the new configuration. Changes are applied all at
+    /// once: if one of them is invalid, e.g. sampling rules which can't be
+    /// read, none is. Spans started since then have the new tags and header
+    /// tags, and traces sampled since then the new rules.
+    pub fn reconfigure(&self, delta: TracerOptionsDelta) -> Result<u64> {
+        let mut config = self.config.lock().map_err(|_| eyre!("mutex lock failed"))?;
+        let debug = delta.debug;
+        let next = config.apply(delta);
+        if config.sampling_changed(&next) {
+            self.buffer
+                .update_sampling_rules(&next.sampling_rules, next.sample_rate)?;
+        }
+        if let Some(debug) = debug {
+            self.logger.set_debug(debug);
+        }
+        let version = next.version;
+        *config = Arc::new(next);
+
+        Ok(version)
+    }
+
+    /// Returns the URL of the trace of `context` in the Datadog app of the
+    /// site of the tracer, e.g. for "view trace" links in error pages.
+    pub fn trace_url(&self, context: &SpanContext) -> String {
+        trace_url(
+            &self.options.site,
+            context.trace_id_high(),
+            context.trace_id(),
+        )
+    }
+
+    /// Returns the integrations of the tracer, which the contrib helpers
+    /// consult before making spans.
+    #[cfg(feature = "contrib")]
+    pub fn integrations(&self) -> &Registry {
+        &self.integrations
+    }
+
+    /// Returns the capabilities discovered from the agent `/info` endpoint, or
+    /// `None` if the agent hasn't answered (yet).
+    pub fn agent_info(&self) -> Result<Option<AgentInfo>> {
+        self.writer.agent_info()
+    }
+
+    /// Applies the `AGENT_CONFIG` remote configuration of the tracer log
+    /// level, e.g. `{"name": "flare-log-level.debug", "config": {"log_level":
+    /// "debug"}}` sent while a flare is prepared. `None`, once the
+    /// configuration is removed, goes back to the `debug` option.
+    pub fn apply_agent_config(&self, config: Option<&Value>) -> Result<()> {
+        let debug = match config {
+            Some(config) => {
+                let level = config
+                    .get("config")
+                    .and_then(|config| config.get("log_level"))
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| eyre!("Agent configuration without log_level: {}", config))?;
+                level.eq_ignore_ascii_case("debug") || level.eq_ignore_ascii_case("trace")
+            }
+            None => self.config()?.debug,
+        };
+        self.logger.set_debug(debug);
+
+        Ok(())
+    }
+
+    /// Sends a flare for the Datadog support case `case_id` through the
+    /// agent: the resolved configuration, the last messages of the tracer
+    /// and the state of its buffers, e.g. when the agent asks for one.
+    pub fn send_flare(&self, case_id: &str, email: &str) -> Result<()> {
+        let mut stats = self.writer.stats()?;
+        stats["pending_traces"] = self.buffer.pending_traces()?.into();
+        stats["runtime_id"] = self.runtime_id.as_str().into();
+        stats["configuration"] = self.config()?.describe(&self.options);
+        if let Ok(rates) = self.agent_sampling_rates() {
+            stats["agent_sampling_rates"] = serde_json::json!(rates);
+        }
+        #[cfg(feature = "contrib")]
+        {
+            stats["integrations"] = self.integrations.to_json()?;
+        }
+        let flare = Flare {
+            config: self.config()?.to_json(&self.options),
+            logs: self.logger.recent_messages(),
+            stats,
+        };
+
+        let hostname = std::env::var("DD_HOSTNAME")
+            .or_else(|_| std::env::var("HOSTNAME"))
+            .unwrap_or_default();
+        let (headers, body) = flare.upload(case_id, email, &hostname, &self.runtime_id);
+        self.writer.post_to_agent(FLARE_ENDPOINT, &headers, &body)
+    }
+
+    /// Must be called right before `fork()`. Flushes what's pending and stops
+    /// the background threads, which don't survive a fork.
+    pub fn prepare_fork(&mut self) -> Result<()> {
+        #[cfg(feature = "threads")]
+        {
+            self.heartbeat = None;
+            self.watchdog = None;
+            self.orphans = None;
+        }
+        self.writer.pause()
+    }
+
+    /// Must be called in the parent process after `fork()` to restart the
+    /// background threads.
+    pub fn after_fork_in_parent(&mut self) -> Result<()> {
+        self.start_heartbeat();
+        self.writer.resume()
+    }
+
+    /// Must be called in the child process after `fork()`. Spans inherited
+    /// from the parent are dropped, the background threads are restarted and
+    /// id generation is reseeded so the child doesn't repeat the parent ids.
+    /// The child gets its own runtime id.
+    pub fn after_fork_in_child(&mut self) -> Result<()> {
+        self.buffer.clear()?;
+        self.writer.clear()?;
+        self.ids.reseed();
+        self.runtime_id = self.ids.next_uuid();
+        self.start_heartbeat();
+        self.writer.resume()
+    }
+}
+
+/// Logs the traces with spans open for at least `max_age`, finishing them
+/// if `finish` is set.
+fn report_abandoned(
+    buffer: &WritingSpanBuffer,
+    logger: &RateLimitedLogger,
+    max_age: Duration,
+    finish: bool,
+) {
+    match buffer.check_abandoned(max_age, finish) {
+        Ok(traces) => {
+            for trace in traces {
+                logger.log(LogLevel::Error, "abandoned", &trace.to_string());
+            }
+        }
+        Err(error) => logger.log(
+            LogLevel::Error,
+            "abandoned",
+            &format!("Failed to check for abandoned spans: {}", error),
+        ),
+    }
+}
+
+#[cfg(feature = "agentless")]
+fn agentless_destination(options: &TracerOptions) -> Result<Destination> {
+    Ok(Destination::Intake(Intake::new(
+        &options.api_key,
+        &options.environment,
+        &options.version,
+    )?))
+}
+
+#[cfg(not(feature = "agentless"))]
+fn agentless_destination(_options: &TracerOptions) -> Result<Destination> {
+    Err(eyre!("Agentless mode requires the agentless feature"))
+}
+
+#[cfg(all(windows, feature = "windows-pipes"))]
+fn pipe_agent_client(options: &TracerOptions) -> Result<Arc<dyn Transport>> {
+    Ok(Arc::new(PipeClient::new(
+        &options.agent_pipe_name,
+        AGENT_TIMEOUT,
+    )))
+}
+
+#[cfg(all(feature = "http-client", not(all(windows, feature = "windows-pipes"))))]
+fn pipe_agent_client(_options: &TracerOptions) -> Result<Arc<dyn Transport>> {
+    Err(eyre!(
+        "Named pipe agent connections require Windows and the windows-pipes feature"
+    ))
+}
+
+#[cfg(feature = "tls")]
+fn https_agent_client(options: &TracerOptions) -> Result<Arc<dyn Transport>> {
+    let client = HttpsClient::from_url(&options.agent_url, AGENT_TIMEOUT)?
+        .with_tls_files(
+            &options.tls_ca_file,
+            &options.tls_cert_file,
+            &options.tls_key_file,
+        )?
+        .with_proxy(&options.proxy_url)?;
+    Ok(Arc::new(client))
+}
+
+const REQUEST_START: &str = "x-request-start";
+const QUEUE_START: &str = "x-queue-start";
+const QUEUE_OPERATION: &str = "http.queue";
+
+const PROXY: &str = "x-dd-proxy";
+const PROXY_REQUEST_TIME: &str = "x-dd-proxy-request-time-ms";
+const PROXY_DOMAIN_NAME: &str = "x-dd-proxy-domain-name";
+const PROXY_METHOD: &str = "x-dd-proxy-httpmethod";
+const PROXY_PATH: &str = "x-dd-proxy-path";
+const PROXY_STAGE: &str = "x-dd-proxy-stage";
+/// The gateways sending `X-Dd-Proxy` headers, and the name of their spans.
+const PROXIES: [(&str, &str); 2] = [
+    ("aws-apigateway", "aws.apigateway"),
+    ("azure-apim", "azure.apim"),
+];
+
+/// Reads the time of a `X-Request-Start` header, e.g. `t=1612345678.123`
+/// from nginx. Load balancers also send it in milliseconds or microseconds,
+/// told apart by magnitude.
+fn parse_request_start(value: &str) -> Option<SystemTime> {
+    let value = value.trim();
+    let time: f64 = value.strip_prefix("t=").unwrap_or(value).parse().ok()?;
+    let seconds = if time < 1e11 {
+        time
+    } else if time < 1e14 {
+        time / 1e3
+    } else {
+        time / 1e6
+    };
+
+    UNIX_EPOCH.checked_add(Duration::try_from_secs_f64(seconds).ok()?)
+}
+
+#[cfg(all(feature = "http-client", not(feature = "tls")))]
+fn https_agent_client(_options: &TracerOptions) -> Result<Arc<dyn Transport>> {
+    Err(eyre!("https agent urls require the tls feature"))
+}
+
+impl opentracing::Tracer for Tracer {
+    fn start_span_with_options(
+        &self,
+        operation_name: &str,
+        options: &StartSpanOptions,
+    ) -> Box<dyn opentracing::Span + '_> {
+        Box::new(Span::new(
+            self,
+            self.start_owned_span(operation_name, options),
+        ))
+    }
+
+    fn inject(
+        &self,
+        sc: &dyn opentracing::SpanContext,
+        writer: &mut dyn TextMapWriter,
+    ) -> Result<()> {
+        let context = sc
+            .as_any()
+            .downcast_ref::<SpanContext>()
+            .ok_or_else(|| eyre!("{:?}", PropagationError::InvalidSpanContext))?;
+
+        // Contexts of local traces propagate their sampling decision, others
+        // what they were extracted with.
+        let sampling_priority = match context.trace_segment() {
+            Some(segment) => self.buffer.assign_sampling_priority(segment)?,
+            None => context.propagated_sampling_priority().clone(),
+        };
+        propagation::inject(
+            context,
+            sampling_priority.as_ref(),
+            &self.options.inject,
+            &InjectOptions {
+                datadog_trace_id_128: self.options.datadog_trace_id_128bit_injection,
+                w3c_trace_id_128: self.options.w3c_trace_id_128bit_injection,
+            },
+            writer,
+        )
+    }
+
+    fn extract(
+        &self,
+        reader: &dyn TextMapReader,
+    ) -> Result<Option<Box<dyn opentracing::SpanContext>>, ExtractionError> {
+        Ok(propagation::extract(reader, &self.options.extract)?
+            .map(|context| Box::new(context) as Box<dyn opentracing::SpanContext>))
+    }
+
+    fn close(&mut self) {
+        let _ = self.writer.pause();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dd::{
+            agent::MockTransport,
+            sample::SamplingPriority,
+            span::{activate, spawn, TraceFilter, TraceProcessor, UserInfo},
+            tags::{EVENTS, RULE_SAMPLE_RATE, SAMPLING_PRIORITY, TRACER_OVERHEAD},
+            tracer::{strip_url_credentials, ConfigSource},
+        },
+        opentracing::Tracer as _,
+    };
+    use serde_json::Value;
+
+    struct Headers(HashMap<String, String>);
+
+    impl TextMapReader for Headers {
+        fn lookup_key(&self, key: &str) -> Result<String, PropagationError> {
+            self.0
+                .get(key)
+                .cloned()
+                .ok_or(PropagationError::KeyNotFound)
+        }
+
+        fn foreach_key(&self, _f: &mut dyn FnMut(&str, &str) -> Result<()>) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl TextMapWriter for Headers {
+        fn set(&mut self, key: &str, value: &str) -> Result<()> {
+            self.0.insert(String::from(key), String::from(value));
+            Ok(())
+        }
+    }
+
+    /// Starts two sibling spans from the context and returns the
+    /// (trace id, parent id) of the spans sent to the agent.
+    fn start_siblings(headers: &[(&str, &str)]) -> (SpanContext, Vec<(u64, u64)>) {
+        let transport = Arc::new(MockTransport::default());
+        let tracer = Tracer::with_transport(TracerOptions::default(), transport.clone()).unwrap();
+        let headers = Headers(
+            headers
+                .iter()
+                .map(|(key, value)| (String::from(*key), String::from(*value)))
+                .collect(),
+        );
+
+        let context = tracer.extract_or_new(&headers);
+        for _ in 0..2 {
+            let options = StartSpanOptions {
+                parent_context: Some(Rc::new(context.clone())),
+                ..Default::default()
+            };
+            let mut span = tracer.start_span_with_options("request", &options);
+            span.finish(Vec::new());
+        }
+        tracer.flush(Duration::from_secs(5)).unwrap();
+
+        let posts = transport.posts.lock().unwrap();
+        let spans = posts
+            .iter()
+            .flat_map(|(_, body)| serde_json::from_slice::<Vec<Vec<Value>>>(body).unwrap())
+            .flatten()
+            .map(|span| {
+                (
+                    span["trace_id"].as_u64().unwrap(),
+                    span["parent_id"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        (context, spans)
+    }
+
+    #[test]
+    fn starts_siblings_from_extracted_context() {
+        let (context, spans) = start_siblings(&[
+            ("x-datadog-trace-id", "100"),
+            ("x-datadog-parent-id", "200"),
+        ]);
+        assert_eq!((context.trace_id(), context.id()), (100, 200));
+        assert_eq!(spans, vec![(100, 200), (100, 200)]);
+
+        let (context, spans) = start_siblings(&[("x-datadog-trace-id", "corrupted")]);
+        assert_eq!(context.id(), 0);
+        let trace_id = context.trace_id();
+        assert_eq!(spans, vec![(trace_id, 0), (trace_id, 0)]);
+    }
+
+    #[test]
+    fn extract_tells_missing_from_corrupted_contexts() {
+        let tracer =
+            Tracer::with_transport(TracerOptions::default(), Arc::new(MockTransport::default()))
+                .unwrap();
+        let extract = |headers: &[(&str, &str)]| {
+            tracer.extract(&Headers(
+                headers
+                    .iter()
+                    .map(|(key, value)| (String::from(*key), String::from(*value)))
+                    .collect(),
+            ))
+        };
+
+        assert!(
+            extract(&[("x-datadog-trace-id", "1"), ("x-datadog-parent-id", "2")])
+                .unwrap()
+                .is_some()
+        );
+        assert!(extract(&[]).unwrap().is_none());
+        assert!(matches!(
+            extract(&[("x-datadog-trace-id", "1"), ("x-datadog-parent-id", "x")]),
+            Err(ExtractionError::Corrupted(_))
+        ));
+    }
+
+    #[test]
+    fn finishes_owned_spans_on_other_threads() {
+        let transport = Arc::new(MockTransport::default());
+        let tracer = Tracer::with_transport(TracerOptions::default(), transport.clone()).unwrap();
+        let root = tracer.start_owned_span("request", &StartSpanOptions::default());
+        let options = StartSpanOptions {
+            parent_context: Some(Rc::new(root.context().clone())),
+            ..Default::default()
+        };
+        let child = tracer.start_owned_span("work", &options);
+        assert!(Arc::ptr_eq(
+            root.context().trace_segment().unwrap(),
+            child.context().trace_segment().unwrap()
+        ));
+
+        let workers: Vec<_> = vec![child, root]
+            .into_iter()
+            .map(|mut span| {
+                std::thread::spawn(move || {
+                    span.set_tag("worker", &Value::Bool(true));
+                    span.finish();
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 1);
+
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+        assert_eq!(traces[0].len(), 2);
+        assert!(traces[0]
+            .iter()
+            .all(|span| span["meta"]["worker"] == "true"));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn flushes_from_async_code() {
+        let transport = Arc::new(MockTransport::default());
+        let tracer = Tracer::with_transport(TracerOptions::default(), transport.clone()).unwrap();
+        tracer
+            .start_owned_span("request", &StartSpanOptions::default())
+            .finish();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let sent = runtime.block_on(tracer.flush_async(Duration::from_secs(5)));
+        assert_eq!(sent.unwrap(), 1);
+        assert_eq!(transport.posts.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn parents_spans_of_workers_to_the_active_span() {
+        let transport = Arc::new(MockTransport::default());
+        let tracer =
+            Arc::new(Tracer::with_transport(TracerOptions::default(), transport.clone()).unwrap());
+        let mut request = tracer.start_owned_span("request", &StartSpanOptions::default());
+        let scope = activate(request.context().clone());
+        let worker_tracer = tracer.clone();
+        let worker = spawn(move || {
+            let mut work = worker_tracer.start_owned_span("work", &StartSpanOptions::default());
+            work.finish();
+        });
+        worker.join().unwrap();
+        drop(scope);
+        request.finish();
+        let mut unrelated = tracer.start_owned_span("unrelated", &StartSpanOptions::default());
+        unrelated.finish();
+        assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 2);
+
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+        let request = traces
+            .iter()
+            .flatten()
+            .find(|span| span["name"] == "request");
+        let work = traces.iter().flatten().find(|span| span["name"] == "work");
+        let unrelated = traces
+            .iter()
+            .flatten()
+            .find(|span| span["name"] == "unrelated");
+        assert_eq!(work.unwrap()["parent_id"], request.unwrap()["span_id"]);
+        assert_eq!(unrelated.unwrap()["parent_id"], 0);
+    }
+
+    #[test]
+    fn records_spans_of_work_measured_elsewhere() {
+        let transport = Arc::new(MockTransport::default());
+        let tracer = Tracer::with_transport(TracerOptions::default(), transport.clone()).unwrap();
+        let start = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let options = StartSpanOptions::default();
+        let mut job = tracer
+            .start_span_at("job", start, Duration::from_secs(90), &options)
+            .unwrap();
+        job.finish();
+
+        assert!(tracer
+            .start_span_at(
+                "job",
+                UNIX_EPOCH - Duration::from_secs(1),
+                Duration::ZERO,
+                &options
+            )
+            .is_err());
+        let later = SystemTime::now() + Duration::from_secs(60);
+        assert!(tracer
+            .start_span_at("job", later, Duration::ZERO, &options)
+            .is_err());
+        assert!(tracer
+            .start_span_at("job", start, Duration::MAX, &options)
+            .is_err());
+
+        assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 1);
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+        assert_eq!(traces[0][0]["start"], 1_600_000_000_000_000_000i64);
+        assert_eq!(traces[0][0]["duration"], 90_000_000_000i64);
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn names_the_feature_of_unsupported_compressions() {
+        let options = TracerOptions {
+            compression: Some(crate::dd::writer::Compression::Zstd),
+            ..Default::default()
+        };
+        let err = Tracer::with_transport(options, Arc::new(MockTransport::default()))
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "zstd compression requires the zstd feature"
+        );
+    }
+
+    #[test]
+    fn reports_the_time_requests_were_queued() {
+        let start = SystemTime::now() - Duration::from_millis(250);
+        let seconds = start.duration_since(UNIX_EPOCH).unwrap().as_secs_f64();
+        assert!(parse_request_start("t=1612345678.5")
+            .is_some_and(|time| time == UNIX_EPOCH + Duration::from_millis(1_612_345_678_500)));
+        assert_eq!(
+            parse_request_start("1612345678500"),
+            parse_request_start("1612345678500000")
+        );
+        assert!(parse_request_start("t=soon").is_none());
+        assert!(parse_request_start("-1").is_none());
+
+        let transport = Arc::new(MockTransport::default());
+        let headers = Headers(
+            vec![(
+                String::from(QUEUE_START),
+                format!("t={}", (seconds * 1e6) as u64),
+            )]
+            .into_iter()
+            .collect(),
+        );
+        let tracer = Tracer::with_transport(TracerOptions::default(), transport.clone()).unwrap();
+        assert!(tracer
+            .start_queue_span(&headers, &StartSpanOptions::default())
+            .is_none());
+
+        let options = TracerOptions {
+            request_queuing: true,
+            ..Default::default()
+        };
+        let tracer = Tracer::with_transport(options, transport.clone()).unwrap();
+        let mut queue = tracer
+            .start_queue_span(&headers, &StartSpanOptions::default())
+            .unwrap();
+        let options = StartSpanOptions {
+            parent_context: Some(Rc::new(queue.context().clone())),
+            ..Default::default()
+        };
+        let mut request = tracer.start_owned_span("request", &options);
+        queue.finish();
+        request.finish();
+
+        assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 1);
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+        let queue = traces[0]
+            .iter()
+            .find(|span| span["name"] == QUEUE_OPERATION)
+            .unwrap();
+        let request = traces[0]
+            .iter()
+            .find(|span| span["name"] == "request")
+            .unwrap();
+        assert_eq!(request["parent_id"], queue["span_id"]);
+        assert!(queue["duration"].as_i64().unwrap() >= 250_000_000);
+    }
+
+    #[test]
+    fn infers_the_spans_of_api_gateways() {
+        let start = SystemTime::now() - Duration::from_millis(250);
+        let millis = start.duration_since(UNIX_EPOCH).unwrap().as_millis();
+        let headers = Headers(
+            vec![
+                (PROXY, String::from("aws-apigateway")),
+                (PROXY_REQUEST_TIME, millis.to_string()),
+                (PROXY_DOMAIN_NAME, String::from("api.example.com")),
+                (PROXY_METHOD, String::from("GET")),
+                (PROXY_PATH, String::from("/users")),
+                (PROXY_STAGE, String::from("prod")),
+            ]
+            .into_iter()
+            .map(|(key, value)| (String::from(key), value))
+            .collect(),
+        );
+        let transport = Arc::new(MockTransport::default());
+        let tracer = Tracer::with_transport(TracerOptions::default(), transport.clone()).unwrap();
+        assert!(tracer
+            .start_proxy_span(&headers, &StartSpanOptions::default())
+            .is_none());
+
+        let options = TracerOptions {
+            inferred_proxy_services: true,
+            ..Default::default()
+        };
+        let tracer = Tracer::with_transport(options, transport.clone()).unwrap();
+        let mut proxy = tracer
+            .start_proxy_span(&headers, &StartSpanOptions::default())
+            .unwrap();
+        let options = StartSpanOptions {
+            parent_context: Some(Rc::new(proxy.context().clone())),
+            ..Default::default()
+        };
+        let mut request = tracer.start_owned_span("request", &options);
+        request.finish();
+        proxy.finish();
+
+        assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 1);
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+        let proxy = traces[0]
+            .iter()
+            .find(|span| span["name"] == "aws.apigateway")
+            .unwrap();
+        let request = traces[0]
+            .iter()
+            .find(|span| span["name"] == "request")
+            .unwrap();
+        assert_eq!(request["parent_id"], proxy["span_id"]);
+        assert_eq!(proxy["service"], "api.example.com");
+        assert_eq!(proxy["resource"], "GET /users");
+        assert_eq!(proxy["type"], "web");
+        assert_eq!(proxy["meta"]["http.url"], "api.example.com/users");
+        assert_eq!(proxy["meta"]["stage"], "prod");
+        assert_eq!(proxy["metrics"][INFERRED_SPAN], 1.0);
+        assert_eq!(proxy["start"], millis as i64 * 1_000_000);
+        assert!(proxy["duration"].as_i64().unwrap() >= 250_000_000);
+
+        let headers = Headers(
+            vec![(String::from(PROXY), String::from("nginx"))]
+                .into_iter()
+                .collect(),
+        );
+        assert!(tracer
+            .start_proxy_span(&headers, &StartSpanOptions::default())
+            .is_none());
+    }
+
+    #[test]
+    fn traces_through_the_global_tracer() {
+        let transport = Arc::new(MockTransport::default());
+        let tracer = Tracer::with_transport(TracerOptions::default(), transport.clone()).unwrap();
+        opentracing::init_global(Arc::new(tracer));
+
+        let tracer = opentracing::global().unwrap();
+        let mut carrier = HashMap::new();
+        {
+            let span = tracer.start_span("legacy", Vec::new());
+            tracer.inject(span.context(), &mut carrier).unwrap();
+        }
+        let context = tracer.extract(&carrier).unwrap().unwrap();
+        let options = StartSpanOptions {
+            parent_context: Some(Rc::from(context)),
+            ..Default::default()
+        };
+        drop(tracer.start_span_with_options("child", &options));
+
+        assert!(carrier.contains_key("x-datadog-trace-id"));
+        assert!(tracer.extract(&HashMap::new()).unwrap().is_none());
+    }
+
+    #[test]
+    fn reconfigures_running_tracers() {
+        let transport = Arc::new(MockTransport::default());
+        let options = TracerOptions {
+            sample_rate: 1.0,
+            tags: vec![(String::from("team"), String::from("web"))]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let tracer = Tracer::with_transport(options, transport.clone()).unwrap();
+        let sampled = |tracer: &Tracer| {
+            let mut span = tracer.start_owned_span("request", &StartSpanOptions::default());
+            let mut headers = Headers(HashMap::new());
+            tracer.inject(span.context(), &mut headers).unwrap();
+            span.finish();
+            headers.0["x-datadog-sampling-priority"].clone()
+        };
+        assert_eq!(sampled(&tracer), "1");
+        let origins = &tracer.config().unwrap().origins;
+        assert_eq!(origins.get("sample_rate"), ConfigSource::Code);
+        assert_eq!(origins.get("write_period_ms"), ConfigSource::Default);
+
+        let invalid = TracerOptionsDelta {
+            sample_rate: Some(0.0),
+            sampling_rules: Some(String::from("{")),
+            debug: Some(true),
+            ..Default::default()
+        };
+        assert!(tracer.reconfigure(invalid).is_err());
+        assert_eq!(tracer.config().unwrap().version, 0);
+        assert_eq!(sampled(&tracer), "1");
+
+        let delta = TracerOptionsDelta {
+            sample_rate: Some(0.0),
+            tags: Some(
+                vec![(String::from("team"), String::from("platform"))]
+                    .into_iter()
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+        assert_eq!(tracer.reconfigure(delta).unwrap(), 1);
+        assert_eq!(sampled(&tracer), "0");
+        let config = tracer.config().unwrap();
+        assert_eq!(config.sample_rate, 0.0);
+        assert_eq!(config.sampling_rules, "[]");
+        assert_eq!(config.to_json(tracer.options())["config_version"], 1);
+        assert_eq!(config.origins.get("tags"), ConfigSource::RemoteConfig);
+        let described = config.describe(tracer.options());
+        let sample_rate = described
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|setting| setting["name"] == "sample_rate")
+            .unwrap();
+        assert_eq!(sample_rate["value"], 0.0);
+        assert_eq!(sample_rate["origin"], "remote_config");
+
+        tracer.flush(Duration::from_secs(5)).unwrap();
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+        let teams: Vec<&Value> = traces
+            .iter()
+            .map(|trace| &trace[0]["meta"]["team"])
+            .collect();
+        assert_eq!(teams, vec!["web", "web", "platform"]);
+    }
+
+    #[test]
+    fn reconfigures_header_tags() {
+        let transport = Arc::new(MockTransport::default());
+        let options = TracerOptions {
+            header_tags: vec![(String::from("x-tenant-id"), String::from("tenant"))]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let tracer = Tracer::with_transport(options, transport.clone()).unwrap();
+        let headers = Headers(
+            vec![
+                (String::from("x-tenant-id"), String::from("acme")),
+                (String::from("user-agent"), String::from("curl/8.0")),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        let request = |tracer: &Tracer| {
+            let mut span = tracer.start_owned_span("request", &StartSpanOptions::default());
+            tracer.tag_request_headers(&headers, &mut span);
+            span.finish();
+        };
+
+        request(&tracer);
+        let delta = TracerOptionsDelta {
+            header_tags: Some(
+                vec![(String::from("User-Agent"), String::from("http.useragent"))]
+                    .into_iter()
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+        assert_eq!(tracer.reconfigure(delta).unwrap(), 1);
+        request(&tracer);
+        let config = tracer.config().unwrap();
+        assert_eq!(
+            config.origins.get("header_tags"),
+            ConfigSource::RemoteConfig
+        );
+
+        tracer.flush(Duration::from_secs(5)).unwrap();
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+        let tags: Vec<(&Value, &Value)> = traces
+            .iter()
+            .map(|trace| {
+                (
+                    &trace[0]["meta"]["tenant"],
+                    &trace[0]["meta"]["http.useragent"],
+                )
+            })
+            .collect();
+        assert_eq!(
+            tags,
+            vec![
+                (&Value::from("acme"), &Value::Null),
+                (&Value::Null, &Value::from("curl/8.0"))
+            ]
+        );
+    }
+
+    #[test]
+    fn decides_sampling_before_starting_traces() {
+        let transport = Arc::new(MockTransport::default());
+        let options = TracerOptions {
+            sampling_rules: String::from(
+                r#"[{"service": "web", "resource": "GET /health", "sample_rate": 0}]"#,
+            ),
+            sample_rate: 1.0,
+            ..Default::default()
+        };
+        let tracer = Tracer::with_transport(options, transport.clone()).unwrap();
+
+        let health = tracer
+            .should_sample("web", "http.request", "GET /health")
+            .unwrap();
+        assert!(!health.keep());
+        assert_eq!(health.priority(), Some(SamplingPriority::SamplerDrop));
+        assert_eq!(health.mechanism(), "-3");
+
+        let users = tracer
+            .should_sample("web", "http.request", "GET /users")
+            .unwrap();
+        assert!(users.keep());
+        assert_eq!(users.result.rule_rate, 1.0);
+        let mut request =
+            tracer.start_decided_span(&users, "http.request", &StartSpanOptions::default());
+        assert_eq!(request.context().trace_id(), users.trace_id);
+        let mut headers = Headers(HashMap::new());
+        tracer.inject(request.context(), &mut headers).unwrap();
+        assert_eq!(headers.0["x-datadog-sampling-priority"], "1");
+        request.finish();
+
+        assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 1);
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+        assert_eq!(traces[0][0]["trace_id"], users.trace_id);
+        assert_eq!(traces[0][0]["parent_id"], 0);
+        assert_eq!(traces[0][0]["metrics"][SAMPLING_PRIORITY], 1.0);
+        assert_eq!(traces[0][0]["metrics"][RULE_SAMPLE_RATE], 1.0);
+    }
+
+    #[test]
+    fn bounds_the_spans_of_traces() {
+        let transport = Arc::new(MockTransport::default());
+        let options = TracerOptions {
+            max_spans_per_trace: 3,
+            ..Default::default()
+        };
+        let tracer = Tracer::with_transport(options, transport.clone()).unwrap();
+        let child_of = |parent: &OwnedSpan| StartSpanOptions {
+            parent_context: Some(Rc::new(parent.context().clone())),
+            ..Default::default()
+        };
+        let root = tracer.start_owned_span("recurse", &StartSpanOptions::default());
+        let mut spans = vec![root];
+        for _ in 0..4 {
+            let span = tracer.start_owned_span("recurse", &child_of(spans.last().unwrap()));
+            spans.push(span);
+        }
+        let recorded = spans[2].context().id();
+        assert_eq!(spans[3].context().id(), recorded);
+        assert_eq!(spans[4].context().id(), recorded);
+        let mut headers = Headers(HashMap::new());
+        tracer.inject(spans[4].context(), &mut headers).unwrap();
+        assert_eq!(headers.0["x-datadog-parent-id"], recorded.to_string());
+        while let Some(mut span) = spans.pop() {
+            span.set_tag("depth", &Value::from(spans.len()));
+            span.finish();
+        }
+
+        assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 1);
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+        assert_eq!(traces[0].len(), 3);
+        let root = traces[0].iter().find(|span| span["parent_id"] == 0);
+        assert_eq!(root.unwrap()["meta"][SPAN_LIMIT_REACHED], "true");
+    }
+
+    #[test]
+    fn tags_local_roots_with_their_user() {
+        let transport = Arc::new(MockTransport::default());
+        let tracer = Tracer::with_transport(TracerOptions::default(), transport.clone()).unwrap();
+        let request = tracer.start_owned_span("http.request", &StartSpanOptions::default());
+        let options = StartSpanOptions {
+            parent_context: Some(Rc::new(request.context().clone())),
+            ..Default::default()
+        };
+        let mut auth = tracer.start_owned_span("auth", &options);
+        auth.set_user(&UserInfo {
+            id: String::from("user-42"),
+            email: String::from("jane@example.com"),
+            propagate: true,
+            ..Default::default()
+        });
+        let mut headers = Headers(HashMap::new());
+        tracer.inject(auth.context(), &mut headers).unwrap();
+        assert_eq!(headers.0["x-datadog-tags"], "_dd.p.usr.id=dXNlci00Mg==");
+        drop(auth);
+        drop(request);
+
+        assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 1);
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+        let root = traces[0].iter().find(|span| span["name"] == "http.request");
+        let meta = &root.unwrap()["meta"];
+        assert_eq!(meta["usr.id"], "user-42");
+        assert_eq!(meta["usr.email"], "jane@example.com");
+        assert_eq!(meta["_dd.p.usr.id"], "dXNlci00Mg==");
+        assert!(meta.get("usr.name").is_none());
+        let auth = traces[0].iter().find(|span| span["name"] == "auth");
+        assert!(auth.unwrap()["meta"].get("usr.id").is_none());
+    }
+
+    #[test]
+    fn restores_traces_saved_by_other_processes() {
+        let options = || TracerOptions {
+            sample_rate: 1.0,
+            trace_id_128bit_generation: true,
+            ..Default::default()
+        };
+        let saving_transport = Arc::new(MockTransport::default());
+        let saving = Tracer::with_transport(options(), saving_transport.clone()).unwrap();
+        let mut job = saving.start_owned_span("job", &StartSpanOptions::default());
+        job.set_baggage_item("tenant", "acme");
+        job.log(&[(String::from("event"), Value::from("checkpoint"))]);
+        let parent = job.context().with_id(job.context().id()).unwrap();
+        let parent: Rc<dyn opentracing::SpanContext> = Rc::new(parent);
+        let step = |name: &str| {
+            let options = StartSpanOptions {
+                parent_context: Some(parent.clone()),
+                ..Default::default()
+            };
+            saving.start_owned_span(name, &options)
+        };
+        step("load").finish();
+        let mut transform = step("transform");
+        transform.set_tag("rows", &Value::from(42));
+        let trace_id = job.context().trace_id();
+        let trace_id_high = job.context().trace_id_high();
+        job.context()
+            .trace_segment()
+            .unwrap()
+            .set_trace_tag("_dd.p.dm", "-3")
+            .unwrap();
+
+        // The other running spans would be lost, so it's finished instead.
+        assert!(saving.save_trace(vec![step("validate")]).is_err());
+        let saved = saving.save_trace(vec![job, transform]).unwrap();
+        assert_eq!(saving.flush(Duration::from_secs(5)).unwrap(), 0);
+        assert!(saving_transport.posts.lock().unwrap().is_empty());
+
+        let transport = Arc::new(MockTransport::default());
+        let restoring = Tracer::with_transport(options(), transport.clone()).unwrap();
+        let mut spans = restoring.restore_trace(&saved).unwrap();
+        assert!(restoring.restore_trace(&saved).is_err());
+        assert_eq!(spans[0].context().trace_id(), trace_id);
+        assert_eq!(spans[0].context().trace_id_high(), trace_id_high);
+        assert_eq!(spans[0].baggage_item("tenant"), "acme");
+        let mut headers = Headers(HashMap::new());
+        restoring.inject(spans[1].context(), &mut headers).unwrap();
+        assert_eq!(headers.0["x-datadog-trace-id"], trace_id.to_string());
+        spans.reverse();
+        for mut span in spans {
+            span.finish();
+        }
+
+        assert_eq!(restoring.flush(Duration::from_secs(5)).unwrap(), 1);
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+        assert_eq!(traces[0].len(), 4);
+        let span = |name: &str| {
+            traces[0]
+                .iter()
+                .find(|span| span["name"] == name)
+                .unwrap()
+                .clone()
+        };
+        let job = span("job");
+        assert_eq!(job["trace_id"], trace_id);
+        assert_eq!(job["metrics"][SAMPLING_PRIORITY], 1.0);
+        assert_eq!(job["meta"]["_dd.p.dm"], "-3");
+        assert_eq!(
+            job["meta"][TRACE_ID_HIGH_TAG],
+            format!("{:016x}", trace_id_high)
+        );
+        assert!(job["meta"][EVENTS].as_str().unwrap().contains("checkpoint"));
+        assert_eq!(span("load")["parent_id"], job["span_id"]);
+        let transform = span("transform");
+        assert_eq!(transform["parent_id"], job["span_id"]);
+        assert_eq!(transform["metrics"]["rows"], 42.0);
+    }
+
+    #[test]
+    fn measures_the_overhead_of_tracing() {
+        let transport = Arc::new(MockTransport::default());
+        let options = TracerOptions {
+            overhead_metrics: true,
+            ..Default::default()
+        };
+        let tracer = Tracer::with_transport(options, transport.clone()).unwrap();
+        let mut root = tracer.start_owned_span("request", &StartSpanOptions::default());
+        root.set_tag("http.method", &Value::from("GET"));
+        let options = StartSpanOptions {
+            parent_context: Some(Rc::new(
+                root.context().with_id(root.context().id()).unwrap(),
+            )),
+            ..Default::default()
+        };
+        tracer.start_owned_span("query", &options).finish();
+        root.finish();
+
+        assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 1);
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+        let root = traces[0]
+            .iter()
+            .find(|span| span["name"] == "request")
+            .unwrap();
+        let overhead = root["metrics"][TRACER_OVERHEAD].as_f64().unwrap();
+        assert!(overhead > 0.0);
+        let query = traces[0]
+            .iter()
+            .find(|span| span["name"] == "query")
+            .unwrap();
+        assert!(query["metrics"].get(TRACER_OVERHEAD).is_none());
+
+        let metrics = tracer.health_metrics().unwrap();
+        assert_eq!(metrics["tracer.overhead_ns"], overhead as u64);
+        assert!(metrics["tracer.encoding_ns"] > 0);
+
+        let tracer = Tracer::with_transport(TracerOptions::default(), transport.clone()).unwrap();
+        assert!(!tracer
+            .health_metrics()
+            .unwrap()
+            .contains_key("tracer.overhead_ns"));
+    }
+
+    #[test]
+    fn runs_independent_tracers_side_by_side() {
+        let new_tracer = |service: &str| {
+            let transport = Arc::new(MockTransport::default());
+            let options = TracerOptions {
+                service: String::from(service),
+                ..Default::default()
+            };
+            let tracer = Tracer::with_transport(options, transport.clone()).unwrap();
+            (tracer, transport)
+        };
+        let (web, web_transport) = new_tracer("web");
+        let (billing, billing_transport) = new_tracer("billing");
+
+        std::thread::scope(|scope| {
+            for tracer in [&web, &billing] {
+                scope.spawn(move || {
+                    for _ in 0..10 {
+                        let mut span =
+                            tracer.start_owned_span("request", &StartSpanOptions::default());
+                        span.finish();
+                    }
+                });
+            }
+        });
+        // A span of the plugin handling a request of the host.
+        let mut request = web.start_owned_span("request", &StartSpanOptions::default());
+        let options = StartSpanOptions {
+            parent_context: Some(Rc::new(request.context().clone())),
+            ..Default::default()
+        };
+        let mut charge = billing.start_owned_span("charge", &options);
+        charge.finish();
+        request.finish();
+        assert_eq!(web.flush(Duration::from_secs(5)).unwrap(), 11);
+        assert_eq!(billing.flush(Duration::from_secs(5)).unwrap(), 11);
+
+        let sent = |transport: &MockTransport| -> Vec<Value> {
+            let posts = transport.posts.lock().unwrap();
+            let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+            traces.into_iter().flatten().collect()
+        };
+        let web_spans = sent(&web_transport);
+        let billing_spans = sent(&billing_transport);
+        assert!(web_spans.iter().all(|span| span["service"] == "web"));
+        assert!(billing_spans
+            .iter()
+            .all(|span| span["service"] == "billing"));
+        assert_ne!(
+            web_spans[0]["meta"][RUNTIME_ID],
+            billing_spans[0]["meta"][RUNTIME_ID]
+        );
+
+        let charge = billing_spans
+            .iter()
+            .find(|span| span["name"] == "charge")
+            .unwrap();
+        let request = web_spans
+            .iter()
+            .find(|span| span["span_id"] == charge["parent_id"])
+            .unwrap();
+        assert_eq!(charge["trace_id"], request["trace_id"]);
+        assert!(charge["meta"].get(RUNTIME_ID).is_some());
+    }
+
+    #[test]
+    fn applies_agent_and_pinned_rates() {
+        let transport = Arc::new(MockTransport {
+            response_body: br#"{"rate_by_service": {"service:web,env:": 0.0}}"#.to_vec(),
+            ..Default::default()
+        });
+        let options = TracerOptions {
+            service: String::from("web"),
+            ..Default::default()
+        };
+        let tracer = Tracer::with_transport(options, transport).unwrap();
+        let sampling_priority = || {
+            let span = tracer.start_owned_span("request", &StartSpanOptions::default());
+            let mut headers = Headers(HashMap::new());
+            tracer.inject(span.context(), &mut headers).unwrap();
+            headers.0.remove("x-datadog-sampling-priority").unwrap()
+        };
+
+        assert_eq!(sampling_priority(), "1");
+        tracer.flush(Duration::from_secs(5)).unwrap();
+        assert_eq!(
+            tracer.agent_sampling_rates().unwrap()["service:web,env:"],
+            0.0
+        );
+        assert_eq!(sampling_priority(), "0");
+
+        tracer.pin_sampling_rate("web", "", 1.0).unwrap();
+        tracer.flush(Duration::from_secs(5)).unwrap();
+        assert_eq!(sampling_priority(), "1");
+        tracer.clear_pinned_sampling_rate("web", "").unwrap();
+        assert_eq!(sampling_priority(), "0");
+    }
+
+    #[test]
+    fn filters_traces() {
+        struct DropBatches;
+
+        impl TraceFilter for DropBatches {
+            fn keep(&self, spans: &[SpanData]) -> bool {
+                spans.iter().all(|span| &*span.name != "batch")
+            }
+        }
+
+        let transport = Arc::new(MockTransport::default());
+        let options = TracerOptions {
+            filter_urls: vec![String::from("/health*")],
+            trace_filters: vec![Arc::new(DropBatches)],
+            ..Default::default()
+        };
+        let tracer = Tracer::with_transport(options, transport.clone()).unwrap();
+
+        let mut span = tracer.start_owned_span("request", &StartSpanOptions::default());
+        span.set_tag("http.url", &Value::from("http://web/healthz"));
+        drop(span);
+        drop(tracer.start_owned_span("batch", &StartSpanOptions::default()));
+        assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 0);
+
+        drop(tracer.start_owned_span("request", &StartSpanOptions::default()));
+        assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 1);
+    }
+
+    #[test]
+    fn processes_traces_before_sending() {
+        struct ScrubQueries;
+
+        impl TraceProcessor for ScrubQueries {
+            fn process(&self, spans: &mut Vec<SpanData>) {
+                spans.retain(|span| &*span.name != "cache.get");
+                for span in spans.iter_mut() {
+                    span.meta.remove("db.statement");
+                }
+            }
+        }
+
+        let transport = Arc::new(MockTransport::default());
+        let options = TracerOptions {
+            trace_processors: vec![Box::new(ScrubQueries)],
+            ..Default::default()
+        };
+        let tracer = Tracer::with_transport(options, transport.clone()).unwrap();
+
+        let mut span = tracer.start_owned_span("db.query", &StartSpanOptions::default());
+        span.set_tag("db.statement", &Value::from("SELECT secret"));
+        drop(span);
+        drop(tracer.start_owned_span("cache.get", &StartSpanOptions::default()));
+        assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 1);
+
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+        assert_eq!(traces.len(), 1);
+        assert_eq!(traces[0][0]["name"], "db.query");
+        assert!(traces[0][0]["meta"].get("db.statement").is_none());
+    }
+
+    #[test]
+    fn tags_local_roots_with_the_process() {
+        let transport = Arc::new(MockTransport::default());
+        let options = TracerOptions {
+            git_repository_url: strip_url_credentials("https://token@github.com/org/repo"),
+            git_commit_sha: String::from("3a1c0d8f1b9e"),
+            ..Default::default()
+        };
+        let tracer = Tracer::with_transport(options, transport.clone()).unwrap();
+        let root = tracer.start_owned_span("request", &StartSpanOptions::default());
+        let child = tracer.start_owned_span(
+            "db.query",
+            &StartSpanOptions {
+                parent_context: Some(Rc::new(root.context().clone())),
+                ..Default::default()
+            },
+        );
+        drop(child);
+        drop(root);
+        tracer.flush(Duration::from_secs(5)).unwrap();
+
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+        let (child, root) = (&traces[0][0], &traces[0][1]);
+        assert_eq!(root["meta"][RUNTIME_ID], tracer.runtime_id.as_str());
+        assert_eq!(tracer.runtime_id.len(), 36);
+        assert_eq!(root["meta"][LANGUAGE], "rust");
+        assert_eq!(root["metrics"][PROCESS_ID], std::process::id() as f64);
+        assert!(child["meta"].get(RUNTIME_ID).is_none());
+        assert_eq!(
+            root["meta"][GIT_REPOSITORY_URL],
+            "https://github.com/org/repo"
+        );
+        assert_eq!(root["meta"][GIT_COMMIT_SHA], "3a1c0d8f1b9e");
+        assert!(child["meta"].get(GIT_COMMIT_SHA).is_none());
+        drop(posts);
+        assert_eq!(transport.header_values("Datadog-Meta-Lang"), ["rust"]);
+    }
+
+    #[test]
+    fn switches_debug_logging_from_agent_config() {
+        let tracer =
+            Tracer::with_transport(TracerOptions::default(), Arc::new(MockTransport::default()))
+                .unwrap();
+        assert!(!tracer.logger.debug());
+
+        let config = serde_json::json!({
+            "name": "flare-log-level.debug",
+            "config": {"log_level": "debug"}
+        });
+        tracer.apply_agent_config(Some(&config)).unwrap();
+        assert!(tracer.logger.debug());
+        assert!(tracer
+            .apply_agent_config(Some(&serde_json::json!({"name": "x"})))
+            .is_err());
+        assert!(tracer.logger.debug());
+
+        tracer.apply_agent_config(None).unwrap();
+        assert!(!tracer.logger.debug());
+    }
+
+    #[test]
+    fn sends_flares_to_the_agent() {
+        let transport = Arc::new(MockTransport::default());
+        let options = TracerOptions {
+            service: String::from("web"),
+            api_key: String::from("secret"),
+            ..Default::default()
+        };
+        let tracer = Tracer::with_transport(options, transport.clone()).unwrap();
+        tracer.logger.log(LogLevel::Error, "send", "agent down");
+
+        tracer.send_flare("12345", "me@example.com").unwrap();
+        let posts = transport.posts.lock().unwrap();
+        let (headers, body) = &posts[0];
+        assert!(headers
+            .iter()
+            .any(|(key, value)| key == "Content-Type" && value.starts_with("multipart/form-data")));
+        let body = String::from_utf8_lossy(body);
+        assert!(body.contains("name=\"case_id\"\r\n\r\n12345\r\n"));
+        assert!(body.contains("\"service\": \"web\""));
+        assert!(body.contains("Error: agent down"));
+        assert!(!body.contains("secret"));
+    }
+
+    #[test]
+    fn propagates_without_sending_in_propagation_only_mode() {
+        let transport = Arc::new(MockTransport::default());
+        let options = TracerOptions {
+            propagation_only: true,
+            sample_rate: 0.0,
+            ..Default::default()
+        };
+        let tracer = Tracer::with_transport(options, transport.clone()).unwrap();
+
+        let span = tracer.start_owned_span("request", &StartSpanOptions::default());
+        let mut headers = Headers(HashMap::new());
+        tracer.inject(span.context(), &mut headers).unwrap();
+        assert_eq!(
+            headers.0["x-datadog-trace-id"],
+            span.context().trace_id().to_string()
+        );
+        assert_eq!(headers.0["x-datadog-sampling-priority"], "0");
+        drop(span);
+
+        assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 0);
+        assert!(transport.posts.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,110 @@
+use super::{ConfigOrigins, ConfigSource, TracerOptions};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// TracerOptionsDelta holds the options which can change while the tracer
+/// runs, the ones remote configuration may change, e.g. from an admin
+/// endpoint. Options left to None are kept.
+#[derive(Debug, Default, Clone)]
+pub struct TracerOptionsDelta {
+    pub sample_rate: Option<f32>,
+    pub sampling_rules: Option<String>,
+    /// Replaces all of the tags added to spans.
+    pub tags: Option<HashMap<String, String>>,
+    /// Replaces all of the headers tagged on the spans of requests, see
+    /// `TracerOptions::header_tags`.
+    pub header_tags: Option<HashMap<String, String>>,
+    pub debug: Option<bool>,
+}
+
+/// ConfigSnapshot is the configuration of a tracer which can change while
+/// it runs. Each change makes a new snapshot with the next version, so that
+/// a span sees a consistent configuration whatever the changes happening
+/// meanwhile.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ConfigSnapshot {
+    /// 0 for the options of the tracer, bumped by each change.
+    pub version: u64,
+    pub sample_rate: f32,
+    pub sampling_rules: String,
+    pub tags: HashMap<String, String>,
+    pub header_tags: HashMap<String, String>,
+    pub debug: bool,
+    pub origins: ConfigOrigins,
+}
+
+impl ConfigSnapshot {
+    pub fn new(options: &TracerOptions) -> Self {
+        Self {
+            version: 0,
+            sample_rate: options.sample_rate,
+            sampling_rules: options.sampling_rules.clone(),
+            tags: options.tags.clone(),
+            header_tags: options.header_tags.clone(),
+            debug: options.debug,
+            origins: options.origins.clone(),
+        }
+    }
+
+    /// Returns the next snapshot, with `delta` applied. The settings it
+    /// changes are recorded as remote configuration.
+    pub fn apply(&self, delta: TracerOptionsDelta) -> Self {
+        let mut origins = self.origins.clone();
+        let changed = [
+            ("sample_rate", delta.sample_rate.is_some()),
+            ("sampling_rules", delta.sampling_rules.is_some()),
+            ("tags", delta.tags.is_some()),
+            ("header_tags", delta.header_tags.is_some()),
+            ("debug", delta.debug.is_some()),
+        ];
+        for (key, _) in changed.iter().filter(|(_, changed)| *changed) {
+            origins.set(key, ConfigSource::RemoteConfig);
+        }
+
+        Self {
+            version: self.version + 1,
+            sample_rate: delta.sample_rate.unwrap_or(self.sample_rate),
+            sampling_rules: delta
+                .sampling_rules
+                .unwrap_or_else(|| self.sampling_rules.clone()),
+            tags: delta.tags.unwrap_or_else(|| self.tags.clone()),
+            header_tags: delta
+                .header_tags
+                .map(|header_tags| {
+                    header_tags
+                        .into_iter()
+                        .map(|(header, tag)| (header.to_ascii_lowercase(), tag))
+                        .collect()
+                })
+                .unwrap_or_else(|| self.header_tags.clone()),
+            debug: delta.debug.unwrap_or(self.debug),
+            origins,
+        }
+    }
+
+    /// Returns the JSON of `options` as changed by the snapshot, e.g. for
+    /// flares.
+    pub fn to_json(&self, options: &TracerOptions) -> Value {
+        let mut config = options.to_json();
+        config["config_version"] = json!(self.version);
+        config["sample_rate"] = json!(self.sample_rate);
+        config["sampling_rules"] = json!(self.sampling_rules);
+        config["tags"] = json!(self.tags);
+        config["header_tags"] = json!(self.header_tags);
+        config["debug"] = json!(self.debug);
+
+        config
+    }
+
+    /// Returns the settings with their origin, see `ConfigOrigins::describe`.
+    pub fn describe(&self, options: &TracerOptions) -> Value {
+        self.origins.describe(&self.to_json(options))
+    }
+
+    /// Whether the sampler has to change to apply `next`.
+    pub fn sampling_changed(&self, next: &ConfigSnapshot) -> bool {
+        // Rates are compared by bits, NaN meaning no default rate.
+        self.sample_rate.to_bits() != next.sample_rate.to_bits()
+            || self.sampling_rules != next.sampling_rules
+    }
+}
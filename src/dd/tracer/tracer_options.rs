@@ -1,8 +1,33 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    sync::Arc,
+};
 
-use super::PropagationStyle;
+use super::{
+    propagation::parse_propagation_styles, ConfigOrigins, ConfigSource, PropagationStyle,
+    StatusCodes,
+};
+use crate::dd::{
+    sample::SamplerOverride,
+    span::{BaggageLimits, SpanFinishHook, TraceFilter, TraceProcessor},
+    utils::{default_log_func, LogFunc},
+    writer::{CircuitOpenPolicy, Compression, QueueFullPolicy},
+};
+use eyre::{eyre, Result};
+use serde_json::{json, Value};
 
 pub struct TracerOptions {
+    /// Turns tracing off when false: `make_tracer` then returns a tracer
+    /// recording nothing, which still passes the propagated span context
+    /// through. Defaults to `DD_TRACE_ENABLED`, which overrides the JSON
+    /// configuration.
+    pub enabled: bool,
+    /// Starts spans and propagates their context, sampling decision
+    /// included, but sends nothing to the agent, e.g. to try the tracer out
+    /// in a sensitive service without changing the traces of the services
+    /// it calls. Enabled by `DD_APM_TRACING_ENABLED=false`.
+    pub propagation_only: bool,
     pub agent_host: String,
     pub agent_port: u16,
     pub service: String,
@@ -11,6 +36,20 @@ pub struct TracerOptions {
     pub sample_rate: f32,
     pub priority_sampling: bool,
     pub sampling_rules: String,
+    /// Decides the sampling priority of traces before the sampling rules,
+    /// e.g. to keep the traces of some customers from a tag of the local
+    /// root. Needs `priority_sampling`.
+    pub sampler_override: Option<SamplerOverride>,
+    /// Keeps the traces the sampler dropped if one of their spans has an
+    /// error, checked as each chunk is written. These traces are tagged
+    /// with their own sampling mechanism. Services called with the dropped
+    /// decision still drop their part. Defaults to `DD_TRACE_ERROR_SAMPLING`.
+    pub error_sampling: bool,
+    /// Keeps the traces the sampler dropped if their local root ran for at
+    /// least this long, in milliseconds, up to `latency_keep_rate_limit`
+    /// traces per second. 0 disables the rule.
+    pub latency_keep_threshold_ms: u32,
+    pub latency_keep_rate_limit: f64,
     pub write_perios_ms: u32,
     pub operation_name_override: String,
     pub extract: HashSet<PropagationStyle>,
@@ -19,6 +58,461 @@ pub struct TracerOptions {
     pub analytics_enabled: bool,
     pub analytics_rate: f32,
     pub tags: HashMap<String, String>,
+    /// Renames services as their spans finish, e.g. `postgres` to
+    /// `orders-db`, along with the `peer.service` tags naming them. Defaults
+    /// to `DD_SERVICE_MAPPING`, e.g. `postgres:orders-db,redis:orders-cache`.
+    pub service_mapping: HashMap<String, String>,
+    /// Tags the spans of requests with the values of their headers, by
+    /// lowercase header name, see `Tracer::tag_request_headers`. Defaults to
+    /// `DD_TRACE_HEADER_TAGS`, e.g. `x-tenant-id:tenant,user-agent`: headers
+    /// without a tag name are tagged as `http.request.headers.<header>`.
+    pub header_tags: HashMap<String, String>,
     pub version: String,
+    /// The repository and commit the service was built from, tagged on
+    /// local roots so that Datadog links traces to the source code. Default
+    /// to `DD_GIT_REPOSITORY_URL` and `DD_GIT_COMMIT_SHA`, or to these
+    /// variables as they were when the tracer was built, e.g. set by the CI
+    /// building the service. Credentials are removed from the url.
+    pub git_repository_url: String,
+    pub git_commit_sha: String,
     pub agent_url: String,
+    /// Named pipe of the Windows agent, taking precedence over the agent
+    /// address. Defaults to `DD_TRACE_PIPE_NAME` and needs the
+    /// `windows-pipes` feature.
+    pub agent_pipe_name: String,
+    /// Period in milliseconds at which partial snapshots of still running
+    /// root spans are written. 0 disables heartbeats.
+    pub heartbeat_period_ms: u32,
+    /// Traces with spans open for this long, in milliseconds, are logged
+    /// with the names and ages of these spans, as they most likely leaked.
+    /// The check runs as often, so reports can come up to twice as late. 0
+    /// disables the check.
+    pub abandoned_span_timeout_ms: u32,
+    /// Spans finished while their local root is still running are sent on
+    /// their own once they have waited this long, in milliseconds, keeping
+    /// their parent id. 0, the default, keeps them until the root finishes.
+    pub orphan_grace_period_ms: u32,
+    /// Also finishes the spans of the reported traces, tagged with
+    /// `_dd.abandoned`, so that the traces are sent.
+    pub finish_abandoned_spans: bool,
+    /// Sends each trace as soon as it completes instead of every write
+    /// period, for serverless functions that are frozen between invocations.
+    /// The Datadog Lambda extension listens on the default agent address.
+    /// Enabled by default when running in AWS Lambda.
+    pub serverless: bool,
+    /// Sends traces straight to the Datadog intake of `site` instead of an
+    /// agent, authenticated with `api_key`. Needs the `agentless` feature.
+    pub agentless: bool,
+    pub api_key: String,
+    pub site: String,
+    /// Compression of the payloads sent to the agent, if it's at least
+    /// `compression_threshold` bytes. A `compression_level` of 0 uses the
+    /// default level of the algorithm.
+    pub compression: Option<Compression>,
+    pub compression_level: u32,
+    pub compression_threshold: usize,
+    /// HTTP proxy for the agent and intake connections. If empty, the
+    /// `HTTPS_PROXY`/`HTTP_PROXY` and `NO_PROXY` variables are used.
+    pub proxy_url: String,
+    /// PEM files for `https://` agent urls: the CA bundle verifying the agent,
+    /// replacing the Mozilla roots, and the certificate chain and private key
+    /// authenticating the tracer. Need the `tls` feature.
+    pub tls_ca_file: String,
+    pub tls_cert_file: String,
+    pub tls_key_file: String,
+    /// Generates 128-bit trace ids for new traces, as
+    /// `DD_TRACE_128_BIT_TRACEID_GENERATION_ENABLED` does. Spans keep 64-bit
+    /// trace ids, the upper bits are sent as the `_dd.p.tid` trace tag.
+    pub trace_id_128bit_generation: bool,
+    /// Whether injected Datadog headers include the upper 64 bits of 128-bit
+    /// trace ids as `_dd.p.tid`, and whether W3C `traceparent` headers carry
+    /// the full id. Turn them off while peers only handle 64-bit ids.
+    pub datadog_trace_id_128bit_injection: bool,
+    pub w3c_trace_id_128bit_injection: bool,
+    /// Bound the baggage set on spans, which is sent with every outbound
+    /// request: items beyond them are dropped or truncated, with a warning.
+    pub baggage_limits: BaggageLimits,
+    /// Called with each span as it finishes, before it's buffered, e.g. to
+    /// add the tenant of the request or the build SHA to all the spans
+    /// without changing every instrumentation.
+    pub on_span_finish: Option<SpanFinishHook>,
+    /// Drops the traces whose local root has the path of its `http.url` or
+    /// its resource matching one of these globs, unless it failed, e.g.
+    /// `/healthz` or `GET /ping`. Defaults to the comma separated
+    /// `DD_TRACE_FILTER_URLS`.
+    pub filter_urls: Vec<String>,
+    /// Decide which traces are sent after `filter_urls`, in order.
+    pub trace_filters: Vec<Arc<dyn TraceFilter>>,
+    /// Receive the spans of each trace before they're sent, after the
+    /// filters, in order. They may change or remove spans, e.g. to scrub
+    /// tags.
+    pub trace_processors: Vec<Box<dyn TraceProcessor>>,
+    /// Receives the messages of the tracer, stderr by default. Repeated
+    /// errors are logged at most once a minute.
+    pub log_func: LogFunc,
+    /// Also logs debug messages. Defaults to `DD_TRACE_DEBUG`, and can be
+    /// switched at runtime by the agent configuration.
+    pub debug: bool,
+    /// Reports the time requests waited in the queue of the load balancer
+    /// in front of servers, from their `X-Request-Start` or `X-Queue-Start`
+    /// header, as an `http.queue` parent of their server span. Defaults to
+    /// `DD_TRACE_REQUEST_QUEUING_ENABLED`.
+    pub request_queuing: bool,
+    /// Reports the API gateway in front of servers which describes itself
+    /// in `X-Dd-Proxy` headers, e.g. AWS API Gateway, as a span parent of
+    /// their server span. Defaults to
+    /// `DD_TRACE_INFERRED_PROXY_SERVICES_ENABLED`.
+    pub inferred_proxy_services: bool,
+    /// Computes the trace stats in the tracer and doesn't send the traces
+    /// dropped by sampling, which the agent would drop, if the agent takes
+    /// client stats and allows it: they're only counted for the agent to
+    /// keep its sampling metrics right. Saves most of the traffic of busy
+    /// services. Defaults to `DD_TRACE_CLIENT_DROP_P0S_ENABLED`.
+    pub client_drop_p0s: bool,
+    /// Measures the time spent in the tracer for each trace: starting and
+    /// tagging spans, sampling and completing the trace. It's reported as
+    /// the `_dd.tracer_overhead_ns` metric of local roots, and along with
+    /// the time spent encoding payloads in the health metrics. Defaults to
+    /// `DD_TRACE_OVERHEAD_METRICS_ENABLED`.
+    pub overhead_metrics: bool,
+    /// Bounds the traces waiting to be sent. Once it's reached traces are
+    /// dropped as set by `trace_queue_policy` rather than blocking the
+    /// threads finishing spans, and counted as `tracer.queue_full`.
+    pub trace_queue_capacity: usize,
+    pub trace_queue_policy: QueueFullPolicy,
+    /// Number of failed flushes in a row after which the agent isn't tried
+    /// again for `circuit_breaker_cooldown_ms`, so that an agent which is
+    /// down doesn't cost a connection attempt every write period. Traces
+    /// written meanwhile are kept or dropped as set by
+    /// `circuit_breaker_policy`. 0 disables it.
+    pub circuit_breaker_failures: u32,
+    pub circuit_breaker_cooldown_ms: u32,
+    pub circuit_breaker_policy: CircuitOpenPolicy,
+    /// Traces the flushes of the writer: each is reported as a
+    /// `datadog.flush` span of the service, in a trace of its own, with the
+    /// traces and bytes it sent and the posts it took. Meant to debug the
+    /// export of traces, it's off by default.
+    pub self_tracing: bool,
+    /// Bounds the spans of a trace started in the process, e.g. against
+    /// runaway recursive instrumentation. Beyond it, spans record nothing
+    /// but still propagate the trace, and the local root is tagged with
+    /// `_dd.span_limit_reached`. 0, the default, is no limit.
+    pub max_spans_per_trace: usize,
+    /// The statuses of the responses which mark the spans of HTTP servers
+    /// and clients as errors, see `TraceLayer::classify_server_status`.
+    /// Default to 5xx for servers and to 4xx and 5xx for clients.
+    /// `from_env` reads them from `DD_TRACE_HTTP_SERVER_ERROR_STATUSES` and
+    /// `DD_TRACE_HTTP_CLIENT_ERROR_STATUSES`, e.g. `400-499,501`.
+    pub http_server_error_statuses: StatusCodes,
+    pub http_client_error_statuses: StatusCodes,
+    /// Where the settings come from, by their key in the JSON
+    /// configuration, reported in startup logs and flares. Settings changed
+    /// in code without being recorded here are detected when the tracer is
+    /// created.
+    pub origins: ConfigOrigins,
+}
+
+fn env_flag(name: &str) -> bool {
+    env::var(name)
+        .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
+/// Parses `from:to` pairs separated by commas, e.g. the value of
+/// `DD_SERVICE_MAPPING`. Pairs without a colon or a service are ignored.
+pub(crate) fn parse_service_mapping(value: &str) -> HashMap<String, String> {
+    value
+        .split(',')
+        .filter_map(|pair| {
+            let (from, to) = pair.split_once(':')?;
+            let (from, to) = (from.trim(), to.trim());
+            (!from.is_empty() && !to.is_empty()).then(|| (String::from(from), String::from(to)))
+        })
+        .collect()
+}
+
+/// Parses `header:tag` pairs separated by commas, e.g. the value of
+/// `DD_TRACE_HEADER_TAGS`. Header names are lowercased, and those without a
+/// tag name are tagged as `http.request.headers.<header>`.
+pub(crate) fn parse_header_tags(value: &str) -> HashMap<String, String> {
+    value
+        .split(',')
+        .filter_map(|pair| {
+            let (header, tag) = pair.split_once(':').unwrap_or((pair, ""));
+            let header = header.trim().to_ascii_lowercase();
+            let tag = match tag.trim() {
+                "" => format!("http.request.headers.{}", header),
+                tag => String::from(tag),
+            };
+            (!header.is_empty()).then_some((header, tag))
+        })
+        .collect()
+}
+
+/// Reads the variable `name`, or its value when the crate was built if it's
+/// unset or empty.
+fn env_or_built(name: &str, built: Option<&str>) -> String {
+    env::var(name)
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+        .or_else(|| built.map(String::from))
+        .map(|value| String::from(value.trim()))
+        .unwrap_or_default()
+}
+
+/// Removes the credentials of a repository url, e.g.
+/// `https://token@github.com/org/repo` becomes `https://github.com/org/repo`.
+/// SSH urls such as `git@github.com:org/repo` are kept as they are.
+pub(crate) fn strip_url_credentials(url: &str) -> String {
+    match url.split_once("://") {
+        Some((scheme, rest)) => {
+            let host_start = rest.find('/').unwrap_or(rest.len());
+            let rest = match rest[..host_start].rfind('@') {
+                Some(at) => &rest[at + 1..],
+                None => rest,
+            };
+            format!("{}://{}", scheme, rest)
+        }
+        None => String::from(url),
+    }
+}
+
+/// Reads a number variable, None if it's unset or isn't a number.
+fn env_usize(name: &str) -> Option<usize> {
+    env::var(name).ok()?.trim().parse().ok()
+}
+
+/// Reads a boolean variable, None if it's unset or isn't a boolean.
+pub(crate) fn env_bool(name: &str) -> Option<bool> {
+    match env::var(name).ok()?.trim() {
+        "1" => Some(true),
+        "0" => Some(false),
+        value if value.eq_ignore_ascii_case("true") => Some(true),
+        value if value.eq_ignore_ascii_case("false") => Some(false),
+        _ => None,
+    }
+}
+
+impl TracerOptions {
+    /// Returns the default options, with the propagation styles and error
+    /// statuses of the environment.
+    pub fn from_env() -> Result<TracerOptions> {
+        let mut options = TracerOptions::default();
+        options.read_propagation_env()?;
+        options.read_status_env()?;
+        Ok(options)
+    }
+
+    /// Reads the statuses of HTTP spans which are errors from
+    /// `DD_TRACE_HTTP_SERVER_ERROR_STATUSES` and
+    /// `DD_TRACE_HTTP_CLIENT_ERROR_STATUSES`.
+    pub(crate) fn read_status_env(&mut self) -> Result<()> {
+        if let Some(statuses) = StatusCodes::from_env("DD_TRACE_HTTP_SERVER_ERROR_STATUSES")? {
+            self.http_server_error_statuses = statuses;
+        }
+        if let Some(statuses) = StatusCodes::from_env("DD_TRACE_HTTP_CLIENT_ERROR_STATUSES")? {
+            self.http_client_error_statuses = statuses;
+        }
+
+        Ok(())
+    }
+
+    /// Reads the propagation styles from `DD_TRACE_PROPAGATION_STYLE`, or the
+    /// more specific `DD_TRACE_PROPAGATION_STYLE_INJECT` and
+    /// `DD_TRACE_PROPAGATION_STYLE_EXTRACT`.
+    pub(crate) fn read_propagation_env(&mut self) -> Result<()> {
+        let read = |name: &str| match env::var(name) {
+            Ok(value) if !value.trim().is_empty() => parse_propagation_styles(&value)
+                .map(Some)
+                .map_err(|error| eyre!("{}: {}", name, error)),
+            _ => Ok(None),
+        };
+
+        if let Some(styles) = read("DD_TRACE_PROPAGATION_STYLE")? {
+            self.inject = styles.clone();
+            self.extract = styles;
+            self.origins
+                .set("propagation_style_inject", ConfigSource::EnvVar);
+            self.origins
+                .set("propagation_style_extract", ConfigSource::EnvVar);
+        }
+        if let Some(styles) = read("DD_TRACE_PROPAGATION_STYLE_INJECT")? {
+            self.inject = styles;
+            self.origins
+                .set("propagation_style_inject", ConfigSource::EnvVar);
+        }
+        if let Some(styles) = read("DD_TRACE_PROPAGATION_STYLE_EXTRACT")? {
+            self.extract = styles;
+            self.origins
+                .set("propagation_style_extract", ConfigSource::EnvVar);
+        }
+
+        Ok(())
+    }
+}
+
+impl TracerOptions {
+    /// Returns the options as resolved from the configuration and the
+    /// environment, e.g. for flares. The API key is left out.
+    pub(crate) fn to_json(&self) -> Value {
+        let styles = |styles: &HashSet<PropagationStyle>| -> Vec<&str> {
+            let mut names: Vec<&str> = styles.iter().map(PropagationStyle::name).collect();
+            names.sort_unstable();
+            names
+        };
+
+        json!({
+            "enabled": self.enabled,
+            "propagation_only": self.propagation_only,
+            "service": self.service,
+            "type": self.service_type,
+            "environment": self.environment,
+            "version": self.version,
+            "git_repository_url": self.git_repository_url,
+            "git_commit_sha": self.git_commit_sha,
+            "agent_host": self.agent_host,
+            "agent_port": self.agent_port,
+            "agent_url": self.agent_url,
+            "agent_pipe_name": self.agent_pipe_name,
+            "agentless": self.agentless,
+            "site": self.site,
+            "proxy_url": self.proxy_url,
+            "sample_rate": self.sample_rate,
+            "priority_sampling": self.priority_sampling,
+            "sampling_rules": self.sampling_rules,
+            "error_sampling": self.error_sampling,
+            "latency_keep_threshold_ms": self.latency_keep_threshold_ms,
+            "propagation_style_extract": styles(&self.extract),
+            "propagation_style_inject": styles(&self.inject),
+            "write_period_ms": self.write_perios_ms,
+            "heartbeat_period_ms": self.heartbeat_period_ms,
+            "abandoned_span_timeout_ms": self.abandoned_span_timeout_ms,
+            "orphan_grace_period_ms": self.orphan_grace_period_ms,
+            "serverless": self.serverless,
+            "compression": self.compression.map(|compression| compression.content_encoding()),
+            "filter_urls": self.filter_urls,
+            "baggage_max_items": self.baggage_limits.max_items,
+            "baggage_max_key_length": self.baggage_limits.max_key_length,
+            "baggage_max_value_length": self.baggage_limits.max_value_length,
+            "tags": self.tags,
+            "service_mapping": self.service_mapping,
+            "header_tags": self.header_tags,
+            "debug": self.debug,
+            "request_queuing": self.request_queuing,
+            "inferred_proxy_services": self.inferred_proxy_services,
+            "client_drop_p0s": self.client_drop_p0s,
+            "overhead_metrics": self.overhead_metrics,
+            "trace_queue_capacity": self.trace_queue_capacity,
+            "trace_queue_policy": match self.trace_queue_policy {
+                QueueFullPolicy::DropNewest => "drop_newest",
+                QueueFullPolicy::DropOldest => "drop_oldest",
+            },
+            "circuit_breaker_failures": self.circuit_breaker_failures,
+            "circuit_breaker_cooldown_ms": self.circuit_breaker_cooldown_ms,
+            "circuit_breaker_policy": match self.circuit_breaker_policy {
+                CircuitOpenPolicy::Buffer => "buffer",
+                CircuitOpenPolicy::Drop => "drop",
+            },
+            "self_tracing": self.self_tracing,
+            "max_spans_per_trace": self.max_spans_per_trace,
+            "http_server_error_statuses": self.http_server_error_statuses.to_string(),
+            "http_client_error_statuses": self.http_client_error_statuses.to_string(),
+        })
+    }
+}
+
+impl Default for TracerOptions {
+    fn default() -> TracerOptions {
+        let mut styles = HashSet::new();
+        styles.insert(PropagationStyle::Datadog);
+
+        TracerOptions {
+            enabled: env_bool("DD_TRACE_ENABLED").unwrap_or(true),
+            propagation_only: env_bool("DD_APM_TRACING_ENABLED") == Some(false),
+            agent_host: String::from("localhost"),
+            agent_port: 8126,
+            service: String::new(),
+            service_type: String::from("web"),
+            environment: String::new(),
+            sample_rate: f32::NAN,
+            priority_sampling: true,
+            sampling_rules: String::from("[]"),
+            sampler_override: None,
+            error_sampling: env_flag("DD_TRACE_ERROR_SAMPLING"),
+            latency_keep_threshold_ms: 0,
+            latency_keep_rate_limit: 1.0,
+            write_perios_ms: 1000,
+            operation_name_override: String::new(),
+            extract: styles.clone(),
+            inject: styles,
+            report_hostname: false,
+            analytics_enabled: false,
+            analytics_rate: f32::NAN,
+            tags: HashMap::new(),
+            service_mapping: env::var("DD_SERVICE_MAPPING")
+                .map(|mapping| parse_service_mapping(&mapping))
+                .unwrap_or_default(),
+            header_tags: env::var("DD_TRACE_HEADER_TAGS")
+                .map(|tags| parse_header_tags(&tags))
+                .unwrap_or_default(),
+            version: String::new(),
+            git_repository_url: strip_url_credentials(&env_or_built(
+                "DD_GIT_REPOSITORY_URL",
+                option_env!("DD_GIT_REPOSITORY_URL"),
+            )),
+            git_commit_sha: env_or_built("DD_GIT_COMMIT_SHA", option_env!("DD_GIT_COMMIT_SHA")),
+            agent_url: String::new(),
+            agent_pipe_name: env::var("DD_TRACE_PIPE_NAME").unwrap_or_default(),
+            heartbeat_period_ms: 0,
+            abandoned_span_timeout_ms: 0,
+            orphan_grace_period_ms: 0,
+            finish_abandoned_spans: false,
+            serverless: env::var_os("AWS_LAMBDA_FUNCTION_NAME").is_some(),
+            agentless: env_flag("DD_TRACE_AGENTLESS"),
+            api_key: env::var("DD_API_KEY").unwrap_or_default(),
+            site: env::var("DD_SITE").unwrap_or_else(|_| String::from("datadoghq.com")),
+            compression: None,
+            compression_level: 0,
+            compression_threshold: 64 * 1024,
+            proxy_url: String::new(),
+            tls_ca_file: String::new(),
+            tls_cert_file: String::new(),
+            tls_key_file: String::new(),
+            trace_id_128bit_generation: env_flag("DD_TRACE_128_BIT_TRACEID_GENERATION_ENABLED"),
+            datadog_trace_id_128bit_injection: true,
+            w3c_trace_id_128bit_injection: true,
+            baggage_limits: BaggageLimits {
+                max_items: env_usize("DD_TRACE_BAGGAGE_MAX_ITEMS").unwrap_or(64),
+                ..Default::default()
+            },
+            on_span_finish: None,
+            filter_urls: env::var("DD_TRACE_FILTER_URLS")
+                .map(|urls| {
+                    urls.split(',')
+                        .map(str::trim)
+                        .filter(|url| !url.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            trace_filters: Vec::new(),
+            trace_processors: Vec::new(),
+            log_func: default_log_func(),
+            debug: env_flag("DD_TRACE_DEBUG"),
+            request_queuing: env_flag("DD_TRACE_REQUEST_QUEUING_ENABLED"),
+            inferred_proxy_services: env_flag("DD_TRACE_INFERRED_PROXY_SERVICES_ENABLED"),
+            client_drop_p0s: env_flag("DD_TRACE_CLIENT_DROP_P0S_ENABLED"),
+            overhead_metrics: env_flag("DD_TRACE_OVERHEAD_METRICS_ENABLED"),
+            trace_queue_capacity: 10_000,
+            trace_queue_policy: QueueFullPolicy::DropNewest,
+            circuit_breaker_failures: 5,
+            circuit_breaker_cooldown_ms: 30_000,
+            circuit_breaker_policy: CircuitOpenPolicy::Buffer,
+            self_tracing: false,
+            max_spans_per_trace: 0,
+            http_server_error_statuses: StatusCodes::server_errors(),
+            http_client_error_statuses: StatusCodes::client_errors(),
+            origins: ConfigOrigins::from_env(),
+        }
+    }
 }
@@ -0,0 +1,74 @@
+use std::time::{Instant, SystemTime};
+#[cfg(any(test, feature = "testing"))]
+use std::{sync::Mutex, time::Duration};
+
+/// TimePoint is a point in time, both on the wall clock, which timestamps
+/// spans, and on the monotonic clock, which measures durations.
+#[derive(Debug, Clone)]
+pub struct TimePoint {
+    pub absolute_time: SystemTime,
+    pub relative_time: Instant,
+}
+
+impl TimePoint {
+    pub fn new() -> TimePoint {
+        Self {
+            absolute_time: SystemTime::now(),
+            relative_time: Instant::now(),
+        }
+    }
+}
+
+impl Default for TimePoint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Clock gives the time to the parts of the tracer which depend on it, e.g.
+/// rate limiters, so that tests control it.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> TimePoint;
+}
+
+/// SystemClock is the time of the system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> TimePoint {
+        TimePoint::new()
+    }
+}
+
+/// ManualClock only moves when advanced, for tests. It starts at the time
+/// it's created.
+#[cfg(any(test, feature = "testing"))]
+#[derive(Debug, Default)]
+pub struct ManualClock(Mutex<TimePoint>);
+
+#[cfg(any(test, feature = "testing"))]
+impl ManualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        now.absolute_time += duration;
+        now.relative_time += duration;
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Clock for ManualClock {
+    fn now(&self) -> TimePoint {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+}
@@ -0,0 +1,48 @@
+/// Matches `value` against a glob `pattern`, where `*` matches any string
+/// and `?` any character, ignoring case as the Datadog sampling rules do.
+pub(crate) fn glob_match(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().flat_map(char::to_lowercase).collect();
+    let value: Vec<char> = value.chars().flat_map(char::to_lowercase).collect();
+    let (mut p, mut v) = (0, 0);
+    // Where to resume after the last `*` if the rest doesn't match.
+    let mut backtrack = None;
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    v = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_globs() {
+        assert!(glob_match("web-*", "web-api"));
+        assert!(glob_match("WEB-*", "web-"));
+        assert!(glob_match("*.query", "postgres.query"));
+        assert!(glob_match("a*b*c", "aXbYbc"));
+        assert!(glob_match("r?d", "red"));
+        assert!(!glob_match("r?d", "read"));
+        assert!(!glob_match("web", "web-api"));
+        assert!(glob_match("*", ""));
+    }
+}
@@ -0,0 +1,75 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::{Mutex, MutexGuard},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+fn random_seed() -> u64 {
+    // RandomState is seeded from the OS once per thread, which a forked child
+    // inherits, so the process id and current time are mixed in as well.
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_nanos())
+            .unwrap_or_default(),
+    );
+    hasher.finish()
+}
+
+/// IdGenerator produces random, non-zero 63-bit span and trace ids.
+#[derive(Debug)]
+pub(crate) struct IdGenerator {
+    state: Mutex<u64>,
+}
+
+impl IdGenerator {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(random_seed()),
+        }
+    }
+
+    // A poisoned state is still a valid seed, so ids can always be generated.
+    fn state(&self) -> MutexGuard<'_, u64> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn next_id(&self) -> u64 {
+        let mut state = self.state();
+        loop {
+            // splitmix64
+            *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = *state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            let id = (z ^ (z >> 31)) >> 1;
+            if id != 0 {
+                return id;
+            }
+        }
+    }
+
+    /// Returns a random (version 4) UUID, e.g. to identify the process.
+    pub fn next_uuid(&self) -> String {
+        let high = (self.next_id() & !0xf000) | 0x4000;
+        let low = (self.next_id() & 0x3fff_ffff_ffff_ffff) | 0x8000_0000_0000_0000;
+        format!(
+            "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+            high >> 32,
+            (high >> 16) & 0xffff,
+            high & 0xffff,
+            low >> 48,
+            low & 0xffff_ffff_ffff
+        )
+    }
+
+    /// Picks a fresh seed, so a forked child doesn't repeat its parent's ids.
+    pub fn reseed(&self) {
+        *self.state() = random_seed();
+    }
+}
@@ -0,0 +1,56 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+/// Interner shares the names repeated by most spans, such as their
+/// operation names, instead of allocating them for every span. It keeps at
+/// most `capacity` names: beyond them names are allocated as before, so that
+/// high cardinality names don't grow it forever.
+pub(crate) struct Interner {
+    names: Mutex<HashSet<Arc<str>>>,
+    capacity: usize,
+}
+
+impl Interner {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            names: Mutex::new(HashSet::new()),
+            capacity,
+        }
+    }
+
+    pub fn intern(&self, name: &str) -> Arc<str> {
+        let mut names = match self.names.lock() {
+            Ok(names) => names,
+            Err(_) => return Arc::from(name),
+        };
+        if let Some(interned) = names.get(name) {
+            return interned.clone();
+        }
+
+        let interned: Arc<str> = Arc::from(name);
+        if names.len() < self.capacity {
+            names.insert(interned.clone());
+        }
+        interned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares_names_up_to_capacity() {
+        let interner = Interner::new(2);
+        let request = interner.intern("request");
+        assert!(Arc::ptr_eq(&request, &interner.intern("request")));
+
+        interner.intern("db.query");
+        let cache = interner.intern("cache.get");
+        assert_eq!(&*cache, "cache.get");
+        assert!(!Arc::ptr_eq(&cache, &interner.intern("cache.get")));
+        assert!(Arc::ptr_eq(&request, &interner.intern("request")));
+    }
+}
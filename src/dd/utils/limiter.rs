@@ -1,31 +1,24 @@
-use super::TimePoint;
+use super::Clock;
 use eyre::{eyre, Result};
-#[cfg(test)]
-use mock_instant::Instant;
-use std::sync::Mutex;
-use std::time::Duration;
-#[cfg(not(test))]
-use std::time::Instant;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 pub(crate) struct LimitResult {
     pub allowed: bool,
     pub effective_rate: f64,
 }
 
-#[derive(Derivative)]
-#[derivative(Debug)]
-struct LimitData<F>
-where
-    F: Fn() -> TimePoint,
-{
-    #[derivative(Debug = "ignore")]
-    pub time_provider: F,
+struct LimitData {
+    pub clock: Arc<dyn Clock>,
 
     pub num_tokens: u64,
     pub max_tokens: u64,
     pub refresh_interval: Duration,
     pub tokens_per_refresh: u64,
     pub next_refresh: Instant,
+    /// Period of each rate averaged into the effective rate.
+    pub bucket: Duration,
     pub previous_rates: Vec<f64>,
     pub previous_rates_sum: f64,
     pub current_period: Instant,
@@ -33,12 +26,9 @@ where
     pub num_requested: u64,
 }
 
-impl<F> LimitData<F>
-where
-    F: Fn() -> TimePoint,
-{
+impl LimitData {
     pub fn new(
-        time_provider: F,
+        clock: Arc<dyn Clock>,
         max_tokens: u64,
         refresh_rate: f64,
         tokens_per_refresh: u64,
@@ -47,17 +37,18 @@ where
         let refresh_interval = Duration::from_secs(1)
             .div_f64(refresh_rate)
             .mul_f64(tokens_per_refresh as f64);
-        let now = time_provider();
+        let now = clock.now();
         let next_refresh = now.relative_time + refresh_interval;
         let current_period = now.relative_time;
         let previous_rates_sum = previous_rates.iter().sum();
         Self {
-            time_provider,
+            clock,
             num_tokens: max_tokens,
             max_tokens,
             refresh_interval,
             tokens_per_refresh,
             next_refresh,
+            bucket: Duration::from_secs(1),
             previous_rates,
             previous_rates_sum,
             current_period,
@@ -67,27 +58,36 @@ where
     }
 }
 
-#[derive(Debug)]
-pub(crate) struct Limiter<F>
-where
-    F: Fn() -> TimePoint,
-{
-    data: Mutex<LimitData<F>>,
+// Clocks have no Debug.
+impl fmt::Debug for LimitData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LimitData")
+            .field("num_tokens", &self.num_tokens)
+            .field("max_tokens", &self.max_tokens)
+            .field("refresh_interval", &self.refresh_interval)
+            .field("tokens_per_refresh", &self.tokens_per_refresh)
+            .field("bucket", &self.bucket)
+            .field("previous_rates", &self.previous_rates)
+            .field("num_allowed", &self.num_allowed)
+            .field("num_requested", &self.num_requested)
+            .finish_non_exhaustive()
+    }
+}
+
+pub(crate) struct Limiter {
+    data: Mutex<LimitData>,
 }
 
-impl<F> Limiter<F>
-where
-    F: Fn() -> TimePoint,
-{
+impl Limiter {
     pub fn new(
-        time_provider: F,
+        clock: Arc<dyn Clock>,
         max_tokens: u64,
         refresh_rate: f64,
         tokens_per_refresh: u64,
     ) -> Self {
         Self {
             data: Mutex::new(LimitData::new(
-                time_provider,
+                clock,
                 max_tokens,
                 refresh_rate,
                 tokens_per_refresh,
@@ -95,31 +95,41 @@ where
         }
     }
 
-    pub fn allow(&mut self, tokens_requested: u64) -> Result<LimitResult> {
+    /// Averages the effective rate over `buckets` periods of `bucket`, the
+    /// current one included, instead of the 10 periods of a second of
+    /// dd-opentracing-cpp. Longer windows smooth out bursts more.
+    #[cfg(test)]
+    pub fn with_window(self, buckets: usize, bucket: Duration) -> Self {
+        let mut data = self
+            .data
+            .into_inner()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        data.previous_rates = vec![1.0; buckets.max(1) - 1];
+        data.previous_rates_sum = data.previous_rates.len() as f64;
+        data.bucket = bucket.max(Duration::from_nanos(1));
+
+        Self {
+            data: Mutex::new(data),
+        }
+    }
+
+    pub fn allow(&self, tokens_requested: u64) -> Result<LimitResult> {
         let mut data = self.data.lock().map_err(|_| eyre!("mutex lock failed"))?;
-        let now = (data.time_provider)();
-        let intervals = (now.relative_time - data.current_period).as_secs() as usize;
+        let now = data.clock.now();
+        let intervals = ((now.relative_time - data.current_period).as_nanos()
+            / data.bucket.as_nanos()) as usize;
         if intervals > 0 {
-            if intervals >= data.previous_rates.len() {
-                for i in data.previous_rates[1..].iter_mut() {
-                    *i = 1.0;
-                }
-            } else {
-                let back = data.previous_rates[0..data.previous_rates.len() - intervals].to_vec();
-                data.previous_rates.truncate(intervals);
-                data.previous_rates.extend_from_slice(&back);
+            // The most recent rate comes first, the buckets without
+            // requests since count as fully allowed.
+            let len = data.previous_rates.len();
+            let mut rates = vec![1.0; len];
+            if intervals <= len {
                 if data.num_requested > 0 {
-                    data.previous_rates[intervals - 1] =
-                        data.num_allowed as f64 / data.num_requested as f64;
-                } else {
-                    data.previous_rates[intervals - 1] = 1.0;
-                }
-                if intervals > 2 {
-                    for i in data.previous_rates[0..intervals - 2].iter_mut() {
-                        *i = 1.0;
-                    }
+                    rates[intervals - 1] = data.num_allowed as f64 / data.num_requested as f64;
                 }
+                rates[intervals..].copy_from_slice(&data.previous_rates[..len - intervals]);
             }
+            data.previous_rates = rates;
             data.previous_rates_sum = data.previous_rates.iter().sum();
             data.num_allowed = 0;
             data.num_requested = 0;
@@ -162,37 +172,47 @@ where
     }
 }
 
+impl fmt::Debug for Limiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.data.lock() {
+            Ok(data) => f.debug_tuple("Limiter").field(&*data).finish(),
+            Err(_) => f.write_str("Limiter(<poisoned>)"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::time::UNIX_EPOCH;
-
     use super::*;
-    use crate::time_point::TimePoint;
-    use mock_instant::MockClock;
+    use crate::dd::utils::ManualClock;
 
     #[test]
     fn limits_requested() {
-        let time_provider = || TimePoint {
-            absolute_time: UNIX_EPOCH,
-            relative_time: Instant::now(),
-        };
-        let mut limiter = Limiter::new(time_provider, 1, 1.0, 1);
+        let clock = Arc::new(ManualClock::new());
+        let limiter = Limiter::new(clock.clone(), 1, 1.0, 1);
         let first = limiter.allow(1).unwrap();
         let second = limiter.allow(1).unwrap();
         assert!(first.allowed);
         assert!(!second.allowed);
     }
 
+    #[test]
+    fn debugs_state_without_clock() {
+        let clock = Arc::new(ManualClock::new());
+        let limiter = Limiter::new(clock.clone(), 2, 1.0, 1);
+        limiter.allow(1).unwrap();
+        let debug = format!("{:?}", limiter);
+        assert!(debug.starts_with("Limiter(LimitData { num_tokens: 1, max_tokens: 2,"));
+        assert!(debug.contains("num_allowed: 1, num_requested: 1, .. })"));
+    }
+
     #[test]
     fn refreshes_over_time() {
-        let time_provider = || TimePoint {
-            absolute_time: UNIX_EPOCH,
-            relative_time: Instant::now(),
-        };
-        let mut limiter = Limiter::new(time_provider, 1, 1.0, 1);
+        let clock = Arc::new(ManualClock::new());
+        let limiter = Limiter::new(clock.clone(), 1, 1.0, 1);
         let first = limiter.allow(1).unwrap();
         let second = limiter.allow(1).unwrap();
-        MockClock::advance(Duration::from_secs(1));
+        clock.advance(Duration::from_secs(1));
         let third = limiter.allow(1).unwrap();
         assert!(first.allowed);
         assert!(!second.allowed);
@@ -201,13 +221,10 @@ mod tests {
 
     #[test]
     fn handles_long_intervals() {
-        let time_provider = || TimePoint {
-            absolute_time: UNIX_EPOCH,
-            relative_time: Instant::now(),
-        };
-        let mut limiter = Limiter::new(time_provider, 1, 1.0, 1);
+        let clock = Arc::new(ManualClock::new());
+        let limiter = Limiter::new(clock.clone(), 1, 1.0, 1);
         let first = limiter.allow(1).unwrap();
-        MockClock::advance(Duration::from_secs(2));
+        clock.advance(Duration::from_secs(2));
         let second = limiter.allow(1).unwrap();
         let third = limiter.allow(1).unwrap();
         assert!(first.allowed);
@@ -217,30 +234,41 @@ mod tests {
 
     #[test]
     fn calculates_effective_rate() {
-        let time_provider = || TimePoint {
-            absolute_time: UNIX_EPOCH,
-            relative_time: Instant::now(),
-        };
-        let mut limiter = Limiter::new(time_provider, 1, 1.0, 1);
+        let clock = Arc::new(ManualClock::new());
+        let limiter = Limiter::new(clock.clone(), 1, 1.0, 1);
         let first = limiter.allow(1).unwrap();
         assert!(first.allowed);
         assert_eq!(first.effective_rate, 1.0);
         let second = limiter.allow(1).unwrap();
         assert!(!second.allowed);
         assert_eq!(second.effective_rate, 0.95);
-        MockClock::advance(Duration::from_secs(10));
+        clock.advance(Duration::from_secs(10));
         let third = limiter.allow(1).unwrap();
         assert!(third.allowed);
         assert_eq!(third.effective_rate, 1.0);
     }
 
+    #[test]
+    fn averages_rates_over_configured_window() {
+        let clock = Arc::new(ManualClock::new());
+        let limiter =
+            Limiter::new(clock.clone(), 1, 1.0, 1).with_window(2, Duration::from_millis(100));
+        assert_eq!(limiter.allow(1).unwrap().effective_rate, 1.0);
+        assert_eq!(limiter.allow(1).unwrap().effective_rate, 0.75);
+        clock.advance(Duration::from_millis(100));
+        let result = limiter.allow(1).unwrap();
+        assert!(!result.allowed);
+        assert_eq!(result.effective_rate, 0.25);
+
+        let limiter = Limiter::new(clock.clone(), 1, 1.0, 1).with_window(1, Duration::ZERO);
+        limiter.allow(1).unwrap();
+        assert_eq!(limiter.allow(1).unwrap().effective_rate, 0.5);
+    }
+
     #[test]
     fn updates_tokens_at_sub_second_intervals() {
-        let time_provider = || TimePoint {
-            absolute_time: UNIX_EPOCH,
-            relative_time: Instant::now(),
-        };
-        let mut limiter = Limiter::new(time_provider, 5, 5.0, 1);
+        let clock = Arc::new(ManualClock::new());
+        let limiter = Limiter::new(clock.clone(), 5, 5.0, 1);
         for _ in 0..5 {
             let result = limiter.allow(1).unwrap();
             assert!(result.allowed);
@@ -248,14 +276,14 @@ mod tests {
         let all_consumed = limiter.allow(1).unwrap();
         assert!(!all_consumed.allowed);
 
-        MockClock::advance(Duration::from_millis(200));
+        clock.advance(Duration::from_millis(200));
 
         let first = limiter.allow(1).unwrap();
         assert!(first.allowed);
         let second = limiter.allow(1).unwrap();
         assert!(!second.allowed);
 
-        MockClock::advance(Duration::from_secs(1));
+        clock.advance(Duration::from_secs(1));
         for _ in 0..5 {
             let result = limiter.allow(1).unwrap();
             assert!(result.allowed);
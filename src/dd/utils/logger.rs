@@ -0,0 +1,174 @@
+use super::{Clock, Limiter, SystemClock};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// How many messages of each class are logged per minute.
+const MESSAGES_PER_MINUTE: f64 = 1.0;
+/// How many of the last messages logged are kept for flares.
+const RECENT_MESSAGES: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+/// Receives the messages of the tracer, as `log_func` does in
+/// dd-opentracing-cpp.
+pub type LogFunc = Arc<dyn Fn(LogLevel, &str) + Send + Sync>;
+
+pub(crate) fn default_log_func() -> LogFunc {
+    Arc::new(|level, message| eprintln!("[dd-opentracing-rs] {:?}: {}", level, message))
+}
+
+struct MessageClass {
+    limiter: Limiter,
+    suppressed: u64,
+}
+
+/// RateLimitedLogger bounds the internal messages of each class (e.g.
+/// failures to reach the agent) to one per minute, so an unreachable agent
+/// doesn't flood the logs. The next message logged reports how many were
+/// suppressed. Debug messages are dropped unless debug logging is on.
+pub(crate) struct RateLimitedLogger {
+    log_func: LogFunc,
+    clock: Arc<dyn Clock>,
+    debug: AtomicBool,
+    classes: Mutex<HashMap<&'static str, MessageClass>>,
+    recent: Mutex<VecDeque<String>>,
+}
+
+impl RateLimitedLogger {
+    pub fn new(log_func: LogFunc) -> Self {
+        Self {
+            log_func,
+            clock: Arc::new(SystemClock),
+            debug: AtomicBool::new(false),
+            classes: Mutex::new(HashMap::new()),
+            recent: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Rate limits messages with the time of `clock`.
+    #[cfg(test)]
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self { clock, ..self }
+    }
+
+    /// Turns debug logging on or off, e.g. while a flare is prepared.
+    pub fn set_debug(&self, debug: bool) {
+        self.debug.store(debug, Ordering::Relaxed);
+    }
+
+    pub fn debug(&self) -> bool {
+        self.debug.load(Ordering::Relaxed)
+    }
+
+    /// Returns the last messages logged, the oldest first.
+    pub fn recent_messages(&self) -> Vec<String> {
+        self.recent
+            .lock()
+            .map(|recent| recent.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn log(&self, level: LogLevel, class: &'static str, message: &str) {
+        if level == LogLevel::Debug && !self.debug() {
+            return;
+        }
+        let mut classes = match self.classes.lock() {
+            Ok(classes) => classes,
+            Err(_) => return,
+        };
+        let class = classes.entry(class).or_insert_with(|| MessageClass {
+            limiter: Limiter::new(self.clock.clone(), 1, MESSAGES_PER_MINUTE / 60.0, 1),
+            suppressed: 0,
+        });
+
+        let allowed = class
+            .limiter
+            .allow(1)
+            .map(|result| result.allowed)
+            .unwrap_or(false);
+        if !allowed {
+            class.suppressed += 1;
+            return;
+        }
+
+        let message = match std::mem::take(&mut class.suppressed) {
+            0 => String::from(message),
+            suppressed => format!("{} ({} similar messages suppressed)", message, suppressed),
+        };
+        drop(classes);
+        (self.log_func)(level, &message);
+
+        if let Ok(mut recent) = self.recent.lock() {
+            if recent.len() == RECENT_MESSAGES {
+                recent.pop_front();
+            }
+            recent.push_back(format!("{:?}: {}", level, message));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dd::utils::ManualClock;
+    use std::time::Duration;
+
+    #[test]
+    fn limits_messages_per_class() {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let captured = messages.clone();
+        let clock = Arc::new(ManualClock::new());
+        let logger = RateLimitedLogger::new(Arc::new(move |_, message: &str| {
+            captured.lock().unwrap().push(String::from(message))
+        }))
+        .with_clock(clock.clone());
+
+        for _ in 0..3 {
+            logger.log(LogLevel::Error, "send", "agent down");
+        }
+        logger.log(LogLevel::Error, "other", "other error");
+        clock.advance(Duration::from_secs(60));
+        logger.log(LogLevel::Error, "send", "agent down");
+
+        assert_eq!(
+            *messages.lock().unwrap(),
+            vec![
+                "agent down",
+                "other error",
+                "agent down (2 similar messages suppressed)"
+            ]
+        );
+        logger.log(LogLevel::Debug, "debug", "sent 2 traces");
+        logger.set_debug(true);
+        logger.log(LogLevel::Debug, "debug", "sent 3 traces");
+        assert_eq!(messages.lock().unwrap().last().unwrap(), "sent 3 traces");
+        messages.lock().unwrap().pop();
+        assert_eq!(
+            *messages.lock().unwrap(),
+            vec![
+                "agent down",
+                "other error",
+                "agent down (2 similar messages suppressed)"
+            ]
+        );
+        assert_eq!(
+            logger.recent_messages()[..3],
+            vec![
+                "Error: agent down",
+                "Error: other error",
+                "Error: agent down (2 similar messages suppressed)"
+            ]
+        );
+    }
+}
@@ -1,7 +1,23 @@
+mod base64;
+pub(crate) mod clock;
+mod glob;
+mod id_generator;
+mod interner;
 mod limiter;
-mod time_point;
-mod tools;
+mod logger;
+#[cfg(feature = "contrib")]
+mod sha256;
+mod trace_ids;
+mod zip;
 
+pub(crate) use self::base64::*;
+pub use clock::*;
+pub(crate) use glob::*;
+pub(crate) use id_generator::*;
+pub(crate) use interner::*;
 pub(crate) use limiter::*;
-pub(crate) use time_point::*;
-pub(crate) use tools::*;
+pub use logger::*;
+#[cfg(feature = "contrib")]
+pub(crate) use sha256::*;
+pub use trace_ids::*;
+pub(crate) use zip::*;
@@ -0,0 +1,72 @@
+/// Returns the 32 hexadecimal digits of a 128-bit trace id, as OpenTelemetry
+/// and W3C `traceparent` headers write it.
+pub fn trace_id_to_hex(trace_id_high: u64, trace_id: u64) -> String {
+    format!("{:016x}{:016x}", trace_id_high, trace_id)
+}
+
+/// Returns the 16 hexadecimal digits of a span id, or of the lower 64 bits
+/// of a trace id, as OpenTelemetry writes it.
+pub fn id_to_hex(id: u64) -> String {
+    format!("{:016x}", id)
+}
+
+/// Reads an OpenTelemetry trace or span id, of up to 32 hexadecimal digits,
+/// as the 64-bit id Datadog shows in decimal: the lower 64 bits of 128-bit
+/// trace ids. Returns None for invalid ids, and for the all-zero id.
+pub fn hex_to_id(hex: &str) -> Option<u64> {
+    if hex.is_empty() || hex.len() > 32 || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let lower = &hex[hex.len().saturating_sub(16)..];
+    u64::from_str_radix(lower, 16)
+        .ok()
+        .filter(|_| hex.bytes().any(|c| c != b'0'))
+}
+
+/// Returns the URL of a trace in the Datadog app of `site`, e.g.
+/// `datadoghq.eu`, to link to it from error pages or alerts. 64-bit trace
+/// ids are written in decimal, 128-bit ones in hexadecimal.
+pub(crate) fn trace_url(site: &str, trace_id_high: u64, trace_id: u64) -> String {
+    let site = site.trim().trim_end_matches('/');
+    // Sites with their own subdomain, e.g. `us3.datadoghq.com`, serve the
+    // app there, the others from their `app` subdomain.
+    let host = if site.matches('.').count() > 1 {
+        String::from(site)
+    } else {
+        format!("app.{}", site)
+    };
+    let trace_id = match trace_id_high {
+        0 => trace_id.to_string(),
+        _ => trace_id_to_hex(trace_id_high, trace_id),
+    };
+
+    format!("https://{}/apm/trace/{}", host, trace_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_ids_for_deep_links() {
+        assert_eq!(
+            trace_id_to_hex(0x6553_4f80_0000_0000, 42),
+            "65534f8000000000000000000000002a"
+        );
+        assert_eq!(id_to_hex(255), "00000000000000ff");
+        assert_eq!(hex_to_id("65534f8000000000000000000000002a"), Some(42));
+        assert_eq!(hex_to_id("00000000000000FF"), Some(255));
+        assert_eq!(hex_to_id("0000000000000000"), None);
+        assert_eq!(hex_to_id("65534f8000000000000000000000002a00"), None);
+        assert_eq!(hex_to_id("xyz"), None);
+
+        assert_eq!(
+            trace_url("datadoghq.com", 0, 42),
+            "https://app.datadoghq.com/apm/trace/42"
+        );
+        assert_eq!(
+            trace_url("us3.datadoghq.com", 0x6553_4f80_0000_0000, 42),
+            "https://us3.datadoghq.com/apm/trace/65534f8000000000000000000000002a"
+        );
+    }
+}
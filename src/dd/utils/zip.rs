@@ -0,0 +1,95 @@
+/// CRC-32 (IEEE) of `data`, as stored in zip archives.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+/// Builds a zip archive of `files`, by name and content. Files are stored
+/// uncompressed: archives are small and only built on demand.
+pub(crate) fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+    // Version 2.0, no flags, stored, 1980-01-01 00:00.
+    const ENTRY_HEADER: [u16; 5] = [20, 0, 0, 0, 0x21];
+
+    let mut archive = Vec::new();
+    let mut directory = Vec::new();
+    let put16 = |out: &mut Vec<u8>, value: u16| out.extend_from_slice(&value.to_le_bytes());
+    let put32 = |out: &mut Vec<u8>, value: u32| out.extend_from_slice(&value.to_le_bytes());
+    for (name, content) in files {
+        let offset = archive.len() as u32;
+        let entry = |out: &mut Vec<u8>| {
+            for field in ENTRY_HEADER.iter() {
+                put16(out, *field);
+            }
+            put32(out, crc32(content));
+            put32(out, content.len() as u32);
+            put32(out, content.len() as u32);
+            put16(out, name.len() as u16);
+            put16(out, 0);
+        };
+
+        put32(&mut archive, 0x0403_4b50);
+        entry(&mut archive);
+        archive.extend_from_slice(name.as_bytes());
+        archive.extend_from_slice(content);
+
+        put32(&mut directory, 0x0201_4b50);
+        put16(&mut directory, 20);
+        entry(&mut directory);
+        // Comment length, disk and attributes.
+        for _ in 0..3 {
+            put16(&mut directory, 0);
+        }
+        put32(&mut directory, 0);
+        put32(&mut directory, offset);
+        directory.extend_from_slice(name.as_bytes());
+    }
+
+    let directory_offset = archive.len() as u32;
+    archive.extend_from_slice(&directory);
+    put32(&mut archive, 0x0605_4b50);
+    put16(&mut archive, 0);
+    put16(&mut archive, 0);
+    put16(&mut archive, files.len() as u16);
+    put16(&mut archive, files.len() as u16);
+    put32(&mut archive, directory.len() as u32);
+    put32(&mut archive, directory_offset);
+    put16(&mut archive, 0);
+
+    archive
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_stored_archives() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+
+        let archive = zip(&[("a.txt", b"hello"), ("b.json", b"{}")]);
+        assert_eq!(&archive[..4], b"PK\x03\x04");
+        assert_eq!(&archive[14..18], &crc32(b"hello").to_le_bytes());
+        assert_eq!(&archive[30..40], b"a.txthello");
+        let end = archive.len() - 22;
+        assert_eq!(&archive[end..end + 4], b"PK\x05\x06");
+        assert_eq!(&archive[end + 10..end + 12], &2u16.to_le_bytes());
+        let directory = u32::from_le_bytes([
+            archive[end + 16],
+            archive[end + 17],
+            archive[end + 18],
+            archive[end + 19],
+        ]) as usize;
+        assert_eq!(&archive[directory..directory + 4], b"PK\x01\x02");
+    }
+}
@@ -0,0 +1,1673 @@
+#[cfg(feature = "agentless")]
+use super::Intake;
+use super::{
+    encode_traces, encode_traces_v05, PayloadCompression, SendError, StatsConcentrator,
+    CONTENT_TYPE, MSGPACK_CONTENT_TYPE,
+};
+use crate::dd::{
+    agent::{AgentInfo, Transport, INFO_ENDPOINT, STATS_ENDPOINT, TRACES_V05_ENDPOINT},
+    span::{nanos_since_epoch, SpanData},
+    tags::{
+        ENVIRONMENT, ERROR_MSG, ERROR_TYPE, SAMPLING_PRIORITY, SPAN_SAMPLING_MECHANISM, TOP_LEVEL,
+    },
+    utils::{IdGenerator, LogLevel, RateLimitedLogger},
+};
+use eyre::{eyre, Result};
+use serde_json::Value;
+#[cfg(feature = "threads")]
+use std::thread::{self, JoinHandle};
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// How often the agent `/info` endpoint is queried again once it answered.
+const INFO_REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+/// Bounds of the wait before sending again to an agent which answered 429,
+/// doubled by each consecutive 429.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Name of the spans of the flushes of the writer, see `SelfTracing`.
+const FLUSH_SPAN_NAME: &str = "datadog.flush";
+
+/// Receives the `rate_by_service` object of the agent responses.
+pub(crate) type RatesHandler = Arc<dyn Fn(&Value) + Send + Sync>;
+
+/// What the writer drops when its queue of traces to send is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueFullPolicy {
+    /// Drops the trace being written, keeping the buffered ones.
+    DropNewest,
+    /// Drops the oldest buffered trace to make room for the new one.
+    DropOldest,
+}
+
+/// What the writer does with traces while the agent is unreachable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitOpenPolicy {
+    /// Keeps them queued, up to the queue limit, for when it's back.
+    Buffer,
+    /// Drops them, counted as `circuit_dropped` in the writer stats.
+    Drop,
+}
+
+/// CircuitBreaker stops the writer from trying to reach an agent which
+/// failed `failures` flushes in a row: the circuit opens, and no flush is
+/// attempted for `cooldown`. The next one probes the agent, closing the
+/// circuit if it succeeds or opening it again if it fails.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct CircuitBreaker {
+    pub failures: u32,
+    pub cooldown: Duration,
+    pub policy: CircuitOpenPolicy,
+}
+
+/// SelfTracing has the writer trace its own flushes, to see in Datadog how
+/// long sending payloads takes when debugging the export. Each flush of
+/// application traces is reported as a `datadog.flush` span, alone in its
+/// trace and sent by the next flush, with the traces and bytes sent and
+/// the number of posts it took as metrics.
+pub(crate) struct SelfTracing {
+    service: Arc<str>,
+    environment: String,
+    ids: IdGenerator,
+}
+
+impl SelfTracing {
+    pub fn new(service: &str, environment: &str) -> Self {
+        Self {
+            service: Arc::from(service),
+            environment: String::from(environment),
+            ids: IdGenerator::new(),
+        }
+    }
+
+    /// Returns the trace of the flush of `payload`, which started at
+    /// `start` and ended with `result`.
+    fn flush_trace(
+        &self,
+        start: SystemTime,
+        payload: &Payload,
+        result: &Result<Option<Value>>,
+    ) -> Vec<SpanData> {
+        let id = self.ids.next_id();
+        let mut span = SpanData {
+            span_type: Arc::from("worker"),
+            service: self.service.clone(),
+            resource: Arc::from(payload.endpoint),
+            name: Arc::from(FLUSH_SPAN_NAME),
+            trace_id: id,
+            span_id: id,
+            start: start
+                .duration_since(UNIX_EPOCH)
+                .map(|since_epoch| since_epoch.as_nanos() as i64)
+                .unwrap_or_default(),
+            duration: start
+                .elapsed()
+                .map(|elapsed| elapsed.as_nanos() as i64)
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+        if !self.environment.is_empty() {
+            span.meta
+                .insert(String::from(ENVIRONMENT), self.environment.clone());
+        }
+        if let Err(error) = result {
+            span.error = 1;
+            span.meta.insert(String::from(ERROR_MSG), error.to_string());
+            span.meta.insert(
+                String::from(ERROR_TYPE),
+                String::from(SendError::of(error).name()),
+            );
+        }
+        // Kept whatever the sampling of the application: it's only traced
+        // on demand.
+        span.metrics.insert(String::from(SAMPLING_PRIORITY), 2.0);
+        span.metrics
+            .insert(String::from("flush.traces"), payload.traces.len() as f64);
+        span.metrics
+            .insert(String::from("flush.sent_traces"), payload.sent as f64);
+        span.metrics
+            .insert(String::from("flush.payload_bytes"), payload.bytes as f64);
+        span.metrics
+            .insert(String::from("flush.posts"), payload.posts as f64);
+
+        vec![span]
+    }
+}
+
+#[derive(Default)]
+struct AgentWriterData {
+    traces: Vec<Vec<SpanData>>,
+    /// Bounds `traces`, which are unbounded without it.
+    queue_limit: Option<(usize, QueueFullPolicy)>,
+    /// Number of traces dropped because the queue was full.
+    queue_full: u64,
+    /// Nanoseconds spent encoding the payloads sent to the agent.
+    encoding_ns: u64,
+    dropped_p0_traces: u64,
+    dropped_p0_spans: u64,
+    /// Computes the stats of traces when the agent takes them, see
+    /// `AgentWriter::set_client_drop_p0s`.
+    stats: Option<StatsConcentrator>,
+    /// Set once the agent first answered that it takes the stats and the
+    /// counts of the traces dropped by sampling, which are then counted
+    /// instead of sent.
+    client_stats: bool,
+    /// Number of failed sends by class, see `SendError::name`.
+    send_errors: HashMap<&'static str, u64>,
+    /// Older traces endpoint used once the agent answered 404 to the newer.
+    traces_endpoint: Option<&'static str>,
+    /// Nothing is sent before then, after the agent answered 429.
+    retry_at: Option<Instant>,
+    backoff: Duration,
+    circuit_breaker: Option<CircuitBreaker>,
+    consecutive_failures: u32,
+    /// Set while the circuit is open: nothing is sent before then, when a
+    /// flush probes the agent.
+    circuit_open_until: Option<Instant>,
+    /// Number of traces dropped while the circuit was open.
+    circuit_dropped: u64,
+    self_tracing: Option<SelfTracing>,
+    agent_info: Option<AgentInfo>,
+    info_refreshed: Option<Instant>,
+    /// Dropped for good once the agent rejects a compressed payload.
+    compression: Option<PayloadCompression>,
+    /// Reports the failures of background flushes.
+    logger: Option<Arc<RateLimitedLogger>>,
+    rates_handler: Option<RatesHandler>,
+    /// Set by `AgentWriter::flush` to wake the background thread up early.
+    flush_requested: bool,
+    /// Whether a flush took the buffered traces and is still sending them.
+    flushing: bool,
+    /// Number of completed flushes, and traces sent by the last one.
+    flush_count: u64,
+    flushed_traces: usize,
+    /// Why the last flush failed, as returned by `flush` to its callers.
+    flush_error: Option<(SendError, String)>,
+    /// Set by `AgentWriter::pause` to stop the background thread.
+    #[cfg(feature = "threads")]
+    stop: bool,
+}
+
+impl AgentWriterData {
+    /// Turns client stats on if the agent, as it first answered, takes them
+    /// along with the counts of dropped traces. The traces waiting to be
+    /// sent are added to the stats, as the payloads sending them tell the
+    /// agent not to compute theirs.
+    fn enable_client_stats(&mut self, info: &AgentInfo) {
+        if !info.client_drop_p0s || !info.supports_stats() {
+            self.stats = None;
+            return;
+        }
+        if let Some(stats) = &mut self.stats {
+            for trace in &self.traces {
+                stats.add_trace(trace);
+            }
+            self.client_stats = true;
+        }
+    }
+
+    /// Adds `trace` to the client stats, returning whether they're on.
+    fn add_stats(&mut self, trace: &[SpanData]) -> bool {
+        match &mut self.stats {
+            Some(stats) if self.client_stats => {
+                stats.add_trace(trace);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Accounts for a trace that was dropped by sampling (P0) instead of
+    /// being written, so the agent can keep its sampling metrics accurate.
+    fn record_dropped_trace(&mut self, span_count: usize) {
+        self.dropped_p0_traces += 1;
+        self.dropped_p0_spans += span_count as u64;
+    }
+
+    /// Puts back traces which couldn't be sent in front of the queue,
+    /// dropping the excess as set by the queue limit.
+    fn requeue(&mut self, mut traces: Vec<Vec<SpanData>>) {
+        traces.append(&mut self.traces);
+        if let Some((capacity, policy)) = self.queue_limit {
+            let excess = traces.len().saturating_sub(capacity);
+            self.queue_full += excess as u64;
+            match policy {
+                QueueFullPolicy::DropNewest => traces.truncate(capacity),
+                QueueFullPolicy::DropOldest => drop(traces.drain(..excess)),
+            }
+        }
+        self.traces = traces;
+    }
+
+    fn circuit_drops(&self) -> bool {
+        self.circuit_open_until.is_some()
+            && self
+                .circuit_breaker
+                .is_some_and(|breaker| breaker.policy == CircuitOpenPolicy::Drop)
+    }
+
+    /// Counts a failed flush, opening the circuit to the agent after too
+    /// many in a row. Returns the message to log when it opens.
+    fn flush_failed(&mut self) -> Option<String> {
+        self.consecutive_failures += 1;
+        let breaker = self
+            .circuit_breaker
+            .filter(|breaker| self.consecutive_failures >= breaker.failures)?;
+        let opening = self.circuit_open_until.is_none();
+        self.circuit_open_until = Some(Instant::now() + breaker.cooldown);
+        if self.circuit_drops() {
+            self.circuit_dropped += self.traces.len() as u64;
+            self.traces.clear();
+        }
+
+        opening.then(|| {
+            format!(
+                "Agent unreachable after {} attempts, pausing sends for {:?}",
+                self.consecutive_failures, breaker.cooldown
+            )
+        })
+    }
+
+    /// Closes the circuit after a successful flush. Returns the message to
+    /// log if it was open.
+    fn flush_succeeded(&mut self) -> Option<String> {
+        self.consecutive_failures = 0;
+        self.circuit_open_until
+            .take()
+            .map(|_| String::from("Agent reachable again, resuming sends"))
+    }
+}
+
+type Shared = Arc<(Mutex<AgentWriterData>, Condvar)>;
+
+/// Where the writer sends traces.
+pub(crate) enum Destination {
+    Agent,
+    #[cfg(feature = "agentless")]
+    Intake(Intake),
+}
+
+/// AgentWriter buffers finished traces and sends them to the agent from a
+/// background thread every write period. The same thread keeps the agent
+/// capabilities up to date.
+///
+/// Without the `threads` feature nothing is sent in the background and the
+/// host has to call `flush` periodically.
+pub(crate) struct AgentWriter {
+    shared: Shared,
+    client: Arc<dyn Transport>,
+    destination: Arc<Destination>,
+    #[cfg_attr(not(feature = "threads"), allow(dead_code))]
+    write_period: Duration,
+    #[cfg(feature = "threads")]
+    worker: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl AgentWriter {
+    pub fn new(
+        client: Arc<dyn Transport>,
+        destination: Destination,
+        write_period: Duration,
+    ) -> Self {
+        let writer = Self {
+            shared: Arc::new((Mutex::new(AgentWriterData::default()), Condvar::new())),
+            client,
+            destination: Arc::new(destination),
+            write_period,
+            #[cfg(feature = "threads")]
+            worker: Mutex::new(None),
+        };
+        let _ = writer.resume();
+
+        writer
+    }
+
+    /// Stops the background thread after a last flush. Used before forking
+    /// so that no thread holds the writer state while the process is copied.
+    #[cfg(feature = "threads")]
+    pub fn pause(&self) -> Result<()> {
+        let worker = self
+            .worker
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .take();
+        let worker = match worker {
+            Some(worker) => worker,
+            None => return Ok(()),
+        };
+
+        self.shared
+            .0
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .stop = true;
+        self.shared.1.notify_all();
+        worker.join().map_err(|_| eyre!("writer thread panicked"))
+    }
+
+    #[cfg(not(feature = "threads"))]
+    pub fn pause(&self) -> Result<()> {
+        flush(&self.shared, self.client.as_ref(), &self.destination, true).map(|_| ())
+    }
+
+    /// Starts the background thread if it isn't running.
+    #[cfg(feature = "threads")]
+    pub fn resume(&self) -> Result<()> {
+        let mut worker = self.worker.lock().map_err(|_| eyre!("mutex lock failed"))?;
+        if worker.is_some() {
+            return Ok(());
+        }
+
+        self.shared
+            .0
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .stop = false;
+        let shared = self.shared.clone();
+        let client = self.client.clone();
+        let destination = self.destination.clone();
+        let write_period = self.write_period;
+        *worker = Some(thread::spawn(move || {
+            run(shared, client, destination, write_period)
+        }));
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "threads"))]
+    pub fn resume(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Sends everything written so far and returns the number of traces
+    /// sent, waiting at most `timeout` for the background thread to do it.
+    #[cfg(feature = "threads")]
+    pub fn flush(&self, timeout: Duration) -> Result<usize> {
+        let running = self
+            .worker
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .is_some();
+        if !running {
+            return flush(&self.shared, self.client.as_ref(), &self.destination, true);
+        }
+
+        let (lock, condvar) = &*self.shared;
+        let mut data = lock.lock().map_err(|_| eyre!("mutex lock failed"))?;
+        // A flush in progress took its traces before this call: wait for the
+        // next one.
+        let target = data.flush_count + if data.flushing { 2 } else { 1 };
+        data.flush_requested = true;
+        condvar.notify_all();
+
+        let (data, result) = condvar
+            .wait_timeout_while(data, timeout, |data| data.flush_count < target)
+            .map_err(|_| eyre!("mutex lock failed"))?;
+        if result.timed_out() {
+            return Err(eyre!("Flush timed out after {:?}", timeout));
+        }
+
+        match &data.flush_error {
+            Some((error, message)) => Err(eyre::Report::new(*error).wrap_err(message.clone())),
+            None => Ok(data.flushed_traces),
+        }
+    }
+
+    /// Sends everything written so far from the calling thread and returns
+    /// the number of traces sent. The transport's own timeout applies.
+    #[cfg(not(feature = "threads"))]
+    pub fn flush(&self, _timeout: Duration) -> Result<usize> {
+        flush(&self.shared, self.client.as_ref(), &self.destination, true)
+    }
+
+    /// Discards everything waiting to be sent, e.g. traces inherited from the
+    /// parent process after a fork.
+    pub fn clear(&self) -> Result<()> {
+        let mut data = self
+            .shared
+            .0
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?;
+        data.traces.clear();
+        data.dropped_p0_traces = 0;
+        data.dropped_p0_spans = 0;
+        if let Some(stats) = &mut data.stats {
+            stats.clear();
+        }
+        if let Some(tracing) = &data.self_tracing {
+            tracing.ids.reseed();
+        }
+
+        Ok(())
+    }
+
+    /// Queues a trace to be sent. When the queue is full a trace is dropped
+    /// as set by `set_queue_limit`, rather than blocking the caller.
+    pub fn write(&self, mut trace: Vec<SpanData>) -> Result<()> {
+        mark_top_level(&mut trace);
+        let logger = {
+            let mut data = self
+                .shared
+                .0
+                .lock()
+                .map_err(|_| eyre!("mutex lock failed"))?;
+            if data.circuit_drops() {
+                data.circuit_dropped += 1;
+                return Ok(());
+            }
+            if data.add_stats(&trace) && is_droppable_p0(&trace) {
+                data.record_dropped_trace(trace.len());
+                return Ok(());
+            }
+            match data.queue_limit {
+                Some((capacity, policy)) if data.traces.len() >= capacity => {
+                    data.queue_full += 1;
+                    if policy == QueueFullPolicy::DropOldest && !data.traces.is_empty() {
+                        data.traces.remove(0);
+                        data.traces.push(trace);
+                    }
+                    data.logger.clone()
+                }
+                _ => {
+                    data.traces.push(trace);
+                    return Ok(());
+                }
+            }
+        };
+
+        if let Some(logger) = logger {
+            logger.log(
+                LogLevel::Warn,
+                "queue",
+                "Dropping a trace: too many traces are waiting to be sent",
+            );
+        }
+        Ok(())
+    }
+
+    /// Bounds the number of traces waiting to be sent to `capacity`.
+    pub fn set_queue_limit(&self, capacity: usize, policy: QueueFullPolicy) -> Result<()> {
+        self.shared
+            .0
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .queue_limit = Some((capacity, policy));
+
+        Ok(())
+    }
+
+    /// Stops trying to reach an agent which keeps failing, see
+    /// `CircuitBreaker`. Without one, every flush is attempted.
+    pub fn set_circuit_breaker(&self, breaker: Option<CircuitBreaker>) -> Result<()> {
+        self.shared
+            .0
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .circuit_breaker = breaker;
+
+        Ok(())
+    }
+
+    /// Traces the flushes of the writer, see `SelfTracing`.
+    pub fn set_self_tracing(&self, tracing: Option<SelfTracing>) -> Result<()> {
+        self.shared
+            .0
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .self_tracing = tracing;
+
+        Ok(())
+    }
+
+    /// Returns how many traces were dropped because the queue was full.
+    pub fn queue_full(&self) -> Result<u64> {
+        let data = self
+            .shared
+            .0
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?;
+
+        Ok(data.queue_full)
+    }
+
+    /// Returns how many sends failed, by class of error, see
+    /// `SendError::name`.
+    #[cfg(test)]
+    pub fn send_errors(&self) -> Result<HashMap<&'static str, u64>> {
+        let data = self
+            .shared
+            .0
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?;
+
+        Ok(data.send_errors.clone())
+    }
+
+    /// Returns how many nanoseconds were spent encoding the payloads sent to
+    /// the agent.
+    pub fn encoding_time(&self) -> Result<u64> {
+        let data = self
+            .shared
+            .0
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?;
+
+        Ok(data.encoding_ns)
+    }
+
+    /// Computes the stats of traces with `stats`, sent to the agent
+    /// `/v0.6/stats` endpoint, and counts the traces dropped by sampling in
+    /// the `Datadog-Client-Dropped-P0-*` headers instead of sending them.
+    /// Both start once the agent `/info` endpoint first tells it takes them,
+    /// if it does: until then, and otherwise, the agent computes the stats
+    /// from all of the traces.
+    pub fn set_client_drop_p0s(&self, stats: Option<StatsConcentrator>) -> Result<()> {
+        self.shared
+            .0
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .stats = stats;
+
+        Ok(())
+    }
+
+    /// Compresses the payloads sent to the agent from now on.
+    pub fn set_compression(&self, compression: Option<PayloadCompression>) -> Result<()> {
+        self.shared
+            .0
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .compression = compression;
+
+        Ok(())
+    }
+
+    /// Logs the failures of background flushes through `logger`.
+    pub fn set_logger(&self, logger: Arc<RateLimitedLogger>) -> Result<()> {
+        self.shared
+            .0
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .logger = Some(logger);
+
+        Ok(())
+    }
+
+    /// Passes the sampling rates the agent answers with to `handler`.
+    pub fn set_rates_handler(&self, handler: RatesHandler) -> Result<()> {
+        self.shared
+            .0
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?
+            .rates_handler = Some(handler);
+
+        Ok(())
+    }
+
+    /// Returns the state of the writer, e.g. for flares.
+    pub fn stats(&self) -> Result<Value> {
+        let data = self
+            .shared
+            .0
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?;
+
+        Ok(serde_json::json!({
+            "buffered_traces": data.traces.len(),
+            "queue_full": data.queue_full,
+            "dropped_p0_traces": data.dropped_p0_traces,
+            "dropped_p0_spans": data.dropped_p0_spans,
+            "flush_count": data.flush_count,
+            "flushed_traces": data.flushed_traces,
+            "compression": data.compression.is_some(),
+            "send_errors": data.send_errors,
+            "circuit_open": data.circuit_open_until.is_some(),
+            "circuit_dropped": data.circuit_dropped,
+            "agent_version": data.agent_info.as_ref().map(|info| info.version.clone()),
+        }))
+    }
+
+    /// Posts `body` to `path` on the agent, e.g. a flare. Fails when traces
+    /// are sent to the intake instead.
+    pub fn post_to_agent(&self, path: &str, headers: &[(&str, String)], body: &[u8]) -> Result<()> {
+        #[cfg(feature = "agentless")]
+        {
+            if let Destination::Intake(_) = *self.destination {
+                return Err(eyre!("Traces are sent to the intake, not to an agent"));
+            }
+        }
+        let response = self.client.post(path, headers, body)?;
+        if !response.is_success() {
+            return Err(eyre!("Agent responded with {}", response.status));
+        }
+
+        Ok(())
+    }
+
+    pub fn agent_info(&self) -> Result<Option<AgentInfo>> {
+        let data = self
+            .shared
+            .0
+            .lock()
+            .map_err(|_| eyre!("mutex lock failed"))?;
+
+        Ok(data.agent_info.clone())
+    }
+}
+
+impl Drop for AgentWriter {
+    fn drop(&mut self) {
+        let _ = self.pause();
+    }
+}
+
+fn fetch_agent_info(client: &dyn Transport) -> Result<AgentInfo> {
+    let response = client.get(INFO_ENDPOINT)?;
+    if !response.is_success() {
+        return Err(eyre!("Agent /info responded with {}", response.status));
+    }
+
+    AgentInfo::from_json(&serde_json::from_slice(&response.body)?)
+}
+
+/// Whether the agent would drop `trace` anyway: it was dropped by sampling,
+/// and none of its spans is an error or kept by span sampling.
+fn is_droppable_p0(trace: &[SpanData]) -> bool {
+    let dropped = trace.iter().any(|span| {
+        span.metrics
+            .get(SAMPLING_PRIORITY)
+            .is_some_and(|priority| *priority <= 0.0)
+    });
+
+    dropped
+        && trace
+            .iter()
+            .all(|span| span.error == 0 && !span.metrics.contains_key(SPAN_SAMPLING_MECHANISM))
+}
+
+/// Marks the spans that are entry points into a service: local roots and
+/// spans whose parent belongs to another service.
+fn mark_top_level(trace: &mut [SpanData]) {
+    let services: HashMap<u64, Arc<str>> = trace
+        .iter()
+        .map(|span| (span.span_id, span.service.clone()))
+        .collect();
+
+    for span in trace.iter_mut() {
+        let top_level = match services.get(&span.parent_id) {
+            Some(parent_service) => *parent_service != span.service,
+            None => true,
+        };
+        if top_level {
+            span.metrics.insert(String::from(TOP_LEVEL), 1.0);
+        }
+    }
+}
+
+struct Payload {
+    traces: Vec<Vec<SpanData>>,
+    endpoint: &'static str,
+    /// Number of traces sent, the first ones.
+    sent: usize,
+    /// Errors met sending the payload, including those worked around.
+    errors: Vec<SendError>,
+    /// Number of posts sending the payload took, and bytes posted.
+    posts: u32,
+    bytes: usize,
+    /// Dropped trace and span counts reported by the payload, cleared once
+    /// the agent accepted them so that later posts don't report them again.
+    dropped_p0_traces: u64,
+    dropped_p0_spans: u64,
+    /// Set when the agent accepted the dropped counts.
+    dropped_p0_reported: bool,
+    /// Whether the stats of the traces are computed by the writer.
+    client_stats: bool,
+    compression: Option<PayloadCompression>,
+    /// Set when the agent refused the compressed payload.
+    compression_rejected: bool,
+    /// Time spent encoding and compressing the payload.
+    encoding: Duration,
+}
+
+impl Payload {
+    fn is_empty(&self) -> bool {
+        self.traces.is_empty() && self.dropped_p0_traces == 0
+    }
+
+    /// Returns the rates of the `response` of the agent, which received the
+    /// dropped trace counts.
+    fn accepted(&mut self, response: &[u8]) -> Option<Value> {
+        self.dropped_p0_traces = 0;
+        self.dropped_p0_spans = 0;
+        self.dropped_p0_reported = true;
+        rates_by_service(response)
+    }
+}
+
+/// Returns the sampling rates by service the agent responded with, if any.
+fn rates_by_service(body: &[u8]) -> Option<Value> {
+    let mut response: Value = serde_json::from_slice(body).ok()?;
+    match response.get_mut("rate_by_service")?.take() {
+        rates @ Value::Object(_) => Some(rates),
+        _ => None,
+    }
+}
+
+/// Sends the traces of `payload` in `range`: to older endpoints while the
+/// agent doesn't have the newer, and in halves while it finds them too
+/// large.
+fn send_traces(
+    client: &dyn Transport,
+    payload: &mut Payload,
+    range: Range<usize>,
+) -> Result<Option<Value>> {
+    let error = match post_traces(client, payload, range.clone()) {
+        Ok(rates) => {
+            payload.sent = range.end;
+            return Ok(rates);
+        }
+        Err(error) => error,
+    };
+    let class = SendError::of(&error);
+    payload.errors.push(class);
+    match class {
+        SendError::EndpointNotFound => {
+            payload.endpoint = AgentInfo::older_traces_endpoint(payload.endpoint).ok_or(error)?;
+            send_traces(client, payload, range)
+        }
+        SendError::PayloadTooLarge if range.len() > 1 => {
+            let middle = range.start + range.len() / 2;
+            let first = send_traces(client, payload, range.start..middle)?;
+            Ok(send_traces(client, payload, middle..range.end)?.or(first))
+        }
+        _ => Err(error),
+    }
+}
+
+fn post_traces(
+    client: &dyn Transport,
+    payload: &mut Payload,
+    range: Range<usize>,
+) -> Result<Option<Value>> {
+    let traces = &payload.traces[range];
+    let endpoint = payload.endpoint;
+    let start = Instant::now();
+    let (content_type, body) = match endpoint {
+        TRACES_V05_ENDPOINT => (MSGPACK_CONTENT_TYPE, encode_traces_v05(traces)),
+        _ => (CONTENT_TYPE, encode_traces(traces)),
+    };
+    payload.encoding += start.elapsed();
+    let mut headers = vec![
+        ("Content-Type", String::from(content_type)),
+        ("X-Datadog-Trace-Count", traces.len().to_string()),
+        // Spans are marked top-level by `mark_top_level` before they're sent.
+        ("Datadog-Client-Computed-Top-Level", String::from("yes")),
+        ("Datadog-Meta-Lang", String::from("rust")),
+        (
+            "Datadog-Meta-Tracer-Version",
+            String::from(env!("CARGO_PKG_VERSION")),
+        ),
+        (
+            "Datadog-Client-Dropped-P0-Traces",
+            payload.dropped_p0_traces.to_string(),
+        ),
+        (
+            "Datadog-Client-Dropped-P0-Spans",
+            payload.dropped_p0_spans.to_string(),
+        ),
+    ];
+    if payload.client_stats {
+        headers.push(("Datadog-Client-Computed-Stats", String::from("yes")));
+    }
+
+    if let Some(compression) = payload
+        .compression
+        .filter(|compression| body.len() >= compression.threshold)
+    {
+        let start = Instant::now();
+        let compressed = compression.compression.compress(&body, compression.level)?;
+        payload.encoding += start.elapsed();
+        let encoding = compression.compression.content_encoding();
+        headers.push(("Content-Encoding", String::from(encoding)));
+        payload.posts += 1;
+        payload.bytes += compressed.len();
+        let response = client.post(endpoint, &headers, &compressed)?;
+        headers.pop();
+
+        // Agents that don't support the encoding refuse the payload with 415:
+        // send it again uncompressed. Other failures aren't about the
+        // encoding and are handled as for uncompressed payloads.
+        match response.status {
+            415 => payload.compression_rejected = true,
+            _ if response.is_success() => return Ok(payload.accepted(&response.body)),
+            status => return Err(SendError::from_status(status).into()),
+        }
+    }
+
+    payload.posts += 1;
+    payload.bytes += body.len();
+    let response = client.post(endpoint, &headers, &body)?;
+    if !response.is_success() {
+        return Err(SendError::from_status(response.status).into());
+    }
+
+    Ok(payload.accepted(&response.body))
+}
+
+/// Posts a payload of client stats to the agent. Stats which can't be sent
+/// are dropped.
+fn post_stats(client: &dyn Transport, body: &[u8]) -> Result<()> {
+    let headers = [
+        ("Content-Type", String::from(MSGPACK_CONTENT_TYPE)),
+        ("Datadog-Meta-Lang", String::from("rust")),
+        (
+            "Datadog-Meta-Tracer-Version",
+            String::from(env!("CARGO_PKG_VERSION")),
+        ),
+    ];
+    let response = client.post(STATS_ENDPOINT, &headers, body)?;
+    if !response.is_success() {
+        return Err(SendError::from_status(response.status).into());
+    }
+
+    Ok(())
+}
+
+fn refresh_agent_info(shared: &Shared, client: &dyn Transport) {
+    let refresh_due = match shared.0.lock() {
+        Ok(data) => data
+            .info_refreshed
+            .is_none_or(|at| at.elapsed() >= INFO_REFRESH_INTERVAL),
+        Err(_) => return,
+    };
+    if !refresh_due {
+        return;
+    }
+
+    // Agents predating /info or not yet reachable are retried every flush.
+    if let Ok(info) = fetch_agent_info(client) {
+        if let Ok(mut data) = shared.0.lock() {
+            if data.agent_info.is_none() {
+                data.enable_client_stats(&info);
+            }
+            data.agent_info = Some(info);
+            data.info_refreshed = Some(Instant::now());
+        }
+    }
+}
+
+/// Sends the buffered traces, and the stats of the buckets which ended, or
+/// of all of them if `all_stats`.
+fn flush(
+    shared: &Shared,
+    client: &dyn Transport,
+    destination: &Destination,
+    all_stats: bool,
+) -> Result<usize> {
+    if matches!(destination, Destination::Agent) {
+        refresh_agent_info(shared, client);
+    }
+
+    let (mut payload, endpoint, dropped_p0, stats) = {
+        let mut data = shared.0.lock().map_err(|_| eyre!("mutex lock failed"))?;
+        data.flush_requested = false;
+        // Traces wait in the queue until the agent can take them again.
+        let now = Instant::now();
+        if data.retry_at.is_some_and(|at| now < at)
+            || data.circuit_open_until.is_some_and(|at| now < at)
+        {
+            data.flush_count += 1;
+            data.flushed_traces = 0;
+            data.flush_error = None;
+            shared.1.notify_all();
+            return Ok(0);
+        }
+        // Dropped counts stay in the writer until the agent accepts them, to
+        // be reported again by the next flush if this one fails. Flushes
+        // overlapping this one don't report them twice.
+        let (dropped_p0_traces, dropped_p0_spans) = if data.flushing {
+            (0, 0)
+        } else {
+            (data.dropped_p0_traces, data.dropped_p0_spans)
+        };
+        data.flushing = true;
+        let client_stats = data.client_stats;
+        let stats = match &mut data.stats {
+            Some(stats) if client_stats => {
+                stats.flush(nanos_since_epoch(SystemTime::now()), all_stats)
+            }
+            _ => None,
+        };
+        let endpoint = data
+            .traces_endpoint
+            .unwrap_or_else(|| AgentInfo::traces_endpoint(data.agent_info.as_ref()));
+        let payload = Payload {
+            traces: std::mem::take(&mut data.traces),
+            endpoint,
+            sent: 0,
+            errors: Vec::new(),
+            posts: 0,
+            bytes: 0,
+            dropped_p0_traces,
+            dropped_p0_spans,
+            dropped_p0_reported: false,
+            client_stats,
+            compression: data.compression,
+            compression_rejected: false,
+            encoding: Duration::ZERO,
+        };
+        (
+            payload,
+            endpoint,
+            (dropped_p0_traces, dropped_p0_spans),
+            stats,
+        )
+    };
+
+    let attempted = !payload.is_empty();
+    // Flushes of nothing but flush spans aren't traced, or every flush
+    // would make another one.
+    let start = SystemTime::now();
+    let traced = payload
+        .traces
+        .iter()
+        .flatten()
+        .any(|span| &*span.name != FLUSH_SPAN_NAME);
+    let result = match destination {
+        // Dropped counts are sent even without traces, otherwise they would
+        // only reach the agent with the next kept trace.
+        _ if payload.is_empty() => Ok(None),
+        Destination::Agent => {
+            let traces = 0..payload.traces.len();
+            send_traces(client, &mut payload, traces)
+        }
+        // The intake has no use for dropped trace counts.
+        #[cfg(feature = "agentless")]
+        Destination::Intake(_) if payload.traces.is_empty() => Ok(None),
+        #[cfg(feature = "agentless")]
+        Destination::Intake(intake) => intake
+            .send(client, &payload.traces)
+            .map(|_| None)
+            .inspect_err(|error| payload.errors.push(SendError::of(error))),
+    };
+    if let Ok(Some(rates)) = &result {
+        let handler = shared
+            .0
+            .lock()
+            .ok()
+            .and_then(|data| data.rates_handler.clone());
+        if let Some(handler) = handler {
+            handler(rates);
+        }
+    }
+    let stats_result = stats.map(|stats| post_stats(client, &stats));
+
+    let mut data = shared.0.lock().map_err(|_| eyre!("mutex lock failed"))?;
+    if payload.compression_rejected {
+        data.compression = None;
+    }
+    data.encoding_ns += payload.encoding.as_nanos() as u64;
+    for error in &payload.errors {
+        *data.send_errors.entry(error.name()).or_default() += 1;
+    }
+    if payload.endpoint != endpoint {
+        data.traces_endpoint = Some(payload.endpoint);
+    }
+    // Cleared by `clear` in the meantime if it's less.
+    if payload.dropped_p0_reported {
+        data.dropped_p0_traces = data.dropped_p0_traces.saturating_sub(dropped_p0.0);
+        data.dropped_p0_spans = data.dropped_p0_spans.saturating_sub(dropped_p0.1);
+    }
+    let flush_trace = data
+        .self_tracing
+        .as_ref()
+        .filter(|_| traced)
+        .map(|tracing| tracing.flush_trace(start, &payload, &result));
+    let mut transition = None;
+    let sent = match &result {
+        Ok(_) => {
+            data.backoff = Duration::ZERO;
+            data.retry_at = None;
+            if attempted {
+                transition = data
+                    .flush_succeeded()
+                    .map(|message| (LogLevel::Info, "agent_up", message));
+            }
+            payload.traces.len()
+        }
+        Err(error) => {
+            if SendError::of(error) == SendError::RateLimited {
+                data.backoff = (data.backoff * 2).clamp(MIN_BACKOFF, MAX_BACKOFF);
+                data.retry_at = Some(Instant::now() + data.backoff);
+                data.requeue(payload.traces.split_off(payload.sent));
+            } else {
+                transition = data
+                    .flush_failed()
+                    .map(|message| (LogLevel::Warn, "agent_down", message));
+                if data.circuit_open_until.is_some() && !data.circuit_drops() {
+                    data.requeue(payload.traces.split_off(payload.sent));
+                }
+            }
+            payload.sent
+        }
+    };
+    if let Some(mut trace) = flush_trace {
+        mark_top_level(&mut trace);
+        let full = data
+            .queue_limit
+            .is_some_and(|(capacity, _)| data.traces.len() >= capacity);
+        if !full && !data.circuit_drops() {
+            data.add_stats(&trace);
+            data.traces.push(trace);
+        }
+    }
+    data.flushing = false;
+    data.flush_count += 1;
+    data.flushed_traces = sent;
+    data.flush_error = result
+        .as_ref()
+        .err()
+        .map(|error| (SendError::of(error), error.to_string()));
+    shared.1.notify_all();
+
+    let logger = data.logger.clone();
+    drop(data);
+    if let (Some((level, class, message)), Some(logger)) = (transition, &logger) {
+        logger.log(level, class, &message);
+    }
+    if let (Some(Err(error)), Some(logger)) = (stats_result, &logger) {
+        logger.log(
+            LogLevel::Error,
+            "stats",
+            &format!("Failed to send stats: {}", error),
+        );
+    }
+
+    result.map(|_| sent)
+}
+
+#[cfg(feature = "threads")]
+fn run(
+    shared: Shared,
+    client: Arc<dyn Transport>,
+    destination: Arc<Destination>,
+    write_period: Duration,
+) {
+    loop {
+        let (stop, requested) = {
+            let (lock, condvar) = &*shared;
+            let data = match lock.lock() {
+                Ok(data) => data,
+                Err(_) => return,
+            };
+            match condvar.wait_timeout_while(data, write_period, |data| {
+                !data.stop && !data.flush_requested
+            }) {
+                Ok((data, _)) => (data.stop, data.flush_requested),
+                Err(_) => return,
+            }
+        };
+
+        // Stats of the current bucket wait for it to end, unless everything
+        // written so far is to be sent.
+        let result = flush(&shared, client.as_ref(), &destination, stop || requested);
+        let logger = shared.0.lock().ok().and_then(|data| data.logger.clone());
+        match (result, logger) {
+            (Ok(0), _) | (_, None) => {}
+            (Ok(sent), Some(logger)) => {
+                logger.log(LogLevel::Debug, "sent", &format!("Sent {} traces", sent))
+            }
+            (Err(error), Some(logger)) => logger.log(
+                LogLevel::Error,
+                "flush",
+                &format!("Failed to send traces: {}", error),
+            ),
+        }
+        if stop {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dd::{agent::MockTransport, writer::decode_msgpack};
+
+    /// `/info` of an agent taking client stats and dropped trace counts.
+    const CLIENT_STATS_INFO: &[u8] =
+        br#"{"client_drop_p0s": true, "endpoints": ["/v0.4/traces", "/v0.6/stats"]}"#;
+
+    fn span(span_id: u64, parent_id: u64, service: &str) -> SpanData {
+        SpanData {
+            span_id,
+            parent_id,
+            service: Arc::from(service),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn marks_service_entry_spans_as_top_level() {
+        let mut trace = vec![
+            span(1, 0, "web"),
+            span(2, 1, "web"),
+            span(3, 2, "db"),
+            span(4, 42, "web"),
+        ];
+        mark_top_level(&mut trace);
+
+        let top_level: Vec<bool> = trace
+            .iter()
+            .map(|span| span.metrics.contains_key(TOP_LEVEL))
+            .collect();
+        assert_eq!(top_level, vec![true, false, true, true]);
+    }
+
+    #[test]
+    fn flush_sends_buffered_traces() {
+        let transport = Arc::new(MockTransport::default());
+        let writer = AgentWriter::new(
+            transport.clone(),
+            Destination::Agent,
+            Duration::from_secs(3600),
+        );
+        writer.write(vec![span(1, 0, "web")]).unwrap();
+        writer.write(vec![span(2, 0, "web")]).unwrap();
+
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 2);
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 0);
+        assert_eq!(transport.header_values("X-Datadog-Trace-Count"), vec!["2"]);
+        assert_eq!(
+            transport.header_values("Datadog-Client-Computed-Top-Level"),
+            vec!["yes"]
+        );
+        assert_eq!(
+            transport.header_values("Datadog-Client-Dropped-P0-Traces"),
+            vec!["0"]
+        );
+        assert_eq!(
+            transport.header_values("Datadog-Client-Dropped-P0-Spans"),
+            vec!["0"]
+        );
+    }
+
+    #[test]
+    fn drops_traces_beyond_the_queue_limit() {
+        let sent_ids = |policy: QueueFullPolicy| {
+            let transport = Arc::new(MockTransport::default());
+            let writer = AgentWriter::new(
+                transport.clone(),
+                Destination::Agent,
+                Duration::from_secs(3600),
+            );
+            writer.set_queue_limit(2, policy).unwrap();
+            for span_id in 1..=4 {
+                writer.write(vec![span(span_id, 0, "web")]).unwrap();
+            }
+            assert_eq!(writer.queue_full().unwrap(), 2);
+
+            writer.flush(Duration::from_secs(5)).unwrap();
+            let posts = transport.posts.lock().unwrap();
+            let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0].1).unwrap();
+            traces
+                .iter()
+                .map(|trace| trace[0]["span_id"].as_u64().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(sent_ids(QueueFullPolicy::DropNewest), vec![1, 2]);
+        assert_eq!(sent_ids(QueueFullPolicy::DropOldest), vec![3, 4]);
+    }
+
+    #[test]
+    fn counts_dropped_traces_instead_of_sending_them() {
+        let transport = Arc::new(MockTransport {
+            info_body: Some(CLIENT_STATS_INFO.to_vec()),
+            ..Default::default()
+        });
+        let writer = AgentWriter::new(
+            transport.clone(),
+            Destination::Agent,
+            Duration::from_secs(3600),
+        );
+        writer
+            .set_client_drop_p0s(Some(StatsConcentrator::default()))
+            .unwrap();
+        let trace = |span_id: u64, priority: f64| {
+            let mut root = span(span_id, 0, "web");
+            root.metrics
+                .insert(String::from(SAMPLING_PRIORITY), priority);
+            vec![root, span(span_id + 1, span_id, "web")]
+        };
+        // The agent hasn't answered yet.
+        writer.write(trace(1, 0.0)).unwrap();
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 1);
+
+        writer.write(trace(3, 0.0)).unwrap();
+        writer.write(trace(5, -1.0)).unwrap();
+        writer.write(trace(7, 1.0)).unwrap();
+        let mut failed = trace(9, 0.0);
+        failed[1].error = 1;
+        writer.write(failed).unwrap();
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 2);
+
+        assert_eq!(
+            transport.header_values("Datadog-Client-Dropped-P0-Traces"),
+            vec!["0", "2"]
+        );
+        assert_eq!(
+            transport.header_values("Datadog-Client-Dropped-P0-Spans"),
+            vec!["0", "4"]
+        );
+        assert_eq!(
+            transport.header_values("Datadog-Client-Computed-Stats"),
+            vec!["yes", "yes"]
+        );
+        assert_eq!(
+            *transport.paths.lock().unwrap(),
+            vec![
+                "/v0.4/traces",
+                STATS_ENDPOINT,
+                "/v0.4/traces",
+                STATS_ENDPOINT
+            ]
+        );
+        let posts = transport.posts.lock().unwrap();
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[2].1).unwrap();
+        let roots: Vec<&Value> = traces.iter().map(|trace| &trace[0]["span_id"]).collect();
+        assert_eq!(roots, vec![7, 9]);
+        // Traces written before the agent answered are in the stats too.
+        let hits: Vec<Value> = [&posts[1].1, &posts[3].1]
+            .iter()
+            .map(|body| {
+                decode_msgpack(&mut body.as_slice())["Stats"][0]["Stats"][0]["Hits"].clone()
+            })
+            .collect();
+        assert_eq!(hits, vec![1, 4]);
+    }
+
+    #[test]
+    fn sends_dropped_traces_to_agents_without_client_stats() {
+        let transport = Arc::new(MockTransport {
+            info_body: Some(br#"{"client_drop_p0s": true}"#.to_vec()),
+            ..Default::default()
+        });
+        let writer = AgentWriter::new(
+            transport.clone(),
+            Destination::Agent,
+            Duration::from_secs(3600),
+        );
+        writer
+            .set_client_drop_p0s(Some(StatsConcentrator::default()))
+            .unwrap();
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 0);
+        let mut root = span(1, 0, "web");
+        root.metrics.insert(String::from(SAMPLING_PRIORITY), 0.0);
+        writer.write(vec![root]).unwrap();
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 1);
+
+        assert_eq!(*transport.paths.lock().unwrap(), vec!["/v0.4/traces"]);
+        assert!(transport
+            .header_values("Datadog-Client-Computed-Stats")
+            .is_empty());
+        assert_eq!(
+            transport.header_values("Datadog-Client-Dropped-P0-Traces"),
+            vec!["0"]
+        );
+    }
+
+    #[test]
+    fn reports_dropped_traces_until_the_agent_accepts_them() {
+        let transport = Arc::new(MockTransport {
+            info_body: Some(CLIENT_STATS_INFO.to_vec()),
+            statuses: Mutex::new(vec![500].into_iter().collect()),
+            ..Default::default()
+        });
+        let writer = AgentWriter::new(
+            transport.clone(),
+            Destination::Agent,
+            Duration::from_secs(3600),
+        );
+        writer
+            .set_client_drop_p0s(Some(StatsConcentrator::default()))
+            .unwrap();
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 0);
+        let mut root = span(1, 0, "web");
+        root.metrics.insert(String::from(SAMPLING_PRIORITY), 0.0);
+        writer.write(vec![root, span(2, 1, "web")]).unwrap();
+
+        assert!(writer.flush(Duration::from_secs(5)).is_err());
+        assert_eq!(writer.stats().unwrap()["dropped_p0_traces"], 1);
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 0);
+        assert_eq!(writer.stats().unwrap()["dropped_p0_traces"], 0);
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 0);
+
+        assert_eq!(
+            transport.header_values("Datadog-Client-Dropped-P0-Traces"),
+            vec!["1", "1"]
+        );
+        assert_eq!(
+            transport.header_values("Datadog-Client-Dropped-P0-Spans"),
+            vec!["2", "2"]
+        );
+    }
+
+    #[test]
+    fn handles_agent_responses() {
+        let agent = |statuses: &[u16]| {
+            let transport = Arc::new(MockTransport {
+                statuses: Mutex::new(statuses.iter().copied().collect()),
+                ..Default::default()
+            });
+            let writer = AgentWriter::new(
+                transport.clone(),
+                Destination::Agent,
+                Duration::from_secs(3600),
+            );
+            (transport, writer)
+        };
+
+        // Older endpoints are tried, and kept, when the agent has no newer.
+        let (transport, writer) = agent(&[404]);
+        writer.write(vec![span(1, 0, "web")]).unwrap();
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 1);
+        writer.write(vec![span(2, 0, "web")]).unwrap();
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 1);
+        assert_eq!(
+            *transport.paths.lock().unwrap(),
+            vec!["/v0.4/traces", "/v0.3/traces", "/v0.3/traces"]
+        );
+
+        // Payloads too large are split.
+        let (transport, writer) = agent(&[413]);
+        for span_id in 1..=3 {
+            writer.write(vec![span(span_id, 0, "web")]).unwrap();
+        }
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 3);
+        assert_eq!(
+            transport.header_values("X-Datadog-Trace-Count"),
+            vec!["3", "1", "2"]
+        );
+
+        // Traces wait for the agent to recover.
+        let (transport, writer) = agent(&[429]);
+        writer.write(vec![span(1, 0, "web")]).unwrap();
+        let error = writer.flush(Duration::from_secs(5)).unwrap_err();
+        assert_eq!(SendError::of(&error), SendError::RateLimited);
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 0);
+        assert_eq!(transport.posts.lock().unwrap().len(), 1);
+        assert_eq!(writer.stats().unwrap()["buffered_traces"], 1);
+        assert_eq!(
+            writer.send_errors().unwrap(),
+            vec![("rate_limited", 1)].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn adds_up_the_encoding_time_of_retries() {
+        let transport = MockTransport {
+            statuses: Mutex::new(vec![404, 413].into_iter().collect()),
+            ..Default::default()
+        };
+        let hour = Duration::from_secs(3600);
+        let mut payload = Payload {
+            traces: vec![vec![span(1, 0, "web")], vec![span(2, 0, "web")]],
+            endpoint: TRACES_V05_ENDPOINT,
+            sent: 0,
+            errors: Vec::new(),
+            posts: 0,
+            bytes: 0,
+            dropped_p0_traces: 0,
+            dropped_p0_spans: 0,
+            dropped_p0_reported: false,
+            client_stats: false,
+            compression: None,
+            compression_rejected: false,
+            encoding: hour,
+        };
+
+        send_traces(&transport, &mut payload, 0..2).unwrap();
+        assert_eq!(payload.posts, 4);
+        assert!(payload.encoding > hour);
+    }
+
+    #[test]
+    fn flush_returns_send_errors() {
+        let transport = Arc::new(MockTransport {
+            statuses: Mutex::new(vec![500].into_iter().collect()),
+            ..Default::default()
+        });
+        let writer = AgentWriter::new(transport, Destination::Agent, Duration::from_secs(3600));
+        writer.write(vec![span(1, 0, "web")]).unwrap();
+
+        let error = writer.flush(Duration::from_secs(5)).unwrap_err();
+        assert_eq!(SendError::of(&error), SendError::Status(500));
+        writer.write(vec![span(2, 0, "web")]).unwrap();
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 1);
+    }
+
+    #[test]
+    fn traces_its_own_flushes() {
+        let transport = Arc::new(MockTransport {
+            statuses: Mutex::new(vec![413].into_iter().collect()),
+            ..Default::default()
+        });
+        let writer = AgentWriter::new(
+            transport.clone(),
+            Destination::Agent,
+            Duration::from_secs(3600),
+        );
+        writer
+            .set_self_tracing(Some(SelfTracing::new("web", "prod")))
+            .unwrap();
+        writer.write(vec![span(1, 0, "web")]).unwrap();
+        writer.write(vec![span(2, 0, "web")]).unwrap();
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 2);
+
+        let flush_span = writer.shared.0.lock().unwrap().traces[0][0].clone();
+        assert_eq!(&*flush_span.name, FLUSH_SPAN_NAME);
+        assert_eq!(&*flush_span.service, "web");
+        assert_eq!(&*flush_span.resource, "/v0.4/traces");
+        assert_eq!(flush_span.meta[ENVIRONMENT], "prod");
+        assert_eq!(flush_span.error, 0);
+        assert_eq!(flush_span.metrics["flush.traces"], 2.0);
+        assert_eq!(flush_span.metrics["flush.sent_traces"], 2.0);
+        // The payload too large, then its halves.
+        assert_eq!(flush_span.metrics["flush.posts"], 3.0);
+        assert!(flush_span.metrics["flush.payload_bytes"] > 0.0);
+
+        // Sending the flush span alone isn't traced.
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 1);
+        assert_eq!(writer.stats().unwrap()["buffered_traces"], 0);
+    }
+
+    #[test]
+    fn opens_circuit_to_failing_agents() {
+        let agent = |cooldown: Duration, policy: CircuitOpenPolicy| {
+            let transport = Arc::new(MockTransport {
+                statuses: Mutex::new(vec![503, 503].into_iter().collect()),
+                ..Default::default()
+            });
+            let writer = AgentWriter::new(
+                transport.clone(),
+                Destination::Agent,
+                Duration::from_secs(3600),
+            );
+            let messages = Arc::new(Mutex::new(Vec::new()));
+            let captured = messages.clone();
+            let logger = RateLimitedLogger::new(Arc::new(move |level, message: &str| {
+                captured
+                    .lock()
+                    .unwrap()
+                    .push(format!("{:?}: {}", level, message))
+            }));
+            writer.set_logger(Arc::new(logger)).unwrap();
+            writer
+                .set_circuit_breaker(Some(CircuitBreaker {
+                    failures: 2,
+                    cooldown,
+                    policy,
+                }))
+                .unwrap();
+            for span_id in 1..=2 {
+                writer.write(vec![span(span_id, 0, "web")]).unwrap();
+                let _ = writer.flush(Duration::from_secs(5));
+            }
+            (transport, writer, messages)
+        };
+
+        // Nothing is sent while the circuit is open.
+        let (transport, writer, messages) =
+            agent(Duration::from_secs(3600), CircuitOpenPolicy::Drop);
+        writer.write(vec![span(3, 0, "web")]).unwrap();
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 0);
+        assert_eq!(transport.posts.lock().unwrap().len(), 2);
+        let stats = writer.stats().unwrap();
+        assert_eq!(stats["circuit_open"], true);
+        assert_eq!(stats["circuit_dropped"], 1);
+        assert_eq!(
+            messages.lock().unwrap().last().unwrap(),
+            "Warn: Agent unreachable after 2 attempts, pausing sends for 3600s"
+        );
+
+        // The next flush probes the agent once the cooldown is over.
+        let (transport, writer, messages) = agent(Duration::ZERO, CircuitOpenPolicy::Buffer);
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 1);
+        assert_eq!(transport.posts.lock().unwrap().len(), 3);
+        assert_eq!(writer.stats().unwrap()["circuit_open"], false);
+        writer.write(vec![span(3, 0, "web")]).unwrap();
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 1);
+        assert_eq!(transport.posts.lock().unwrap().len(), 4);
+        assert_eq!(
+            messages.lock().unwrap().last().unwrap(),
+            "Info: Agent reachable again, resuming sends"
+        );
+    }
+
+    #[test]
+    fn sends_v05_payloads_to_agents_advertising_it() {
+        let transport = Arc::new(MockTransport {
+            info_body: Some(br#"{"endpoints": ["/v0.4/traces", "/v0.5/traces"]}"#.to_vec()),
+            statuses: Mutex::new(vec![200, 404].into_iter().collect()),
+            ..Default::default()
+        });
+        let writer = AgentWriter::new(
+            transport.clone(),
+            Destination::Agent,
+            Duration::from_secs(3600),
+        );
+        writer.write(vec![span(1, 0, "web")]).unwrap();
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 1);
+        // Agents losing v0.5 are sent v0.4 payloads again.
+        writer.write(vec![span(2, 0, "web")]).unwrap();
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 1);
+
+        assert_eq!(
+            *transport.paths.lock().unwrap(),
+            vec!["/v0.5/traces", "/v0.5/traces", "/v0.4/traces"]
+        );
+        assert_eq!(
+            transport.header_values("Content-Type"),
+            vec![
+                "application/msgpack",
+                "application/msgpack",
+                "application/json"
+            ]
+        );
+        let posts = transport.posts.lock().unwrap();
+        assert_eq!(posts[0].1[0], 0x92);
+        let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[2].1).unwrap();
+        assert_eq!(traces[0][0]["span_id"], 2);
+    }
+
+    #[test]
+    fn passes_agent_rates_to_handler() {
+        let transport = Arc::new(MockTransport {
+            response_body: br#"{"rate_by_service": {"service:web,env:": 0.5}}"#.to_vec(),
+            ..Default::default()
+        });
+        let writer = AgentWriter::new(transport, Destination::Agent, Duration::from_secs(3600));
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let handler_received = received.clone();
+        writer
+            .set_rates_handler(Arc::new(move |rates: &Value| {
+                handler_received.lock().unwrap().push(rates.clone())
+            }))
+            .unwrap();
+
+        writer.write(vec![span(1, 0, "web")]).unwrap();
+        writer.flush(Duration::from_secs(5)).unwrap();
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![serde_json::json!({"service:web,env:": 0.5})]
+        );
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn falls_back_to_uncompressed_payloads() {
+        use crate::dd::writer::Compression;
+
+        let transport = Arc::new(MockTransport {
+            reject_compressed: true,
+            ..Default::default()
+        });
+        let writer = AgentWriter::new(
+            transport.clone(),
+            Destination::Agent,
+            Duration::from_secs(3600),
+        );
+        let compression = PayloadCompression {
+            compression: Compression::Gzip,
+            level: 0,
+            threshold: 0,
+        };
+        writer.set_compression(Some(compression)).unwrap();
+
+        writer.write(vec![span(1, 0, "web")]).unwrap();
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 1);
+        writer.write(vec![span(2, 0, "web")]).unwrap();
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 1);
+
+        // Compressed then uncompressed, then uncompressed only.
+        let encodings: Vec<Option<String>> = transport
+            .posts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(headers, _)| {
+                headers
+                    .iter()
+                    .find(|(key, _)| key == "Content-Encoding")
+                    .map(|(_, value)| value.clone())
+            })
+            .collect();
+        assert_eq!(encodings, vec![Some(String::from("gzip")), None, None]);
+
+        // Bad requests aren't taken for a rejected encoding.
+        let transport = Arc::new(MockTransport {
+            statuses: Mutex::new(vec![400].into_iter().collect()),
+            ..Default::default()
+        });
+        let writer = AgentWriter::new(
+            transport.clone(),
+            Destination::Agent,
+            Duration::from_secs(3600),
+        );
+        writer.set_compression(Some(compression)).unwrap();
+        writer.write(vec![span(1, 0, "web")]).unwrap();
+        let error = writer.flush(Duration::from_secs(5)).unwrap_err();
+        assert_eq!(SendError::of(&error), SendError::Status(400));
+        writer.write(vec![span(2, 0, "web")]).unwrap();
+        assert_eq!(writer.flush(Duration::from_secs(5)).unwrap(), 1);
+        assert_eq!(
+            transport.header_values("Content-Encoding"),
+            vec!["gzip", "gzip"]
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use eyre::{eyre, Result};
+
+/// Content encodings trace payloads can be compressed with. Each of them
+/// needs the cargo feature of the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn content_encoding(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    /// Returns the cargo feature the compression needs.
+    pub fn feature(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    pub fn is_supported(&self) -> bool {
+        match self {
+            Compression::Gzip => cfg!(feature = "gzip"),
+            Compression::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+
+    /// Compresses `data`. A `level` of 0 uses the default level of the
+    /// algorithm.
+    #[cfg_attr(not(any(feature = "gzip", feature = "zstd")), allow(unused_variables))]
+    pub fn compress(&self, data: &[u8], level: u32) -> Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => {
+                use flate2::write::GzEncoder;
+                use std::io::Write;
+
+                let level = match level {
+                    0 => flate2::Compression::default(),
+                    level => flate2::Compression::new(level.min(9)),
+                };
+                let mut encoder = GzEncoder::new(Vec::new(), level);
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(zstd::encode_all(data, level.min(22) as i32)?),
+            #[allow(unreachable_patterns)]
+            _ => Err(eyre!(
+                "{} compression requires the {} feature",
+                self.content_encoding(),
+                self.feature()
+            )),
+        }
+    }
+}
+
+/// PayloadCompression is how the writer compresses payloads sent to the
+/// agent: payloads smaller than `threshold` bytes are sent as they are.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PayloadCompression {
+    pub compression: Compression,
+    pub level: u32,
+    pub threshold: usize,
+}
+
+#[cfg(all(test, any(feature = "gzip", feature = "zstd")))]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_round_trip() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let data = vec![b'x'; 4096];
+        let compressed = Compression::Gzip.compress(&data, 0).unwrap();
+        assert!(compressed.len() < data.len());
+
+        let mut decompressed = Vec::new();
+        GzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_round_trip() {
+        let data = vec![b'x'; 4096];
+        let compressed = Compression::Zstd.compress(&data, 3).unwrap();
+        assert_eq!(zstd::decode_all(compressed.as_slice()).unwrap(), data);
+    }
+}
@@ -0,0 +1,381 @@
+use crate::dd::span::SpanData;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+pub(crate) const CONTENT_TYPE: &str = "application/json";
+pub(crate) const MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
+
+fn encode_span(span: &SpanData) -> Value {
+    json!({
+        "type": &*span.span_type,
+        "service": &*span.service,
+        "resource": &*span.resource,
+        "name": &*span.name,
+        "trace_id": span.trace_id,
+        "span_id": span.span_id,
+        "parent_id": span.parent_id,
+        "start": span.start,
+        "duration": span.duration,
+        "error": span.error,
+        "meta": span.meta,
+        "metrics": span.metrics,
+    })
+}
+
+/// Encodes traces into the payload expected by the agent traces endpoints: an
+/// array of traces, each of them an array of spans.
+pub(crate) fn encode_traces(traces: &[Vec<SpanData>]) -> Vec<u8> {
+    let payload: Vec<Value> = traces
+        .iter()
+        .map(|trace| Value::Array(trace.iter().map(encode_span).collect()))
+        .collect();
+
+    Value::Array(payload).to_string().into_bytes()
+}
+
+/// Interns the strings of a v0.5 payload, index 0 being the empty string.
+struct StringTable<'a> {
+    strings: Vec<&'a str>,
+    indexes: HashMap<&'a str, u32>,
+}
+
+impl<'a> StringTable<'a> {
+    fn new() -> Self {
+        let mut table = StringTable {
+            strings: Vec::new(),
+            indexes: HashMap::new(),
+        };
+        table.index("");
+        table
+    }
+
+    fn index(&mut self, string: &'a str) -> u32 {
+        let strings = &mut self.strings;
+        *self.indexes.entry(string).or_insert_with(|| {
+            strings.push(string);
+            strings.len() as u32 - 1
+        })
+    }
+}
+
+fn write_header(out: &mut Vec<u8>, len: usize, fix: u8, fix_max: usize, marker16: u8) {
+    if len <= fix_max {
+        out.push(fix | len as u8);
+    } else if len <= u16::MAX as usize {
+        out.push(marker16);
+        out.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        out.push(marker16 + 1);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+}
+
+pub(crate) fn write_array_len(out: &mut Vec<u8>, len: usize) {
+    write_header(out, len, 0x90, 15, 0xdc);
+}
+
+pub(crate) fn write_map_len(out: &mut Vec<u8>, len: usize) {
+    write_header(out, len, 0x80, 15, 0xde);
+}
+
+pub(crate) fn write_str(out: &mut Vec<u8>, string: &str) {
+    let len = string.len();
+    if len < 32 {
+        out.push(0xa0 | len as u8);
+    } else if len <= u8::MAX as usize {
+        out.extend_from_slice(&[0xd9, len as u8]);
+    } else if len <= u16::MAX as usize {
+        out.push(0xda);
+        out.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        out.push(0xdb);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+    out.extend_from_slice(string.as_bytes());
+}
+
+pub(crate) fn write_uint(out: &mut Vec<u8>, value: u64) {
+    if value < 0x80 {
+        out.push(value as u8);
+    } else if value <= u64::from(u8::MAX) {
+        out.extend_from_slice(&[0xcc, value as u8]);
+    } else if value <= u64::from(u16::MAX) {
+        out.push(0xcd);
+        out.extend_from_slice(&(value as u16).to_be_bytes());
+    } else if value <= u64::from(u32::MAX) {
+        out.push(0xce);
+        out.extend_from_slice(&(value as u32).to_be_bytes());
+    } else {
+        out.push(0xcf);
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+pub(crate) fn write_int(out: &mut Vec<u8>, value: i64) {
+    if value >= 0 {
+        write_uint(out, value as u64);
+    } else if value >= -32 {
+        out.push(value as u8);
+    } else {
+        out.push(0xd3);
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+pub(crate) fn write_f64(out: &mut Vec<u8>, value: f64) {
+    out.push(0xcb);
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+pub(crate) fn write_bool(out: &mut Vec<u8>, value: bool) {
+    out.push(if value { 0xc3 } else { 0xc2 });
+}
+
+pub(crate) fn write_bin(out: &mut Vec<u8>, bytes: &[u8]) {
+    let len = bytes.len();
+    if len <= u8::MAX as usize {
+        out.extend_from_slice(&[0xc4, len as u8]);
+    } else if len <= u16::MAX as usize {
+        out.push(0xc5);
+        out.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        out.push(0xc6);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+    out.extend_from_slice(bytes);
+}
+
+fn encode_span_v05<'a>(out: &mut Vec<u8>, table: &mut StringTable<'a>, span: &'a SpanData) {
+    write_array_len(out, 12);
+    for string in [&*span.service, &*span.name, &*span.resource].iter() {
+        write_uint(out, u64::from(table.index(string)));
+    }
+    write_uint(out, span.trace_id);
+    write_uint(out, span.span_id);
+    write_uint(out, span.parent_id);
+    write_int(out, span.start);
+    write_int(out, span.duration);
+    write_int(out, i64::from(span.error));
+
+    // Tags are sorted so that equal spans encode to equal payloads.
+    let mut meta: Vec<_> = span.meta.iter().collect();
+    meta.sort();
+    write_map_len(out, meta.len());
+    for (key, value) in meta {
+        write_uint(out, u64::from(table.index(key)));
+        write_uint(out, u64::from(table.index(value)));
+    }
+    let mut metrics: Vec<_> = span.metrics.iter().collect();
+    metrics.sort_by(|a, b| a.0.cmp(b.0));
+    write_map_len(out, metrics.len());
+    for (key, value) in metrics {
+        write_uint(out, u64::from(table.index(key)));
+        write_f64(out, *value);
+    }
+
+    write_uint(out, u64::from(table.index(&span.span_type)));
+}
+
+/// Encodes traces into the msgpack payload of `/v0.5/traces`: the table of
+/// the strings of the payload, then the traces, their spans being arrays
+/// whose strings are indexes into the table.
+pub(crate) fn encode_traces_v05(traces: &[Vec<SpanData>]) -> Vec<u8> {
+    let mut table = StringTable::new();
+    let mut spans = Vec::new();
+    write_array_len(&mut spans, traces.len());
+    for trace in traces {
+        write_array_len(&mut spans, trace.len());
+        for span in trace {
+            encode_span_v05(&mut spans, &mut table, span);
+        }
+    }
+
+    let mut out = Vec::with_capacity(spans.len());
+    write_array_len(&mut out, 2);
+    write_array_len(&mut out, table.strings.len());
+    for string in &table.strings {
+        write_str(&mut out, string);
+    }
+    out.extend_from_slice(&spans);
+    out
+}
+
+/// Decodes the msgpack value at the start of `bytes`, advancing past it.
+/// Only handles what the encoders write; map keys become strings.
+#[cfg(test)]
+pub(crate) fn decode_msgpack(bytes: &mut &[u8]) -> Value {
+    fn take<'a>(bytes: &mut &'a [u8], len: usize) -> &'a [u8] {
+        let (taken, rest) = bytes.split_at(len);
+        *bytes = rest;
+        taken
+    }
+    fn uint(bytes: &mut &[u8], len: usize) -> u64 {
+        take(bytes, len)
+            .iter()
+            .fold(0, |value, byte| value << 8 | u64::from(*byte))
+    }
+    fn array(bytes: &mut &[u8], len: usize) -> Value {
+        Value::Array((0..len).map(|_| decode_msgpack(bytes)).collect())
+    }
+    fn map(bytes: &mut &[u8], len: usize) -> Value {
+        let entries = (0..len).map(|_| {
+            let key = match decode_msgpack(bytes) {
+                Value::String(key) => key,
+                key => key.to_string(),
+            };
+            (key, decode_msgpack(bytes))
+        });
+        Value::Object(entries.collect())
+    }
+    fn string(bytes: &mut &[u8], len: usize) -> Value {
+        Value::from(std::str::from_utf8(take(bytes, len)).unwrap())
+    }
+    fn bin(bytes: &mut &[u8], len: usize) -> Value {
+        Value::from(take(bytes, len).to_vec())
+    }
+
+    let marker = take(bytes, 1)[0];
+    match marker {
+        0x00..=0x7f => Value::from(marker),
+        0x80..=0x8f => map(bytes, usize::from(marker & 0x0f)),
+        0x90..=0x9f => array(bytes, usize::from(marker & 0x0f)),
+        0xa0..=0xbf => string(bytes, usize::from(marker & 0x1f)),
+        0xc2 => Value::from(false),
+        0xc3 => Value::from(true),
+        0xc4 => {
+            let len = uint(bytes, 1) as usize;
+            bin(bytes, len)
+        }
+        0xc5 => {
+            let len = uint(bytes, 2) as usize;
+            bin(bytes, len)
+        }
+        0xcb => Value::from(f64::from_bits(uint(bytes, 8))),
+        0xcc => Value::from(uint(bytes, 1)),
+        0xcd => Value::from(uint(bytes, 2)),
+        0xce => Value::from(uint(bytes, 4)),
+        0xcf => Value::from(uint(bytes, 8)),
+        0xd3 => Value::from(uint(bytes, 8) as i64),
+        0xd9 => {
+            let len = uint(bytes, 1) as usize;
+            string(bytes, len)
+        }
+        0xda => {
+            let len = uint(bytes, 2) as usize;
+            string(bytes, len)
+        }
+        0xdc => {
+            let len = uint(bytes, 2) as usize;
+            array(bytes, len)
+        }
+        0xde => {
+            let len = uint(bytes, 2) as usize;
+            map(bytes, len)
+        }
+        0xe0..=0xff => Value::from(marker as i8),
+        _ => panic!("unexpected msgpack marker {:#x}", marker),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, sync::Arc};
+
+    /// Compares the `decoded` payload to the golden file `name` in
+    /// `snapshots`, or writes it if `UPDATE_SNAPSHOTS` is set. Payloads are
+    /// compared once decoded, as the order of JSON tags isn't stable.
+    fn assert_snapshot(name: &str, decoded: Value) {
+        let path = format!(
+            "{}/src/dd/writer/snapshots/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            let mut pretty = serde_json::to_string_pretty(&decoded).unwrap();
+            pretty.push('\n');
+            fs::write(&path, pretty).unwrap();
+            return;
+        }
+
+        let golden: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(
+            decoded, golden,
+            "{} changed, run with UPDATE_SNAPSHOTS=1 if that's intended",
+            name
+        );
+    }
+
+    fn decode_v05(payload: &[u8]) -> Value {
+        let mut bytes = payload;
+        let decoded = decode_msgpack(&mut bytes);
+        assert!(bytes.is_empty(), "trailing bytes after the payload");
+        decoded
+    }
+
+    fn traces() -> Vec<Vec<SpanData>> {
+        let mut root = SpanData {
+            span_type: Arc::from("web"),
+            service: Arc::from("checkout"),
+            resource: Arc::from("GET /cart"),
+            name: Arc::from("http.request"),
+            trace_id: 0xfedc_ba98_7654_3210,
+            span_id: 0xfedc_ba98_7654_3210,
+            start: 1_700_000_000_000_000_000,
+            duration: 12_500_000,
+            ..Default::default()
+        };
+        root.meta
+            .insert(String::from("http.url"), String::from("/cart?id=é"));
+        root.meta
+            .insert(String::from("_dd.p.dm"), String::from("-3"));
+        root.metrics
+            .insert(String::from("_sampling_priority_v1"), 2.0);
+        root.metrics.insert(String::from("_dd.rule_psr"), 0.25);
+        let child = SpanData {
+            span_type: Arc::from("sql"),
+            resource: Arc::from("SELECT * FROM carts WHERE id = ?"),
+            name: Arc::from("postgres.query"),
+            span_id: 2,
+            parent_id: root.span_id,
+            start: root.start + 1_000_000,
+            duration: 8_000_000,
+            error: 1,
+            ..root.clone()
+        };
+        let other = SpanData {
+            service: Arc::from("worker"),
+            name: Arc::from("job"),
+            resource: Arc::from("job"),
+            trace_id: 3,
+            span_id: 3,
+            ..Default::default()
+        };
+
+        vec![vec![root, child], vec![other]]
+    }
+
+    #[test]
+    fn encodes_v04_payloads() {
+        let decode = |payload: Vec<u8>| serde_json::from_slice(&payload).unwrap();
+        assert_snapshot("v04_traces.json", decode(encode_traces(&traces())));
+        assert_snapshot("v04_empty.json", decode(encode_traces(&[])));
+    }
+
+    #[test]
+    fn encodes_v05_payloads() {
+        assert_snapshot("v05_traces.json", decode_v05(&encode_traces_v05(&traces())));
+        assert_snapshot("v05_empty.json", decode_v05(&encode_traces_v05(&[])));
+    }
+
+    #[test]
+    fn encodes_long_v05_strings() {
+        let span = SpanData {
+            resource: Arc::from("x".repeat(300)),
+            ..Default::default()
+        };
+        let decoded = decode_v05(&encode_traces_v05(&[vec![span]]));
+        assert_eq!(decoded[0][1], "x".repeat(300));
+        assert_eq!(decoded[1][0][0][2], 1);
+    }
+}
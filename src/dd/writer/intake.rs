@@ -0,0 +1,212 @@
+use super::{Compression, SendError};
+use crate::dd::{agent::Transport, span::SpanData, tags::SAMPLING_PRIORITY};
+use eyre::{eyre, Result};
+use std::collections::HashMap;
+
+pub(crate) const INTAKE_TRACES_ENDPOINT: &str = "/api/v0.2/traces";
+pub(crate) const INTAKE_PORT: u16 = 443;
+
+const LANGUAGE: &str = "rust";
+
+/// Host of the trace intake for a Datadog site, e.g. `datadoghq.eu`.
+pub(crate) fn intake_host(site: &str) -> String {
+    format!("trace.agent.{}", site)
+}
+
+/// Minimal protobuf encoder for the intake payload messages. Fields holding
+/// their default value are omitted, as in proto3.
+#[derive(Default)]
+struct Message(Vec<u8>);
+
+impl Message {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u8) {
+        self.varint(((field as u64) << 3) | wire_type as u64);
+    }
+
+    fn uint64(&mut self, field: u32, value: u64) {
+        if value != 0 {
+            self.key(field, 0);
+            self.varint(value);
+        }
+    }
+
+    fn int64(&mut self, field: u32, value: i64) {
+        self.uint64(field, value as u64);
+    }
+
+    fn int32(&mut self, field: u32, value: i32) {
+        // Negative int32 are sign-extended to 64 bits.
+        self.uint64(field, value as i64 as u64);
+    }
+
+    fn double(&mut self, field: u32, value: f64) {
+        if value != 0.0 {
+            self.key(field, 1);
+            self.0.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    fn bytes(&mut self, field: u32, value: &[u8]) {
+        self.key(field, 2);
+        self.varint(value.len() as u64);
+        self.0.extend_from_slice(value);
+    }
+
+    fn string(&mut self, field: u32, value: &str) {
+        if !value.is_empty() {
+            self.bytes(field, value.as_bytes());
+        }
+    }
+
+    fn message(&mut self, field: u32, message: &Message) {
+        self.bytes(field, &message.0);
+    }
+
+    fn string_map(&mut self, field: u32, map: &HashMap<String, String>) {
+        for (key, value) in map {
+            let mut entry = Message::default();
+            entry.string(1, key);
+            entry.string(2, value);
+            self.message(field, &entry);
+        }
+    }
+
+    fn double_map(&mut self, field: u32, map: &HashMap<String, f64>) {
+        for (key, value) in map {
+            let mut entry = Message::default();
+            entry.string(1, key);
+            entry.double(2, *value);
+            self.message(field, &entry);
+        }
+    }
+}
+
+fn encode_span(span: &SpanData) -> Message {
+    let mut message = Message::default();
+    message.string(1, &span.service);
+    message.string(2, &span.name);
+    message.string(3, &span.resource);
+    message.uint64(4, span.trace_id);
+    message.uint64(5, span.span_id);
+    message.uint64(6, span.parent_id);
+    message.int64(7, span.start);
+    message.int64(8, span.duration);
+    message.int32(9, span.error);
+    message.string_map(10, &span.meta);
+    message.double_map(11, &span.metrics);
+    message.string(12, &span.span_type);
+    message
+}
+
+fn encode_chunk(trace: &[SpanData]) -> Message {
+    let priority = trace
+        .iter()
+        .find_map(|span| span.metrics.get(SAMPLING_PRIORITY))
+        .map_or(1, |priority| *priority as i32);
+
+    let mut message = Message::default();
+    message.int32(1, priority);
+    for span in trace {
+        message.message(3, &encode_span(span));
+    }
+    message
+}
+
+/// Encodes traces as an `AgentPayload`, the message the agent itself sends
+/// to the intake.
+fn encode_payload(traces: &[Vec<SpanData>], env: &str, version: &str) -> Vec<u8> {
+    let mut tracer_payload = Message::default();
+    tracer_payload.string(2, LANGUAGE);
+    tracer_payload.string(4, env!("CARGO_PKG_VERSION"));
+    for trace in traces {
+        tracer_payload.message(6, &encode_chunk(trace));
+    }
+    tracer_payload.string(8, env);
+    tracer_payload.string(10, version);
+
+    let mut payload = Message::default();
+    payload.string(2, env);
+    payload.message(5, &tracer_payload);
+    payload.0
+}
+
+/// Intake sends traces straight to the Datadog intake, authenticated with
+/// an API key, for environments where no agent can run.
+pub(crate) struct Intake {
+    api_key: String,
+    env: String,
+    version: String,
+}
+
+impl Intake {
+    pub fn new(api_key: &str, env: &str, version: &str) -> Result<Self> {
+        if api_key.is_empty() {
+            return Err(eyre!("Agentless mode requires an API key"));
+        }
+
+        Ok(Self {
+            api_key: String::from(api_key),
+            env: String::from(env),
+            version: String::from(version),
+        })
+    }
+
+    pub fn send(&self, client: &dyn Transport, traces: &[Vec<SpanData>]) -> Result<()> {
+        let body =
+            Compression::Gzip.compress(&encode_payload(traces, &self.env, &self.version), 0)?;
+
+        let headers = [
+            ("Content-Type", String::from("application/x-protobuf")),
+            ("Content-Encoding", String::from("gzip")),
+            ("DD-API-KEY", self.api_key.clone()),
+            ("X-Datadog-Reported-Languages", String::from(LANGUAGE)),
+        ];
+        let response = client.post(INTAKE_TRACES_ENDPOINT, &headers, &body)?;
+        if !response.is_success() {
+            return Err(SendError::from_status(response.status).into());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_protobuf_fields() {
+        let mut message = Message::default();
+        message.uint64(4, 300);
+        message.int32(9, -1);
+        message.string(1, "web");
+        message.string(2, "");
+        message.double(8, 0.5);
+        assert_eq!(
+            message.0,
+            vec![
+                0x20, 0xac, 0x02, // field 4, varint 300
+                0x48, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+                0x01, // field 9, -1
+                0x0a, 0x03, b'w', b'e', b'b', // field 1, "web"
+                0x41, 0, 0, 0, 0, 0, 0, 0xe0, 0x3f, // field 8, 0.5
+            ]
+        );
+    }
+
+    #[test]
+    fn encodes_sampling_priority_of_chunks() {
+        let mut root = SpanData::default();
+        root.metrics.insert(String::from(SAMPLING_PRIORITY), 2.0);
+        assert_eq!(&encode_chunk(&[root]).0[..2], &[0x08, 0x02]);
+        assert_eq!(&encode_chunk(&[SpanData::default()]).0[..2], &[0x08, 0x01]);
+    }
+}
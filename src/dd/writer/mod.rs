@@ -0,0 +1,17 @@
+mod agent_writer;
+mod compression;
+mod encoder;
+#[cfg(feature = "agentless")]
+mod intake;
+mod send_error;
+mod sketch;
+mod stats;
+
+pub use agent_writer::*;
+pub use compression::*;
+pub(crate) use encoder::*;
+#[cfg(feature = "agentless")]
+pub(crate) use intake::*;
+pub(crate) use send_error::*;
+pub(crate) use sketch::*;
+pub(crate) use stats::*;
@@ -0,0 +1,100 @@
+use eyre::Report;
+use std::{fmt, io};
+
+/// SendError is why a payload of traces wasn't accepted, which decides what
+/// the writer does about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SendError {
+    /// 404: the agent doesn't have the endpoint, the older ones are tried.
+    EndpointNotFound,
+    /// 413: the payload is split in halves sent separately.
+    PayloadTooLarge,
+    /// 429: the traces are sent again once the agent had time to recover.
+    RateLimited,
+    /// The agent refused the connection: it's down or not there.
+    AgentDown,
+    /// Any other status.
+    Status(u16),
+    /// Any other failure of the transport, e.g. a timeout.
+    Transport,
+}
+
+impl SendError {
+    pub fn from_status(status: u16) -> Self {
+        match status {
+            404 => SendError::EndpointNotFound,
+            413 => SendError::PayloadTooLarge,
+            429 => SendError::RateLimited,
+            status => SendError::Status(status),
+        }
+    }
+
+    /// Classifies the error of a send, returned either by the writer or by
+    /// the transport.
+    pub fn of(error: &Report) -> Self {
+        if let Some(error) = error.downcast_ref::<SendError>() {
+            return *error;
+        }
+        let refused = error.chain().any(|cause| {
+            cause
+                .downcast_ref::<io::Error>()
+                .is_some_and(|error| error.kind() == io::ErrorKind::ConnectionRefused)
+        });
+        if refused {
+            SendError::AgentDown
+        } else {
+            SendError::Transport
+        }
+    }
+
+    /// Name of the class of the error in the counters of the writer.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SendError::EndpointNotFound => "endpoint_not_found",
+            SendError::PayloadTooLarge => "payload_too_large",
+            SendError::RateLimited => "rate_limited",
+            SendError::AgentDown => "agent_down",
+            SendError::Status(_) => "status",
+            SendError::Transport => "transport",
+        }
+    }
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::EndpointNotFound => f.write_str("Endpoint not found (404)"),
+            SendError::PayloadTooLarge => f.write_str("Payload too large (413)"),
+            SendError::RateLimited => f.write_str("Too many requests (429)"),
+            SendError::AgentDown => f.write_str("Connection refused"),
+            SendError::Status(status) => write!(f, "Responded with {}", status),
+            SendError::Transport => f.write_str("Transport failure"),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use eyre::{eyre, WrapErr};
+
+    #[test]
+    fn classifies_send_errors() {
+        assert_eq!(SendError::from_status(404), SendError::EndpointNotFound);
+        assert_eq!(SendError::from_status(503), SendError::Status(503));
+        assert_eq!(
+            SendError::of(&Report::new(SendError::RateLimited)),
+            SendError::RateLimited
+        );
+
+        let refused: Result<(), io::Error> = Err(io::ErrorKind::ConnectionRefused.into());
+        let refused = refused.wrap_err("Unable to connect").unwrap_err();
+        assert_eq!(SendError::of(&refused), SendError::AgentDown);
+        assert_eq!(
+            SendError::of(&eyre!("Malformed HTTP response")),
+            SendError::Transport
+        );
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+
+/// Relative accuracy of the quantiles of sketches, as in the agent.
+const RELATIVE_ACCURACY: f64 = 0.01;
+
+/// Sketch is a DDSketch of positive values, e.g. span durations: values are
+/// counted in bins whose bounds grow by a constant ratio, so that any
+/// quantile is known within `RELATIVE_ACCURACY`. It's sent in the
+/// protobuf encoding the agent merges sketches from.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Sketch {
+    bins: BTreeMap<i32, u64>,
+    zero_count: u64,
+}
+
+fn gamma() -> f64 {
+    (1.0 + RELATIVE_ACCURACY) / (1.0 - RELATIVE_ACCURACY)
+}
+
+/// Returns the bin of `value`, as the logarithmic mapping of the agent
+/// sketches does without index offset.
+fn index(value: f64) -> i32 {
+    (value.ln() / gamma().ln()).floor() as i32
+}
+
+/// Returns the value the bin `index` stands for, within the relative
+/// accuracy of all the values it counts.
+#[cfg(test)]
+fn value(index: i32) -> f64 {
+    gamma().powi(index) * (1.0 + RELATIVE_ACCURACY)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_double(out: &mut Vec<u8>, field: u8, value: f64) {
+    out.push(field << 3 | 1);
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_message(out: &mut Vec<u8>, field: u8, message: &[u8]) {
+    out.push(field << 3 | 2);
+    write_varint(out, message.len() as u64);
+    out.extend_from_slice(message);
+}
+
+impl Sketch {
+    pub fn add(&mut self, value: f64) {
+        if value > 0.0 {
+            *self.bins.entry(index(value)).or_default() += 1;
+        } else {
+            self.zero_count += 1;
+        }
+    }
+
+    /// Encodes the sketch as a `DDSketch` protobuf message, the bins as a
+    /// contiguous store.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut mapping = Vec::new();
+        write_double(&mut mapping, 1, gamma());
+
+        let mut store = Vec::new();
+        if let (Some((&first, _)), Some((&last, _))) =
+            (self.bins.first_key_value(), self.bins.last_key_value())
+        {
+            let mut counts = Vec::new();
+            for index in first..=last {
+                let count = self.bins.get(&index).copied().unwrap_or_default();
+                counts.extend_from_slice(&(count as f64).to_le_bytes());
+            }
+            write_message(&mut store, 2, &counts);
+            if first != 0 {
+                store.push(3 << 3);
+                // sint32, zigzag encoded.
+                write_varint(&mut store, ((first << 1) ^ (first >> 31)) as u32 as u64);
+            }
+        }
+
+        let mut out = Vec::new();
+        write_message(&mut out, 1, &mapping);
+        write_message(&mut out, 2, &store);
+        if self.zero_count > 0 {
+            write_double(&mut out, 4, self.zero_count as f64);
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_values_within_the_relative_accuracy() {
+        for duration in [1.0, 2.5, 999.0, 12_500_000.0, 3.6e12].iter() {
+            let approximation = value(index(*duration));
+            // Bounds of bins are within rounding errors of the accuracy.
+            let error = (approximation - duration).abs() / duration;
+            assert!(error <= RELATIVE_ACCURACY * (1.0 + 1e-9));
+        }
+
+        let mut sketch = Sketch::default();
+        sketch.add(0.0);
+        sketch.add(100.0);
+        sketch.add(100.5);
+        sketch.add(200.0);
+        assert_eq!(sketch.zero_count, 1);
+        assert_eq!(sketch.bins.values().collect::<Vec<_>>(), vec![&2, &1]);
+    }
+
+    #[test]
+    fn encodes_protobuf_sketches() {
+        let mut sketch = Sketch::default();
+        sketch.add(1.0);
+        sketch.add(1.0);
+        sketch.add(gamma().powi(-2) * 1.001);
+        sketch.add(0.0);
+
+        let mut expected = vec![0x0a, 0x09, 0x09];
+        expected.extend_from_slice(&gamma().to_le_bytes());
+        // Bins -2 to 0, offset -2 as zigzag 3.
+        expected.extend_from_slice(&[0x12, 0x1c, 0x12, 0x18]);
+        for count in [1.0f64, 0.0, 2.0].iter() {
+            expected.extend_from_slice(&count.to_le_bytes());
+        }
+        expected.extend_from_slice(&[0x18, 0x03, 0x21]);
+        expected.extend_from_slice(&1.0f64.to_le_bytes());
+        assert_eq!(sketch.encode(), expected);
+
+        let empty = Sketch::default().encode();
+        assert_eq!(&empty[11..], &[0x12, 0x00]);
+    }
+}
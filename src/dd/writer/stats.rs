@@ -0,0 +1,309 @@
+use super::{write_array_len, write_bin, write_bool, write_map_len, write_str, write_uint, Sketch};
+use crate::dd::{
+    span::SpanData,
+    tags::{MEASURED, ORIGIN, PARTIAL_VERSION, TOP_LEVEL},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+/// Width of the time buckets stats are aggregated in, in nanoseconds, as in
+/// the other Datadog tracers.
+const BUCKET_NS: i64 = 10_000_000_000;
+const HTTP_STATUS_CODE: &str = "http.status_code";
+
+/// What the stats of spans are grouped by in a bucket.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct AggregationKey {
+    service: Arc<str>,
+    name: Arc<str>,
+    resource: Arc<str>,
+    span_type: Arc<str>,
+    http_status_code: u32,
+    synthetics: bool,
+}
+
+impl AggregationKey {
+    fn of(span: &SpanData, synthetics: bool) -> Self {
+        let http_status_code = match span.meta.get(HTTP_STATUS_CODE) {
+            Some(code) => code.parse().unwrap_or_default(),
+            None => span
+                .metrics
+                .get(HTTP_STATUS_CODE)
+                .map_or(0, |code| *code as u32),
+        };
+
+        Self {
+            service: span.service.clone(),
+            name: span.name.clone(),
+            resource: span.resource.clone(),
+            span_type: span.span_type.clone(),
+            http_status_code,
+            synthetics,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct GroupedStats {
+    hits: u64,
+    top_level_hits: u64,
+    errors: u64,
+    /// Sum of the durations, in nanoseconds.
+    duration: u64,
+    ok_summary: Sketch,
+    error_summary: Sketch,
+}
+
+/// StatsConcentrator computes the stats of traces in the tracer, as the
+/// agent would from the traces it receives: the hits, errors and durations
+/// of the top-level and measured spans, by service, name, resource, type
+/// and HTTP status, in buckets of 10 seconds. Once the agent takes them,
+/// it no longer needs every trace, e.g. those dropped by sampling.
+#[derive(Debug, Default)]
+pub(crate) struct StatsConcentrator {
+    env: String,
+    version: String,
+    /// Stats by start of bucket, in nanoseconds since the epoch.
+    buckets: BTreeMap<i64, HashMap<AggregationKey, GroupedStats>>,
+    /// Number of payloads encoded so far.
+    sequence: u64,
+}
+
+impl StatsConcentrator {
+    pub fn new(env: &str, version: &str) -> Self {
+        Self {
+            env: String::from(env),
+            version: String::from(version),
+            ..Default::default()
+        }
+    }
+
+    /// Adds the top-level and measured spans of `trace` to the bucket of
+    /// the time they ended. Partial copies of running spans aren't counted.
+    pub fn add_trace(&mut self, trace: &[SpanData]) {
+        let synthetics = trace.iter().any(|span| {
+            span.meta
+                .get(ORIGIN)
+                .is_some_and(|origin| origin.starts_with("synthetics"))
+        });
+        for span in trace {
+            let top_level = span.metrics.get(TOP_LEVEL) == Some(&1.0);
+            let measured = span.metrics.get(MEASURED) == Some(&1.0);
+            if !(top_level || measured) || span.metrics.contains_key(PARTIAL_VERSION) {
+                continue;
+            }
+
+            let end = span.start + span.duration;
+            let stats = self
+                .buckets
+                .entry(end - end.rem_euclid(BUCKET_NS))
+                .or_default()
+                .entry(AggregationKey::of(span, synthetics))
+                .or_default();
+            let duration = span.duration.max(0) as u64;
+            stats.hits += 1;
+            stats.duration += duration;
+            if top_level {
+                stats.top_level_hits += 1;
+            }
+            if span.error != 0 {
+                stats.errors += 1;
+                stats.error_summary.add(duration as f64);
+            } else {
+                stats.ok_summary.add(duration as f64);
+            }
+        }
+    }
+
+    /// Takes the buckets which ended at `now` (in nanoseconds since the
+    /// epoch), or all of them if `all`, encoded as a payload of the agent
+    /// `/v0.6/stats` endpoint. None if there are none.
+    pub fn flush(&mut self, now: i64, all: bool) -> Option<Vec<u8>> {
+        let buckets = if all {
+            std::mem::take(&mut self.buckets)
+        } else {
+            let current = self.buckets.split_off(&(now - now.rem_euclid(BUCKET_NS)));
+            std::mem::replace(&mut self.buckets, current)
+        };
+        if buckets.is_empty() {
+            return None;
+        }
+        self.sequence += 1;
+
+        Some(self.encode(&buckets))
+    }
+
+    pub fn clear(&mut self) {
+        self.buckets.clear();
+    }
+
+    /// Encodes `buckets` as a msgpack `ClientStatsPayload`.
+    fn encode(&self, buckets: &BTreeMap<i64, HashMap<AggregationKey, GroupedStats>>) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_map_len(&mut out, 7);
+        write_str(&mut out, "Hostname");
+        write_str(&mut out, "");
+        write_str(&mut out, "Env");
+        write_str(&mut out, &self.env);
+        write_str(&mut out, "Version");
+        write_str(&mut out, &self.version);
+        write_str(&mut out, "Lang");
+        write_str(&mut out, "rust");
+        write_str(&mut out, "TracerVersion");
+        write_str(&mut out, env!("CARGO_PKG_VERSION"));
+        write_str(&mut out, "Sequence");
+        write_uint(&mut out, self.sequence);
+
+        write_str(&mut out, "Stats");
+        write_array_len(&mut out, buckets.len());
+        for (start, stats) in buckets {
+            write_map_len(&mut out, 3);
+            write_str(&mut out, "Start");
+            write_uint(&mut out, (*start).max(0) as u64);
+            write_str(&mut out, "Duration");
+            write_uint(&mut out, BUCKET_NS as u64);
+            write_str(&mut out, "Stats");
+            write_array_len(&mut out, stats.len());
+            for (key, stats) in stats {
+                encode_grouped_stats(&mut out, key, stats);
+            }
+        }
+
+        out
+    }
+}
+
+fn encode_grouped_stats(out: &mut Vec<u8>, key: &AggregationKey, stats: &GroupedStats) {
+    write_map_len(out, 12);
+    write_str(out, "Service");
+    write_str(out, &key.service);
+    write_str(out, "Name");
+    write_str(out, &key.name);
+    write_str(out, "Resource");
+    write_str(out, &key.resource);
+    write_str(out, "Type");
+    write_str(out, &key.span_type);
+    write_str(out, "HTTPStatusCode");
+    write_uint(out, u64::from(key.http_status_code));
+    write_str(out, "Synthetics");
+    write_bool(out, key.synthetics);
+    write_str(out, "Hits");
+    write_uint(out, stats.hits);
+    write_str(out, "TopLevelHits");
+    write_uint(out, stats.top_level_hits);
+    write_str(out, "Errors");
+    write_uint(out, stats.errors);
+    write_str(out, "Duration");
+    write_uint(out, stats.duration);
+    write_str(out, "OkSummary");
+    write_bin(out, &stats.ok_summary.encode());
+    write_str(out, "ErrorSummary");
+    write_bin(out, &stats.error_summary.encode());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dd::writer::decode_msgpack;
+    use serde_json::Value;
+
+    const START: i64 = 1_700_000_000_000_000_000;
+
+    fn span(span_id: u64, resource: &str, start: i64, duration: i64) -> SpanData {
+        let mut span = SpanData {
+            service: Arc::from("web"),
+            name: Arc::from("http.request"),
+            resource: Arc::from(resource),
+            span_type: Arc::from("web"),
+            span_id,
+            start,
+            duration,
+            ..Default::default()
+        };
+        span.metrics.insert(String::from(TOP_LEVEL), 1.0);
+        span
+    }
+
+    fn decode(payload: Vec<u8>) -> Value {
+        let mut bytes = payload.as_slice();
+        let decoded = decode_msgpack(&mut bytes);
+        assert!(bytes.is_empty(), "trailing bytes after the payload");
+        decoded
+    }
+
+    #[test]
+    fn aggregates_top_level_and_measured_spans() {
+        let mut concentrator = StatsConcentrator::new("prod", "1.2.3");
+        let mut failed = span(2, "GET /cart", START, 3_000);
+        failed.error = 1;
+        failed
+            .meta
+            .insert(String::from(HTTP_STATUS_CODE), String::from("500"));
+        let mut child = span(3, "SELECT", START, 500);
+        child.metrics.clear();
+        let mut measured = child.clone();
+        measured.metrics.insert(String::from(MEASURED), 1.0);
+        let mut partial = span(4, "GET /cart", START, 1_000);
+        partial.metrics.insert(String::from(PARTIAL_VERSION), 1.0);
+        concentrator.add_trace(&[span(1, "GET /cart", START, 1_000), child, measured, partial]);
+        concentrator.add_trace(&[span(5, "GET /cart", START, 2_000), failed]);
+
+        let payload = decode(concentrator.flush(START, true).unwrap());
+        assert_eq!(payload["Env"], "prod");
+        assert_eq!(payload["Version"], "1.2.3");
+        assert_eq!(payload["Lang"], "rust");
+        assert_eq!(payload["Sequence"], 1);
+        assert_eq!(payload["Stats"][0]["Start"], START as u64);
+        assert_eq!(payload["Stats"][0]["Duration"], BUCKET_NS as u64);
+
+        let mut stats: Vec<(&str, u64, u64, u64, u64, u64)> = payload["Stats"][0]["Stats"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|stats| {
+                (
+                    stats["Resource"].as_str().unwrap(),
+                    stats["HTTPStatusCode"].as_u64().unwrap(),
+                    stats["Hits"].as_u64().unwrap(),
+                    stats["TopLevelHits"].as_u64().unwrap(),
+                    stats["Errors"].as_u64().unwrap(),
+                    stats["Duration"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        stats.sort_unstable();
+        assert_eq!(
+            stats,
+            vec![
+                ("GET /cart", 0, 2, 2, 0, 3_000),
+                ("GET /cart", 500, 1, 1, 1, 3_000),
+                ("SELECT", 0, 1, 0, 0, 500),
+            ]
+        );
+        assert!(concentrator.flush(START, true).is_none());
+    }
+
+    #[test]
+    fn flushes_the_buckets_which_ended() {
+        let mut concentrator = StatsConcentrator::default();
+        let mut synthetics = span(1, "GET /", START, 1_000);
+        synthetics
+            .meta
+            .insert(String::from(ORIGIN), String::from("synthetics-browser"));
+        concentrator.add_trace(&[synthetics]);
+        concentrator.add_trace(&[span(2, "GET /", START + BUCKET_NS, 1_000)]);
+
+        let payload = decode(concentrator.flush(START + BUCKET_NS, false).unwrap());
+        let buckets = payload["Stats"].as_array().unwrap();
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0]["Stats"][0]["Synthetics"], true);
+        assert!(concentrator.flush(START + BUCKET_NS, false).is_none());
+
+        let payload = decode(concentrator.flush(START + BUCKET_NS, true).unwrap());
+        assert_eq!(payload["Sequence"], 2);
+        assert_eq!(payload["Stats"][0]["Start"], (START + BUCKET_NS) as u64);
+        assert_eq!(payload["Stats"][0]["Stats"][0]["Synthetics"], false);
+    }
+}
@@ -0,0 +1,327 @@
+//! C interface to the Datadog tracer, so that C/C++ hosts such as nginx,
+//! envoy or haproxy can load it as a plugin instead of dd-opentracing-cpp.
+//!
+//! Tracers are created from the same JSON configuration as the C++ plugin.
+//! Spans borrow the tracer that created them: every span must be finished
+//! before its tracer is freed.
+
+use crate::{
+    dd::{tracer_options_from_json, OwnedSpan, SpanContext, Tracer},
+    opentracing::{
+        self, PropagationError, SpanReferenceType, StartSpanOptions, TextMapReader, TextMapWriter,
+        Tracer as _,
+    },
+};
+use eyre::{eyre, Result};
+use std::{
+    ffi::{CStr, CString},
+    os::raw::{c_char, c_int, c_void},
+    ptr,
+    rc::Rc,
+    time::Duration,
+};
+
+/// Looks up `key` in `carrier`. Returns NULL if the key isn't present; the
+/// returned string only has to stay valid until the next call.
+pub type DdLookupFn = extern "C" fn(carrier: *mut c_void, key: *const c_char) -> *const c_char;
+
+/// Sets `key` to `value` in `carrier`. Returns 0 on success.
+pub type DdSetFn =
+    extern "C" fn(carrier: *mut c_void, key: *const c_char, value: *const c_char) -> c_int;
+
+/// Opaque span handle.
+pub struct DdSpan {
+    span: Box<dyn opentracing::Span>,
+    /// The inferred span of the API gateway in front of the server span,
+    /// finished after it.
+    proxy: Option<OwnedSpan>,
+}
+
+struct CallbackReader {
+    lookup: DdLookupFn,
+    carrier: *mut c_void,
+}
+
+impl TextMapReader for CallbackReader {
+    fn lookup_key(&self, key: &str) -> Result<String, PropagationError> {
+        let key = CString::new(key).map_err(|_| PropagationError::InvalidCarrier)?;
+        let value = (self.lookup)(self.carrier, key.as_ptr());
+        if value.is_null() {
+            return Err(PropagationError::KeyNotFound);
+        }
+
+        // The host guarantees the value is a valid C string until the next call.
+        let value = unsafe { CStr::from_ptr(value) };
+        Ok(value.to_string_lossy().into_owned())
+    }
+
+    fn foreach_key(&self, _f: &mut dyn FnMut(&str, &str) -> Result<()>) -> Result<()> {
+        Err(eyre!("{:?}", PropagationError::LookupKeyNotSupported))
+    }
+}
+
+struct CallbackWriter {
+    set: DdSetFn,
+    carrier: *mut c_void,
+}
+
+impl TextMapWriter for CallbackWriter {
+    fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let key = CString::new(key)?;
+        let value = CString::new(value)?;
+        match (self.set)(self.carrier, key.as_ptr(), value.as_ptr()) {
+            0 => Ok(()),
+            code => Err(eyre!(
+                "carrier rejected {} with code {}",
+                key.to_string_lossy(),
+                code
+            )),
+        }
+    }
+}
+
+unsafe fn to_str<'a>(value: *const c_char) -> Option<&'a str> {
+    if value.is_null() {
+        return None;
+    }
+    CStr::from_ptr(value).to_str().ok()
+}
+
+unsafe fn context_of<'a>(span: *const DdSpan) -> Option<&'a SpanContext> {
+    span.as_ref()?
+        .span
+        .context()
+        .as_any()
+        .downcast_ref::<SpanContext>()
+}
+
+unsafe fn start_span(
+    tracer: *const Tracer,
+    operation_name: *const c_char,
+    parent: Option<Rc<dyn opentracing::SpanContext>>,
+) -> *mut DdSpan {
+    let (tracer, operation_name) = match (tracer.as_ref(), to_str(operation_name)) {
+        (Some(tracer), Some(operation_name)) => (tracer, operation_name),
+        _ => return ptr::null_mut(),
+    };
+
+    let mut options = StartSpanOptions::default();
+    if let Some(parent) = parent {
+        options
+            .references
+            .push((SpanReferenceType::ChildOfRef, parent));
+    }
+    let span = tracer.start_span_with_options(operation_name, &options);
+
+    Box::into_raw(Box::new(DdSpan { span, proxy: None }))
+}
+
+/// Creates a tracer from a JSON configuration. Returns NULL if the
+/// configuration is invalid.
+///
+/// # Safety
+///
+/// `json_config` must be NULL or a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn dd_tracer_new(json_config: *const c_char) -> *mut Tracer {
+    let tracer = to_str(json_config)
+        .ok_or_else(|| eyre!("Invalid configuration string"))
+        .and_then(tracer_options_from_json)
+        .and_then(Tracer::new);
+
+    match tracer {
+        Ok(tracer) => Box::into_raw(Box::new(tracer)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Flushes and frees a tracer created by `dd_tracer_new`.
+///
+/// # Safety
+///
+/// `tracer` must be NULL or come from `dd_tracer_new`, and all of its spans
+/// must be finished.
+#[no_mangle]
+pub unsafe extern "C" fn dd_tracer_free(tracer: *mut Tracer) {
+    if !tracer.is_null() {
+        drop(Box::from_raw(tracer));
+    }
+}
+
+/// Sends the finished traces, waiting up to `timeout_ms`. Returns the number
+/// of traces sent, or -1 on failure.
+///
+/// # Safety
+///
+/// `tracer` must come from `dd_tracer_new`.
+#[no_mangle]
+pub unsafe extern "C" fn dd_tracer_flush(tracer: *const Tracer, timeout_ms: u64) -> c_int {
+    match tracer
+        .as_ref()
+        .map(|tracer| tracer.flush(Duration::from_millis(timeout_ms)))
+    {
+        Some(Ok(sent)) => sent as c_int,
+        _ => -1,
+    }
+}
+
+/// Starts a span, as a child of `parent` unless it's NULL.
+///
+/// # Safety
+///
+/// `tracer` must come from `dd_tracer_new`, `operation_name` must be a valid
+/// C string and `parent` must be NULL or an unfinished span.
+#[no_mangle]
+pub unsafe extern "C" fn dd_span_start(
+    tracer: *const Tracer,
+    operation_name: *const c_char,
+    parent: *const DdSpan,
+) -> *mut DdSpan {
+    let parent = context_of(parent)
+        .and_then(|context| context.with_id(context.id()).ok())
+        .map(|context| Rc::new(context) as Rc<dyn opentracing::SpanContext>);
+
+    start_span(tracer, operation_name, parent)
+}
+
+/// Starts a span continuing the trace propagated in `carrier`, or a new trace
+/// if the carrier holds no valid span context. With `request_queuing`, it's
+/// the child of an `http.queue` span of the time the request was queued.
+/// With `inferred_proxy_services`, these are children of the span of the
+/// API gateway which forwarded the request, finished with the span.
+///
+/// # Safety
+///
+/// `tracer` must come from `dd_tracer_new`, `operation_name` must be a valid
+/// C string and `lookup` must be safe to call with `carrier`.
+#[no_mangle]
+pub unsafe extern "C" fn dd_span_start_extracted(
+    tracer: *const Tracer,
+    operation_name: *const c_char,
+    lookup: DdLookupFn,
+    carrier: *mut c_void,
+) -> *mut DdSpan {
+    let reader = CallbackReader { lookup, carrier };
+    let mut parent = tracer
+        .as_ref()
+        .and_then(|tracer| tracer.extract(&reader).ok().flatten().map(Rc::from));
+    let proxy = tracer.as_ref().and_then(|tracer| {
+        let options = StartSpanOptions {
+            parent_context: parent.clone(),
+            ..Default::default()
+        };
+        tracer.start_proxy_span(&reader, &options)
+    });
+    if let Some(proxy) = &proxy {
+        parent = Some(Rc::new(proxy.context().clone()));
+    }
+    let queue = tracer.as_ref().and_then(|tracer| {
+        let options = StartSpanOptions {
+            parent_context: parent.clone(),
+            ..Default::default()
+        };
+        tracer.start_queue_span(&reader, &options)
+    });
+    if let Some(queue) = &queue {
+        parent = Some(Rc::new(queue.context().clone()));
+    }
+
+    let span = start_span(tracer, operation_name, parent);
+    if let Some(mut queue) = queue {
+        queue.finish();
+    }
+    if let Some(span) = span.as_mut() {
+        span.proxy = proxy;
+    }
+    span
+}
+
+/// Sets a string tag on the span.
+///
+/// # Safety
+///
+/// `span` must be an unfinished span, `key` and `value` valid C strings.
+#[no_mangle]
+pub unsafe extern "C" fn dd_span_set_tag(
+    span: *mut DdSpan,
+    key: *const c_char,
+    value: *const c_char,
+) {
+    if let (Some(span), Some(key), Some(value)) = (span.as_mut(), to_str(key), to_str(value)) {
+        span.span
+            .set_tag(key, &serde_json::Value::String(String::from(value)));
+    }
+}
+
+/// Sets a numeric tag on the span.
+///
+/// # Safety
+///
+/// `span` must be an unfinished span and `key` a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn dd_span_set_metric(span: *mut DdSpan, key: *const c_char, value: f64) {
+    if let (Some(span), Some(key)) = (span.as_mut(), to_str(key)) {
+        span.span.set_tag(key, &serde_json::json!(value));
+    }
+}
+
+/// Returns the id of the span, or 0 if it's NULL.
+///
+/// # Safety
+///
+/// `span` must be NULL or an unfinished span.
+#[no_mangle]
+pub unsafe extern "C" fn dd_span_id(span: *const DdSpan) -> u64 {
+    context_of(span).map_or(0, SpanContext::id)
+}
+
+/// Returns the lower 64 bits of the trace id of the span, or 0 if it's
+/// NULL.
+///
+/// # Safety
+///
+/// `span` must be NULL or an unfinished span.
+#[no_mangle]
+pub unsafe extern "C" fn dd_span_trace_id(span: *const DdSpan) -> u64 {
+    context_of(span).map_or(0, SpanContext::trace_id)
+}
+
+/// Writes the span context into `carrier`. Returns 0 on success.
+///
+/// # Safety
+///
+/// `span` must be an unfinished span and `set` must be safe to call with
+/// `carrier`.
+#[no_mangle]
+pub unsafe extern "C" fn dd_span_inject(
+    span: *const DdSpan,
+    set: DdSetFn,
+    carrier: *mut c_void,
+) -> c_int {
+    let span = match span.as_ref() {
+        Some(span) => span,
+        None => return -1,
+    };
+
+    let mut writer = CallbackWriter { set, carrier };
+    match span.span.tracer().inject(span.span.context(), &mut writer) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Finishes the span and frees it.
+///
+/// # Safety
+///
+/// `span` must be NULL or a span that wasn't finished yet.
+#[no_mangle]
+pub unsafe extern "C" fn dd_span_finish(span: *mut DdSpan) {
+    if !span.is_null() {
+        let mut span = Box::from_raw(span);
+        span.span.finish(Vec::new());
+        if let Some(mut proxy) = span.proxy.take() {
+            proxy.finish();
+        }
+    }
+}
@@ -1,5 +1,41 @@
-#[macro_use]
-extern crate derivative;
+#![cfg_attr(not(feature = "std"), no_std)]
+// The JSON of the tracer options outgrows the default limit of `json!`.
+#![recursion_limit = "256"]
 
+extern crate alloc;
+
+#[cfg(feature = "std")]
 mod dd;
-mod opentracing;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "std")]
+use opentracing_rs_api as opentracing;
+pub mod propagation;
+pub mod sampling;
+/// Clocks controlling the time of the tracer, e.g. to test rate limits
+/// without waiting.
+#[cfg(feature = "testing")]
+pub mod testing {
+    pub use crate::dd::utils::clock::{Clock, ManualClock, SystemClock, TimePoint};
+}
+
+pub use propagation::{PropagationStyle, SamplingPriority};
+
+/// The spans of common clients and of tower services.
+#[cfg(feature = "contrib")]
+pub use dd::contrib;
+#[cfg(feature = "http-client")]
+pub use dd::TracerFactory;
+/// The Datadog tracer: it starts spans, samples and propagates their traces
+/// and sends them to the agent.
+#[cfg(feature = "std")]
+pub use dd::{
+    activate, active_context, hex_to_id, id_to_hex, spawn, trace_id_to_hex,
+    tracer_options_from_json, AgentInfo, BaggageLimits, CircuitOpenPolicy, Clock, Compression,
+    ConfigOrigins, ConfigSource, HttpResponse, LogFunc, LogLevel, MessageCarrier, MetadataCarrier,
+    OwnedSpan, QueueFullPolicy, RateUpdateStats, SampleResult, SamplerOverride, SamplingDecision,
+    SavedSpan, SavedTrace, Scope, ScopedContext, Span, SpanContext, SpanData, SpanExt,
+    SpanFinishHook, StatusCodes, SystemClock, TimePoint, Timer, TraceFilter, TraceProcessor,
+    Tracer, TracerOptions, TracerOptionsDelta, Transport, UrlFilter, UserInfo, WithContext,
+    WithSpan,
+};
@@ -1,11 +0,0 @@
-mod noop;
-mod propagation;
-mod span;
-mod tracer;
-mod tracer_factory;
-
-pub(crate) use noop::*;
-pub(crate) use propagation::*;
-pub(crate) use span::*;
-pub(crate) use tracer::*;
-pub(crate) use tracer_factory::*;
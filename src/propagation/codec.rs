@@ -0,0 +1,788 @@
+use super::{PropagatedContext, PropagationStyle, SamplingPriority};
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::fmt;
+
+pub const BAGGAGE_PREFIX: &str = "ot-baggage-";
+
+const TRACEPARENT: &str = "traceparent";
+const TRACESTATE: &str = "tracestate";
+const B3: &str = "b3";
+const DATADOG_TAGS: &str = "x-datadog-tags";
+/// Trace tag holding the upper 64 bits of 128-bit trace ids, in hex.
+pub const TRACE_ID_HIGH_TAG: &str = "_dd.p.tid";
+/// The prefix of the trace tags propagated in `x-datadog-tags`.
+pub const PROPAGATED_TAG_PREFIX: &str = "_dd.p.";
+
+/// Styles are tried in this order when extracting.
+pub const STYLES: [PropagationStyle; 4] = [
+    PropagationStyle::Datadog,
+    PropagationStyle::B3,
+    PropagationStyle::B3Single,
+    PropagationStyle::W3C,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExtractError {
+    InvalidId(String),
+    InvalidSamplingPriority(String),
+    InvalidTraceparent(String),
+    InvalidB3(String),
+    InvalidToken(String),
+    IncompleteContext,
+}
+
+impl fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExtractError::InvalidId(value) => write!(f, "Invalid id: {}", value),
+            ExtractError::InvalidSamplingPriority(value) => {
+                write!(f, "Invalid sampling priority: {}", value)
+            }
+            ExtractError::InvalidTraceparent(value) => write!(f, "Invalid traceparent: {}", value),
+            ExtractError::InvalidB3(value) => write!(f, "Invalid b3 header: {}", value),
+            ExtractError::InvalidToken(value) => write!(f, "Invalid span context token: {}", value),
+            ExtractError::IncompleteContext => write!(f, "Incomplete span context in headers"),
+        }
+    }
+}
+
+/// How 128-bit trace ids are injected. Turning the options off lets peers
+/// that only handle 64-bit ids be migrated after the rest of the fleet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InjectOptions {
+    /// Adds the upper 64 bits of the trace id as `_dd.p.tid` in
+    /// `x-datadog-tags`. `x-datadog-trace-id` only holds the lower 64 bits.
+    pub datadog_trace_id_128: bool,
+    /// Writes the full trace id in W3C `traceparent` headers, instead of
+    /// zeroing its upper 64 bits.
+    pub w3c_trace_id_128: bool,
+}
+
+impl Default for InjectOptions {
+    fn default() -> Self {
+        Self {
+            datadog_trace_id_128: true,
+            w3c_trace_id_128: true,
+        }
+    }
+}
+
+struct HeaderNames {
+    trace_id: &'static str,
+    span_id: &'static str,
+    sampling_priority: &'static str,
+    origin: Option<&'static str>,
+    radix: u32,
+}
+
+fn header_names(style: &PropagationStyle) -> Option<HeaderNames> {
+    match style {
+        PropagationStyle::Datadog => Some(HeaderNames {
+            trace_id: "x-datadog-trace-id",
+            span_id: "x-datadog-parent-id",
+            sampling_priority: "x-datadog-sampling-priority",
+            origin: Some("x-datadog-origin"),
+            radix: 10,
+        }),
+        PropagationStyle::B3 => Some(HeaderNames {
+            trace_id: "x-b3-traceid",
+            span_id: "x-b3-spanid",
+            sampling_priority: "x-b3-sampled",
+            origin: None,
+            radix: 16,
+        }),
+        PropagationStyle::B3Single | PropagationStyle::W3C => None,
+    }
+}
+
+/// Id formatted on the stack, as ids are injected in every outbound
+/// request: up to two 16-digit hex ids, or a decimal one.
+struct FormattedId {
+    digits: [u8; 32],
+    len: usize,
+}
+
+impl FormattedId {
+    fn new() -> Self {
+        Self {
+            digits: [0; 32],
+            len: 0,
+        }
+    }
+
+    /// Appends `id` as 16 hex digits.
+    fn hex(mut self, id: u64) -> Self {
+        const DIGITS: &[u8; 16] = b"0123456789abcdef";
+        for shift in (0..16).rev() {
+            self.digits[self.len] = DIGITS[(id >> (shift * 4)) as usize & 0xf];
+            self.len += 1;
+        }
+        self
+    }
+
+    /// Appends `id` in decimal.
+    fn decimal(mut self, mut id: u64) -> Self {
+        let mut reversed = [0; 20];
+        let mut len = 0;
+        loop {
+            reversed[len] = b'0' + (id % 10) as u8;
+            len += 1;
+            id /= 10;
+            if id == 0 {
+                break;
+            }
+        }
+        for digit in reversed[..len].iter().rev() {
+            self.digits[self.len] = *digit;
+            self.len += 1;
+        }
+        self
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.digits[..self.len]).unwrap_or_default()
+    }
+}
+
+fn format_id(id: u64, radix: u32) -> FormattedId {
+    if radix == 16 {
+        FormattedId::new().hex(id)
+    } else {
+        FormattedId::new().decimal(id)
+    }
+}
+
+fn format_trace_id(trace_id_high: u64, trace_id: u64, radix: u32) -> FormattedId {
+    if radix == 16 && trace_id_high != 0 {
+        FormattedId::new().hex(trace_id_high).hex(trace_id)
+    } else {
+        format_id(trace_id, radix)
+    }
+}
+
+fn parse_id(value: &str, radix: u32) -> Result<u64, ExtractError> {
+    let value = value.trim();
+    u64::from_str_radix(value, radix).map_err(|_| ExtractError::InvalidId(String::from(value)))
+}
+
+/// Parses a trace id into its upper and lower 64 bits. Only hex ids can be
+/// longer than 64 bits.
+fn parse_trace_id(value: &str, radix: u32) -> Result<(u64, u64), ExtractError> {
+    let value = value.trim();
+    if radix != 16 || value.len() <= 16 {
+        return Ok((0, parse_id(value, radix)?));
+    }
+    if value.len() > 32 || !value.is_char_boundary(value.len() - 16) {
+        return Err(ExtractError::InvalidId(String::from(value)));
+    }
+
+    let (high, low) = value.split_at(value.len() - 16);
+    Ok((parse_id(high, 16)?, parse_id(low, 16)?))
+}
+
+/// Reads `_dd.p.tid` from `x-datadog-tags`. Malformed values are ignored, as
+/// the lower 64 bits are enough to continue the trace.
+fn parse_trace_id_high(tags: &str) -> u64 {
+    tags.split(',')
+        .find_map(|tag| {
+            tag.trim()
+                .strip_prefix(TRACE_ID_HIGH_TAG)?
+                .strip_prefix('=')
+        })
+        .filter(|value| value.len() == 16)
+        .and_then(|value| u64::from_str_radix(value, 16).ok())
+        .unwrap_or_default()
+}
+
+/// Reads the `_dd.p.*` trace tags of `x-datadog-tags` but `_dd.p.tid`.
+/// Malformed tags are ignored.
+fn parse_trace_tags(tags: &str) -> BTreeMap<String, String> {
+    tags.split(',')
+        .filter_map(|tag| {
+            let (key, value) = tag.trim().split_once('=')?;
+            (key.starts_with(PROPAGATED_TAG_PREFIX)
+                && key != TRACE_ID_HIGH_TAG
+                && !value.is_empty())
+            .then(|| (String::from(key), String::from(value)))
+        })
+        .collect()
+}
+
+/// Formats the value of `x-datadog-tags`: `_dd.p.tid` if `trace_id_high`
+/// isn't 0, then the `_dd.p.*` tags of `trace_tags` which can be written
+/// there, i.e. without commas, nor `=` in their key.
+fn format_trace_tags(trace_id_high: u64, trace_tags: &BTreeMap<String, String>) -> String {
+    let mut tags = String::new();
+    if trace_id_high != 0 {
+        tags.push_str(TRACE_ID_HIGH_TAG);
+        tags.push('=');
+        tags.push_str(FormattedId::new().hex(trace_id_high).as_str());
+    }
+    let valid = |(key, value): &(&String, &String)| {
+        key.starts_with(PROPAGATED_TAG_PREFIX)
+            && key.as_str() != TRACE_ID_HIGH_TAG
+            && !key.contains([',', '='])
+            && !value.is_empty()
+            && !value.contains(',')
+    };
+    for (key, value) in trace_tags.iter().filter(valid) {
+        if !tags.is_empty() {
+            tags.push(',');
+        }
+        tags.push_str(key);
+        tags.push('=');
+        tags.push_str(value);
+    }
+    tags
+}
+
+fn encode_priority(style: &PropagationStyle, priority: &SamplingPriority) -> &'static str {
+    match (style, priority) {
+        (PropagationStyle::Datadog, SamplingPriority::UserDrop) => "-1",
+        (PropagationStyle::Datadog, SamplingPriority::UserKeep) => "2",
+        (_, priority) if priority.is_keep() => "1",
+        _ => "0",
+    }
+}
+
+fn decode_priority(
+    style: &PropagationStyle,
+    value: &str,
+) -> Result<SamplingPriority, ExtractError> {
+    let priority = match style {
+        PropagationStyle::Datadog => value
+            .trim()
+            .parse::<i32>()
+            .ok()
+            .and_then(SamplingPriority::from_i32),
+        _ => match value.trim() {
+            "0" => Some(SamplingPriority::SamplerDrop),
+            "1" => Some(SamplingPriority::SamplerKeep),
+            _ => None,
+        },
+    };
+
+    priority.ok_or_else(|| ExtractError::InvalidSamplingPriority(String::from(value)))
+}
+
+fn inject_w3c<E>(
+    context: &PropagatedContext,
+    options: &InjectOptions,
+    set: &mut dyn FnMut(&str, &str) -> Result<(), E>,
+) -> Result<(), E> {
+    let sampled = context
+        .sampling_priority
+        .as_ref()
+        .is_some_and(SamplingPriority::is_keep);
+    let trace_id_high = if options.w3c_trace_id_128 {
+        context.trace_id_high
+    } else {
+        0
+    };
+    let mut traceparent = String::with_capacity(55);
+    traceparent.push_str("00-");
+    traceparent.push_str(
+        FormattedId::new()
+            .hex(trace_id_high)
+            .hex(context.trace_id)
+            .as_str(),
+    );
+    traceparent.push('-');
+    traceparent.push_str(FormattedId::new().hex(context.parent_id).as_str());
+    traceparent.push_str(if sampled { "-01" } else { "-00" });
+    set(TRACEPARENT, &traceparent)?;
+
+    let mut state = String::from("dd=");
+    if let Some(priority) = &context.sampling_priority {
+        state.push_str("s:");
+        state.push_str(encode_priority(&PropagationStyle::Datadog, priority));
+    }
+    if !context.origin.is_empty() {
+        if state.len() > 3 {
+            state.push(';');
+        }
+        // ',', ';' and '=' aren't allowed in tracestate values.
+        let origin: String = context
+            .origin
+            .chars()
+            .map(|c| {
+                if c == ',' || c == ';' || c == '=' {
+                    '_'
+                } else {
+                    c
+                }
+            })
+            .collect();
+        state.push_str("o:");
+        state.push_str(&origin);
+    }
+    if state.len() > 3 {
+        set(TRACESTATE, &state)?;
+    }
+
+    Ok(())
+}
+
+fn inject_b3_single<E>(
+    context: &PropagatedContext,
+    set: &mut dyn FnMut(&str, &str) -> Result<(), E>,
+) -> Result<(), E> {
+    let mut value = String::with_capacity(51);
+    value.push_str(format_trace_id(context.trace_id_high, context.trace_id, 16).as_str());
+    value.push('-');
+    value.push_str(FormattedId::new().hex(context.parent_id).as_str());
+    if let Some(priority) = &context.sampling_priority {
+        value.push('-');
+        value.push_str(encode_priority(&PropagationStyle::B3Single, priority));
+    }
+    set(B3, &value)
+}
+
+/// Writes the headers of `style` for `context` through `set`. Baggage is
+/// written as `ot-baggage-` headers regardless of the style.
+pub fn inject<E>(
+    style: &PropagationStyle,
+    context: &PropagatedContext,
+    set: &mut dyn FnMut(&str, &str) -> Result<(), E>,
+) -> Result<(), E> {
+    inject_with_options(style, context, &InjectOptions::default(), set)
+}
+
+/// Writes the headers of `style` for `context` through `set`, formatting
+/// 128-bit trace ids as set in `options`.
+pub fn inject_with_options<E>(
+    style: &PropagationStyle,
+    context: &PropagatedContext,
+    options: &InjectOptions,
+    set: &mut dyn FnMut(&str, &str) -> Result<(), E>,
+) -> Result<(), E> {
+    match header_names(style) {
+        Some(names) => {
+            set(
+                names.trace_id,
+                format_trace_id(context.trace_id_high, context.trace_id, names.radix).as_str(),
+            )?;
+            set(
+                names.span_id,
+                format_id(context.parent_id, names.radix).as_str(),
+            )?;
+            if let Some(priority) = &context.sampling_priority {
+                set(names.sampling_priority, encode_priority(style, priority))?;
+            }
+            if let Some(origin) = names.origin.filter(|_| !context.origin.is_empty()) {
+                set(origin, &context.origin)?;
+            }
+            if *style == PropagationStyle::Datadog {
+                let trace_id_high = match options.datadog_trace_id_128 {
+                    true => context.trace_id_high,
+                    false => 0,
+                };
+                let tags = format_trace_tags(trace_id_high, &context.trace_tags);
+                if !tags.is_empty() {
+                    set(DATADOG_TAGS, &tags)?;
+                }
+            }
+        }
+        None if *style == PropagationStyle::B3Single => inject_b3_single(context, set)?,
+        None => inject_w3c(context, options, set)?,
+    }
+
+    let mut name = String::from(BAGGAGE_PREFIX);
+    for (key, value) in &context.baggage {
+        name.truncate(BAGGAGE_PREFIX.len());
+        name.push_str(key);
+        set(&name, value)?;
+    }
+
+    Ok(())
+}
+
+fn extract_w3c(
+    lookup: &mut dyn FnMut(&str) -> Option<String>,
+) -> Result<Option<PropagatedContext>, ExtractError> {
+    let traceparent = match lookup(TRACEPARENT) {
+        Some(traceparent) => traceparent,
+        None => return Ok(None),
+    };
+    let invalid = || ExtractError::InvalidTraceparent(traceparent.clone());
+
+    let mut parts = traceparent.trim().split('-');
+    let (version, trace_id, parent_id, flags) =
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(version), Some(trace_id), Some(parent_id), Some(flags)) => {
+                (version, trace_id, parent_id, flags)
+            }
+            _ => return Err(invalid()),
+        };
+    if version.len() != 2 || version == "ff" || trace_id.len() != 32 || parent_id.len() != 16 {
+        return Err(invalid());
+    }
+    let flags = u8::from_str_radix(flags, 16).map_err(|_| invalid())?;
+
+    let (trace_id_high, trace_id) = parse_trace_id(trace_id, 16)?;
+    let mut context = PropagatedContext {
+        trace_id,
+        trace_id_high,
+        parent_id: parse_id(parent_id, 16)?,
+        ..Default::default()
+    };
+    if context.trace_id == 0 || context.parent_id == 0 {
+        return Err(invalid());
+    }
+
+    let sampled = flags & 1 == 1;
+    let mut priority = None;
+    let tracestate = lookup(TRACESTATE).unwrap_or_default();
+    let dd_state = tracestate
+        .split(',')
+        .find_map(|member| member.trim().strip_prefix("dd="))
+        .unwrap_or_default();
+    for field in dd_state.split(';') {
+        if let Some(value) = field.strip_prefix("s:") {
+            priority = value
+                .parse::<i32>()
+                .ok()
+                .and_then(SamplingPriority::from_i32);
+        } else if let Some(value) = field.strip_prefix("o:") {
+            context.origin = String::from(value);
+        }
+    }
+    // The flag wins over a tracestate written by a tracer that didn't update it.
+    context.sampling_priority = match priority {
+        Some(priority) if priority.is_keep() == sampled => Some(priority),
+        _ if sampled => Some(SamplingPriority::SamplerKeep),
+        _ => Some(SamplingPriority::SamplerDrop),
+    };
+
+    Ok(Some(context))
+}
+
+fn extract_b3_single(
+    lookup: &mut dyn FnMut(&str) -> Option<String>,
+) -> Result<Option<PropagatedContext>, ExtractError> {
+    let value = match lookup(B3) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    let invalid = || ExtractError::InvalidB3(value.clone());
+
+    // {trace id}-{span id}[-{sampled}[-{parent span id}]], or only a
+    // sampling decision which doesn't continue a trace.
+    let parts: Vec<&str> = value.trim().split('-').collect();
+    if parts.len() == 1 {
+        return match parts[0] {
+            "0" | "1" | "d" => Ok(None),
+            _ => Err(invalid()),
+        };
+    }
+    if parts.len() > 4 {
+        return Err(invalid());
+    }
+
+    let sampling_priority = match parts.get(2) {
+        // Debug requests are always kept.
+        Some(&"d") => Some(SamplingPriority::UserKeep),
+        Some(sampled) => Some(decode_priority(&PropagationStyle::B3Single, sampled)?),
+        None => None,
+    };
+
+    let (trace_id_high, trace_id) = parse_trace_id(parts[0], 16)?;
+    Ok(Some(PropagatedContext {
+        trace_id,
+        trace_id_high,
+        parent_id: parse_id(parts[1], 16)?,
+        sampling_priority,
+        ..Default::default()
+    }))
+}
+
+/// Reads the headers of `style` through `lookup`. Returns `None` if they're
+/// absent and an error if they're corrupted or incomplete. Baggage isn't
+/// extracted since `lookup` can't enumerate the headers.
+pub fn extract(
+    style: &PropagationStyle,
+    lookup: &mut dyn FnMut(&str) -> Option<String>,
+) -> Result<Option<PropagatedContext>, ExtractError> {
+    let names = match header_names(style) {
+        Some(names) => names,
+        None if *style == PropagationStyle::B3Single => return extract_b3_single(lookup),
+        None => return extract_w3c(lookup),
+    };
+
+    let trace_id = lookup(names.trace_id);
+    let span_id = lookup(names.span_id);
+    let origin = names.origin.and_then(&mut *lookup).unwrap_or_default();
+
+    let ((mut trace_id_high, trace_id), parent_id) = match (trace_id, span_id) {
+        (None, None) => return Ok(None),
+        (Some(trace_id), Some(span_id)) => (
+            parse_trace_id(&trace_id, names.radix)?,
+            parse_id(&span_id, names.radix)?,
+        ),
+        // Synthetics requests carry an origin and a trace id but no parent.
+        (Some(trace_id), None) if !origin.is_empty() => {
+            (parse_trace_id(&trace_id, names.radix)?, 0)
+        }
+        _ => return Err(ExtractError::IncompleteContext),
+    };
+    let mut trace_tags = BTreeMap::new();
+    if *style == PropagationStyle::Datadog {
+        let tags = lookup(DATADOG_TAGS).unwrap_or_default();
+        trace_id_high = parse_trace_id_high(&tags);
+        trace_tags = parse_trace_tags(&tags);
+    }
+
+    let sampling_priority = match lookup(names.sampling_priority) {
+        Some(value) => Some(decode_priority(style, &value)?),
+        None => None,
+    };
+
+    Ok(Some(PropagatedContext {
+        trace_id,
+        trace_id_high,
+        parent_id,
+        sampling_priority,
+        origin,
+        trace_tags,
+        ..Default::default()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{collections::BTreeMap, format, string::ToString};
+
+    fn inject_to_map(
+        style: &PropagationStyle,
+        context: &PropagatedContext,
+    ) -> BTreeMap<String, String> {
+        let mut headers = BTreeMap::new();
+        inject::<()>(style, context, &mut |key, value| {
+            headers.insert(String::from(key), String::from(value));
+            Ok(())
+        })
+        .unwrap();
+        headers
+    }
+
+    fn extract_from_map(
+        style: &PropagationStyle,
+        headers: &BTreeMap<String, String>,
+    ) -> Result<Option<PropagatedContext>, ExtractError> {
+        extract(style, &mut |key| headers.get(key).cloned())
+    }
+
+    fn context() -> PropagatedContext {
+        PropagatedContext {
+            trace_id: 0x1234,
+            parent_id: 0xabc,
+            sampling_priority: Some(SamplingPriority::UserKeep),
+            origin: String::from("synthetics"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn formats_ids_like_format() {
+        for id in [0, 1, 9, 10, 0xabc, u32::MAX as u64, u64::MAX].iter() {
+            assert_eq!(format_id(*id, 10).as_str(), id.to_string());
+            assert_eq!(format_id(*id, 16).as_str(), format!("{:016x}", id));
+            let trace_id = match id {
+                0 => format!("{:016x}", 1),
+                _ => format!("{:016x}{:016x}", id, 1),
+            };
+            assert_eq!(format_trace_id(*id, 1, 16).as_str(), trace_id);
+        }
+    }
+
+    #[test]
+    fn datadog_round_trip() {
+        let mut context = context();
+        context
+            .baggage
+            .insert(String::from("user"), String::from("42"));
+        let headers = inject_to_map(&PropagationStyle::Datadog, &context);
+
+        assert_eq!(headers["x-datadog-trace-id"], "4660");
+        assert_eq!(headers["x-datadog-parent-id"], "2748");
+        assert_eq!(headers["x-datadog-sampling-priority"], "2");
+        assert_eq!(headers["x-datadog-origin"], "synthetics");
+        assert_eq!(headers["ot-baggage-user"], "42");
+
+        context.baggage.clear();
+        let extracted = extract_from_map(&PropagationStyle::Datadog, &headers).unwrap();
+        assert_eq!(extracted, Some(context));
+    }
+
+    #[test]
+    fn b3_round_trip() {
+        let mut headers = inject_to_map(&PropagationStyle::B3, &context());
+        assert_eq!(headers["x-b3-traceid"], "0000000000001234");
+        assert_eq!(headers["x-b3-spanid"], "0000000000000abc");
+        assert_eq!(headers["x-b3-sampled"], "1");
+
+        headers.insert(
+            String::from("x-b3-traceid"),
+            String::from("ffffffffffffffff0000000000001234"),
+        );
+        let extracted = extract_from_map(&PropagationStyle::B3, &headers)
+            .unwrap()
+            .unwrap();
+        assert_eq!(extracted.trace_id, 0x1234);
+        assert_eq!(extracted.parent_id, 0xabc);
+        assert_eq!(
+            extracted.sampling_priority,
+            Some(SamplingPriority::SamplerKeep)
+        );
+    }
+
+    #[test]
+    fn b3_single_round_trip() {
+        let style = PropagationStyle::B3Single;
+        let mut headers = inject_to_map(&style, &context());
+        assert_eq!(headers["b3"], "0000000000001234-0000000000000abc-1");
+        let extracted = extract_from_map(&style, &headers).unwrap().unwrap();
+        assert_eq!(extracted.trace_id, 0x1234);
+        assert_eq!(extracted.parent_id, 0xabc);
+        assert_eq!(
+            extracted.sampling_priority,
+            Some(SamplingPriority::SamplerKeep)
+        );
+
+        headers.insert(
+            String::from("b3"),
+            String::from("80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-d-05e3ac9a4f6e3b90"),
+        );
+        let extracted = extract_from_map(&style, &headers).unwrap().unwrap();
+        assert_eq!(extracted.trace_id, 0x64fe8b2a57d3eff7);
+        assert_eq!(
+            extracted.sampling_priority,
+            Some(SamplingPriority::UserKeep)
+        );
+
+        headers.insert(String::from("b3"), String::from("0"));
+        assert_eq!(extract_from_map(&style, &headers), Ok(None));
+        headers.insert(String::from("b3"), String::from("1234"));
+        assert!(extract_from_map(&style, &headers).is_err());
+    }
+
+    #[test]
+    fn w3c_round_trip() {
+        let mut headers = inject_to_map(&PropagationStyle::W3C, &context());
+        assert_eq!(
+            headers["traceparent"],
+            "00-00000000000000000000000000001234-0000000000000abc-01"
+        );
+        assert_eq!(headers["tracestate"], "dd=s:2;o:synthetics");
+        let extracted = extract_from_map(&PropagationStyle::W3C, &headers).unwrap();
+        assert_eq!(extracted, Some(context()));
+
+        // A tracestate contradicting the sampled flag is ignored.
+        headers.insert(
+            String::from("traceparent"),
+            String::from("00-00000000000000000000000000001234-0000000000000abc-00"),
+        );
+        let extracted = extract_from_map(&PropagationStyle::W3C, &headers)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            extracted.sampling_priority,
+            Some(SamplingPriority::SamplerDrop)
+        );
+    }
+
+    #[test]
+    fn keeps_128bit_trace_ids() {
+        let context = PropagatedContext {
+            trace_id_high: 0x640cfd8d00000000,
+            ..context()
+        };
+        for style in STYLES.iter() {
+            let headers = inject_to_map(style, &context);
+            let extracted = extract_from_map(style, &headers).unwrap().unwrap();
+            assert_eq!(
+                (extracted.trace_id_high, extracted.trace_id),
+                (0x640cfd8d00000000, 0x1234),
+                "{}",
+                style
+            );
+        }
+
+        let mut headers = inject_to_map(&PropagationStyle::Datadog, &context);
+        assert_eq!(headers["x-datadog-tags"], "_dd.p.tid=640cfd8d00000000");
+        headers.insert(
+            String::from("x-datadog-tags"),
+            String::from("_dd.p.tid=bad"),
+        );
+        let extracted = extract_from_map(&PropagationStyle::Datadog, &headers).unwrap();
+        assert_eq!(extracted.map(|context| context.trace_id_high), Some(0));
+
+        let mut headers = BTreeMap::new();
+        headers.insert(String::from("b3"), format!("{:033x}-{:016x}", 1, 2));
+        assert!(extract_from_map(&PropagationStyle::B3Single, &headers).is_err());
+    }
+
+    #[test]
+    fn propagates_trace_tags_in_datadog_headers() {
+        let mut context = PropagatedContext {
+            trace_id_high: 0x640cfd8d00000000,
+            ..context()
+        };
+        for (key, value) in [
+            ("_dd.p.usr.id", "dXNlci00Mg=="),
+            ("_dd.p.bad", "a,b"),
+            ("user", "not propagated"),
+        ] {
+            context
+                .trace_tags
+                .insert(String::from(key), String::from(value));
+        }
+
+        let headers = inject_to_map(&PropagationStyle::Datadog, &context);
+        assert_eq!(
+            headers["x-datadog-tags"],
+            "_dd.p.tid=640cfd8d00000000,_dd.p.usr.id=dXNlci00Mg=="
+        );
+        let extracted = extract_from_map(&PropagationStyle::Datadog, &headers)
+            .unwrap()
+            .unwrap();
+        assert_eq!(extracted.trace_tags.len(), 1);
+        assert_eq!(extracted.trace_tags["_dd.p.usr.id"], "dXNlci00Mg==");
+
+        let headers = inject_to_map(&PropagationStyle::W3C, &context);
+        assert!(!headers.contains_key("x-datadog-tags"));
+    }
+
+    #[test]
+    fn extraction_edge_cases() {
+        let datadog = PropagationStyle::Datadog;
+        let mut headers = BTreeMap::new();
+        assert_eq!(extract_from_map(&datadog, &headers), Ok(None));
+        assert_eq!(extract_from_map(&PropagationStyle::W3C, &headers), Ok(None));
+
+        headers.insert(String::from("x-datadog-trace-id"), String::from("nope"));
+        headers.insert(String::from("x-datadog-parent-id"), String::from("1"));
+        assert!(extract_from_map(&datadog, &headers).is_err());
+
+        headers.clear();
+        headers.insert(String::from("x-datadog-trace-id"), String::from("1"));
+        assert_eq!(
+            extract_from_map(&datadog, &headers),
+            Err(ExtractError::IncompleteContext)
+        );
+
+        headers.insert(String::from("x-datadog-origin"), String::from("synthetics"));
+        let extracted = extract_from_map(&datadog, &headers).unwrap().unwrap();
+        assert_eq!(extracted.parent_id, 0);
+
+        headers.insert(
+            String::from("x-datadog-sampling-priority"),
+            String::from("7"),
+        );
+        assert!(extract_from_map(&datadog, &headers).is_err());
+
+        headers.insert(String::from("traceparent"), String::from("00-1234-abc-01"));
+        assert!(extract_from_map(&PropagationStyle::W3C, &headers).is_err());
+    }
+}
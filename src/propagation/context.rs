@@ -0,0 +1,183 @@
+use super::{ExtractError, SamplingPriority};
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+/// PropagatedContext is what crosses process boundaries: the ids of the
+/// trace and of the calling span, plus the trace-level decisions.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PropagatedContext {
+    pub trace_id: u64,
+    /// Upper 64 bits of 128-bit trace ids, 0 for 64-bit ones.
+    pub trace_id_high: u64,
+    /// 0 if the caller didn't send a span id (e.g. synthetics requests).
+    pub parent_id: u64,
+    pub sampling_priority: Option<SamplingPriority>,
+    pub origin: String,
+    pub baggage: BTreeMap<String, String>,
+    /// The `_dd.p.*` trace tags other than `_dd.p.tid`, e.g. `_dd.p.usr.id`,
+    /// carried in `x-datadog-tags` by the Datadog style only.
+    pub trace_tags: BTreeMap<String, String>,
+}
+
+impl PropagatedContext {
+    /// Encodes the context as a single string, for carriers holding one
+    /// opaque value (job queues, cron payloads, command line arguments):
+    /// `trace:span[:priority[:origin]]` with hex ids (the trace id up to 128
+    /// bits), followed by
+    /// `;key=value` baggage items. Separators are percent-encoded.
+    pub fn to_token(&self) -> String {
+        let trace_id = (self.trace_id_high as u128) << 64 | self.trace_id as u128;
+        let mut token = format!("{:x}:{:x}", trace_id, self.parent_id);
+        let priority = self
+            .sampling_priority
+            .as_ref()
+            .map(|priority| priority.as_i32().to_string())
+            .unwrap_or_default();
+        if !priority.is_empty() || !self.origin.is_empty() {
+            token.push(':');
+            token.push_str(&priority);
+        }
+        if !self.origin.is_empty() {
+            token.push(':');
+            token.push_str(&escape(&self.origin));
+        }
+        for (key, value) in &self.baggage {
+            token.push_str(&format!(";{}={}", escape(key), escape(value)));
+        }
+        token
+    }
+
+    /// Decodes a token written by `to_token`.
+    pub fn from_token(token: &str) -> Result<PropagatedContext, ExtractError> {
+        let invalid = || ExtractError::InvalidToken(String::from(token));
+        let mut items = token.trim().split(';');
+        let fields: Vec<&str> = items.next().unwrap_or_default().split(':').collect();
+        if fields.len() < 2 || fields.len() > 4 {
+            return Err(invalid());
+        }
+
+        let id = |field: &str| u64::from_str_radix(field, 16).map_err(|_| invalid());
+        let trace_id = u128::from_str_radix(fields[0], 16).map_err(|_| invalid())?;
+        let mut context = PropagatedContext {
+            trace_id: trace_id as u64,
+            trace_id_high: (trace_id >> 64) as u64,
+            parent_id: id(fields[1])?,
+            ..Default::default()
+        };
+        if context.trace_id == 0 {
+            return Err(invalid());
+        }
+        if let Some(priority) = fields.get(2).filter(|priority| !priority.is_empty()) {
+            context.sampling_priority = Some(
+                priority
+                    .parse::<i32>()
+                    .ok()
+                    .and_then(SamplingPriority::from_i32)
+                    .ok_or_else(invalid)?,
+            );
+        }
+        if let Some(origin) = fields.get(3) {
+            context.origin = unescape(origin).ok_or_else(invalid)?;
+        }
+        for item in items {
+            let mut parts = item.splitn(2, '=');
+            let key = unescape(parts.next().unwrap_or_default()).ok_or_else(invalid)?;
+            let value = unescape(parts.next().ok_or_else(invalid)?).ok_or_else(invalid)?;
+            context.baggage.insert(key, value);
+        }
+
+        Ok(context)
+    }
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'%' | b':' | b';' | b'=' => escaped.push_str(&format!("%{:02X}", byte)),
+            byte if byte.is_ascii_graphic() => escaped.push(byte as char),
+            byte => escaped.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    escaped
+}
+
+fn unescape(value: &str) -> Option<String> {
+    let mut bytes = Vec::new();
+    let mut input = value.bytes();
+    while let Some(byte) = input.next() {
+        if byte == b'%' {
+            let hex = [input.next()?, input.next()?];
+            let hex = core::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_round_trip() {
+        let mut context = PropagatedContext {
+            trace_id: 0x1234,
+            parent_id: 0xabc,
+            sampling_priority: Some(SamplingPriority::UserKeep),
+            origin: String::from("synthetics"),
+            ..Default::default()
+        };
+        assert_eq!(context.to_token(), "1234:abc:2:synthetics");
+
+        context
+            .baggage
+            .insert(String::from("user id"), String::from("a=b;c"));
+        let token = context.to_token();
+        assert_eq!(token, "1234:abc:2:synthetics;user%20id=a%3Db%3Bc");
+        assert_eq!(PropagatedContext::from_token(&token), Ok(context));
+
+        let context = PropagatedContext {
+            trace_id: 1,
+            trace_id_high: 0x640cfd8d00000000,
+            ..Default::default()
+        };
+        assert_eq!(context.to_token(), "640cfd8d000000000000000000000001:0");
+        assert_eq!(
+            PropagatedContext::from_token(&context.to_token()),
+            Ok(context)
+        );
+
+        let context = PropagatedContext {
+            trace_id: 1,
+            ..Default::default()
+        };
+        assert_eq!(context.to_token(), "1:0");
+        assert_eq!(PropagatedContext::from_token("1:0"), Ok(context));
+    }
+
+    #[test]
+    fn rejects_invalid_tokens() {
+        for token in &[
+            "",
+            "1234",
+            "0:1",
+            "x:1",
+            "1:1:9",
+            "1:1:1:o:extra",
+            "1:1;key",
+            "1:1:1:%zz",
+        ] {
+            assert_eq!(
+                PropagatedContext::from_token(token),
+                Err(ExtractError::InvalidToken(String::from(*token)))
+            );
+        }
+    }
+}
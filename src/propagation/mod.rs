@@ -0,0 +1,13 @@
+//! Span context model and propagation codecs. They only depend on `alloc`,
+//! so runtimes that can't run the full tracer (no_std, embedded) can still
+//! propagate Datadog, B3 and W3C headers.
+
+mod codec;
+mod context;
+mod propagation_style;
+mod sampling_priority;
+
+pub use codec::*;
+pub use context::*;
+pub use propagation_style::*;
+pub use sampling_priority::*;
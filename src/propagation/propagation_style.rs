@@ -0,0 +1,115 @@
+use alloc::string::String;
+use core::{fmt, str::FromStr};
+
+/// Header format of propagated span contexts. Styles are named as in the
+/// `DD_TRACE_PROPAGATION_STYLE` variables and parsed ignoring case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PropagationStyle {
+    /// `x-datadog-*` headers.
+    Datadog,
+    /// B3 multiple headers (`x-b3-*`), also named `b3multi`.
+    B3,
+    /// B3 single `b3` header.
+    B3Single,
+    /// W3C `traceparent` and `tracestate` headers, also named `w3c`.
+    W3C,
+}
+
+impl PropagationStyle {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PropagationStyle::Datadog => "Datadog",
+            PropagationStyle::B3 => "B3",
+            PropagationStyle::B3Single => "B3 single header",
+            PropagationStyle::W3C => "tracecontext",
+        }
+    }
+}
+
+impl fmt::Display for PropagationStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseStyleError(pub String);
+
+impl fmt::Display for ParseStyleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Unknown propagation style '{}', expected Datadog, B3, B3 single header or \
+             tracecontext",
+            self.0
+        )
+    }
+}
+
+impl FromStr for PropagationStyle {
+    type Err = ParseStyleError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let name = name.trim();
+        let is = |alias: &str| name.eq_ignore_ascii_case(alias);
+        if is("datadog") {
+            Ok(PropagationStyle::Datadog)
+        } else if is("b3") || is("b3multi") {
+            Ok(PropagationStyle::B3)
+        } else if is("b3 single header") {
+            Ok(PropagationStyle::B3Single)
+        } else if is("tracecontext") || is("w3c") {
+            Ok(PropagationStyle::W3C)
+        } else {
+            Err(ParseStyleError(String::from(name)))
+        }
+    }
+}
+
+/// Styles are (de)serialized as their names.
+#[cfg(feature = "serde")]
+impl serde::Serialize for PropagationStyle {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for PropagationStyle {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::propagation::STYLES;
+    use alloc::string::ToString;
+
+    #[test]
+    fn parses_and_displays_names() {
+        for style in STYLES.iter() {
+            assert_eq!(style.to_string().parse::<PropagationStyle>(), Ok(*style));
+        }
+        assert_eq!("B3MULTI".parse(), Ok(PropagationStyle::B3));
+        assert_eq!(" w3c ".parse(), Ok(PropagationStyle::W3C));
+        assert_eq!(
+            "jaeger".parse::<PropagationStyle>(),
+            Err(ParseStyleError(String::from("jaeger")))
+        );
+    }
+
+    #[cfg(all(feature = "serde", feature = "std"))]
+    #[test]
+    fn serializes_as_names() {
+        let json = serde_json::to_string(&[PropagationStyle::Datadog, PropagationStyle::B3Single])
+            .unwrap();
+        assert_eq!(json, r#"["Datadog","B3 single header"]"#);
+        let styles: alloc::vec::Vec<PropagationStyle> =
+            serde_json::from_str(r#"["tracecontext","b3multi"]"#).unwrap();
+        assert_eq!(styles, [PropagationStyle::W3C, PropagationStyle::B3]);
+        assert!(serde_json::from_str::<PropagationStyle>(r#""jaeger""#).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+/// SamplingPriority is the decision to keep or drop a trace, propagated to
+/// the services it calls. Its wire values are those of Datadog headers and
+/// of the `_sampling_priority_v1` metric.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SamplingPriority {
+    UserDrop,
+    SamplerDrop,
+    SamplerKeep,
+    UserKeep,
+}
+
+impl SamplingPriority {
+    /// Value used on the wire and in the `_sampling_priority_v1` metric.
+    pub fn as_i32(&self) -> i32 {
+        match self {
+            SamplingPriority::UserDrop => -1,
+            SamplingPriority::SamplerDrop => 0,
+            SamplingPriority::SamplerKeep => 1,
+            SamplingPriority::UserKeep => 2,
+        }
+    }
+
+    pub fn from_i32(value: i32) -> Option<SamplingPriority> {
+        match value {
+            -1 => Some(SamplingPriority::UserDrop),
+            0 => Some(SamplingPriority::SamplerDrop),
+            1 => Some(SamplingPriority::SamplerKeep),
+            2 => Some(SamplingPriority::UserKeep),
+            _ => None,
+        }
+    }
+
+    /// Whether the trace is kept.
+    pub fn is_keep(&self) -> bool {
+        self.as_i32() > 0
+    }
+
+    /// Whether the decision was made by the user, e.g. through a sampling
+    /// rule or a manual keep, rather than by the agent rates.
+    pub fn is_user_decision(&self) -> bool {
+        matches!(
+            self,
+            SamplingPriority::UserDrop | SamplingPriority::UserKeep
+        )
+    }
+}
+
+/// Converts any wire value, as other Datadog tracers do: values above 2
+/// are user keeps and values below -1 user drops.
+impl From<i32> for SamplingPriority {
+    fn from(value: i32) -> Self {
+        SamplingPriority::from_i32(value.clamp(-1, 2)).unwrap_or(SamplingPriority::UserKeep)
+    }
+}
+
+impl From<SamplingPriority> for i64 {
+    fn from(priority: SamplingPriority) -> Self {
+        priority.as_i32() as i64
+    }
+}
+
+/// Priorities are (de)serialized as their wire values.
+#[cfg(feature = "serde")]
+impl serde::Serialize for SamplingPriority {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i32(self.as_i32())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SamplingPriority {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = i32::deserialize(deserializer)?;
+        SamplingPriority::from_i32(value).ok_or_else(|| {
+            serde::de::Error::invalid_value(
+                serde::de::Unexpected::Signed(value as i64),
+                &"-1, 0, 1 or 2",
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_wire_values() {
+        for value in -1..=2 {
+            let priority = SamplingPriority::from(value);
+            assert_eq!(i64::from(priority.clone()), value as i64);
+            assert_eq!(priority.is_keep(), value > 0);
+        }
+        assert_eq!(SamplingPriority::from(5), SamplingPriority::UserKeep);
+        assert_eq!(SamplingPriority::from(-3), SamplingPriority::UserDrop);
+        assert!(SamplingPriority::UserDrop.is_user_decision());
+        assert!(!SamplingPriority::SamplerKeep.is_user_decision());
+    }
+
+    #[cfg(all(feature = "serde", feature = "std"))]
+    #[test]
+    fn serializes_as_wire_values() {
+        let json =
+            serde_json::to_string(&[SamplingPriority::UserDrop, SamplingPriority::SamplerKeep])
+                .unwrap();
+        assert_eq!(json, "[-1,1]");
+        let priority: SamplingPriority = serde_json::from_str("2").unwrap();
+        assert_eq!(priority, SamplingPriority::UserKeep);
+        assert!(serde_json::from_str::<SamplingPriority>("3").is_err());
+    }
+}
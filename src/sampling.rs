@@ -0,0 +1,30 @@
+//! Trace sampling by rate, shared by the Datadog tracers: a trace id is
+//! kept at a given rate if its Knuth multiplicative hash is below the rate
+//! scaled to the u64 range, as in dd-trace-go, dd-trace-java and
+//! dd-opentracing-cpp. Custom samplers using these functions make the same
+//! decisions as the tracers of the other services of a trace. They only
+//! depend on `core`.
+
+/// Knuth multiplicative hashing factor used by all Datadog tracers.
+pub const KNUTH_FACTOR: u64 = 1111111111111111111;
+
+/// Hashes the lower 64 bits of a trace id.
+pub fn knuth_hash(trace_id: u64) -> u64 {
+    trace_id.wrapping_mul(KNUTH_FACTOR)
+}
+
+/// Returns the hash below which trace ids are kept at `rate`.
+pub fn max_hash(rate: f64) -> u64 {
+    if rate >= 1.0 {
+        u64::MAX
+    } else if rate > 0.0 {
+        (rate * u64::MAX as f64) as u64
+    } else {
+        0
+    }
+}
+
+/// Whether the trace is kept at `rate`, between 0 and 1.
+pub fn sampled_by_rate(trace_id: u64, rate: f64) -> bool {
+    rate >= 1.0 || knuth_hash(trace_id) < max_hash(rate)
+}
@@ -0,0 +1,298 @@
+//! Replays the propagation and sampling scenarios of dd-opentracing-cpp's
+//! tests against the public API, so that services traced by either library
+//! continue each other's traces the same way.
+
+use dd_opentracing_rs::{
+    propagation::{extract, inject, PropagatedContext, STYLES},
+    sampling::{knuth_hash, max_hash, sampled_by_rate},
+    PropagationStyle, SamplingPriority,
+};
+use std::collections::BTreeMap;
+
+/// What extracting the headers of a fixture gives.
+#[derive(Debug)]
+enum Expected {
+    /// No span context: a new trace is started.
+    NoContext,
+    /// The span context is corrupted or incomplete.
+    Error,
+    /// Trace id, parent id, sampling priority and origin.
+    Context(u64, u64, Option<i32>, &'static str),
+}
+
+struct Fixture {
+    name: &'static str,
+    style: PropagationStyle,
+    headers: &'static [(&'static str, &'static str)],
+    expected: Expected,
+}
+
+const FIXTURES: &[Fixture] = &[
+    Fixture {
+        name: "datadog context",
+        style: PropagationStyle::Datadog,
+        headers: &[
+            ("x-datadog-trace-id", "123"),
+            ("x-datadog-parent-id", "456"),
+            ("x-datadog-sampling-priority", "1"),
+        ],
+        expected: Expected::Context(123, 456, Some(1), ""),
+    },
+    Fixture {
+        name: "datadog context with origin",
+        style: PropagationStyle::Datadog,
+        headers: &[
+            ("x-datadog-trace-id", "123"),
+            ("x-datadog-parent-id", "456"),
+            ("x-datadog-sampling-priority", "2"),
+            ("x-datadog-origin", "madeuporigin"),
+        ],
+        expected: Expected::Context(123, 456, Some(2), "madeuporigin"),
+    },
+    Fixture {
+        name: "datadog context without sampling priority",
+        style: PropagationStyle::Datadog,
+        headers: &[
+            ("x-datadog-trace-id", "123"),
+            ("x-datadog-parent-id", "456"),
+        ],
+        expected: Expected::Context(123, 456, None, ""),
+    },
+    Fixture {
+        name: "datadog user drop",
+        style: PropagationStyle::Datadog,
+        headers: &[
+            ("x-datadog-trace-id", "123"),
+            ("x-datadog-parent-id", "456"),
+            ("x-datadog-sampling-priority", "-1"),
+        ],
+        expected: Expected::Context(123, 456, Some(-1), ""),
+    },
+    Fixture {
+        name: "synthetics request without parent id",
+        style: PropagationStyle::Datadog,
+        headers: &[
+            ("x-datadog-trace-id", "123"),
+            ("x-datadog-origin", "synthetics"),
+        ],
+        expected: Expected::Context(123, 0, None, "synthetics"),
+    },
+    Fixture {
+        name: "max ids",
+        style: PropagationStyle::Datadog,
+        headers: &[
+            ("x-datadog-trace-id", "18446744073709551615"),
+            ("x-datadog-parent-id", "18446744073709551615"),
+        ],
+        expected: Expected::Context(u64::MAX, u64::MAX, None, ""),
+    },
+    Fixture {
+        name: "ids padded with whitespace",
+        style: PropagationStyle::Datadog,
+        headers: &[
+            ("x-datadog-trace-id", " 123 "),
+            ("x-datadog-parent-id", "456\t"),
+        ],
+        expected: Expected::Context(123, 456, None, ""),
+    },
+    Fixture {
+        name: "no headers",
+        style: PropagationStyle::Datadog,
+        headers: &[("x-other-header", "123")],
+        expected: Expected::NoContext,
+    },
+    Fixture {
+        name: "missing parent id",
+        style: PropagationStyle::Datadog,
+        headers: &[("x-datadog-trace-id", "123")],
+        expected: Expected::Error,
+    },
+    Fixture {
+        name: "missing trace id",
+        style: PropagationStyle::Datadog,
+        headers: &[("x-datadog-parent-id", "456")],
+        expected: Expected::Error,
+    },
+    Fixture {
+        name: "non-numeric trace id",
+        style: PropagationStyle::Datadog,
+        headers: &[
+            ("x-datadog-trace-id", "gfhjkd"),
+            ("x-datadog-parent-id", "456"),
+        ],
+        expected: Expected::Error,
+    },
+    Fixture {
+        name: "negative parent id",
+        style: PropagationStyle::Datadog,
+        headers: &[
+            ("x-datadog-trace-id", "123"),
+            ("x-datadog-parent-id", "-456"),
+        ],
+        expected: Expected::Error,
+    },
+    Fixture {
+        name: "trace id overflow",
+        style: PropagationStyle::Datadog,
+        headers: &[
+            ("x-datadog-trace-id", "18446744073709551616"),
+            ("x-datadog-parent-id", "456"),
+        ],
+        expected: Expected::Error,
+    },
+    Fixture {
+        name: "unknown sampling priority",
+        style: PropagationStyle::Datadog,
+        headers: &[
+            ("x-datadog-trace-id", "123"),
+            ("x-datadog-parent-id", "456"),
+            ("x-datadog-sampling-priority", "5"),
+        ],
+        expected: Expected::Error,
+    },
+    Fixture {
+        name: "b3 context",
+        style: PropagationStyle::B3,
+        headers: &[
+            ("x-b3-traceid", "7b"),
+            ("x-b3-spanid", "1c8"),
+            ("x-b3-sampled", "1"),
+        ],
+        expected: Expected::Context(123, 456, Some(1), ""),
+    },
+    Fixture {
+        name: "b3 drop",
+        style: PropagationStyle::B3,
+        headers: &[
+            ("x-b3-traceid", "000000000000007b"),
+            ("x-b3-spanid", "00000000000001c8"),
+            ("x-b3-sampled", "0"),
+        ],
+        expected: Expected::Context(123, 456, Some(0), ""),
+    },
+    Fixture {
+        name: "b3 decimal sampling priority",
+        style: PropagationStyle::B3,
+        headers: &[
+            ("x-b3-traceid", "7b"),
+            ("x-b3-spanid", "1c8"),
+            ("x-b3-sampled", "2"),
+        ],
+        expected: Expected::Error,
+    },
+    Fixture {
+        name: "b3 non-hex span id",
+        style: PropagationStyle::B3,
+        headers: &[("x-b3-traceid", "7b"), ("x-b3-spanid", "xyz")],
+        expected: Expected::Error,
+    },
+];
+
+fn extract_fixture(fixture: &Fixture) -> Result<Option<PropagatedContext>, String> {
+    let headers: BTreeMap<&str, &str> = fixture.headers.iter().cloned().collect();
+    extract(&fixture.style, &mut |key| {
+        headers.get(key).map(|value| value.to_string())
+    })
+    .map_err(|error| error.to_string())
+}
+
+fn inject_to_map(
+    style: &PropagationStyle,
+    context: &PropagatedContext,
+) -> BTreeMap<String, String> {
+    let mut headers = BTreeMap::new();
+    inject::<()>(style, context, &mut |key, value| {
+        headers.insert(String::from(key), String::from(value));
+        Ok(())
+    })
+    .unwrap();
+    headers
+}
+
+#[test]
+fn extracts_fixtures() {
+    for fixture in FIXTURES {
+        let extracted = extract_fixture(fixture);
+        let matches = match (&fixture.expected, &extracted) {
+            (Expected::NoContext, Ok(None)) | (Expected::Error, Err(_)) => true,
+            (Expected::Context(trace_id, parent_id, priority, origin), Ok(Some(context))) => {
+                context.trace_id == *trace_id
+                    && context.parent_id == *parent_id
+                    && context
+                        .sampling_priority
+                        .as_ref()
+                        .map(SamplingPriority::as_i32)
+                        == *priority
+                    && context.origin == *origin
+            }
+            _ => false,
+        };
+        assert!(
+            matches,
+            "{}: expected {:?}, got {:?}",
+            fixture.name, fixture.expected, extracted
+        );
+    }
+}
+
+#[test]
+fn propagates_priority_and_origin_across_styles() {
+    let context = PropagatedContext {
+        trace_id: 123,
+        parent_id: 456,
+        sampling_priority: Some(SamplingPriority::UserKeep),
+        origin: String::from("synthetics"),
+        ..Default::default()
+    };
+
+    let headers = inject_to_map(&PropagationStyle::Datadog, &context);
+    assert_eq!(headers["x-datadog-trace-id"], "123");
+    assert_eq!(headers["x-datadog-parent-id"], "456");
+    assert_eq!(headers["x-datadog-sampling-priority"], "2");
+    assert_eq!(headers["x-datadog-origin"], "synthetics");
+
+    // B3 only has a keep or drop flag, and no origin.
+    let headers = inject_to_map(&PropagationStyle::B3, &context);
+    assert_eq!(headers["x-b3-sampled"], "1");
+    assert!(!headers.contains_key("x-datadog-origin"));
+    let dropped = PropagatedContext {
+        sampling_priority: Some(SamplingPriority::UserDrop),
+        ..context.clone()
+    };
+    let headers = inject_to_map(&PropagationStyle::B3, &dropped);
+    assert_eq!(headers["x-b3-sampled"], "0");
+
+    // Every style reads back the trace it wrote.
+    for style in STYLES.iter() {
+        let headers = inject_to_map(style, &context);
+        let extracted = extract(style, &mut |key| headers.get(key).cloned())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (extracted.trace_id, extracted.parent_id),
+            (123, 456),
+            "{}",
+            style
+        );
+        assert!(extracted.sampling_priority.unwrap().is_keep(), "{}", style);
+    }
+}
+
+#[test]
+fn samples_as_dd_opentracing_cpp() {
+    // The hash of trace id 1 is the Knuth factor, about 0.06 of the range.
+    assert_eq!(knuth_hash(1), 1111111111111111111);
+    assert!(sampled_by_rate(1, 0.1));
+    assert!(!sampled_by_rate(1, 0.05));
+
+    assert_eq!(max_hash(1.0), u64::MAX);
+    assert_eq!(max_hash(0.0), 0);
+    assert!((0..1000).all(|trace_id| sampled_by_rate(trace_id, 1.0)));
+    assert!((1..1000).all(|trace_id| !sampled_by_rate(trace_id, 0.0)));
+
+    // Rates keep their share of sequential trace ids.
+    let kept = (1..=10_000u64)
+        .filter(|trace_id| sampled_by_rate(*trace_id, 0.25))
+        .count();
+    assert!((2300..=2700).contains(&kept), "kept {}", kept);
+}
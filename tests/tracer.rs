@@ -0,0 +1,79 @@
+//! Traces a request with the public API only, as services linking the
+//! crate do.
+#![cfg(feature = "std")]
+
+use dd_opentracing_rs::{HttpResponse, Span, Tracer, TracerOptions, Transport};
+use opentracing_rs_api::{Span as _, StartSpanOptions, Tracer as _};
+use serde_json::Value;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Answers every request with 200, keeping the bodies of the posted ones.
+#[derive(Default)]
+struct Agent {
+    posts: Mutex<Vec<Vec<u8>>>,
+}
+
+impl Transport for Agent {
+    fn get(&self, _path: &str) -> eyre::Result<HttpResponse> {
+        Ok(HttpResponse {
+            status: 404,
+            body: Vec::new(),
+        })
+    }
+
+    fn post(
+        &self,
+        _path: &str,
+        _headers: &[(&str, String)],
+        body: &[u8],
+    ) -> eyre::Result<HttpResponse> {
+        self.posts.lock().unwrap().push(body.to_vec());
+        Ok(HttpResponse {
+            status: 200,
+            body: Vec::new(),
+        })
+    }
+}
+
+#[test]
+fn sends_the_spans_of_requests_to_the_agent() {
+    let agent = Arc::new(Agent::default());
+    let options = TracerOptions {
+        service: String::from("web"),
+        ..Default::default()
+    };
+    let tracer = Tracer::with_transport(options, agent.clone()).unwrap();
+
+    let mut span = tracer.start_span("request", Vec::new());
+    span.set_tag("http.method", &Value::from("GET"));
+    span.finish(Vec::new());
+    drop(span);
+    assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 1);
+
+    let posts = agent.posts.lock().unwrap();
+    let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0]).unwrap();
+    assert_eq!(traces[0][0]["name"], "request");
+    assert_eq!(traces[0][0]["service"], "web");
+    assert_eq!(traces[0][0]["meta"]["http.method"], "GET");
+}
+
+#[test]
+fn measures_custom_spans() {
+    let agent = Arc::new(Agent::default());
+    let tracer = Tracer::with_transport(TracerOptions::default(), agent.clone()).unwrap();
+
+    let owned = tracer.start_owned_span("render", &StartSpanOptions::default());
+    let mut span = Span::new(&tracer, owned);
+    span.set_measured(true);
+    span.finish(Vec::new());
+    drop(span);
+    assert_eq!(tracer.flush(Duration::from_secs(5)).unwrap(), 1);
+
+    let posts = agent.posts.lock().unwrap();
+    let traces: Vec<Vec<Value>> = serde_json::from_slice(&posts[0]).unwrap();
+    assert_eq!(traces[0][0]["metrics"]["_dd.measured"], 1.0);
+    assert_eq!(traces[0][0]["metrics"]["_dd.top_level"], 1.0);
+}